@@ -167,6 +167,21 @@ void tmprl_poll_workflow_activation(struct tmprl_worker_t *worker,
                                     void *user_data,
                                     tmprl_callback callback);
 
+/**
+ * Poll for a batch of workflow activations.
+ *
+ * The `req_proto` and `req_proto_len` represent a byte array for a
+ * [bridge::PollWorkflowActivationsRequest] protobuf message.
+ *
+ * The callback is invoked on completion with a [bridge::PollWorkflowActivationsResponse] protobuf
+ * message.
+ */
+void tmprl_poll_workflow_activations(struct tmprl_worker_t *worker,
+                                     const uint8_t *req_proto,
+                                     size_t req_proto_len,
+                                     void *user_data,
+                                     tmprl_callback callback);
+
 /**
  * Poll for an activity task.
  *
@@ -196,6 +211,19 @@ void tmprl_complete_workflow_activation(struct tmprl_worker_t *worker,
                                         void *user_data,
                                         tmprl_callback callback);
 
+/**
+ * Complete a batch of workflow activations.
+ *
+ * The `req_proto` and `req_proto_len` represent a byte array for a
+ * [bridge::CompleteWorkflowActivationsRequest] protobuf message. The callback is invoked on
+ * completion with a [bridge::CompleteWorkflowActivationsResponse] protobuf message.
+ */
+void tmprl_complete_workflow_activations(struct tmprl_worker_t *worker,
+                                         const uint8_t *req_proto,
+                                         size_t req_proto_len,
+                                         void *user_data,
+                                         tmprl_callback callback);
+
 /**
  * Complete an activity task.
  *
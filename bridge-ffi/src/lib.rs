@@ -460,6 +460,58 @@ pub extern "C" fn tmprl_poll_workflow_activation(
     });
 }
 
+/// Poll for a batch of workflow activations.
+///
+/// The `req_proto` and `req_proto_len` represent a byte array for a
+/// [bridge::PollWorkflowActivationsRequest] protobuf message.
+///
+/// The callback is invoked on completion with a [bridge::PollWorkflowActivationsResponse] protobuf
+/// message.
+#[no_mangle]
+pub extern "C" fn tmprl_poll_workflow_activations(
+    worker: *mut tmprl_worker_t,
+    req_proto: *const u8,
+    req_proto_len: libc::size_t,
+    user_data: *mut libc::c_void,
+    callback: tmprl_callback,
+) {
+    let worker = unsafe { &mut *worker };
+    let req = match tmprl_worker_t::decode_proto::<bridge::PollWorkflowActivationsRequest>(
+        req_proto,
+        req_proto_len,
+    ) {
+        Ok(req) => req,
+        Err(message) => {
+            let resp = bridge::PollWorkflowActivationsResponse {
+                response: Some(bridge::poll_workflow_activations_response::Response::Error(
+                    bridge::poll_workflow_activation_response::Error {
+                        message,
+                        shutdown: false,
+                    },
+                )),
+            };
+            unsafe {
+                callback(user_data, worker.encode_proto(&resp).into_raw());
+            }
+            return;
+        }
+    };
+    let user_data = UserDataHandle(user_data);
+    worker.tokio_runtime.clone().spawn(async move {
+        let resp = bridge::PollWorkflowActivationsResponse {
+            response: Some(match worker.poll_workflow_activations(req).await {
+                Ok(activations) => {
+                    bridge::poll_workflow_activations_response::Response::Activations(
+                        bridge::poll_workflow_activations_response::Activations { activations },
+                    )
+                }
+                Err(err) => bridge::poll_workflow_activations_response::Response::Error(err),
+            }),
+        };
+        unsafe { callback(user_data.into(), worker.encode_proto(&resp).into_raw()) };
+    });
+}
+
 /// Poll for an activity task.
 ///
 /// The `req_proto` and `req_proto_len` represent a byte array for a
@@ -537,6 +589,51 @@ pub extern "C" fn tmprl_complete_workflow_activation(
     });
 }
 
+/// Complete a batch of workflow activations.
+///
+/// The `req_proto` and `req_proto_len` represent a byte array for a
+/// [bridge::CompleteWorkflowActivationsRequest] protobuf message. The callback is invoked on
+/// completion with a [bridge::CompleteWorkflowActivationsResponse] protobuf message.
+#[no_mangle]
+pub extern "C" fn tmprl_complete_workflow_activations(
+    worker: *mut tmprl_worker_t,
+    req_proto: *const u8,
+    req_proto_len: libc::size_t,
+    user_data: *mut libc::c_void,
+    callback: tmprl_callback,
+) {
+    let worker = unsafe { &mut *worker };
+    let req = match tmprl_worker_t::decode_proto::<bridge::CompleteWorkflowActivationsRequest>(
+        req_proto,
+        req_proto_len,
+    ) {
+        Ok(req) => req,
+        Err(message) => {
+            let resp = bridge::CompleteWorkflowActivationsResponse {
+                responses: vec![bridge::CompleteWorkflowActivationResponse {
+                    error: Some(bridge::complete_workflow_activation_response::Error { message }),
+                }],
+            };
+            unsafe {
+                callback(user_data, worker.encode_proto(&resp).into_raw());
+            }
+            return;
+        }
+    };
+    let user_data = UserDataHandle(user_data);
+    worker.tokio_runtime.clone().spawn(async move {
+        let resp = bridge::CompleteWorkflowActivationsResponse {
+            responses: worker
+                .complete_workflow_activations(req)
+                .await
+                .into_iter()
+                .map(|r| bridge::CompleteWorkflowActivationResponse { error: r.err() })
+                .collect(),
+        };
+        unsafe { callback(user_data.into(), worker.encode_proto(&resp).into_raw()) };
+    });
+}
+
 /// Complete an activity task.
 ///
 /// The `req_proto` and `req_proto_len` represent a byte array for a
@@ -743,6 +840,22 @@ impl tmprl_worker_t {
         })
     }
 
+    async fn poll_workflow_activations(
+        &self,
+        req: bridge::PollWorkflowActivationsRequest,
+    ) -> Result<
+        Vec<temporal_sdk_core_protos::coresdk::workflow_activation::WorkflowActivation>,
+        bridge::poll_workflow_activation_response::Error,
+    > {
+        self.worker
+            .poll_workflow_activations(req.max as usize)
+            .await
+            .map_err(|err| bridge::poll_workflow_activation_response::Error {
+                message: format!("{}", err),
+                shutdown: matches!(err, temporal_sdk_core_api::errors::PollWfError::ShutDown),
+            })
+    }
+
     async fn poll_activity_task(
         &self,
     ) -> Result<
@@ -772,6 +885,22 @@ impl tmprl_worker_t {
             })
     }
 
+    async fn complete_workflow_activations(
+        &self,
+        req: bridge::CompleteWorkflowActivationsRequest,
+    ) -> Vec<Result<(), bridge::complete_workflow_activation_response::Error>> {
+        self.worker
+            .complete_workflow_activations(req.completions)
+            .await
+            .into_iter()
+            .map(|r| {
+                r.map_err(|err| bridge::complete_workflow_activation_response::Error {
+                    message: format!("{}", err),
+                })
+            })
+            .collect()
+    }
+
     async fn complete_activity_task(
         &self,
         req: bridge::CompleteActivityTaskRequest,
@@ -41,6 +41,7 @@ use temporal_sdk_core_protos::{
         enums::v1::{TaskQueueKind, WorkflowTaskFailedCause},
         failure::v1::Failure,
         query::v1::{WorkflowQuery, WorkflowQueryResult},
+        sdk::v1::WorkflowTaskCompletedMetadata,
         taskqueue::v1::{StickyExecutionAttributes, TaskQueue, TaskQueueMetadata},
         workflowservice::v1::{workflow_service_client::WorkflowServiceClient, *},
     },
@@ -388,6 +389,9 @@ pub struct WorkflowTaskCompletion {
     pub return_new_workflow_task: bool,
     /// Force a new WFT to be created after this completion
     pub force_create_new_workflow_task: bool,
+    /// Metadata the SDK records in the workflow task completed event, such as which internal
+    /// flags were used
+    pub sdk_metadata: WorkflowTaskCompletedMetadata,
 }
 
 /// Interceptor which attaches common metadata (like "client-name") to every outgoing call
@@ -481,7 +485,7 @@ impl Client {
 
 /// This trait provides higher-level friendlier interaction with the server.
 /// See the [WorkflowService] trait for a lower-level client.
-#[cfg_attr(test, mockall::automock)]
+#[cfg_attr(any(test, feature = "mocks"), mockall::automock)]
 #[async_trait::async_trait]
 pub trait WorkflowClientTrait {
     /// Starts workflow execution.
@@ -774,6 +778,7 @@ impl WorkflowClientTrait for Client {
                 })
                 .collect(),
             namespace: self.namespace.clone(),
+            sdk_metadata: Some(request.sdk_metadata),
         };
         Ok(self
             .wf_svc()
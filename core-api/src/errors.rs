@@ -1,10 +1,14 @@
 //! Error types exposed by public APIs
 
 use prost_types::TimestampOutOfSystemRangeError;
-use temporal_sdk_core_protos::coresdk::{
-    activity_result::ActivityExecutionResult,
-    workflow_activation::remove_from_cache::EvictionReason,
-    workflow_completion::WorkflowActivationCompletion,
+use std::fmt::{Display, Formatter};
+use temporal_sdk_core_protos::{
+    coresdk::{
+        activity_result::ActivityExecutionResult,
+        workflow_activation::{remove_from_cache::EvictionReason, NondeterminismDetails},
+        workflow_completion::WorkflowActivationCompletion,
+    },
+    temporal::api::history::v1::HistoryEvent,
 };
 
 /// Errors thrown by [crate::Worker::poll_workflow_activation]
@@ -84,7 +88,7 @@ pub enum CompleteActivityError {
 #[derive(thiserror::Error, Debug)]
 pub enum WFMachinesError {
     #[error("Nondeterminism error: {0}")]
-    Nondeterminism(String),
+    Nondeterminism(NondeterminismReport),
     #[error("Fatal error in workflow machines: {0}")]
     Fatal(String),
 
@@ -103,6 +107,91 @@ impl WFMachinesError {
     }
 }
 
+impl WFMachinesError {
+    /// Returns the structured nondeterminism report, if this is a nondeterminism error
+    pub fn nondeterminism_details(&self) -> Option<NondeterminismDetails> {
+        match self {
+            WFMachinesError::Nondeterminism(report) => Some(report.clone().into()),
+            _ => None,
+        }
+    }
+}
+
+/// Describes how a workflow's behavior diverged from its history. State machines provide the
+/// message, and the rest is filled in as the error propagates out of the workflow's machines.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NondeterminismReport {
+    /// Description of the mismatch
+    pub message: String,
+    /// The history event being applied when the mismatch was found, if any. Its attributes
+    /// may be omitted.
+    pub event: Option<Box<HistoryEvent>>,
+    /// The command produced by the workflow which did not match history, if any
+    pub command: Option<String>,
+    /// The kind of state machine which detected the mismatch, if any
+    pub machine_type: Option<String>,
+    /// The state that machine was in
+    pub machine_state: Option<String>,
+    /// The (1-based) number of the workflow task being processed
+    pub workflow_task_number: Option<u32>,
+    /// The jobs of the run's most recent activations, oldest first
+    pub recent_jobs: Vec<String>,
+}
+
+impl From<String> for NondeterminismReport {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for NondeterminismReport {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl Display for NondeterminismReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(event) = self.event.as_ref() {
+            write!(f, "\n  history has:   {}", event)?;
+        }
+        if let Some(command) = self.command.as_ref() {
+            write!(f, "\n  workflow sent: {}", command)?;
+        }
+        if let Some(machine_type) = self.machine_type.as_ref() {
+            write!(f, "\n  machine:       {}", machine_type)?;
+            if let Some(state) = self.machine_state.as_ref() {
+                write!(f, " in state {}", state)?;
+            }
+        }
+        if let Some(wft_num) = self.workflow_task_number {
+            write!(f, "\n  workflow task: {}", wft_num)?;
+        }
+        if !self.recent_jobs.is_empty() {
+            write!(f, "\n  recent jobs:   {}", self.recent_jobs.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<NondeterminismReport> for NondeterminismDetails {
+    fn from(r: NondeterminismReport) -> Self {
+        Self {
+            message: r.message,
+            event: r.event.map(|e| *e),
+            command: r.command.unwrap_or_default(),
+            machine_type: r.machine_type.unwrap_or_default(),
+            machine_state: r.machine_state.unwrap_or_default(),
+            workflow_task_number: r.workflow_task_number.unwrap_or_default(),
+            recent_jobs: r.recent_jobs,
+        }
+    }
+}
+
 impl From<TimestampOutOfSystemRangeError> for WFMachinesError {
     fn from(_: TimestampOutOfSystemRangeError) -> Self {
         Self::Fatal("Could not decode timestamp".to_string())
@@ -5,6 +5,7 @@ use crate::{
     errors::{CompleteActivityError, CompleteWfError, PollActivityError, PollWfError},
     worker::WorkerConfig,
 };
+use futures::{stream, stream::BoxStream, StreamExt};
 use log::Level;
 use opentelemetry::metrics::Meter;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -36,6 +37,29 @@ pub trait Worker: Send + Sync {
     /// concurrently internally.
     async fn poll_workflow_activation(&self) -> Result<WorkflowActivation, PollWfError>;
 
+    /// Like [Worker::poll_workflow_activation], but may return up to `max` activations at once,
+    /// each for a different run. Blocks until at least one activation is available, then includes
+    /// any others which are ready immediately. A `max` of zero is treated as one.
+    ///
+    /// Useful for language SDKs which pay a per-call overhead to cross into core.
+    async fn poll_workflow_activations(
+        &self,
+        max: usize,
+    ) -> Result<Vec<WorkflowActivation>, PollWfError>;
+
+    /// Returns a stream of the activations [Worker::poll_workflow_activation] would produce.
+    /// Rather than yielding [PollWfError::ShutDown], the stream ends once the worker has shut
+    /// down. The worker is only polled as the stream is, so a slow consumer applies backpressure.
+    fn activation_stream(&self) -> BoxStream<'_, Result<WorkflowActivation, PollWfError>> {
+        stream::unfold(self, |worker| async move {
+            match worker.poll_workflow_activation().await {
+                Err(PollWfError::ShutDown) => None,
+                r => Some((r, worker)),
+            }
+        })
+        .boxed()
+    }
+
     /// Ask the worker for some work, returning an [ActivityTask]. It is then the language SDK's
     /// responsibility to call the appropriate activity code with the provided inputs. Blocks
     /// indefinitely until such work is available or [Worker::shutdown] is called.
@@ -47,12 +71,32 @@ pub trait Worker: Send + Sync {
     /// concurrently internally.
     async fn poll_activity_task(&self) -> Result<ActivityTask, PollActivityError>;
 
+    /// Returns a stream of the tasks [Worker::poll_activity_task] would produce, which ends once the
+    /// worker has shut down. See [Worker::activation_stream].
+    fn activity_task_stream(&self) -> BoxStream<'_, Result<ActivityTask, PollActivityError>> {
+        stream::unfold(self, |worker| async move {
+            match worker.poll_activity_task().await {
+                Err(PollActivityError::ShutDown) => None,
+                r => Some((r, worker)),
+            }
+        })
+        .boxed()
+    }
+
     /// Tell the worker that a workflow activation has completed. May be freely called concurrently.
     async fn complete_workflow_activation(
         &self,
         completion: WorkflowActivationCompletion,
     ) -> Result<(), CompleteWfError>;
 
+    /// Tell the worker that several workflow activations have completed. The completions are
+    /// processed concurrently, and the result for each is returned in the same order as the
+    /// completions were provided.
+    async fn complete_workflow_activations(
+        &self,
+        completions: Vec<WorkflowActivationCompletion>,
+    ) -> Vec<Result<(), CompleteWfError>>;
+
     /// Tell the worker that an activity has finished executing. May be freely called concurrently.
     async fn complete_activity_task(
         &self,
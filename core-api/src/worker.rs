@@ -1,4 +1,5 @@
-use std::time::Duration;
+use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
+use temporal_sdk_core_protos::coresdk::common::Payload;
 
 /// Defines per-worker configuration options
 #[derive(Debug, Clone, derive_builder::Builder)]
@@ -10,13 +11,46 @@ pub struct WorkerConfig {
     /// What task queue will this worker poll from? This task queue name will be used for both
     /// workflow and activity polling.
     pub task_queue: String,
+    /// Additional task queues this worker will poll from, beyond `task_queue`. Workflows and
+    /// activities from all of the worker's queues share its workflow cache, sticky queue, and task
+    /// slots.
+    #[builder(default)]
+    pub additional_task_queues: Vec<String>,
+    /// Relative weights used to divide this worker's pollers among its task queues. Queues which
+    /// are not present in the map have a weight of 1. Every queue always gets at least one poller.
+    #[builder(default)]
+    pub task_queue_poller_weights: HashMap<String, f32>,
     /// If set nonzero, workflows will be cached and sticky task queues will be used, meaning that
     /// history updates are applied incrementally to suspended instances of workflow execution.
-    /// Workflows are evicted according to a least-recently-used policy one the cache maximum is
-    /// reached. Workflows may also be explicitly evicted at any time, or as a result of errors
-    /// or failures.
+    /// Once the cache reaches this many runs, or [WorkerConfig::max_cached_workflows_bytes], runs
+    /// are evicted according to [WorkerConfig::workflow_cache_eviction_policy]. Workflows may also
+    /// be explicitly evicted at any time, or as a result of errors or failures.
     #[builder(default = "0")]
     pub max_cached_workflows: usize,
+    /// Determines which cached workflow run is chosen for eviction when the cache reaches
+    /// [WorkerConfig::max_cached_workflows] or [WorkerConfig::max_cached_workflows_bytes].
+    #[builder(default)]
+    pub workflow_cache_eviction_policy: WorkflowCacheEvictionPolicy,
+    /// If set, workflows will be evicted from the cache once the approximate total memory used by
+    /// all cached runs (their state machines plus the history they have processed) exceeds this
+    /// many bytes. Has no effect if `max_cached_workflows` is zero.
+    #[builder(setter(strip_option), default)]
+    pub max_cached_workflows_bytes: Option<usize>,
+    /// What the worker does when it finds a workflow run has behaved nondeterministically. May be
+    /// overridden for specific workflow types with [WorkerConfig::nondeterminism_policy_by_type].
+    #[builder(default)]
+    pub nondeterminism_policy: NondeterminismPolicy,
+    /// Overrides [WorkerConfig::nondeterminism_policy] for the workflow types in this map
+    #[builder(default)]
+    pub nondeterminism_policy_by_type: HashMap<String, NondeterminismPolicy>,
+    /// Workflow activations will suggest the workflow continue as new once its history contains at
+    /// least this many events
+    #[builder(default = "10_000")]
+    pub continue_as_new_suggested_history_length: u32,
+    /// Workflow activations will suggest the workflow continue as new once the approximate size
+    /// of its history reaches this many bytes
+    #[builder(default = "10 * 1024 * 1024")]
+    pub continue_as_new_suggested_history_bytes: u64,
     /// The maximum allowed number of workflow tasks that will ever be given to this worker at one
     /// time. Note that one workflow task may require multiple activations - so the WFT counts as
     /// "outstanding" until all activations it requires have been completed.
@@ -67,15 +101,188 @@ pub struct WorkerConfig {
     #[builder(default = "Duration::from_secs(30)")]
     pub default_heartbeat_throttle_interval: Duration,
 
+    /// If set nonzero, this worker will host up to this many activity sessions at once. A session
+    /// pins a series of activities to one worker, by routing them to a task queue only this
+    /// worker polls. Requires remote activities to be enabled.
+    #[builder(default = "0")]
+    pub max_concurrent_sessions: usize,
+
     /// Sets the maximum number of activities per second the task queue will dispatch, controlled
     /// server-side. Note that this only takes effect upon an activity poll request. If multiple
     /// workers on the same queue have different values set, they will thrash with the last poller
     /// winning.
     #[builder(setter(strip_option), default)]
     pub max_task_queue_activities_per_second: Option<f64>,
+
+    /// If set, this worker will start at most this many activities per second across all of its
+    /// task queues. Unlike `max_task_queue_activities_per_second`, this is enforced by the worker
+    /// itself and only limits this worker, which does not poll for activities while at the limit.
+    #[builder(setter(strip_option), default)]
+    pub max_worker_activities_per_second: Option<f64>,
+    /// Limits how many activities of a given type this worker will start per second. Tasks for a
+    /// throttled type are held by the worker until they may start, without taking an activity
+    /// slot. Since their timeouts are already running, tasks which would have to wait longer than
+    /// any of them are instead failed back to the server to be retried. Limits are tracked
+    /// separately for remote and local activities.
+    #[builder(default)]
+    pub max_activities_per_second_by_type: HashMap<String, f64>,
+    /// If set, this worker will start at most this many local activities per second
+    #[builder(setter(strip_option), default)]
+    pub max_local_activities_per_second: Option<f64>,
+    /// If set, limits how long a workflow task may be kept alive by heartbeating it while waiting
+    /// on local activities. Once a run has been waiting this long, its local activities which are
+    /// still running are promoted to normal activities. See also
+    /// [WorkerConfig::promote_local_activities_past_retry_threshold].
+    #[builder(setter(strip_option), default)]
+    pub max_local_activity_wft_heartbeat_duration: Option<Duration>,
+    /// If true, a local activity whose next retry backoff exceeds its local retry threshold is
+    /// promoted to a normal activity, rather than lang backing off with a timer and scheduling it
+    /// again as a local activity.
+    #[builder(default = "false")]
+    pub promote_local_activities_past_retry_threshold: bool,
+    /// How many results of finished local activities this worker keeps, so that if the workflow
+    /// task which would have recorded them fails or times out, they are reused rather than
+    /// executed again when the run is reprocessed on this worker. Zero disables reuse.
+    #[builder(default = "1000")]
+    pub max_cached_local_activity_results: usize,
+    /// How long lang has to acknowledge the cancellation of a running local activity, by
+    /// completing it, before core resolves it as cancelled anyway. Workflows which cancel local
+    /// activities with `WAIT_CANCELLATION_COMPLETED` will wait at most this long.
+    #[builder(default = "Duration::from_secs(10)")]
+    pub local_activity_cancel_grace_period: Duration,
+    /// If set, the details of every activity heartbeat lang records are persisted here before
+    /// they may be throttled, and activities resume from the checkpoint when retried on this
+    /// worker. Pending throttled heartbeats are also flushed to the server during shutdown.
+    #[builder(setter(strip_option), default)]
+    pub heartbeat_checkpoint_store: Option<Arc<dyn HeartbeatCheckpointStore>>,
+}
+
+/// Activity type which workflows schedule on a worker's task queue to open a session. Core handles
+/// these itself, reserving a session slot and completing the activity with the name of the task
+/// queue the session's activities must be scheduled on.
+pub const SESSION_CREATION_ACTIVITY_TYPE: &str = "__temporal_internal_session_creation";
+/// Activity type which workflows schedule on a session's task queue, with the creation activity's
+/// id as input, to hold the session open. Core heartbeats these itself until they are cancelled,
+/// so if the worker is lost the activity times out and the workflow learns the session failed.
+pub const SESSION_KEEPALIVE_ACTIVITY_TYPE: &str = "__temporal_internal_session_keepalive";
+/// Query type which core answers itself, without involving lang. The response is a JSON list of
+/// the commands the workflow has issued which have not yet resolved, each with its `seq`,
+/// `command_type`, `state`, and `scheduled_time_ms` (workflow time at which it was issued).
+pub const STACK_TRACE_QUERY_TYPE: &str = "__stack_trace";
+
+/// Determines which workflow run is evicted when the workflow cache is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WorkflowCacheEvictionPolicy {
+    /// Evict the run which was least recently used
+    Lru,
+    /// Evict the run which has been used the fewest number of times, ties are broken by recency
+    Lfu,
+    /// Evict the run using the most memory, as approximated by the size of its state machines and
+    /// the history it has processed
+    SizeAware,
+}
+
+#[allow(clippy::derivable_impls)] // `#[default]` on variants needs a newer Rust than CI's
+impl Default for WorkflowCacheEvictionPolicy {
+    fn default() -> Self {
+        Self::Lru
+    }
+}
+
+/// Determines how a worker reacts to a workflow run which behaved nondeterministically, which
+/// is to say the commands it produced do not match its history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NondeterminismPolicy {
+    /// Evict the run and fail the workflow task. The server will retry the task, so the run makes
+    /// progress again once the workflow code is fixed and redeployed.
+    FailWorkflowTask,
+    /// Fail the workflow execution with a `NonDeterministicError` application failure
+    FailWorkflow,
+    /// Evict the run and stop processing it. Its workflow tasks are not failed, but are dropped as
+    /// they arrive until the worker is restarted, and the `workflow_quarantined` metric is
+    /// incremented so an operator can intervene. Only the most recently quarantined runs (a
+    /// thousand of them) are remembered.
+    Quarantine,
+}
+
+#[allow(clippy::derivable_impls)] // `#[default]` on variants needs a newer Rust than CI's
+impl Default for NondeterminismPolicy {
+    fn default() -> Self {
+        Self::FailWorkflowTask
+    }
+}
+
+/// Persists the latest heartbeat details of the activities a worker runs, so that if the worker
+/// crashes before throttled heartbeats reach the server, a retry of the activity can resume from
+/// its freshest checkpoint rather than the details the server last received.
+///
+/// Core removes the checkpoint of an activity which completes, is cancelled, or fails for the
+/// last time. It cannot know when an activity times out or its retries are run by other workers,
+/// so stores are responsible for eventually expiring checkpoints which are never removed.
+pub trait HeartbeatCheckpointStore: Debug + Send + Sync {
+    /// Replace the checkpoint stored for the activity. Called synchronously every time lang
+    /// heartbeats, so the checkpoint is durable once this returns.
+    fn save(
+        &self,
+        key: &HeartbeatCheckpointKey,
+        checkpoint: &HeartbeatCheckpoint,
+    ) -> Result<(), anyhow::Error>;
+    /// Returns the checkpoint stored for the activity, if there is one
+    fn load(
+        &self,
+        key: &HeartbeatCheckpointKey,
+    ) -> Result<Option<HeartbeatCheckpoint>, anyhow::Error>;
+    /// Drop the checkpoint stored for the activity, which will never run again
+    fn remove(&self, key: &HeartbeatCheckpointKey) -> Result<(), anyhow::Error>;
+    /// Make sure everything saved so far is durable. Called when the worker shuts down.
+    fn flush(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// Identifies an activity across all of its attempts
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HeartbeatCheckpointKey {
+    /// Namespace of the workflow which scheduled the activity
+    pub namespace: String,
+    /// Id of the workflow which scheduled the activity
+    pub workflow_id: String,
+    /// Run id of the workflow which scheduled the activity
+    pub run_id: String,
+    /// The activity's id, unique within its workflow run
+    pub activity_id: String,
+}
+
+/// The details an activity most recently heartbeated
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeartbeatCheckpoint {
+    /// The attempt of the activity which heartbeated
+    pub attempt: u32,
+    /// The heartbeated details
+    pub details: Vec<Payload>,
 }
 
 impl WorkerConfig {
+    /// All task queues this worker polls, starting with [WorkerConfig::task_queue]
+    pub fn task_queues(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.task_queue.as_str())
+            .chain(self.additional_task_queues.iter().map(String::as_str))
+    }
+
+    /// Divides `total_pollers` among this worker's task queues according to their poller weights.
+    /// Since every queue gets at least one poller, the result may add up to more than
+    /// `total_pollers` when there are many queues.
+    pub fn pollers_per_task_queue(&self, total_pollers: usize) -> Vec<(String, usize)> {
+        let weight = |tq: &str| *self.task_queue_poller_weights.get(tq).unwrap_or(&1.0);
+        let total_weight: f32 = self.task_queues().map(weight).sum();
+        self.task_queues()
+            .map(|tq| {
+                let share = total_pollers as f32 * weight(tq) / total_weight;
+                (tq.to_owned(), (share.round() as usize).max(1))
+            })
+            .collect()
+    }
+
     pub fn max_nonsticky_polls(&self) -> usize {
         ((self.max_concurrent_wft_polls as f32 * self.nonsticky_to_sticky_poll_ratio) as usize)
             .max(1)
@@ -92,6 +299,33 @@ impl WorkerConfigBuilder {
         if self.max_concurrent_wft_polls == Some(0) {
             return Err("`max_concurrent_wft_polls` must be at least 1".to_owned());
         }
+        if let Some(weights) = self.task_queue_poller_weights.as_ref() {
+            if weights.values().any(|w| !w.is_finite() || *w <= 0.0) {
+                return Err("Task queue poller weights must be positive".to_owned());
+            }
+        }
+        let valid_rate = |r: &f64| r.is_finite() && *r > 0.0;
+        if !self
+            .max_worker_activities_per_second
+            .flatten()
+            .iter()
+            .chain(self.max_local_activities_per_second.flatten().iter())
+            .chain(
+                self.max_activities_per_second_by_type
+                    .iter()
+                    .flat_map(|m| m.values()),
+            )
+            .all(valid_rate)
+        {
+            return Err("Activity rate limits must be positive".to_owned());
+        }
+        if let Some(additional) = self.additional_task_queues.as_ref() {
+            let mut all: Vec<_> = additional.iter().chain(self.task_queue.iter()).collect();
+            all.sort();
+            if all.windows(2).any(|w| w[0] == w[1]) {
+                return Err("A worker may not poll the same task queue more than once".to_owned());
+            }
+        }
         if self.max_outstanding_workflow_tasks > self.max_cached_workflows {
             return Err(
                 "Maximum concurrent workflow tasks cannot exceed the maximum number of cached \
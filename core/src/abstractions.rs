@@ -1,6 +1,7 @@
 //! This module contains very generic helpers that can be used codebase-wide
 
 use crate::MetricsContext;
+use std::time::{Duration, Instant};
 use tokio::sync::{AcquireError, Semaphore, SemaphorePermit};
 
 /// Wraps a [Semaphore] with a function call that is fed the available permits any time a permit is
@@ -43,3 +44,132 @@ impl MeteredSemaphore {
         }
     }
 }
+
+/// A token bucket which refills continuously at a fixed rate, and holds at most one second's worth
+/// of tokens (but always at least one).
+///
+/// Tokens are handed out as reservations: taking one may put the bucket in debt, in which case the
+/// caller is told how long to wait before acting on its token.
+pub(crate) struct TokenBucket {
+    per_second: f64,
+    capacity: f64,
+    state: parking_lot::Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(per_second: f64) -> Self {
+        let capacity = per_second.max(1.0);
+        Self {
+            per_second,
+            capacity,
+            state: parking_lot::Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Takes a token, returning how long the caller must wait before it may be used
+    pub fn reserve(&self) -> Duration {
+        self.try_reserve(None)
+            .expect("Reservations without a maximum wait always succeed")
+    }
+
+    /// Takes a token, returning how long the caller must wait before it may be used. If that wait
+    /// would be longer than `max_wait`, no token is taken and `None` is returned.
+    pub fn try_reserve(&self, max_wait: Option<Duration>) -> Option<Duration> {
+        let mut state = self.state.lock();
+        let tokens = self.refill(&mut state) - 1.0;
+        let wait = self.wait_for(tokens);
+        if matches!(max_wait, Some(max) if wait > max) {
+            return None;
+        }
+        state.0 = tokens;
+        Some(wait)
+    }
+
+    /// Returns a token taken by a reservation which ended up not being used
+    pub fn unreserve(&self) {
+        let mut state = self.state.lock();
+        state.0 = (self.refill(&mut state) + 1.0).min(self.capacity);
+    }
+
+    /// Resolves once a token could be taken without waiting, without taking it
+    pub async fn available(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock();
+                let tokens = self.refill(&mut state) - 1.0;
+                self.wait_for(tokens)
+            };
+            if wait.is_zero() {
+                return;
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn refill(&self, state: &mut (f64, Instant)) -> f64 {
+        let (tokens, last_refill) = state;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*last_refill).as_secs_f64() * self.per_second)
+            .min(self.capacity);
+        *last_refill = now;
+        *tokens
+    }
+
+    fn wait_for(&self, tokens: f64) -> Duration {
+        if tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-tokens / self.per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_goes_into_debt_once_empty() {
+        let bucket = TokenBucket::new(2.0);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        let third = bucket.reserve();
+        assert!(third > Duration::from_millis(400) && third <= Duration::from_millis(500));
+        let fourth = bucket.reserve();
+        assert!(fourth > Duration::from_millis(900) && fourth <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn token_bucket_debt_can_be_capped() {
+        let bucket = TokenBucket::new(1.0);
+        assert_eq!(
+            bucket.try_reserve(Some(Duration::ZERO)),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(bucket.try_reserve(Some(Duration::from_millis(500))), None);
+        // The refused reservation didn't take a token
+        let wait = bucket.try_reserve(Some(Duration::from_secs(1))).unwrap();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+        bucket.unreserve();
+        assert!(bucket.try_reserve(None).unwrap() > Duration::from_millis(900));
+    }
+
+    #[tokio::test]
+    async fn token_bucket_available_waits_for_refill_without_taking() {
+        let bucket = TokenBucket::new(4.0);
+        for _ in 0..4 {
+            bucket.reserve();
+        }
+        let start = Instant::now();
+        bucket.available().await;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+    }
+
+    #[test]
+    fn slow_token_bucket_allows_one_at_a_time() {
+        let bucket = TokenBucket::new(0.5);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        assert!(bucket.reserve() > Duration::from_millis(1900));
+    }
+}
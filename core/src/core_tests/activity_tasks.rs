@@ -1,38 +1,55 @@
 use crate::{
     job_assert,
+    replay::TestHistoryBuilder,
     test_help::{
         build_fake_worker, canned_histories, gen_assert_and_reply, mock_manual_poller, mock_poller,
         mock_worker, poll_and_reply, test_worker_cfg, MockWorker, MocksHolder,
     },
     worker::client::mocks::{mock_manual_workflow_client, mock_workflow_client},
-    workflow::WorkflowCachingPolicy::NonSticky,
-    ActivityHeartbeat, Worker, WorkerConfigBuilder,
+    workflow::{managed_wf::ManagedWFFunc, WorkflowCachingPolicy::NonSticky},
+    ActivityHeartbeat, FileHeartbeatCheckpointStore, Worker, WorkerConfigBuilder,
 };
 use futures::FutureExt;
 use std::{
     cell::RefCell,
     collections::{hash_map::Entry, HashMap, VecDeque},
     rc::Rc,
-    sync::atomic::{AtomicUsize, Ordering},
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use temporal_sdk::{ActivityOptions, SessionError, SessionOptions, WfContext, WorkflowFunction};
+use temporal_sdk_core_api::{
+    worker::{SESSION_CREATION_ACTIVITY_TYPE, SESSION_KEEPALIVE_ACTIVITY_TYPE},
+    Worker as WorkerTrait,
 };
-use temporal_sdk_core_api::Worker as WorkerTrait;
 use temporal_sdk_core_protos::{
     coresdk::{
         activity_result::{activity_resolution, ActivityExecutionResult, ActivityResolution},
-        activity_task::{activity_task, ActivityTask},
+        activity_task::{activity_task, ActivityCancelReason, ActivityTask, Cancel, Start},
+        common::Payload,
         workflow_activation::{workflow_activation_job, ResolveActivity, WorkflowActivationJob},
         workflow_commands::{
             ActivityCancellationType, CompleteWorkflowExecution, RequestCancelActivity,
             ScheduleActivity,
         },
-        ActivityTaskCompletion,
+        ActivityTaskCompletion, IntoPayloadsExt,
     },
-    temporal::api::workflowservice::v1::{
-        PollActivityTaskQueueResponse, RecordActivityTaskHeartbeatResponse,
-        RespondActivityTaskCanceledResponse, RespondActivityTaskCompletedResponse,
-        RespondActivityTaskFailedResponse,
+    temporal::api::{
+        command::v1::command,
+        common::v1::{ActivityType, RetryPolicy, WorkflowExecution},
+        enums::v1::{CommandType, EventType},
+        failure::v1::{failure::FailureInfo, Failure},
+        history::v1::{ActivityTaskFailedEventAttributes, ActivityTaskTimedOutEventAttributes},
+        workflowservice::v1::{
+            PollActivityTaskQueueResponse, RecordActivityTaskHeartbeatResponse,
+            RespondActivityTaskCanceledResponse, RespondActivityTaskCompletedResponse,
+            RespondActivityTaskFailedResponse,
+        },
     },
+    TaskToken,
 };
 use temporal_sdk_core_test_utils::{fanout_tasks, start_timer_cmd};
 use tokio::{join, time::sleep};
@@ -208,6 +225,85 @@ async fn heartbeats_report_cancels_only_once() {
     core.shutdown().await;
 }
 
+#[tokio::test]
+async fn missed_heartbeat_deadline_issues_cancel() {
+    let mut mock_poller = mock_manual_poller();
+    let mut poll_resps = VecDeque::from(vec![
+        async {
+            Some(Ok(PollActivityTaskQueueResponse {
+                task_token: vec![1],
+                heartbeat_timeout: Some(Duration::from_millis(50).into()),
+                ..Default::default()
+            }))
+        }
+        .boxed(),
+        async {
+            sleep(Duration::from_secs(10)).await;
+            unreachable!("Long poll")
+        }
+        .boxed(),
+    ]);
+    mock_poller
+        .expect_poll()
+        .returning(move || poll_resps.pop_front().unwrap());
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_cancel_activity_task()
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskCanceledResponse::default()));
+
+    let mw = MockWorker {
+        act_poller: Some(Box::from(mock_poller)),
+        ..Default::default()
+    };
+    let core = mock_worker(MocksHolder::from_mock_worker(mock_client.into(), mw));
+
+    core.poll_activity_task().await.unwrap();
+    // Lang never heartbeats, so core should cancel the activity on its own
+    let act = core.poll_activity_task().await.unwrap();
+    assert_matches!(
+        &act,
+        ActivityTask {
+            task_token,
+            variant: Some(activity_task::Variant::Cancel(Cancel { reason })),
+            ..
+        } => {
+            task_token == &vec![1] && *reason == ActivityCancelReason::HeartbeatTimeout as i32
+        }
+    );
+    core.complete_activity_task(ActivityTaskCompletion {
+        task_token: act.task_token,
+        result: Some(ActivityExecutionResult::cancel_from_details(None)),
+    })
+    .await
+    .unwrap();
+    core.shutdown().await;
+}
+
+#[tokio::test]
+async fn heartbeat_for_unknown_activity_issues_not_found_cancel() {
+    let core = mock_worker(MocksHolder::from_client_with_responses(
+        mock_workflow_client(),
+        [],
+        [],
+    ));
+
+    core.record_activity_heartbeat(ActivityHeartbeat {
+        task_token: vec![1],
+        details: vec![vec![1_u8, 2, 3].into()],
+    });
+    let act = core.poll_activity_task().await.unwrap();
+    assert_matches!(
+        &act,
+        ActivityTask {
+            task_token,
+            variant: Some(activity_task::Variant::Cancel(Cancel { reason })),
+            ..
+        } => { task_token == &vec![1] && *reason == ActivityCancelReason::NotFound as i32 }
+    );
+    core.shutdown().await;
+}
+
 #[tokio::test]
 async fn activity_cancel_interrupts_poll() {
     let mut mock_poller = mock_manual_poller();
@@ -582,6 +678,131 @@ async fn complete_act_with_fail_flushes_heartbeat() {
     assert_eq!(last_seen_payload.data, &[last_hb]);
 }
 
+/// If the worker checkpoints heartbeats, a retry of an activity starts with the details last
+/// recorded by lang, even if the server never received them.
+#[tokio::test]
+async fn retried_activity_resumes_from_heartbeat_checkpoint() {
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_record_activity_heartbeat()
+        .returning(|_, _| Ok(RecordActivityTaskHeartbeatResponse::default()));
+    mock_client
+        .expect_fail_activity_task()
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskFailedResponse::default()));
+    mock_client
+        .expect_complete_activity_task()
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskCompletedResponse::default()));
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    let store = Arc::new(FileHeartbeatCheckpointStore::new(&dir).unwrap());
+    let attempt = |task_token: u8, attempt: i32| PollActivityTaskQueueResponse {
+        task_token: vec![task_token],
+        workflow_namespace: "ns".to_string(),
+        workflow_execution: Some(WorkflowExecution {
+            workflow_id: "wf".to_string(),
+            run_id: "run".to_string(),
+        }),
+        activity_id: "act1".to_string(),
+        attempt,
+        // As though the worker was lost before the throttled heartbeats reached the server
+        heartbeat_details: vec![Payload::from(vec![1_u8])].into_payloads(),
+        heartbeat_timeout: Some(Duration::from_secs(10).into()),
+        ..Default::default()
+    };
+    let mut mh =
+        MocksHolder::from_client_with_responses(mock_client, [], [attempt(1, 1), attempt(2, 2)]);
+    let store_clone = store.clone();
+    mh.worker_cfg(|cfg| cfg.heartbeat_checkpoint_store = Some(store_clone));
+    let core = mock_worker(mh);
+
+    let act = core.poll_activity_task().await.unwrap();
+    for i in 1..=5_u8 {
+        core.record_activity_heartbeat(ActivityHeartbeat {
+            task_token: act.task_token.clone(),
+            details: vec![vec![i].into()],
+        });
+    }
+    core.complete_activity_task(ActivityTaskCompletion {
+        task_token: act.task_token,
+        result: Some(ActivityExecutionResult::fail("Ahh".into())),
+    })
+    .await
+    .unwrap();
+
+    let act = core.poll_activity_task().await.unwrap();
+    assert_matches!(
+        act.variant,
+        Some(activity_task::Variant::Start(Start { heartbeat_details, .. }))
+            if heartbeat_details == vec![vec![5_u8].into()]
+    );
+    core.complete_activity_task(ActivityTaskCompletion {
+        task_token: act.task_token,
+        result: Some(ActivityExecutionResult::ok(vec![1].into())),
+    })
+    .await
+    .unwrap();
+    // Completing the activity drops its checkpoint
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    core.shutdown().await;
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Checkpoints are dropped once an activity fails an attempt which will not be retried
+#[tokio::test]
+async fn heartbeat_checkpoint_removed_after_last_attempt_fails() {
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_record_activity_heartbeat()
+        .returning(|_, _| Ok(RecordActivityTaskHeartbeatResponse::default()));
+    mock_client
+        .expect_fail_activity_task()
+        .times(2)
+        .returning(|_, _| Ok(RespondActivityTaskFailedResponse::default()));
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    let store = Arc::new(FileHeartbeatCheckpointStore::new(&dir).unwrap());
+    let attempt = |task_token: u8, attempt: i32| PollActivityTaskQueueResponse {
+        task_token: vec![task_token],
+        workflow_namespace: "ns".to_string(),
+        workflow_execution: Some(WorkflowExecution {
+            workflow_id: "wf".to_string(),
+            run_id: "run".to_string(),
+        }),
+        activity_id: "act1".to_string(),
+        attempt,
+        retry_policy: Some(RetryPolicy {
+            maximum_attempts: 2,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let mut mh =
+        MocksHolder::from_client_with_responses(mock_client, [], [attempt(1, 1), attempt(2, 2)]);
+    let store_clone = store.clone();
+    mh.worker_cfg(|cfg| cfg.heartbeat_checkpoint_store = Some(store_clone));
+    let core = mock_worker(mh);
+
+    for expected_checkpoints in [1, 0] {
+        let act = core.poll_activity_task().await.unwrap();
+        core.record_activity_heartbeat(ActivityHeartbeat {
+            task_token: act.task_token.clone(),
+            details: vec![vec![1].into()],
+        });
+        core.complete_activity_task(ActivityTaskCompletion {
+            task_token: act.task_token,
+            result: Some(ActivityExecutionResult::fail("Ahh".into())),
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read_dir(&dir).unwrap().count(),
+            expected_checkpoints
+        );
+    }
+    core.shutdown().await;
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
 #[tokio::test]
 async fn max_tq_acts_set_passed_to_poll_properly() {
     let rate = 9.28;
@@ -606,3 +827,518 @@ async fn max_tq_acts_set_passed_to_poll_properly() {
     let worker = Worker::new_test(cfg, mock_client);
     worker.poll_activity_task().await.unwrap();
 }
+
+#[tokio::test]
+async fn per_type_rate_limit_holds_back_activities() {
+    let mut mock_client = mock_workflow_client();
+    let mut task_num = 0_u8;
+    mock_client
+        .expect_poll_activity_task()
+        .returning(move |_, _| {
+            task_num += 1;
+            Ok(PollActivityTaskQueueResponse {
+                task_token: vec![task_num],
+                activity_type: Some(ActivityType {
+                    name: "slow".to_string(),
+                }),
+                ..Default::default()
+            })
+        });
+
+    let cfg = WorkerConfigBuilder::default()
+        .namespace("enchi")
+        .task_queue("cat")
+        .max_concurrent_at_polls(1_usize)
+        .max_activities_per_second_by_type(HashMap::from([("slow".to_string(), 2.0)]))
+        .build()
+        .unwrap();
+    let worker = Worker::new_test(cfg, mock_client);
+    let start = Instant::now();
+    worker.poll_activity_task().await.unwrap();
+    worker.poll_activity_task().await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(250));
+    // The bucket is empty now, so the next one must wait for a token to refill
+    worker.poll_activity_task().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn overall_rate_limit_only_charges_received_tasks() {
+    let mut mock_client = mock_manual_workflow_client();
+    let mut polls = 0_u8;
+    mock_client
+        .expect_poll_activity_task()
+        .returning(move |_, _| {
+            polls += 1;
+            let resp = if polls == 1 {
+                // Long poll timeout
+                PollActivityTaskQueueResponse::default()
+            } else {
+                PollActivityTaskQueueResponse {
+                    task_token: vec![polls],
+                    ..Default::default()
+                }
+            };
+            async move { Ok(resp) }.boxed()
+        });
+
+    let cfg = WorkerConfigBuilder::default()
+        .namespace("enchi")
+        .task_queue("cat")
+        .max_concurrent_at_polls(1_usize)
+        .max_worker_activities_per_second(1.0)
+        .build()
+        .unwrap();
+    let worker = Worker::new_test(cfg, mock_client);
+    let start = Instant::now();
+    // The empty poll must not have used up the only token
+    worker.poll_activity_task().await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn rate_limited_activities_are_failed_on_shutdown() {
+    let mut mock_client = mock_manual_workflow_client();
+    let mut polls = 0_u8;
+    mock_client
+        .expect_poll_activity_task()
+        .returning(move |_, _| {
+            polls += 1;
+            if polls > 2 {
+                return async {
+                    sleep(Duration::from_secs(10)).await;
+                    unreachable!("Long poll")
+                }
+                .boxed();
+            }
+            let resp = PollActivityTaskQueueResponse {
+                task_token: vec![polls],
+                activity_type: Some(ActivityType {
+                    name: "slow".to_string(),
+                }),
+                ..Default::default()
+            };
+            async move { Ok(resp) }.boxed()
+        });
+    mock_client
+        .expect_complete_activity_task()
+        .times(1)
+        .returning(|_, _| async { Ok(RespondActivityTaskCompletedResponse::default()) }.boxed());
+    mock_client
+        .expect_fail_activity_task()
+        .withf(|tt, _| tt == &TaskToken(vec![2]))
+        .times(1)
+        .returning(|_, _| async { Ok(RespondActivityTaskFailedResponse::default()) }.boxed());
+
+    let cfg = WorkerConfigBuilder::default()
+        .namespace("enchi")
+        .task_queue("cat")
+        .max_concurrent_at_polls(1_usize)
+        .max_outstanding_activities(2_usize)
+        .max_activities_per_second_by_type(HashMap::from([("slow".to_string(), 1.0)]))
+        .build()
+        .unwrap();
+    let worker = Worker::new_test(cfg, mock_client);
+    let task = worker.poll_activity_task().await.unwrap();
+    // The second task is received, but held back by the rate limit, without holding a slot
+    tokio::select! {
+        _ = worker.poll_activity_task() => panic!("Second task must be throttled"),
+        _ = sleep(Duration::from_millis(100)) => {}
+    }
+    assert_eq!(worker.available_activity_permits(), Some(1));
+    worker
+        .complete_activity_task(ActivityTaskCompletion {
+            task_token: task.task_token,
+            result: Some(ActivityExecutionResult::ok(vec![1].into())),
+        })
+        .await
+        .unwrap();
+    worker.shutdown().await;
+    assert_eq!(worker.available_activity_permits(), Some(2));
+}
+
+#[tokio::test]
+async fn rate_limited_activities_given_back_when_wait_exceeds_timeouts() {
+    let mut mock_client = mock_workflow_client();
+    let mut task_num = 0_u8;
+    mock_client
+        .expect_poll_activity_task()
+        .returning(move |_, _| {
+            task_num += 1;
+            let act_type = if task_num == 3 { "fast" } else { "slow" };
+            Ok(PollActivityTaskQueueResponse {
+                task_token: vec![task_num],
+                activity_type: Some(ActivityType {
+                    name: act_type.to_string(),
+                }),
+                start_to_close_timeout: Some(Duration::from_secs(1).into()),
+                ..Default::default()
+            })
+        });
+    mock_client
+        .expect_fail_activity_task()
+        .withf(|tt, f| {
+            tt == &TaskToken(vec![2])
+                && matches!(f, Some(Failure {
+                    failure_info: Some(FailureInfo::ApplicationFailureInfo(info)),
+                    ..
+                }) if !info.non_retryable)
+        })
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskFailedResponse::default()));
+
+    let cfg = WorkerConfigBuilder::default()
+        .namespace("enchi")
+        .task_queue("cat")
+        .max_concurrent_at_polls(1_usize)
+        .max_outstanding_activities(2_usize)
+        .max_activities_per_second_by_type(HashMap::from([("slow".to_string(), 0.1)]))
+        .build()
+        .unwrap();
+    let worker = Worker::new_test(cfg, mock_client);
+    let start = Instant::now();
+    let first = worker.poll_activity_task().await.unwrap();
+    assert_eq!(first.task_token, vec![1]);
+    // The second slow task would have to wait ten seconds, longer than its start-to-close
+    // timeout, so it's given back to the server and the next task starts right away
+    let next = worker.poll_activity_task().await.unwrap();
+    assert_eq!(next.task_token, vec![3]);
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert_eq!(worker.available_activity_permits(), Some(0));
+}
+
+#[tokio::test]
+async fn activities_polled_from_all_task_queues() {
+    let mut mock_client = mock_manual_workflow_client();
+    let mut served = HashMap::<String, bool>::new();
+    mock_client
+        .expect_poll_activity_task()
+        .returning(move |tq, _| {
+            // Each queue has exactly one task, after which its polls hang
+            if served.insert(tq.clone(), true).is_some() {
+                return async {
+                    sleep(Duration::from_secs(10)).await;
+                    unreachable!("Long poll")
+                }
+                .boxed();
+            }
+            async move {
+                Ok(PollActivityTaskQueueResponse {
+                    task_token: tq.into_bytes(),
+                    ..Default::default()
+                })
+            }
+            .boxed()
+        });
+
+    let cfg = WorkerConfigBuilder::default()
+        .namespace("enchi")
+        .task_queue("cat")
+        .additional_task_queues(vec!["dog".to_string()])
+        .max_concurrent_at_polls(2_usize)
+        .max_outstanding_activities(2_usize)
+        .build()
+        .unwrap();
+    let worker = Worker::new_test(cfg, mock_client);
+    let mut tokens = vec![
+        worker.poll_activity_task().await.unwrap().task_token,
+        worker.poll_activity_task().await.unwrap().task_token,
+    ];
+    tokens.sort();
+    assert_eq!(tokens, vec![b"cat".to_vec(), b"dog".to_vec()]);
+    // Both queues draw from the same pool of activity slots
+    assert_eq!(worker.available_activity_permits(), Some(0));
+}
+
+#[tokio::test]
+async fn sessions_are_reserved_kept_alive_and_ended_by_core() {
+    let act_task =
+        |tt: u8, act_type: &str, act_id: &str, input: Option<&str>| PollActivityTaskQueueResponse {
+            task_token: vec![tt],
+            activity_id: act_id.to_string(),
+            activity_type: Some(ActivityType {
+                name: act_type.to_string(),
+            }),
+            workflow_execution: Some(WorkflowExecution {
+                workflow_id: "wf".to_string(),
+                run_id: "run".to_string(),
+            }),
+            input: input.map(Into::into),
+            heartbeat_timeout: Some(Duration::from_millis(20).into()),
+            ..Default::default()
+        };
+    let mut poll_resps = VecDeque::from(vec![
+        // The first creation reserves the only session slot, so the second is rejected
+        act_task(1, SESSION_CREATION_ACTIVITY_TYPE, "c1", None),
+        act_task(2, SESSION_CREATION_ACTIVITY_TYPE, "c2", None),
+        act_task(3, SESSION_KEEPALIVE_ACTIVITY_TYPE, "k1", Some("c1")),
+    ]);
+    let mut mock_poller = mock_manual_poller();
+    mock_poller
+        .expect_poll()
+        .returning(move || match poll_resps.pop_front() {
+            Some(r) => async { Some(Ok(r)) }.boxed(),
+            None => async {
+                sleep(Duration::from_secs(10)).await;
+                unreachable!("Long poll")
+            }
+            .boxed(),
+        });
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_complete_activity_task()
+        .withf(|tt, res| {
+            tt == &TaskToken(vec![1])
+                && res.as_ref().unwrap().payloads[0]
+                    .data
+                    .starts_with(b"unit-test")
+        })
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskCompletedResponse::default()));
+    mock_client
+        .expect_fail_activity_task()
+        .withf(|tt, _| tt == &TaskToken(vec![2]))
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskFailedResponse::default()));
+    // The workflow ends the session by cancelling the keepalive, which it learns about when core
+    // heartbeats it
+    mock_client
+        .expect_record_activity_heartbeat()
+        .withf(|tt, _| tt == &TaskToken(vec![3]))
+        .times(1)
+        .returning(|_, _| {
+            Ok(RecordActivityTaskHeartbeatResponse {
+                cancel_requested: true,
+            })
+        });
+    mock_client
+        .expect_cancel_activity_task()
+        .withf(|tt, _| tt == &TaskToken(vec![3]))
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskCanceledResponse::default()));
+
+    let mw = MockWorker {
+        act_poller: Some(Box::from(mock_poller)),
+        config: test_worker_cfg()
+            .max_concurrent_sessions(1_usize)
+            .build()
+            .unwrap(),
+        ..Default::default()
+    };
+    let core = mock_worker(MocksHolder::from_mock_worker(mock_client.into(), mw));
+    // None of the session activities are ever given to lang
+    let res = tokio::time::timeout(Duration::from_millis(300), core.poll_activity_task()).await;
+    assert!(res.is_err());
+    core.shutdown().await;
+}
+
+#[tokio::test]
+async fn session_keepalives_heartbeat_while_lang_is_not_polling() {
+    let act_task = |tt: u8, act_type: &str, input: Option<&str>| PollActivityTaskQueueResponse {
+        task_token: vec![tt],
+        activity_id: format!("act-{}", tt),
+        activity_type: Some(ActivityType {
+            name: act_type.to_string(),
+        }),
+        workflow_execution: Some(WorkflowExecution {
+            workflow_id: "wf".to_string(),
+            run_id: "run".to_string(),
+        }),
+        input: input.map(Into::into),
+        heartbeat_timeout: Some(Duration::from_millis(20).into()),
+        ..Default::default()
+    };
+    let mut poll_resps = VecDeque::from(vec![
+        act_task(1, SESSION_CREATION_ACTIVITY_TYPE, None),
+        act_task(2, SESSION_KEEPALIVE_ACTIVITY_TYPE, Some("act-1")),
+    ]);
+    let mut mock_poller = mock_manual_poller();
+    mock_poller
+        .expect_poll()
+        .returning(move || match poll_resps.pop_front() {
+            Some(r) => async { Some(Ok(r)) }.boxed(),
+            None => async {
+                sleep(Duration::from_secs(10)).await;
+                unreachable!("Long poll")
+            }
+            .boxed(),
+        });
+    let heartbeats = Arc::new(AtomicUsize::new(0));
+    let hb_count = heartbeats.clone();
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_complete_activity_task()
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskCompletedResponse::default()));
+    mock_client
+        .expect_record_activity_heartbeat()
+        .withf(|tt, _| tt == &TaskToken(vec![2]))
+        .returning(move |_, _| {
+            hb_count.fetch_add(1, Ordering::SeqCst);
+            Ok(RecordActivityTaskHeartbeatResponse::default())
+        });
+    // Shutting down ends the session
+    mock_client
+        .expect_fail_activity_task()
+        .withf(|tt, _| tt == &TaskToken(vec![2]))
+        .times(1)
+        .returning(|_, _| Ok(RespondActivityTaskFailedResponse::default()));
+
+    let mw = MockWorker {
+        act_poller: Some(Box::from(mock_poller)),
+        config: test_worker_cfg()
+            .max_concurrent_sessions(1_usize)
+            .build()
+            .unwrap(),
+        ..Default::default()
+    };
+    let core = mock_worker(MocksHolder::from_mock_worker(mock_client.into(), mw));
+    // Polling just long enough for the session to start
+    let res = tokio::time::timeout(Duration::from_millis(50), core.poll_activity_task()).await;
+    assert!(res.is_err());
+    let beats_when_polling_stopped = heartbeats.load(Ordering::SeqCst);
+    sleep(Duration::from_millis(100)).await;
+    assert!(heartbeats.load(Ordering::SeqCst) >= beats_when_polling_stopped + 3);
+    core.shutdown().await;
+}
+
+/// Schedules, starts, and resolves the session creation activity of a workflow's first session,
+/// offering the provided session task queue if it is `Some`
+fn session_creation_history(session_tq: Option<&str>) -> TestHistoryBuilder {
+    let mut t = TestHistoryBuilder::default();
+    t.add_by_type(EventType::WorkflowExecutionStarted);
+    t.add_full_wf_task();
+    let scheduled_event_id = t.add_activity_task_scheduled("session-creation-1");
+    let started_event_id = t.add_activity_task_started(scheduled_event_id);
+    match session_tq {
+        Some(tq) => t.add_activity_task_completed(
+            scheduled_event_id,
+            started_event_id,
+            tq.as_bytes().into(),
+        ),
+        None => t.add(
+            EventType::ActivityTaskFailed,
+            ActivityTaskFailedEventAttributes {
+                scheduled_event_id,
+                started_event_id,
+                ..Default::default()
+            }
+            .into(),
+        ),
+    }
+    t.add_full_wf_task();
+    t
+}
+
+fn last_command_types(wfm: &mut ManagedWFFunc) -> Vec<CommandType> {
+    wfm.get_server_commands()
+        .commands
+        .into_iter()
+        .map(|c| c.command_type())
+        .collect()
+}
+
+#[tokio::test]
+async fn sdk_session_runs_activities_on_its_task_queue_and_completes() {
+    let func = WorkflowFunction::new(|ctx: WfContext| async move {
+        let session = ctx.create_session(SessionOptions::default()).await.unwrap();
+        assert_eq!(session.task_queue(), "sessq");
+        let res = session
+            .activity(&ctx, ActivityOptions::default())
+            .await
+            .unwrap();
+        assert!(res.completed_ok());
+        session.complete(&ctx).await;
+        Ok(().into())
+    });
+    let mut t = session_creation_history(Some("sessq"));
+    t.add_activity_task_scheduled("2");
+    let act_id = t.add_activity_task_scheduled("3");
+    let started_id = t.add_activity_task_started(act_id);
+    t.add_activity_task_completed(act_id, started_id, Default::default());
+    t.add_workflow_task_scheduled_and_started();
+    let mut wfm = ManagedWFFunc::new(t, func, vec![]);
+
+    wfm.get_next_activation().await.unwrap();
+    assert_eq!(
+        last_command_types(&mut wfm),
+        vec![CommandType::ScheduleActivityTask]
+    );
+    wfm.get_next_activation().await.unwrap();
+    let commands = wfm.get_server_commands().commands;
+    let scheduled: Vec<_> = commands
+        .into_iter()
+        .map(|c| match c.attributes {
+            Some(command::Attributes::ScheduleActivityTaskCommandAttributes(a)) => a,
+            _ => panic!("Expected only activities to be scheduled"),
+        })
+        .collect();
+    // The keepalive and the session's activity both go to the session's task queue
+    assert_eq!(scheduled.len(), 2);
+    assert_eq!(
+        scheduled[0].activity_type.as_ref().unwrap().name,
+        SESSION_KEEPALIVE_ACTIVITY_TYPE
+    );
+    for a in scheduled {
+        assert_eq!(a.task_queue.unwrap().name, "sessq");
+    }
+    wfm.process_all_activations().await.unwrap();
+    // Completing the session cancels its keepalive
+    assert_eq!(
+        last_command_types(&mut wfm),
+        vec![
+            CommandType::RequestCancelActivityTask,
+            CommandType::CompleteWorkflowExecution
+        ]
+    );
+    wfm.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn sdk_session_creation_failure_is_reported() {
+    let func = WorkflowFunction::new(|ctx: WfContext| async move {
+        let res = ctx.create_session(SessionOptions::default()).await;
+        assert!(matches!(res, Err(SessionError::CreationFailed(_))));
+        Ok(().into())
+    });
+    let mut wfm = ManagedWFFunc::new(session_creation_history(None), func, vec![]);
+    wfm.process_all_activations().await.unwrap();
+    assert_eq!(
+        last_command_types(&mut wfm),
+        vec![CommandType::CompleteWorkflowExecution]
+    );
+    wfm.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn sdk_session_activity_is_cancelled_when_session_fails() {
+    let func = WorkflowFunction::new(|ctx: WfContext| async move {
+        let session = ctx.create_session(SessionOptions::default()).await.unwrap();
+        let res = session.activity(&ctx, ActivityOptions::default()).await;
+        assert_matches!(res, Err(SessionError::Failed(_)));
+        Ok(().into())
+    });
+    let mut t = session_creation_history(Some("sessq"));
+    let keepalive_id = t.add_activity_task_scheduled("2");
+    t.add_activity_task_scheduled("3");
+    // The session's worker is lost, so its keepalive times out
+    t.add(
+        EventType::ActivityTaskTimedOut,
+        ActivityTaskTimedOutEventAttributes {
+            scheduled_event_id: keepalive_id,
+            ..Default::default()
+        }
+        .into(),
+    );
+    t.add_workflow_task_scheduled_and_started();
+    let mut wfm = ManagedWFFunc::new(t, func, vec![]);
+    wfm.process_all_activations().await.unwrap();
+    assert_eq!(
+        last_command_types(&mut wfm),
+        vec![
+            CommandType::RequestCancelActivityTask,
+            CommandType::CompleteWorkflowExecution
+        ]
+    );
+    wfm.shutdown().await.unwrap();
+}
@@ -1,4 +1,5 @@
 use crate::{
+    internal_flags::CoreInternalFlags,
     replay::DEFAULT_WORKFLOW_TYPE,
     test_help::{canned_histories, mock_sdk, mock_sdk_cfg, MockPollCfg, ResponseType},
     worker::client::mocks::mock_workflow_client,
@@ -8,8 +9,16 @@ use std::{
     time::Duration,
 };
 use temporal_client::WorkflowOptions;
-use temporal_sdk::{WfContext, WorkflowResult};
-use temporal_sdk_core_protos::temporal::api::enums::v1::WorkflowTaskFailedCause;
+use temporal_sdk::{ActivityOptions, WfContext, WorkflowResult};
+use temporal_sdk_core_protos::{
+    temporal::api::{
+        common::v1::ActivityType,
+        enums::v1::{EventType, WorkflowTaskFailedCause},
+        history::v1::{ActivityTaskScheduledEventAttributes, WorkflowTaskCompletedEventAttributes},
+        sdk::v1::WorkflowTaskCompletedMetadata,
+    },
+    TestHistoryBuilder,
+};
 
 static DID_FAIL: AtomicBool = AtomicBool::new(false);
 pub async fn timer_wf_fails_once(ctx: WfContext) -> WorkflowResult<()> {
@@ -105,3 +114,89 @@ async fn test_wf_task_rejected_properly_due_to_nondeterminism(#[case] use_cache:
     // timer and proceed without restarting
     assert_eq!(2, started_count.load(Ordering::Relaxed));
 }
+
+#[rstest::rstest]
+#[case::flag_recorded(true)]
+#[case::flag_not_recorded(false)]
+#[tokio::test]
+async fn activity_type_mismatch_only_checked_with_flag(#[case] flag_recorded: bool) {
+    let wf_id = "fakeid";
+    let wf_type = DEFAULT_WORKFLOW_TYPE;
+    let mut t = TestHistoryBuilder::default();
+    t.add_by_type(EventType::WorkflowExecutionStarted);
+    t.add_workflow_task_scheduled_and_started();
+    t.add(
+        EventType::WorkflowTaskCompleted,
+        WorkflowTaskCompletedEventAttributes {
+            scheduled_event_id: 2,
+            started_event_id: 3,
+            sdk_metadata: flag_recorded.then(|| WorkflowTaskCompletedMetadata {
+                core_used_flags: vec![CoreInternalFlags::IdAndTypeDeterminismChecks as u32],
+                lang_used_flags: vec![],
+            }),
+            ..Default::default()
+        }
+        .into(),
+    );
+    let scheduled_event_id = t.add_get_event_id(
+        EventType::ActivityTaskScheduled,
+        Some(
+            ActivityTaskScheduledEventAttributes {
+                activity_id: "1".to_owned(),
+                activity_type: Some(ActivityType {
+                    name: "recorded_type".to_owned(),
+                }),
+                ..Default::default()
+            }
+            .into(),
+        ),
+    );
+    let started_event_id = t.add_activity_task_started(scheduled_event_id);
+    t.add_activity_task_completed(scheduled_event_id, started_event_id, Default::default());
+    t.add_full_wf_task();
+    t.add_workflow_execution_completed();
+
+    let mock = mock_workflow_client();
+    // When the check applies, the first attempt fails and history must be delivered again
+    let resps = if flag_recorded {
+        vec![ResponseType::AllHistory, ResponseType::AllHistory]
+    } else {
+        vec![ResponseType::AllHistory]
+    };
+    let mut mh = MockPollCfg::from_resp_batches(wf_id, t, resps, mock);
+    if flag_recorded {
+        mh.num_expected_fails = Some(1);
+        mh.expect_fail_wft_matcher =
+            Box::new(|_, cause, _| matches!(cause, WorkflowTaskFailedCause::NonDeterministicError));
+    }
+    let mut worker = mock_sdk(mh);
+
+    let started_count: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+    worker.register_wf(wf_type.to_owned(), move |ctx: WfContext| async move {
+        // Only the first attempt uses a type which differs from the one in history
+        let activity_type = if started_count.fetch_add(1, Ordering::Relaxed) == 0 {
+            "different_type"
+        } else {
+            "recorded_type"
+        };
+        ctx.activity(ActivityOptions {
+            activity_id: Some("1".to_owned()),
+            activity_type: activity_type.to_owned(),
+            ..Default::default()
+        })
+        .await;
+        Ok(().into())
+    });
+    worker
+        .submit_wf(
+            wf_id.to_owned(),
+            wf_type.to_owned(),
+            vec![],
+            WorkflowOptions::default(),
+        )
+        .await
+        .unwrap();
+    worker.run_until_done().await.unwrap();
+    let expected_starts = if flag_recorded { 2 } else { 1 };
+    assert_eq!(expected_starts, started_count.load(Ordering::Relaxed));
+}
@@ -6,7 +6,7 @@ use crate::{
 use anyhow::anyhow;
 use futures::future::join_all;
 use std::{
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     time::Duration,
 };
 use temporal_client::WorkflowOptions;
@@ -342,3 +342,204 @@ async fn local_act_retry_long_backoff_uses_timer() {
         .unwrap();
     worker.run_until_done().await.unwrap();
 }
+
+#[tokio::test]
+async fn local_act_retry_long_backoff_promoted() {
+    let mut t = TestHistoryBuilder::default();
+    t.add_by_type(EventType::WorkflowExecutionStarted);
+    t.add_full_wf_task();
+    t.add_local_activity_promoted_marker(
+        1,
+        "1",
+        Failure::application_failure("la failed".to_string(), false),
+    );
+    let scheduled_event_id = t.add_activity_task_scheduled("2");
+    let started_event_id = t.add_activity_task_started(scheduled_event_id);
+    t.add_activity_task_completed(
+        scheduled_event_id,
+        started_event_id,
+        "hi".as_json_payload().unwrap(),
+    );
+    t.add_full_wf_task();
+    t.add_workflow_execution_completed();
+
+    let wf_id = "fakeid";
+    let mock = mock_workflow_client();
+    let mh = MockPollCfg::from_resp_batches(wf_id, t, [1.into(), ResponseType::AllHistory], mock);
+    let mut worker = mock_sdk_cfg(mh, |w| {
+        w.max_cached_workflows = 1;
+        w.promote_local_activities_past_retry_threshold = true;
+    });
+
+    worker.register_wf(
+        DEFAULT_WORKFLOW_TYPE.to_owned(),
+        |ctx: WfContext| async move {
+            let la_res = ctx
+                .local_activity(LocalActivityOptions {
+                    activity_type: "echo".to_string(),
+                    input: "hi".as_json_payload().expect("serializes fine"),
+                    retry_policy: RetryPolicy {
+                        initial_interval: Some(Duration::from_millis(65).into()),
+                        // The second backoff is 65 seconds, past the timer threshold
+                        backoff_coefficient: 1_000.,
+                        maximum_interval: Some(Duration::from_secs(600).into()),
+                        maximum_attempts: 3,
+                        non_retryable_error_types: vec![],
+                    },
+                    ..Default::default()
+                })
+                .await;
+            // Resolved by the normal activity which ran in its place
+            assert!(la_res.completed_ok());
+            Ok(().into())
+        },
+    );
+    let attempts: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+    worker.register_activity("echo", move |_ctx: ActContext, _: String| async move {
+        attempts.fetch_add(1, Ordering::Relaxed);
+        Result::<(), _>::Err(anyhow!("Oh no I failed!"))
+    });
+    worker
+        .submit_wf(
+            wf_id.to_owned(),
+            DEFAULT_WORKFLOW_TYPE.to_owned(),
+            vec![],
+            WorkflowOptions::default(),
+        )
+        .await
+        .unwrap();
+    worker.run_until_done().await.unwrap();
+    // Two local attempts were made before promotion
+    assert_eq!(2, attempts.load(Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn local_act_promoted_after_max_wft_heartbeat_duration() {
+    let mut t = TestHistoryBuilder::default();
+    let wft_timeout = Duration::from_millis(200);
+    let mut wes_short_wft_timeout = default_wes_attribs();
+    wes_short_wft_timeout.workflow_task_timeout = Some(wft_timeout.into());
+    t.add(
+        EventType::WorkflowExecutionStarted,
+        wes_short_wft_timeout.into(),
+    );
+    t.add_full_wf_task();
+    // Task created by WFT heartbeat
+    t.add_full_wf_task();
+    t.add_local_activity_promoted_marker(
+        1,
+        "1",
+        Failure::application_failure(
+            "Local activity was promoted to a normal activity".to_string(),
+            false,
+        ),
+    );
+    let scheduled_event_id = t.add_activity_task_scheduled("2");
+    let started_event_id = t.add_activity_task_started(scheduled_event_id);
+    t.add_activity_task_completed(
+        scheduled_event_id,
+        started_event_id,
+        "hi".as_json_payload().unwrap(),
+    );
+    t.add_full_wf_task();
+    t.add_workflow_execution_completed();
+
+    let wf_id = "fakeid";
+    let mock = mock_workflow_client();
+    let mut mh = MockPollCfg::from_resp_batches(
+        wf_id,
+        t,
+        [1.into(), 2.into(), ResponseType::AllHistory],
+        mock,
+    );
+    mh.enforce_correct_number_of_polls = false;
+    let mut worker = mock_sdk_cfg(mh, |w| {
+        w.max_cached_workflows = 1;
+        // Reached by the time the first WFT must be heartbeat
+        w.max_local_activity_wft_heartbeat_duration = Some(wft_timeout / 4);
+    });
+
+    worker.register_wf(
+        DEFAULT_WORKFLOW_TYPE.to_owned(),
+        |ctx: WfContext| async move {
+            let la_res = ctx
+                .local_activity(LocalActivityOptions {
+                    activity_type: "echo".to_string(),
+                    input: "hi".as_json_payload().expect("serializes fine"),
+                    ..Default::default()
+                })
+                .await;
+            // Resolved by the normal activity which ran in its place
+            assert!(la_res.completed_ok());
+            Ok(().into())
+        },
+    );
+    let stopped_locally: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+    worker.register_activity("echo", move |ctx: ActContext, _: String| async move {
+        // Runs until lang is asked to stop it, so it can be promoted
+        ctx.cancelled().await;
+        stopped_locally.fetch_add(1, Ordering::Relaxed);
+        Result::<String, _>::Err(anyhow!("Stopped to be promoted"))
+    });
+    worker
+        .submit_wf(
+            wf_id.to_owned(),
+            DEFAULT_WORKFLOW_TYPE.to_owned(),
+            vec![],
+            WorkflowOptions::default(),
+        )
+        .await
+        .unwrap();
+    worker.run_until_done().await.unwrap();
+    assert_eq!(1, stopped_locally.load(Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn local_act_result_reused_after_wft_failure() {
+    let mut t = TestHistoryBuilder::default();
+    t.add_by_type(EventType::WorkflowExecutionStarted);
+    t.add_workflow_task_scheduled_and_started();
+
+    let wf_id = "fakeid";
+    let mock = mock_workflow_client();
+    let mut mh = MockPollCfg::from_resp_batches(wf_id, t, [1, 1], mock);
+    mh.num_expected_fails = Some(1);
+    let mut worker = mock_sdk_cfg(mh, |w| w.max_cached_workflows = 1);
+
+    let failed_once: &'static _ = Box::leak(Box::new(AtomicBool::new(false)));
+    worker.register_wf(
+        DEFAULT_WORKFLOW_TYPE.to_owned(),
+        move |ctx: WfContext| async move {
+            let la_res = ctx
+                .local_activity(LocalActivityOptions {
+                    activity_type: "echo".to_string(),
+                    input: "hi".as_json_payload().expect("serializes fine"),
+                    ..Default::default()
+                })
+                .await;
+            assert!(la_res.completed_ok());
+            // Fail the first workflow task after the activity ran
+            if !failed_once.swap(true, Ordering::Relaxed) {
+                panic!("Workflow task fails");
+            }
+            Ok(().into())
+        },
+    );
+    let executions: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+    worker.register_activity("echo", move |_ctx: ActContext, s: String| async move {
+        executions.fetch_add(1, Ordering::Relaxed);
+        Ok(s)
+    });
+    worker
+        .submit_wf(
+            wf_id.to_owned(),
+            DEFAULT_WORKFLOW_TYPE.to_owned(),
+            vec![],
+            WorkflowOptions::default(),
+        )
+        .await
+        .unwrap();
+    worker.run_until_done().await.unwrap();
+    assert!(failed_once.load(Ordering::Relaxed));
+    assert_eq!(1, executions.load(Ordering::Relaxed));
+}
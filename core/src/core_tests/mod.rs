@@ -3,7 +3,9 @@ mod child_workflows;
 mod determinism;
 mod local_activities;
 mod queries;
+mod replay;
 mod replay_flag;
+mod test_env;
 mod workers;
 mod workflow_cancels;
 mod workflow_tasks;
@@ -1,6 +1,8 @@
 use crate::{
+    errors::PollWfError,
     test_help::{
-        canned_histories, hist_to_poll_resp, mock_worker, MocksHolder, ResponseType, TEST_Q,
+        canned_histories, hist_to_poll_resp, mock_worker, MocksHolder, ResponseType,
+        NO_MORE_WORK_ERROR_MSG, TEST_Q,
     },
     worker::client::mocks::mock_workflow_client,
 };
@@ -8,15 +10,15 @@ use std::{
     collections::{HashMap, VecDeque},
     time::Duration,
 };
-use temporal_sdk_core_api::Worker as WorkerTrait;
+use temporal_sdk_core_api::{worker::STACK_TRACE_QUERY_TYPE, Worker as WorkerTrait};
 use temporal_sdk_core_protos::{
     coresdk::{
         workflow_activation::{
             remove_from_cache::EvictionReason, workflow_activation_job, WorkflowActivationJob,
         },
         workflow_commands::{
-            ActivityCancellationType, CompleteWorkflowExecution, ContinueAsNewWorkflowExecution,
-            QueryResult, QuerySuccess, RequestCancelActivity,
+            query_result, ActivityCancellationType, CompleteWorkflowExecution,
+            ContinueAsNewWorkflowExecution, QueryResult, QuerySuccess, RequestCancelActivity,
         },
         workflow_completion::WorkflowActivationCompletion,
     },
@@ -26,8 +28,9 @@ use temporal_sdk_core_protos::{
         history::v1::History,
         query::v1::WorkflowQuery,
         workflowservice::v1::{
-            GetWorkflowExecutionHistoryResponse, RespondQueryTaskCompletedResponse,
-            RespondWorkflowTaskCompletedResponse,
+            GetWorkflowExecutionHistoryResponse, PollWorkflowTaskQueueResponse,
+            RespondQueryTaskCompletedResponse, RespondWorkflowTaskCompletedResponse,
+            RespondWorkflowTaskFailedResponse,
         },
     },
 };
@@ -648,3 +651,207 @@ async fn query_replay_with_continue_as_new_doesnt_reply_empty_command() {
 
     core.shutdown().await;
 }
+
+#[tokio::test]
+async fn stack_trace_query_answered_by_core() {
+    let wfid = "fake_wf_id";
+    let t = canned_histories::single_timer("1");
+    let tasks = VecDeque::from(vec![
+        hist_to_poll_resp(&t, wfid.to_owned(), 1.into(), TEST_Q.to_string()),
+        {
+            let mut pr = hist_to_poll_resp(&t, wfid.to_owned(), 1.into(), TEST_Q.to_string());
+            pr.query = Some(WorkflowQuery {
+                query_type: STACK_TRACE_QUERY_TYPE.to_string(),
+                query_args: None,
+                header: None,
+            });
+            pr.history = Some(History { events: vec![] });
+            pr
+        },
+        {
+            let mut pr = hist_to_poll_resp(
+                &t,
+                wfid.to_owned(),
+                ResponseType::OneTask(2),
+                TEST_Q.to_string(),
+            );
+            pr.queries = HashMap::new();
+            pr.queries.insert(
+                "q1".to_string(),
+                WorkflowQuery {
+                    query_type: STACK_TRACE_QUERY_TYPE.to_string(),
+                    query_args: None,
+                    header: None,
+                },
+            );
+            pr
+        },
+    ]);
+    let stack_trace_of = |qr: &QueryResult| -> String {
+        assert_matches!(
+            &qr.variant,
+            Some(query_result::Variant::Succeeded(QuerySuccess { response: Some(p) }))
+                => String::from_utf8(p.data.clone()).unwrap()
+        )
+    };
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_respond_legacy_query()
+        .times(1)
+        .returning(move |_, qr| {
+            let trace = stack_trace_of(&qr);
+            assert!(trace
+                .starts_with(r#"[{"seq":1,"command_type":"Timer","state":"StartCommandCreated","#));
+            assert!(trace.contains(r#""scheduled_time_ms":1"#));
+            Ok(RespondQueryTaskCompletedResponse::default())
+        });
+    mock_client
+        .expect_complete_workflow_task()
+        .times(1)
+        .returning(|_| Ok(RespondWorkflowTaskCompletedResponse::default()));
+    mock_client
+        .expect_complete_workflow_task()
+        .times(1)
+        .returning(move |wftc| {
+            assert_matches!(wftc.query_responses.as_slice(), [qr] => {
+                assert_eq!(qr.query_id, "q1");
+                assert_eq!(stack_trace_of(qr), "[]");
+            });
+            Ok(RespondWorkflowTaskCompletedResponse::default())
+        });
+
+    let mut mock = MocksHolder::from_client_with_responses(mock_client, tasks, vec![]);
+    mock.worker_cfg(|wc| wc.max_cached_workflows = 10);
+    let core = mock_worker(mock);
+
+    let task = core.poll_workflow_activation().await.unwrap();
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmd(
+        task.run_id,
+        start_timer_cmd(1, Duration::from_secs(1)),
+    ))
+    .await
+    .unwrap();
+
+    // Neither the legacy query nor the one attached to the next task ever reach lang
+    let task = core.poll_workflow_activation().await.unwrap();
+    assert_matches!(
+        task.jobs.as_slice(),
+        [WorkflowActivationJob {
+            variant: Some(workflow_activation_job::Variant::FireTimer(_)),
+        }]
+    );
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmds(
+        task.run_id,
+        vec![CompleteWorkflowExecution { result: None }.into()],
+    ))
+    .await
+    .unwrap();
+    core.shutdown().await;
+}
+
+#[tokio::test]
+async fn stack_trace_query_answered_by_retried_task_after_lang_fails_activation() {
+    let wfid = "fake_wf_id";
+    let t = canned_histories::single_timer("1");
+    let with_stack_trace_query = |mut pr: PollWorkflowTaskQueueResponse| {
+        pr.queries = HashMap::from([(
+            "q1".to_string(),
+            WorkflowQuery {
+                query_type: STACK_TRACE_QUERY_TYPE.to_string(),
+                query_args: None,
+                header: None,
+            },
+        )]);
+        pr
+    };
+    let tasks = VecDeque::from(vec![
+        hist_to_poll_resp(&t, wfid.to_owned(), 1.into(), TEST_Q.to_string()),
+        with_stack_trace_query(hist_to_poll_resp(
+            &t,
+            wfid.to_owned(),
+            ResponseType::OneTask(2),
+            TEST_Q.to_string(),
+        )),
+        with_stack_trace_query(hist_to_poll_resp(
+            &t,
+            wfid.to_owned(),
+            2.into(),
+            TEST_Q.to_string(),
+        )),
+    ]);
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_complete_workflow_task()
+        .times(1)
+        .returning(|_| Ok(RespondWorkflowTaskCompletedResponse::default()));
+    mock_client
+        .expect_fail_workflow_task()
+        .times(1)
+        .returning(|_, _, _| Ok(RespondWorkflowTaskFailedResponse::default()));
+    mock_client
+        .expect_complete_workflow_task()
+        .times(1)
+        .returning(|wftc| {
+            assert_matches!(wftc.query_responses.as_slice(), [qr] => {
+                assert_eq!(qr.query_id, "q1");
+                assert_matches!(qr.variant, Some(query_result::Variant::Succeeded(_)));
+            });
+            Ok(RespondWorkflowTaskCompletedResponse::default())
+        });
+
+    let mut mock = MocksHolder::from_client_with_responses(mock_client, tasks, vec![]);
+    mock.worker_cfg(|wc| wc.max_cached_workflows = 10);
+    let core = mock_worker(mock);
+
+    let task = core.poll_workflow_activation().await.unwrap();
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmd(
+        task.run_id,
+        start_timer_cmd(1, Duration::from_secs(1)),
+    ))
+    .await
+    .unwrap();
+
+    // The query core answers is held while lang handles the rest of the activation, which fails.
+    // Failing the task can't carry the answer, so the server delivers the query again with the
+    // retried task.
+    let task = core.poll_workflow_activation().await.unwrap();
+    core.complete_workflow_activation(WorkflowActivationCompletion::fail(
+        task.run_id,
+        Failure {
+            message: "Ahh i broke".to_string(),
+            ..Default::default()
+        },
+    ))
+    .await
+    .unwrap();
+    let task = core.poll_workflow_activation().await.unwrap();
+    assert_matches!(
+        task.jobs.as_slice(),
+        [WorkflowActivationJob {
+            variant: Some(workflow_activation_job::Variant::RemoveFromCache(_)),
+        }]
+    );
+    core.complete_workflow_activation(WorkflowActivationCompletion::empty(task.run_id))
+        .await
+        .unwrap();
+
+    let task = core.poll_workflow_activation().await.unwrap();
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmd(
+        task.run_id,
+        start_timer_cmd(1, Duration::from_secs(1)),
+    ))
+    .await
+    .unwrap();
+    let task = core.poll_workflow_activation().await.unwrap();
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmds(
+        task.run_id,
+        vec![CompleteWorkflowExecution { result: None }.into()],
+    ))
+    .await
+    .unwrap();
+    // Core answers the query once replay is done, which completes the task, leaving nothing more
+    // for lang
+    assert_matches!(core.poll_workflow_activation().await.unwrap_err(),
+                    PollWfError::TonicError(err) if err.message() == NO_MORE_WORK_ERROR_MSG);
+    core.shutdown().await;
+}
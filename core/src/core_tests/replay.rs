@@ -0,0 +1,200 @@
+use crate::{
+    replay::{
+        histories_from_dir, histories_from_server, replay_worker, HistoryForReplay,
+        ReplayWorkerClient,
+    },
+    test_help::{canned_histories, test_worker_cfg, TEST_Q},
+};
+use futures::{stream, StreamExt};
+use prost::Message;
+use std::{sync::Arc, time::Duration};
+use temporal_client::MockWorkflowClientTrait;
+use temporal_sdk::{ActivityOptions, WfContext, Worker};
+use temporal_sdk_core_protos::{
+    temporal::api::{
+        common::v1::WorkflowExecution, enums::v1::CommandType, history::v1::History,
+        workflowservice::v1::GetWorkflowExecutionHistoryResponse,
+    },
+    DEFAULT_WORKFLOW_TYPE,
+};
+use temporal_sdk_core_test_utils::canned_history;
+
+#[tokio::test]
+async fn replay_client_paginates_and_reports_each_run() {
+    let num_timers = 5;
+    let histories: Vec<_> = (1..=3)
+        .map(|i| {
+            // The histories stop at the start of the task which fires the last timer, so the
+            // worker reports that task's commands like it would for a workflow still running
+            HistoryForReplay::new(
+                canned_histories::long_sequential_timers(num_timers)
+                    .get_history_info(num_timers)
+                    .unwrap()
+                    .into(),
+                format!("wf-{}", i),
+            )
+        })
+        .collect();
+    // Histories are far longer than one page, so the worker must fetch the rest of each one
+    let page_size = 4;
+    let expected_pages_fetched = histories[0].hist.events.chunks(page_size).count() - 1;
+    assert!(expected_pages_fetched > 1);
+    let client = ReplayWorkerClient::new(stream::iter(histories).boxed()).with_page_size(page_size);
+    let cfg = test_worker_cfg()
+        .max_cached_workflows(2_usize)
+        .build()
+        .unwrap();
+    let core = replay_worker(cfg, client.clone());
+
+    let mut worker = Worker::new_from_core(Arc::new(core), TEST_Q);
+    worker.register_wf(DEFAULT_WORKFLOW_TYPE, move |ctx: WfContext| async move {
+        for _ in 1..=num_timers {
+            ctx.timer(Duration::from_secs(1)).await;
+        }
+        Ok(().into())
+    });
+    worker.run().await.unwrap();
+
+    let report = client.report();
+    assert!(report.all_succeeded());
+    let wf_ids: Vec<_> = report
+        .results
+        .iter()
+        .map(|r| r.workflow_id.as_str())
+        .collect();
+    assert_eq!(wf_ids, ["wf-1", "wf-2", "wf-3"]);
+    for result in &report.results {
+        assert_eq!(client.pages_fetched(&result.run_id), expected_pages_fetched);
+        let command_types: Vec<_> = result.commands.iter().map(|c| c.command_type()).collect();
+        assert_eq!(command_types, [CommandType::StartTimer]);
+    }
+}
+
+#[tokio::test]
+async fn canned_history_dsl_replays() {
+    let t = canned_history! {
+        started; wft;
+        timer_started 1; timer_fired 1; wft;
+        activity_scheduled act1; activity_started act1; activity_completed act1 "done"; wft;
+        completed;
+    };
+    let history = HistoryForReplay::new(t.get_full_history_info().unwrap().into(), "wf");
+    let client = ReplayWorkerClient::new(stream::iter([history]).boxed());
+    let cfg = test_worker_cfg()
+        .max_cached_workflows(1_usize)
+        .build()
+        .unwrap();
+    let core = replay_worker(cfg, client.clone());
+
+    let mut worker = Worker::new_from_core(Arc::new(core), TEST_Q);
+    worker.register_wf(DEFAULT_WORKFLOW_TYPE, |ctx: WfContext| async move {
+        ctx.timer(Duration::from_secs(1)).await;
+        ctx.activity(ActivityOptions {
+            activity_id: Some("act1".to_string()),
+            activity_type: "echo".to_string(),
+            start_to_close_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        })
+        .await;
+        Ok(().into())
+    });
+    worker.run().await.unwrap();
+    assert!(client.report().all_succeeded());
+}
+
+#[tokio::test]
+async fn histories_read_from_dir() {
+    let dir = std::env::temp_dir().join(format!("replay-histories-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    let timer_hist: History = canned_histories::single_timer("1")
+        .get_full_history_info()
+        .unwrap()
+        .into();
+    let activity_hist: History = canned_histories::single_activity("act")
+        .get_full_history_info()
+        .unwrap()
+        .into();
+    std::fs::write(dir.join("timer-wf_history.bin"), timer_hist.encode_to_vec()).unwrap();
+    std::fs::write(dir.join("activity-wf.bin"), activity_hist.encode_to_vec()).unwrap();
+    std::fs::write(dir.join("notes.txt"), "Not a history").unwrap();
+
+    let mut histories = histories_from_dir(&dir).await.unwrap();
+    histories.sort_by(|a, b| a.workflow_id.cmp(&b.workflow_id));
+    let read: Vec<_> = histories
+        .into_iter()
+        .map(|h| (h.workflow_id, h.hist))
+        .collect();
+    assert_eq!(
+        read,
+        vec![
+            ("activity-wf".to_string(), activity_hist),
+            ("timer-wf".to_string(), timer_hist)
+        ]
+    );
+
+    std::fs::write(dir.join("broken.bin"), b"definitely not protobuf").unwrap();
+    assert!(histories_from_dir(&dir).await.is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn histories_fetched_from_server_by_page() {
+    let history: History = canned_histories::long_sequential_timers(3)
+        .get_full_history_info()
+        .unwrap()
+        .into();
+    let (first_page, second_page) = history.events.split_at(5);
+    let (first_page, second_page) = (first_page.to_vec(), second_page.to_vec());
+    let mut mock_client = MockWorkflowClientTrait::new();
+    mock_client
+        .expect_get_workflow_execution_history()
+        .withf(|wf_id, run_id, page_token| {
+            wf_id == "paged" && run_id.as_deref() == Some("run") && page_token.is_empty()
+        })
+        .times(1)
+        .returning(move |_, _, _| {
+            Ok(GetWorkflowExecutionHistoryResponse {
+                history: Some(History {
+                    events: first_page.clone(),
+                }),
+                next_page_token: b"page 2".to_vec(),
+                ..Default::default()
+            })
+        });
+    mock_client
+        .expect_get_workflow_execution_history()
+        .withf(|wf_id, _, page_token| wf_id == "paged" && page_token == b"page 2")
+        .times(1)
+        .returning(move |_, _, _| {
+            Ok(GetWorkflowExecutionHistoryResponse {
+                history: Some(History {
+                    events: second_page.clone(),
+                }),
+                ..Default::default()
+            })
+        });
+    // Executions without a run id fetch the latest run, and those which can't be fetched are
+    // skipped
+    mock_client
+        .expect_get_workflow_execution_history()
+        .withf(|wf_id, run_id, _| wf_id == "missing" && run_id.is_none())
+        .times(1)
+        .returning(|_, _, _| Err(tonic::Status::not_found("No such workflow")));
+
+    let executions = stream::iter([
+        WorkflowExecution {
+            workflow_id: "paged".to_string(),
+            run_id: "run".to_string(),
+        },
+        WorkflowExecution {
+            workflow_id: "missing".to_string(),
+            run_id: "".to_string(),
+        },
+    ]);
+    let fetched: Vec<_> = histories_from_server(Arc::new(mock_client), executions)
+        .collect()
+        .await;
+    assert_eq!(fetched.len(), 1);
+    assert_eq!(fetched[0].workflow_id, "paged");
+    assert_eq!(fetched[0].hist, history);
+}
@@ -0,0 +1,98 @@
+use crate::{
+    test_env::{TestWorkflowEnvironment, TestWorkflowResult},
+    test_help::{test_worker_cfg, TEST_Q},
+};
+use std::time::{Duration, Instant, SystemTime};
+use temporal_sdk::{ActContext, ActivityOptions, WfContext, Worker};
+use temporal_sdk_core_protos::{
+    coresdk::{AsJsonPayloadExt, FromJsonPayloadExt},
+    temporal::api::enums::v1::EventType,
+};
+
+const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+
+fn env() -> TestWorkflowEnvironment {
+    TestWorkflowEnvironment::new(
+        test_worker_cfg()
+            .max_cached_workflows(10_usize)
+            .build()
+            .unwrap(),
+    )
+}
+
+fn time_of(t: &prost_types::Timestamp) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::new(t.seconds as u64, t.nanos as u32)
+}
+
+#[tokio::test]
+async fn month_long_timer_completes_without_waiting() {
+    let env = env();
+    let started_at = env.now();
+    let mut worker = Worker::new_from_core(env.worker(), TEST_Q);
+    worker.register_wf("month_timer", |ctx: WfContext| async move {
+        ctx.timer(DAY * 30).await;
+        Ok(().into())
+    });
+    let run_id = env.start_workflow("month_timer_wf", "month_timer", vec![]);
+
+    let real_start = Instant::now();
+    worker.run().await.unwrap();
+    assert!(real_start.elapsed() < Duration::from_secs(5));
+    assert_eq!(env.now(), started_at + DAY * 30);
+    assert_matches!(
+        env.workflow_result(&run_id),
+        Some(TestWorkflowResult::Completed(_))
+    );
+}
+
+#[tokio::test]
+async fn activities_are_dispatched_or_stubbed() {
+    let env = env();
+    env.register_activity_stub("stubbed", |_| Ok("from stub".as_json_payload().unwrap()));
+    let mut worker = Worker::new_from_core(env.worker(), TEST_Q);
+    worker.register_activity("echo", |_ctx: ActContext, s: String| async move { Ok(s) });
+    worker.register_wf("acts_and_timers", |ctx: WfContext| async move {
+        for (activity_type, expected) in [("echo", "hi"), ("stubbed", "from stub")] {
+            let res = ctx
+                .activity(ActivityOptions {
+                    activity_type: activity_type.to_string(),
+                    input: "hi".as_json_payload().unwrap(),
+                    start_to_close_timeout: Some(Duration::from_secs(5)),
+                    ..Default::default()
+                })
+                .await;
+            assert_eq!(
+                String::from_json_payload(&res.unwrap_ok_payload()).unwrap(),
+                expected
+            );
+            ctx.timer(DAY).await;
+        }
+        Ok(().into())
+    });
+    let run_ids: Vec<_> = (1..=2)
+        .map(|i| env.start_workflow(format!("wf-{}", i), "acts_and_timers", vec![]))
+        .collect();
+    worker.run().await.unwrap();
+
+    for run_id in run_ids {
+        assert_matches!(
+            env.workflow_result(&run_id),
+            Some(TestWorkflowResult::Completed(_))
+        );
+        let events = env.history(&run_id).unwrap().events;
+        let fired: Vec<_> = events
+            .iter()
+            .filter(|e| e.event_type() == EventType::TimerFired)
+            .map(|e| time_of(e.event_time.as_ref().unwrap()))
+            .collect();
+        let started_at = time_of(events[0].event_time.as_ref().unwrap());
+        assert_eq!(fired, [started_at + DAY, started_at + DAY * 2]);
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| e.event_type() == EventType::ActivityTaskCompleted)
+                .count(),
+            2
+        );
+    }
+}
@@ -6,7 +6,7 @@ use crate::{
     worker::client::mocks::mock_workflow_client,
     PollActivityError, PollWfError,
 };
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt};
 use std::{cell::RefCell, time::Duration};
 use temporal_sdk_core_api::Worker;
 use temporal_sdk_core_protos::{
@@ -57,6 +57,36 @@ async fn after_shutdown_of_worker_get_shutdown_err() {
     });
 }
 
+#[tokio::test]
+async fn activation_stream_ends_after_shutdown() {
+    let t = canned_histories::single_timer("1");
+    let worker = build_fake_worker("fake_wf_id", t, [1]);
+    let mut activations = worker.activation_stream();
+    let res = activations.next().await.unwrap().unwrap();
+    let run_id = res.run_id;
+
+    tokio::join!(worker.shutdown(), async {
+        worker
+            .complete_workflow_activation(WorkflowActivationCompletion::from_cmd(
+                run_id.clone(),
+                start_timer_cmd(1, Duration::from_secs(1)),
+            ))
+            .await
+            .unwrap();
+        let res = activations.next().await.unwrap().unwrap();
+        assert_matches!(
+            res.jobs[0].variant,
+            Some(workflow_activation_job::Variant::RemoveFromCache(_))
+        );
+        worker
+            .complete_workflow_activation(WorkflowActivationCompletion::empty(run_id.clone()))
+            .await
+            .unwrap();
+        // Rather than a shutdown error, the stream ends
+        assert!(activations.next().await.is_none());
+    });
+}
+
 #[tokio::test]
 async fn shutdown_worker_can_complete_pending_activation() {
     let t = canned_histories::single_timer("1");
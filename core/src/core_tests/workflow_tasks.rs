@@ -1,7 +1,7 @@
 use crate::{
     errors::PollWfError,
     job_assert,
-    replay::TestHistoryBuilder,
+    replay::{TestHistoryBuilder, DEFAULT_WORKFLOW_TYPE},
     test_help::{
         build_fake_worker, build_mock_pollers, build_multihist_mock_sg, canned_histories,
         gen_assert_and_fail, gen_assert_and_reply, hist_to_poll_resp, mock_worker, poll_and_reply,
@@ -14,17 +14,17 @@ use crate::{
 };
 use rstest::{fixture, rstest};
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     sync::atomic::{AtomicU64, AtomicUsize, Ordering},
     time::Duration,
 };
-use temporal_sdk_core_api::Worker as WorkerTrait;
+use temporal_sdk_core_api::{worker::NondeterminismPolicy, Worker as WorkerTrait};
 use temporal_sdk_core_protos::{
     coresdk::{
         activity_result::{self as ar, activity_resolution, ActivityResolution},
         workflow_activation::{
-            remove_from_cache::EvictionReason, workflow_activation_job, FireTimer, ResolveActivity,
-            StartWorkflow, UpdateRandomSeed, WorkflowActivationJob,
+            remove_from_cache::EvictionReason, workflow_activation_job, FireTimer, RemoveFromCache,
+            ResolveActivity, StartWorkflow, UpdateRandomSeed, WorkflowActivationJob,
         },
         workflow_commands::{
             ActivityCancellationType, CancelTimer, CompleteWorkflowExecution,
@@ -33,8 +33,9 @@ use temporal_sdk_core_protos::{
         workflow_completion::WorkflowActivationCompletion,
     },
     temporal::api::{
+        command::v1::{command, Command as ProtoCommand, FailWorkflowExecutionCommandAttributes},
         enums::v1::{EventType, WorkflowTaskFailedCause},
-        failure::v1::Failure,
+        failure::v1::{failure::FailureInfo, ApplicationFailureInfo, Failure},
         history::v1::{history_event, History, TimerFiredEventAttributes},
         workflowservice::v1::{
             GetWorkflowExecutionHistoryResponse, RespondWorkflowTaskCompletedResponse,
@@ -1262,6 +1263,238 @@ async fn buffered_work_drained_on_shutdown() {
     });
 }
 
+#[tokio::test]
+async fn nondeterminism_eviction_includes_details() {
+    let t = canned_histories::long_sequential_timers(1);
+    let mut mh = MockPollCfg::from_resp_batches(
+        "fake_wf_id",
+        t,
+        [ResponseType::AllHistory],
+        mock_workflow_client(),
+    );
+    mh.num_expected_fails = Some(1);
+    mh.expect_fail_wft_matcher = Box::new(|_, cause, failure| {
+        let failure = failure.as_ref().unwrap();
+        let has_details = matches!(
+            &failure.failure_info,
+            Some(FailureInfo::ApplicationFailureInfo(ApplicationFailureInfo {
+                details: Some(d),
+                ..
+            })) if d.payloads.len() == 1
+        );
+        matches!(cause, WorkflowTaskFailedCause::NonDeterministicError)
+            && failure.message.contains("history has:")
+            && has_details
+    });
+    let mut mock = build_mock_pollers(mh);
+    mock.worker_cfg(|wc| {
+        wc.max_cached_workflows = 2;
+    });
+    let core = mock_worker(mock);
+
+    let act = core.poll_workflow_activation().await.unwrap();
+    // Start an activity instead of a timer, triggering nondeterminism error
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmds(
+        act.run_id.clone(),
+        vec![ScheduleActivity {
+            activity_id: "fake_activity".to_string(),
+            ..Default::default()
+        }
+        .into()],
+    ))
+    .await
+    .unwrap();
+    let evict_act = core.poll_workflow_activation().await.unwrap();
+    let details = assert_matches!(
+        evict_act.jobs.as_slice(),
+        [WorkflowActivationJob {
+            variant: Some(workflow_activation_job::Variant::RemoveFromCache(RemoveFromCache {
+                reason,
+                nondeterminism_details: Some(details),
+                ..
+            })),
+        }] if *reason == EvictionReason::Nondeterminism as i32 => details
+    );
+    assert_eq!(
+        details.event.as_ref().unwrap().event_type(),
+        EventType::TimerStarted
+    );
+    assert!(details.command.contains("ScheduleActivityTask"));
+    assert_eq!(details.machine_type, "Activity");
+    assert_eq!(details.workflow_task_number, 1);
+    assert_eq!(details.recent_jobs, vec!["StartWorkflow".to_string()]);
+    core.complete_workflow_activation(WorkflowActivationCompletion::empty(evict_act.run_id))
+        .await
+        .unwrap();
+    core.shutdown().await;
+}
+
+async fn complete_nondeterministically(core: &Worker) -> String {
+    let act = core.poll_workflow_activation().await.unwrap();
+    // Start an activity instead of a timer, triggering nondeterminism error
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmds(
+        act.run_id.clone(),
+        vec![ScheduleActivity {
+            activity_id: "fake_activity".to_string(),
+            ..Default::default()
+        }
+        .into()],
+    ))
+    .await
+    .unwrap();
+    let evict_act = core.poll_workflow_activation().await.unwrap();
+    assert_matches!(
+        evict_act.jobs.as_slice(),
+        [WorkflowActivationJob {
+            variant: Some(workflow_activation_job::Variant::RemoveFromCache(_)),
+        }]
+    );
+    core.complete_workflow_activation(WorkflowActivationCompletion::empty(&evict_act.run_id))
+        .await
+        .unwrap();
+    evict_act.run_id
+}
+
+#[tokio::test]
+async fn nondeterminism_policy_fail_workflow() {
+    let t = canned_histories::long_sequential_timers(1);
+    let mut mock_client = mock_workflow_client();
+    mock_client
+        .expect_complete_workflow_task()
+        .withf(|comp| {
+            matches!(
+                comp.commands.as_slice(),
+                [ProtoCommand {
+                    attributes: Some(command::Attributes::FailWorkflowExecutionCommandAttributes(
+                        FailWorkflowExecutionCommandAttributes {
+                            failure: Some(Failure {
+                                failure_info: Some(FailureInfo::ApplicationFailureInfo(ai)),
+                                ..
+                            }),
+                        }
+                    )),
+                    ..
+                }] if ai.r#type == "NonDeterministicError"
+            )
+        })
+        .times(1)
+        .returning(|_| Ok(Default::default()));
+    let mut mh =
+        MockPollCfg::from_resp_batches("fake_wf_id", t, [ResponseType::AllHistory], mock_client);
+    mh.num_expected_fails = Some(0);
+    let mut mock = build_mock_pollers(mh);
+    mock.worker_cfg(|wc| {
+        wc.max_cached_workflows = 2;
+        wc.nondeterminism_policy_by_type = HashMap::from([(
+            DEFAULT_WORKFLOW_TYPE.to_owned(),
+            NondeterminismPolicy::FailWorkflow,
+        )]);
+    });
+    let core = mock_worker(mock);
+
+    complete_nondeterministically(&core).await;
+    core.shutdown().await;
+}
+
+#[tokio::test]
+async fn nondeterminism_policy_fail_workflow_when_applying_poll_response() {
+    let t = canned_histories::long_sequential_timers(1);
+    let mut mock_client = mock_workflow_client();
+    let is_fail_cmd = |cmds: &[ProtoCommand]| {
+        matches!(
+            cmds,
+            [ProtoCommand {
+                attributes: Some(command::Attributes::FailWorkflowExecutionCommandAttributes(
+                    FailWorkflowExecutionCommandAttributes {
+                        failure: Some(Failure {
+                            failure_info: Some(FailureInfo::ApplicationFailureInfo(ai)),
+                            ..
+                        }),
+                    }
+                )),
+                ..
+            }] if ai.r#type == "NonDeterministicError"
+        )
+    };
+    // The first task is completed with an activity, which the next task's history contradicts
+    mock_client
+        .expect_complete_workflow_task()
+        .withf(move |comp| !is_fail_cmd(&comp.commands))
+        .times(1)
+        .returning(|_| Ok(Default::default()));
+    mock_client
+        .expect_complete_workflow_task()
+        .withf(move |comp| is_fail_cmd(&comp.commands))
+        .times(1)
+        .returning(|_| Ok(Default::default()));
+    let mut mh = MockPollCfg::from_resp_batches("fake_wf_id", t, [1, 2], mock_client);
+    mh.num_expected_fails = Some(0);
+    let mut mock = build_mock_pollers(mh);
+    mock.worker_cfg(|wc| {
+        wc.max_cached_workflows = 2;
+        wc.nondeterminism_policy = NondeterminismPolicy::FailWorkflow;
+    });
+    let outstanding = mock.outstanding_task_map.clone().unwrap();
+    let core = mock_worker(mock);
+
+    let act = core.poll_workflow_activation().await.unwrap();
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmds(
+        act.run_id.clone(),
+        vec![ScheduleActivity {
+            activity_id: "fake_activity".to_string(),
+            ..Default::default()
+        }
+        .into()],
+    ))
+    .await
+    .unwrap();
+    // Completions go to the client mocked here, so the mock must be told the task is done
+    outstanding.write().clear();
+    let evict_act = core.poll_workflow_activation().await.unwrap();
+    assert_matches!(
+        evict_act.jobs.as_slice(),
+        [WorkflowActivationJob {
+            variant: Some(workflow_activation_job::Variant::RemoveFromCache(_)),
+        }]
+    );
+    core.complete_workflow_activation(WorkflowActivationCompletion::empty(evict_act.run_id))
+        .await
+        .unwrap();
+    core.shutdown().await;
+}
+
+#[tokio::test]
+async fn nondeterminism_policy_quarantine() {
+    let t = canned_histories::long_sequential_timers(1);
+    let mut mock_client = mock_workflow_client();
+    mock_client.expect_complete_workflow_task().times(0);
+    let mut mh = MockPollCfg::from_resp_batches(
+        "fake_wf_id",
+        t,
+        [ResponseType::AllHistory, ResponseType::AllHistory],
+        mock_client,
+    );
+    mh.num_expected_fails = Some(0);
+    mh.enforce_correct_number_of_polls = false;
+    let mut mock = build_mock_pollers(mh);
+    mock.worker_cfg(|wc| {
+        wc.max_cached_workflows = 2;
+        wc.nondeterminism_policy = NondeterminismPolicy::Quarantine;
+    });
+    let outstanding = mock.outstanding_task_map.clone().unwrap();
+    let core = mock_worker(mock);
+
+    complete_nondeterministically(&core).await;
+    // Simulate the server timing out the task, which makes the mock hand out the next one. It must
+    // be dropped rather than applied, leaving the mock out of work.
+    outstanding.write().clear();
+    let act = core.poll_workflow_activation().await;
+    assert_matches!(act, Err(PollWfError::TonicError(err))
+                    if err.message() == NO_MORE_WORK_ERROR_MSG);
+    assert_eq!(core.outstanding_workflow_tasks(), 0);
+    core.shutdown().await;
+}
+
 #[tokio::test]
 async fn fail_wft_then_recover() {
     let t = canned_histories::long_sequential_timers(1);
@@ -1893,3 +2126,107 @@ async fn eviction_waits_until_replay_finished() {
 
     core.shutdown().await;
 }
+
+#[tokio::test]
+async fn activations_report_history_size_and_suggest_continue_as_new() {
+    let t = canned_histories::single_timer("1");
+    let mut mh = MockPollCfg::from_resp_batches(
+        "fake_wf_id",
+        t,
+        [ResponseType::ToTaskNum(1), ResponseType::AllHistory],
+        mock_workflow_client(),
+    );
+    mh.num_expected_fails = Some(0);
+    let mut mock = build_mock_pollers(mh);
+    mock.worker_cfg(|wc| {
+        wc.max_cached_workflows = 1;
+        wc.continue_as_new_suggested_history_length = 5;
+    });
+    let core = mock_worker(mock);
+
+    let act = core.poll_workflow_activation().await.unwrap();
+    assert_eq!(act.history_length, 3);
+    assert!(act.history_size_bytes > 0);
+    assert!(!act.continue_as_new_suggested);
+    let first_size = act.history_size_bytes;
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmds(
+        act.run_id,
+        vec![start_timer_cmd(1, Duration::from_secs(1))],
+    ))
+    .await
+    .unwrap();
+
+    let act = core.poll_workflow_activation().await.unwrap();
+    assert_eq!(act.history_length, 8);
+    assert!(act.history_size_bytes > first_size);
+    assert!(act.continue_as_new_suggested);
+    core.complete_workflow_activation(WorkflowActivationCompletion::from_cmds(
+        act.run_id,
+        vec![CompleteWorkflowExecution { result: None }.into()],
+    ))
+    .await
+    .unwrap();
+    core.shutdown().await;
+}
+
+#[tokio::test]
+async fn pending_activations_polled_and_completed_in_batches() {
+    let mh = MockPollCfg::new(
+        ["wf1", "wf2"]
+            .into_iter()
+            .map(|wf_id| FakeWfResponses {
+                wf_id: wf_id.to_string(),
+                hist: canned_histories::single_timer("1"),
+                response_batches: vec![ResponseType::ToTaskNum(1)],
+            })
+            .collect(),
+        false,
+        None,
+    );
+    let mut mock = build_mock_pollers(mh);
+    mock.worker_cfg(|wc| wc.max_cached_workflows = 2);
+    let core = mock_worker(mock);
+
+    let r1 = core.poll_workflow_activation().await.unwrap();
+    let r2 = core.poll_workflow_activation().await.unwrap();
+    let results = core
+        .complete_workflow_activations(
+            [&r1, &r2]
+                .into_iter()
+                .map(|act| {
+                    WorkflowActivationCompletion::from_cmd(
+                        act.run_id.clone(),
+                        start_timer_cmd(1, Duration::from_secs(1)),
+                    )
+                })
+                .collect(),
+        )
+        .await;
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(Result::is_ok));
+
+    // Both evictions are pending, so one poll hands both out
+    core.request_workflow_eviction(&r1.run_id);
+    core.request_workflow_eviction(&r2.run_id);
+    let evictions = core.poll_workflow_activations(5).await.unwrap();
+    assert_eq!(evictions.len(), 2);
+    for act in &evictions {
+        assert_matches!(
+            act.jobs.as_slice(),
+            [WorkflowActivationJob {
+                variant: Some(workflow_activation_job::Variant::RemoveFromCache(_)),
+            }]
+        );
+    }
+    let results = core
+        .complete_workflow_activations(
+            evictions
+                .into_iter()
+                .map(|act| WorkflowActivationCompletion::empty(act.run_id))
+                .collect(),
+        )
+        .await;
+    assert!(results.iter().all(Result::is_ok));
+    assert_eq!(core.cached_workflows(), 0);
+    core.shutdown().await;
+}
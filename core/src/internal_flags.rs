@@ -0,0 +1,158 @@
+//! Internal flags allow core to change behavior which affects what commands workflows produce or
+//! how history is interpreted, without breaking workflows whose histories were recorded by older
+//! versions of core.
+//!
+//! Whenever core uses a flag while executing a new workflow task, it records that in the metadata
+//! of the task's completion, which the server stores in the `WorkflowTaskCompleted` event. While
+//! replaying, a flag is only considered in effect once such an event says it was, so replay takes
+//! the same branch the original execution did.
+
+use std::collections::BTreeSet;
+use temporal_sdk_core_protos::temporal::api::{
+    history::v1::WorkflowTaskCompletedEventAttributes, sdk::v1::WorkflowTaskCompletedMetadata,
+};
+
+/// Behaviors core gates behind flags. Values are recorded in history, so they must never be
+/// changed or reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u32)]
+pub(crate) enum CoreInternalFlags {
+    /// Activity ids and types, and child workflow ids and types, must match those in the events
+    /// recording their commands, or the workflow is considered nondeterministic
+    IdAndTypeDeterminismChecks = 1,
+    /// Timers and local activity timeouts which would extend past the run deadline are capped at
+    /// it
+    CapTimeoutsAtRunDeadline = 2,
+}
+
+impl CoreInternalFlags {
+    fn from_u32(v: u32) -> Option<Self> {
+        match v {
+            1 => Some(Self::IdAndTypeDeterminismChecks),
+            2 => Some(Self::CapTimeoutsAtRunDeadline),
+            _ => None,
+        }
+    }
+}
+
+/// Tracks which internal flags are in effect for a workflow run
+#[derive(Debug, Default)]
+pub(crate) struct InternalFlags {
+    /// Flags known to be in effect, because history recorded them or they were used while not
+    /// replaying
+    core: BTreeSet<CoreInternalFlags>,
+    /// Flags first used since the last workflow task completion recorded in history, which must be
+    /// recorded by the next one
+    core_since_last_complete: BTreeSet<CoreInternalFlags>,
+}
+
+impl InternalFlags {
+    /// Records the flags from a workflow task completed event as being in effect. Returns the first
+    /// flag this version of core does not know about as an error, since such a workflow can't be
+    /// replayed faithfully.
+    pub(crate) fn add_from_complete(
+        &mut self,
+        attrs: &WorkflowTaskCompletedEventAttributes,
+    ) -> Result<(), u32> {
+        // Anything used before this completion has now been recorded
+        self.core_since_last_complete.clear();
+        self.load_from_complete(attrs)
+    }
+
+    /// Like [Self::add_from_complete], but for completions which have not been applied yet. Used to
+    /// learn which flags were in effect while a task's commands were produced, before the event
+    /// recording their completion is reached.
+    pub(crate) fn load_from_complete(
+        &mut self,
+        attrs: &WorkflowTaskCompletedEventAttributes,
+    ) -> Result<(), u32> {
+        for &flag in attrs
+            .sdk_metadata
+            .iter()
+            .flat_map(|md| md.core_used_flags.iter())
+        {
+            self.core
+                .insert(CoreInternalFlags::from_u32(flag).ok_or(flag)?);
+        }
+        Ok(())
+    }
+
+    /// Returns true if core should take the branch gated by `flag`. When not replaying
+    /// (`should_record` is true) flags are always used, and recorded if they were not already.
+    /// Otherwise a flag is only used if history says it was in effect.
+    pub(crate) fn try_use(&mut self, flag: CoreInternalFlags, should_record: bool) -> bool {
+        if should_record {
+            if self.core.insert(flag) {
+                self.core_since_last_complete.insert(flag);
+            }
+            true
+        } else {
+            self.core.contains(&flag)
+        }
+    }
+
+    /// Returns the metadata that should be sent when completing the current workflow task
+    pub(crate) fn gather_for_wft_complete(&self) -> WorkflowTaskCompletedMetadata {
+        WorkflowTaskCompletedMetadata {
+            core_used_flags: self
+                .core_since_last_complete
+                .iter()
+                .map(|f| *f as u32)
+                .collect(),
+            lang_used_flags: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completed_with(flags: Vec<u32>) -> WorkflowTaskCompletedEventAttributes {
+        WorkflowTaskCompletedEventAttributes {
+            sdk_metadata: Some(WorkflowTaskCompletedMetadata {
+                core_used_flags: flags,
+                lang_used_flags: vec![],
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn replay_only_uses_recorded_flags() {
+        let mut f = InternalFlags::default();
+        assert!(!f.try_use(CoreInternalFlags::IdAndTypeDeterminismChecks, false));
+        f.add_from_complete(&completed_with(vec![1])).unwrap();
+        assert!(f.try_use(CoreInternalFlags::IdAndTypeDeterminismChecks, false));
+        // Already recorded, so nothing new to send
+        assert!(f.try_use(CoreInternalFlags::IdAndTypeDeterminismChecks, true));
+        assert!(f.gather_for_wft_complete().core_used_flags.is_empty());
+    }
+
+    #[test]
+    fn new_uses_recorded_until_completion_seen() {
+        let mut f = InternalFlags::default();
+        assert!(f.try_use(CoreInternalFlags::IdAndTypeDeterminismChecks, true));
+        assert_eq!(f.gather_for_wft_complete().core_used_flags, vec![1]);
+        f.add_from_complete(&completed_with(vec![1])).unwrap();
+        assert!(f.gather_for_wft_complete().core_used_flags.is_empty());
+    }
+
+    #[test]
+    fn loading_ahead_does_not_forget_unrecorded_flags() {
+        let mut f = InternalFlags::default();
+        assert!(f.try_use(CoreInternalFlags::IdAndTypeDeterminismChecks, true));
+        f.load_from_complete(&completed_with(vec![2])).unwrap();
+        assert!(f.try_use(CoreInternalFlags::CapTimeoutsAtRunDeadline, false));
+        assert_eq!(f.gather_for_wft_complete().core_used_flags, vec![1]);
+    }
+
+    #[test]
+    fn unknown_flags_are_errors() {
+        let mut f = InternalFlags::default();
+        assert_eq!(
+            f.add_from_complete(&completed_with(vec![1, 9000])),
+            Err(9000)
+        );
+    }
+}
@@ -11,6 +11,7 @@ pub extern crate assert_matches;
 extern crate tracing;
 
 mod abstractions;
+mod internal_flags;
 mod log_export;
 mod pending_activations;
 mod pollers;
@@ -18,6 +19,7 @@ mod protosext;
 pub mod replay;
 pub(crate) mod retry_logic;
 pub(crate) mod telemetry;
+pub mod test_env;
 mod worker;
 mod workflow;
 
@@ -40,10 +42,10 @@ pub use temporal_sdk_core_api as api;
 pub use temporal_sdk_core_protos as protos;
 pub use temporal_sdk_core_protos::TaskToken;
 pub use url::Url;
-pub use worker::{Worker, WorkerConfig, WorkerConfigBuilder};
+pub use worker::{FileHeartbeatCheckpointStore, Worker, WorkerConfig, WorkerConfigBuilder};
 
 use crate::{
-    replay::mock_client_from_history,
+    replay::{client_for_history, replay_worker},
     telemetry::metrics::{MetricsContext, METRIC_METER},
     worker::client::WorkerClientBag,
 };
@@ -86,14 +88,15 @@ where
         worker_config.namespace.clone(),
     ));
     let sticky_q = sticky_q_name_for_worker(&c_opts.identity, &worker_config);
+    let session_q = session_q_name_for_worker(&c_opts.identity, &worker_config);
     let metrics = MetricsContext::top_level(worker_config.namespace.clone())
         .with_task_q(worker_config.task_queue.clone());
-    Worker::new(worker_config, sticky_q, client_bag, metrics)
+    Worker::new(worker_config, sticky_q, session_q, client_bag, metrics)
 }
 
 /// Create a worker for replaying a specific history. It will auto-shutdown as soon as the history
-/// has finished being replayed. The provided client should be a mock, and this should only be used
-/// for workflow testing purposes.
+/// has finished being replayed, or failed to replay. This should only be used for workflow testing
+/// purposes.
 pub fn init_replay_worker(
     mut config: WorkerConfig,
     history: &History,
@@ -104,14 +107,9 @@ pub fn init_replay_worker(
     );
     config.max_cached_workflows = 1;
     config.max_concurrent_wft_polls = 1;
-    config.no_remote_activities = true;
-    // Could possibly just use mocked pollers here?
-    let client = mock_client_from_history(history, &config.task_queue);
-    let run_id = history.extract_run_id_from_start()?.to_string();
-    let last_event = history.last_event_id();
-    let mut worker = Worker::new(config, None, Arc::new(client), MetricsContext::default());
-    worker.set_shutdown_on_run_reaches_event(run_id, last_event);
-    Ok(worker)
+    // Make sure the history is replayable before handing it to the worker
+    history.extract_run_id_from_start()?;
+    Ok(replay_worker(config, client_for_history(history)))
 }
 
 pub(crate) fn sticky_q_name_for_worker(
@@ -127,3 +125,17 @@ pub(crate) fn sticky_q_name_for_worker(
         None
     }
 }
+
+pub(crate) fn session_q_name_for_worker(
+    process_identity: &str,
+    config: &WorkerConfig,
+) -> Option<String> {
+    if config.max_concurrent_sessions > 0 && !config.no_remote_activities {
+        Some(format!(
+            "{}-{}-session-{}",
+            &process_identity, &config.task_queue, *PROCCESS_UNIQ_ID
+        ))
+    } else {
+        None
+    }
+}
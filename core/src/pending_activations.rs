@@ -2,7 +2,7 @@ use parking_lot::RwLock;
 use slotmap::SlotMap;
 use std::collections::{HashMap, VecDeque};
 use temporal_sdk_core_protos::coresdk::workflow_activation::{
-    remove_from_cache::EvictionReason, RemoveFromCache,
+    remove_from_cache::EvictionReason, NondeterminismDetails, RemoveFromCache,
 };
 
 /// Tracks pending activations using an internal queue, while also allowing lookup and removal of
@@ -22,6 +22,23 @@ struct PaInner {
     queue: VecDeque<ActivationKey>,
 }
 
+/// Why a run is being evicted
+#[derive(Debug, Clone)]
+pub struct EvictionCause {
+    pub reason: EvictionReason,
+    /// Describes where the workflow diverged from history, if that's why it is being evicted
+    pub nondeterminism: Option<NondeterminismDetails>,
+}
+
+impl From<EvictionReason> for EvictionCause {
+    fn from(reason: EvictionReason) -> Self {
+        Self {
+            reason,
+            nondeterminism: None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PendingActInfo {
     pub needs_eviction: Option<RemoveFromCache>,
@@ -43,12 +60,13 @@ impl PendingActivations {
         };
     }
 
-    pub fn notify_needs_eviction(&self, run_id: &str, message: String, reason: EvictionReason) {
+    pub fn notify_needs_eviction(&self, run_id: &str, message: String, cause: EvictionCause) {
         let mut inner = self.inner.write();
 
         let evictjob = RemoveFromCache {
             message,
-            reason: reason as i32,
+            reason: cause.reason as i32,
+            nondeterminism_details: cause.nondeterminism,
         };
 
         if let Some(key) = inner.by_run_id.get(run_id).copied() {
@@ -129,8 +147,16 @@ mod tests {
         let rid1 = "1";
         let rid2 = "2";
         pas.notify_needs_activation(rid1);
-        pas.notify_needs_eviction(rid1, "whatever".to_string(), EvictionReason::Unspecified);
-        pas.notify_needs_eviction(rid2, "whatever".to_string(), EvictionReason::Unspecified);
+        pas.notify_needs_eviction(
+            rid1,
+            "whatever".to_string(),
+            EvictionReason::Unspecified.into(),
+        );
+        pas.notify_needs_eviction(
+            rid2,
+            "whatever".to_string(),
+            EvictionReason::Unspecified.into(),
+        );
         pas.notify_needs_activation(rid2);
         assert!(pas.has_pending(rid1));
         assert!(pas.has_pending(rid2));
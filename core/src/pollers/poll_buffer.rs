@@ -1,8 +1,9 @@
 use crate::{
     pollers::{self, Poller},
+    telemetry::metrics::MetricsContext,
     worker::client::WorkerClientBag,
 };
-use futures::{prelude::stream::FuturesUnordered, StreamExt};
+use futures::{prelude::stream::FuturesUnordered, FutureExt, StreamExt};
 use std::{
     fmt::Debug,
     future::Future,
@@ -107,6 +108,23 @@ where
     pub fn set_num_pollers_handler(&mut self, handler: impl Fn(usize) + Send + Sync + 'static) {
         self.num_pollers_changed = Some(Box::new(handler));
     }
+
+    /// Allow one more poll of the server, whose result is taken with [Self::take_polled]
+    fn request_poll(&self) {
+        self.polls_requested.add_permits(1);
+        if let Some(fun) = self.num_pollers_changed.as_ref() {
+            fun(self.active_pollers.load(Ordering::Relaxed));
+        }
+    }
+
+    /// Wait for the result of a requested poll. Returns `None` if the buffer has been shut down.
+    async fn take_polled(&self) -> Option<pollers::Result<T>> {
+        let res = self.buffered_polls.lock().await.recv().await;
+        if let Some(fun) = self.num_pollers_changed.as_ref() {
+            fun(self.active_pollers.load(Ordering::Relaxed));
+        }
+        res
+    }
 }
 
 #[async_trait::async_trait]
@@ -126,28 +144,145 @@ where
     /// Returns `None` if the poll buffer has been shut down
     #[instrument(name = "long_poll", level = "trace", skip(self))]
     async fn poll(&self) -> Option<pollers::Result<T>> {
-        self.polls_requested.add_permits(1);
-        if let Some(fun) = self.num_pollers_changed.as_ref() {
-            fun(self.active_pollers.load(Ordering::Relaxed));
-        }
+        self.request_poll();
+        self.take_polled().await
+    }
+
+    fn notify_shutdown(&self) {
+        let _ = self.shutdown.send(true);
+    }
 
-        let mut locked = self.buffered_polls.lock().await;
-        let res = (*locked).recv().await;
+    async fn shutdown(mut self) {
+        let _ = self.shutdown.send(true);
+        while self.join_handles.next().await.is_some() {}
+    }
 
-        if let Some(fun) = self.num_pollers_changed.as_ref() {
-            fun(self.active_pollers.load(Ordering::Relaxed));
+    async fn shutdown_box(self: Box<Self>) {
+        let this = *self;
+        this.shutdown().await;
+    }
+}
+
+/// A poller capable of polling several task queues for the same worker. Every queue is polled for
+/// each requested poll, and the first task to arrive from any of them is returned, so idle queues
+/// never hold up busy ones. A queue has at most as many server polls outstanding as there are
+/// requested polls waiting. When several queues have tasks ready, they take turns in proportion
+/// to their number of pollers.
+pub struct MultiQueuePoller<T> {
+    queues: Vec<QueuePoller<T>>,
+    /// For each turn, indexes into `queues` in the order they are preferred when ready
+    turn_orders: Vec<Vec<usize>>,
+    next_turn: AtomicUsize,
+    /// Requested polls which are waiting for a task
+    waiting: AtomicUsize,
+}
+
+struct QueuePoller<T> {
+    buffer: LongPollBuffer<T>,
+    /// Tagged with the queue
+    metrics: MetricsContext,
+    /// Server polls requested whose results have not been taken yet
+    outstanding: AtomicUsize,
+}
+
+impl<T> MultiQueuePoller<T> {
+    pub fn new(pollers: Vec<(LongPollBuffer<T>, MetricsContext)>) -> Self {
+        assert!(!pollers.is_empty(), "Must poll at least one task queue");
+        // Interleave the turns, ex: pollers counts of [3, 1] take turns as [0, 1, 0, 0]
+        let num_pollers: Vec<_> = pollers.iter().map(|(p, _)| p.join_handles.len()).collect();
+        let turns: Vec<_> = (0..num_pollers.iter().copied().max().unwrap_or_default())
+            .flat_map(|round| {
+                num_pollers
+                    .iter()
+                    .enumerate()
+                    .filter(move |(_, n)| **n > round)
+                    .map(|(i, _)| i)
+            })
+            .collect();
+        // Each turn prefers queues in the order their next turns come up
+        let turn_orders = (0..turns.len())
+            .map(|turn| {
+                let mut order = Vec::with_capacity(pollers.len());
+                for offset in 0..turns.len() {
+                    let queue = turns[(turn + offset) % turns.len()];
+                    if !order.contains(&queue) {
+                        order.push(queue);
+                    }
+                }
+                order
+            })
+            .collect();
+        Self {
+            queues: pollers
+                .into_iter()
+                .map(|(buffer, metrics)| QueuePoller {
+                    buffer,
+                    metrics,
+                    outstanding: AtomicUsize::new(0),
+                })
+                .collect(),
+            turn_orders,
+            next_turn: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Poller<T> for MultiQueuePoller<T>
+where
+    T: Send + Sync + Debug + Default + PartialEq + 'static,
+{
+    async fn poll(&self) -> Option<pollers::Result<T>> {
+        let _waiting = ActiveCounter::new(&self.waiting);
+        let waiting = self.waiting.load(Ordering::Relaxed);
+        // Make sure every queue is being polled on behalf of each waiting poll. Results of polls
+        // which are still outstanding when a task arrives elsewhere are kept for later polls.
+        for queue in &self.queues {
+            let mut outstanding = queue.outstanding.load(Ordering::Relaxed);
+            while outstanding < waiting {
+                match queue.outstanding.compare_exchange(
+                    outstanding,
+                    outstanding + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        queue.buffer.request_poll();
+                        outstanding += 1;
+                    }
+                    Err(actual) => outstanding = actual,
+                }
+            }
         }
 
+        // Results are checked in order, so this turn's queue is taken first if it is ready
+        let turn = self.next_turn.fetch_add(1, Ordering::Relaxed) % self.turn_orders.len();
+        let order = &self.turn_orders[turn];
+        let (res, ix, _) = futures::future::select_all(
+            order
+                .iter()
+                .map(|&queue| self.queues[queue].buffer.take_polled().boxed()),
+        )
+        .await;
+        let queue = &self.queues[order[ix]];
+        queue.outstanding.fetch_sub(1, Ordering::Relaxed);
+        if matches!(&res, Some(Ok(r)) if r != &T::default()) {
+            queue.metrics.task_queue_task_received();
+        }
         res
     }
 
     fn notify_shutdown(&self) {
-        let _ = self.shutdown.send(true);
+        for queue in &self.queues {
+            queue.buffer.notify_shutdown();
+        }
     }
 
-    async fn shutdown(mut self) {
-        let _ = self.shutdown.send(true);
-        while self.join_handles.next().await.is_some() {}
+    async fn shutdown(self) {
+        for queue in self.queues {
+            queue.buffer.shutdown().await;
+        }
     }
 
     async fn shutdown_box(self: Box<Self>) {
@@ -157,9 +292,10 @@ where
 }
 
 /// A poller capable of polling on a sticky and a nonsticky queue simultaneously for workflow tasks.
+/// The nonsticky side may span several task queues.
 #[derive(derive_more::Constructor)]
 pub struct WorkflowTaskPoller {
-    normal_poller: PollWorkflowTaskBuffer,
+    normal_poller: MultiQueuePoller<PollWorkflowTaskQueueResponse>,
     sticky_poller: Option<PollWorkflowTaskBuffer>,
 }
 
@@ -237,9 +373,9 @@ pub(crate) fn new_activity_task_buffer(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::worker::client::mocks::mock_manual_workflow_client;
+    use crate::worker::client::mocks::{mock_manual_workflow_client, mock_workflow_client};
     use futures::FutureExt;
-    use std::time::Duration;
+    use std::{collections::HashMap, time::Duration};
     use tokio::{select, sync::mpsc::channel};
 
     #[tokio::test]
@@ -288,4 +424,91 @@ mod tests {
         pb.poll().await.unwrap().unwrap();
         pb.shutdown().await;
     }
+
+    fn multi_queue_poller(
+        client: &Arc<WorkerClientBag>,
+        queues: &[(&str, usize)],
+    ) -> MultiQueuePoller<PollActivityTaskQueueResponse> {
+        MultiQueuePoller::new(
+            queues
+                .iter()
+                .map(|(tq, pollers)| {
+                    (
+                        new_activity_task_buffer(client.clone(), tq.to_string(), *pollers, 1, None),
+                        MetricsContext::default(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn multi_queue_poller_prefers_queues_by_turn() {
+        let server_polls = Arc::new(AtomicUsize::new(0));
+        let sp = server_polls.clone();
+        let mut mock_client = mock_workflow_client();
+        mock_client
+            .expect_poll_activity_task()
+            .returning(move |tq, _| {
+                sp.fetch_add(1, Ordering::SeqCst);
+                Ok(PollActivityTaskQueueResponse {
+                    activity_id: tq,
+                    ..Default::default()
+                })
+            });
+        let client: Arc<WorkerClientBag> = Arc::new(mock_client.into());
+        let poller = multi_queue_poller(&client, &[("q1", 3), ("q2", 1)]);
+        // Turns go [q1, q2, q1, q1], and each prefers the queues in the order their turns follow
+        assert_eq!(
+            poller.turn_orders,
+            vec![vec![0, 1], vec![1, 0], vec![0, 1], vec![0, 1]]
+        );
+
+        let mut seen = HashMap::<String, usize>::new();
+        for _ in 1..=8 {
+            let resp = poller.poll().await.unwrap().unwrap();
+            *seen.entry(resp.activity_id).or_default() += 1;
+        }
+        assert!(seen["q1"] > 0 && seen["q2"] > 0);
+        poller.shutdown().await;
+        // Each queue only had one poll outstanding at a time for the single waiting poll
+        assert!(server_polls.load(Ordering::SeqCst) <= 8 + 2);
+    }
+
+    #[tokio::test]
+    async fn multi_queue_poller_does_not_wait_on_idle_queues() {
+        let idle_polls = Arc::new(AtomicUsize::new(0));
+        let ip = idle_polls.clone();
+        let mut mock_client = mock_manual_workflow_client();
+        mock_client
+            .expect_poll_activity_task()
+            .returning(move |tq, _| {
+                if tq == "idle" {
+                    ip.fetch_add(1, Ordering::SeqCst);
+                    return futures::future::pending().boxed();
+                }
+                async move {
+                    Ok(PollActivityTaskQueueResponse {
+                        activity_id: tq,
+                        ..Default::default()
+                    })
+                }
+                .boxed()
+            });
+        let client: Arc<WorkerClientBag> = Arc::new(mock_client.into());
+        // The idle queue has most of the turns
+        let poller = multi_queue_poller(&client, &[("idle", 3), ("busy", 1)]);
+
+        for _ in 0..10 {
+            let resp = tokio::time::timeout(Duration::from_secs(1), poller.poll())
+                .await
+                .expect("Busy queue's tasks must not wait on the idle queue")
+                .unwrap()
+                .unwrap();
+            assert_eq!(resp.activity_id, "busy");
+        }
+        // The idle queue's one poll stays outstanding rather than being issued again each time
+        assert_eq!(idle_polls.load(Ordering::SeqCst), 1);
+        poller.shutdown().await;
+    }
 }
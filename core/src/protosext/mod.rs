@@ -290,6 +290,22 @@ impl LACloseTimeouts {
             LACloseTimeouts::Both { sched, start } => (Some(sched), Some(start)),
         }
     }
+
+    /// Caps schedule-to-close (setting it, if only start-to-close was set) and start-to-close at
+    /// `cap`. Returns true if either of the original timeouts was longer than `cap`.
+    pub fn cap_at(&mut self, cap: Duration) -> bool {
+        let (sched, start) = self.into_sched_and_start();
+        let exceeded = sched.into_iter().chain(start).any(|t| t > cap);
+        let sched = sched.map_or(cap, |s| s.min(cap));
+        *self = match start {
+            Some(start) => LACloseTimeouts::Both {
+                sched,
+                start: start.min(cap),
+            },
+            None => LACloseTimeouts::ScheduleOnly(sched),
+        };
+        exceeded
+    }
 }
 
 #[cfg(test)]
@@ -389,3 +405,48 @@ impl Display for ValidScheduleLA {
         write!(f, "ValidScheduleLA({}, {})", self.seq, self.activity_type)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_at_caps_both_timeouts() {
+        let mut t = LACloseTimeouts::Both {
+            sched: Duration::from_secs(60),
+            start: Duration::from_secs(30),
+        };
+        assert!(t.cap_at(Duration::from_secs(10)));
+        assert_eq!(
+            t.into_sched_and_start(),
+            (Some(Duration::from_secs(10)), Some(Duration::from_secs(10)))
+        );
+    }
+
+    #[test]
+    fn cap_at_leaves_shorter_timeouts_alone() {
+        let mut t = LACloseTimeouts::ScheduleOnly(Duration::from_secs(5));
+        assert!(!t.cap_at(Duration::from_secs(10)));
+        assert_eq!(
+            t.into_sched_and_start(),
+            (Some(Duration::from_secs(5)), None)
+        );
+    }
+
+    #[test]
+    fn cap_at_sets_schedule_to_close_when_only_start_set() {
+        let mut t = LACloseTimeouts::StartOnly(Duration::from_secs(5));
+        assert!(!t.cap_at(Duration::from_secs(10)));
+        assert_eq!(
+            t.into_sched_and_start(),
+            (Some(Duration::from_secs(10)), Some(Duration::from_secs(5)))
+        );
+
+        let mut t = LACloseTimeouts::StartOnly(Duration::from_secs(60));
+        assert!(t.cap_at(Duration::from_secs(10)));
+        assert_eq!(
+            t.into_sched_and_start(),
+            (Some(Duration::from_secs(10)), Some(Duration::from_secs(10)))
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! A client which serves canned histories to a replay worker in place of the server
+
+use super::{HistoryForReplay, ReplayOutcome, ReplayReport, WorkflowReplayResult};
+use crate::worker::client::WorkerClient;
+use futures::{
+    future,
+    stream::{BoxStream, Peekable},
+    StreamExt,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use temporal_client::WorkflowTaskCompletion;
+use temporal_sdk_core_protos::{
+    coresdk::workflow_commands::QueryResult,
+    temporal::api::{
+        command::v1::Command,
+        common::v1::{Payloads, WorkflowExecution},
+        enums::v1::WorkflowTaskFailedCause,
+        failure::v1::Failure,
+        history::v1::{History, HistoryEvent},
+        workflowservice::v1::*,
+    },
+    HistoryInfo, TaskToken,
+};
+use tokio::sync::Notify;
+
+type Result<T, E = tonic::Status> = std::result::Result<T, E>;
+
+/// How many events are included in each page of history served to the worker
+const DEFAULT_HISTORY_PAGE_SIZE: usize = 256;
+
+/// Serves each history from a stream in its own workflow task, paginating them like the server
+/// would, and keeps track of what the worker reported back about each run.
+///
+/// Once the stream is exhausted, workflow task polls never resolve. The replay worker shuts itself
+/// down when every run has been resolved, which interrupts them.
+#[derive(Clone)]
+pub(crate) struct ReplayWorkerClient {
+    state: Arc<ReplayClientState>,
+}
+
+type HistoryStream = Peekable<BoxStream<'static, HistoryForReplay>>;
+
+struct ReplayClientState {
+    /// The stream of histories to replay. Taken out while a history is awaited from it, so no lock
+    /// is held across the wait, and put back by [TakenHistories] when done.
+    histories: Mutex<Option<HistoryStream>>,
+    /// Notified whenever the history stream is put back
+    histories_returned: Notify,
+    page_size: usize,
+    /// Set once the last history has been handed to the worker
+    all_delivered: AtomicBool,
+    runs: Mutex<ReplayRuns>,
+}
+
+#[derive(Default)]
+struct ReplayRuns {
+    by_run_id: HashMap<String, ReplayRun>,
+    /// Run ids in the order they were handed to the worker
+    order: Vec<String>,
+    task_tokens: HashMap<TaskToken, String>,
+}
+
+struct ReplayRun {
+    workflow_id: String,
+    last_event: i64,
+    /// All events of the run's history, from which pages are served. Dropped once the run is
+    /// resolved.
+    events: Arc<Vec<HistoryEvent>>,
+    /// How many pages of history the worker fetched beyond the one in the workflow task
+    pages_fetched: usize,
+    commands: Vec<Command>,
+    outcome: Option<ReplayOutcome>,
+}
+
+impl ReplayWorkerClient {
+    pub(crate) fn new(histories: BoxStream<'static, HistoryForReplay>) -> Self {
+        Self {
+            state: Arc::new(ReplayClientState {
+                histories: Mutex::new(Some(histories.peekable())),
+                histories_returned: Notify::new(),
+                page_size: DEFAULT_HISTORY_PAGE_SIZE,
+                all_delivered: AtomicBool::new(false),
+                runs: Default::default(),
+            }),
+        }
+    }
+
+    /// Override how many events are served per page of history. Must be called before the client
+    /// is shared.
+    #[cfg(test)]
+    pub(crate) fn with_page_size(mut self, page_size: usize) -> Self {
+        Arc::get_mut(&mut self.state)
+            .expect("Page size must be set before the replay client is shared")
+            .page_size = page_size;
+        self
+    }
+
+    /// Returns true if the history stream has nothing left in it. Waits for the next history to
+    /// become available if need be.
+    pub(crate) async fn histories_exhausted(&self) -> bool {
+        let mut histories = self.take_histories().await;
+        let exhausted = Pin::new(&mut *histories).peek().await.is_none();
+        if exhausted {
+            self.state.all_delivered.store(true, Ordering::Release);
+        }
+        exhausted
+    }
+
+    /// Takes the history stream, waiting for whoever has it to put it back if need be
+    async fn take_histories(&self) -> TakenHistories<'_> {
+        loop {
+            if let Some(stream) = self.state.histories.lock().take() {
+                return TakenHistories {
+                    state: &self.state,
+                    stream: Some(stream),
+                };
+            }
+            // If the stream was put back since we looked, a permit is stored and this resolves
+            // immediately
+            self.state.histories_returned.notified().await;
+        }
+    }
+
+    /// Marks every run which has processed its whole history as successful, returning their ids
+    pub(crate) fn runs_reached_end(&self, last_processed: impl Fn(&str) -> i64) -> Vec<String> {
+        let mut runs = self.state.runs.lock();
+        runs.by_run_id
+            .iter_mut()
+            .filter(|(run_id, r)| r.outcome.is_none() && last_processed(run_id) >= r.last_event)
+            .map(|(run_id, r)| {
+                r.outcome = Some(ReplayOutcome::Success);
+                r.events = Default::default();
+                run_id.clone()
+            })
+            .collect()
+    }
+
+    /// Returns true once every history has been handed out and every run has been resolved
+    pub(crate) fn is_done(&self) -> bool {
+        self.state.all_delivered.load(Ordering::Acquire)
+            && self
+                .state
+                .runs
+                .lock()
+                .by_run_id
+                .values()
+                .all(|r| r.outcome.is_some())
+    }
+
+    /// Describes the outcome of each run handed out so far. Runs which have not been resolved are
+    /// reported as failed.
+    pub(crate) fn report(&self) -> ReplayReport {
+        let runs = self.state.runs.lock();
+        ReplayReport {
+            results: runs
+                .order
+                .iter()
+                .filter_map(|run_id| runs.by_run_id.get(run_id).map(|r| (run_id, r)))
+                .map(|(run_id, r)| WorkflowReplayResult {
+                    workflow_id: r.workflow_id.clone(),
+                    run_id: run_id.clone(),
+                    outcome: r.outcome.clone().unwrap_or_else(|| ReplayOutcome::Failed {
+                        details: "History was not fully replayed".to_string(),
+                    }),
+                    commands: r.commands.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns how many pages of history the worker fetched for the run beyond the first
+    #[cfg(test)]
+    pub(crate) fn pages_fetched(&self, run_id: &str) -> usize {
+        self.state
+            .runs
+            .lock()
+            .by_run_id
+            .get(run_id)
+            .map_or(0, |r| r.pages_fetched)
+    }
+
+    fn record_failure(&self, task_token: &TaskToken, outcome: ReplayOutcome) {
+        let mut runs = self.state.runs.lock();
+        let run_id = match runs.task_tokens.get(task_token) {
+            Some(r) => r.clone(),
+            None => return,
+        };
+        if let Some(r) = runs.by_run_id.get_mut(&run_id) {
+            r.outcome.get_or_insert(outcome);
+            r.events = Default::default();
+        }
+    }
+
+    fn page_of(&self, events: &[HistoryEvent], from: usize) -> (Vec<HistoryEvent>, Vec<u8>) {
+        let to = (from + self.state.page_size).min(events.len());
+        let next_page_token = if to < events.len() {
+            to.to_string().into_bytes()
+        } else {
+            vec![]
+        };
+        (events[from..to].to_vec(), next_page_token)
+    }
+}
+
+/// The history stream, taken out of [ReplayClientState]. Puts it back when dropped, including if
+/// the poll holding it is cancelled.
+struct TakenHistories<'a> {
+    state: &'a ReplayClientState,
+    stream: Option<HistoryStream>,
+}
+
+impl Deref for TakenHistories<'_> {
+    type Target = HistoryStream;
+
+    fn deref(&self) -> &Self::Target {
+        self.stream
+            .as_ref()
+            .expect("Stream is only removed on drop")
+    }
+}
+
+impl DerefMut for TakenHistories<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.stream
+            .as_mut()
+            .expect("Stream is only removed on drop")
+    }
+}
+
+impl Drop for TakenHistories<'_> {
+    fn drop(&mut self) {
+        *self.state.histories.lock() = self.stream.take();
+        self.state.histories_returned.notify_one();
+    }
+}
+
+#[async_trait::async_trait]
+impl WorkerClient for ReplayWorkerClient {
+    async fn poll_workflow_task(
+        &self,
+        task_queue: String,
+        _is_sticky: bool,
+    ) -> Result<PollWorkflowTaskQueueResponse> {
+        loop {
+            let next = self.take_histories().await.next().await;
+            let next = match next {
+                Some(h) => h,
+                None => {
+                    self.state.all_delivered.store(true, Ordering::Release);
+                    future::pending::<()>().await;
+                    unreachable!("Pending future never resolves")
+                }
+            };
+            let hist_info = match HistoryInfo::new_from_history(&next.hist, None) {
+                Ok(hi) => hi,
+                Err(e) => {
+                    error!(workflow_id = %next.workflow_id, error = ?e,
+                           "History cannot be replayed, skipping it");
+                    let run_id = next
+                        .hist
+                        .extract_run_id_from_start()
+                        .unwrap_or_default()
+                        .to_string();
+                    let mut runs = self.state.runs.lock();
+                    runs.order.push(run_id.clone());
+                    runs.by_run_id.insert(
+                        run_id,
+                        ReplayRun {
+                            workflow_id: next.workflow_id,
+                            last_event: 0,
+                            events: Default::default(),
+                            pages_fetched: 0,
+                            commands: vec![],
+                            outcome: Some(ReplayOutcome::Failed {
+                                details: format!("History cannot be replayed: {}", e),
+                            }),
+                        },
+                    );
+                    continue;
+                }
+            };
+            let run_id = hist_info.orig_run_id().to_string();
+            let events = Arc::new(hist_info.events().to_vec());
+            let (first_page, next_page_token) = self.page_of(&events, 0);
+            let mut resp = hist_info.as_poll_wft_response(task_queue);
+            resp.history = Some(History { events: first_page });
+            resp.next_page_token = next_page_token;
+            resp.workflow_execution = Some(WorkflowExecution {
+                workflow_id: next.workflow_id.clone(),
+                run_id: run_id.clone(),
+            });
+            {
+                let mut runs = self.state.runs.lock();
+                runs.task_tokens
+                    .insert(TaskToken(resp.task_token.clone()), run_id.clone());
+                runs.order.push(run_id.clone());
+                runs.by_run_id.insert(
+                    run_id,
+                    ReplayRun {
+                        workflow_id: next.workflow_id,
+                        last_event: next.hist.last_event_id(),
+                        events,
+                        pages_fetched: 0,
+                        commands: vec![],
+                        outcome: None,
+                    },
+                );
+            }
+            // Learn if that was the last history now, so the worker can shut down as soon as it
+            // has been replayed
+            self.histories_exhausted().await;
+            return Ok(resp);
+        }
+    }
+
+    async fn poll_activity_task(
+        &self,
+        _task_queue: String,
+        _max_tasks_per_sec: Option<f64>,
+    ) -> Result<PollActivityTaskQueueResponse> {
+        // Replay workers never run remote activities
+        future::pending().await
+    }
+
+    async fn complete_workflow_task(
+        &self,
+        request: WorkflowTaskCompletion,
+    ) -> Result<RespondWorkflowTaskCompletedResponse> {
+        let mut runs = self.state.runs.lock();
+        if let Some(run_id) = runs.task_tokens.get(&request.task_token).cloned() {
+            if let Some(r) = runs.by_run_id.get_mut(&run_id) {
+                r.commands.extend(request.commands);
+            }
+        }
+        Ok(RespondWorkflowTaskCompletedResponse::default())
+    }
+
+    async fn complete_activity_task(
+        &self,
+        _task_token: TaskToken,
+        _result: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCompletedResponse> {
+        Ok(RespondActivityTaskCompletedResponse::default())
+    }
+
+    async fn record_activity_heartbeat(
+        &self,
+        _task_token: TaskToken,
+        _details: Option<Payloads>,
+    ) -> Result<RecordActivityTaskHeartbeatResponse> {
+        Ok(RecordActivityTaskHeartbeatResponse::default())
+    }
+
+    async fn cancel_activity_task(
+        &self,
+        _task_token: TaskToken,
+        _details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCanceledResponse> {
+        Ok(RespondActivityTaskCanceledResponse::default())
+    }
+
+    async fn fail_activity_task(
+        &self,
+        _task_token: TaskToken,
+        _failure: Option<Failure>,
+    ) -> Result<RespondActivityTaskFailedResponse> {
+        Ok(RespondActivityTaskFailedResponse::default())
+    }
+
+    async fn fail_workflow_task(
+        &self,
+        task_token: TaskToken,
+        cause: WorkflowTaskFailedCause,
+        failure: Option<Failure>,
+    ) -> Result<RespondWorkflowTaskFailedResponse> {
+        let details = failure.map(|f| f.message).unwrap_or_default();
+        let outcome = if cause == WorkflowTaskFailedCause::NonDeterministicError {
+            ReplayOutcome::Nondeterminism { details }
+        } else {
+            ReplayOutcome::Failed { details }
+        };
+        self.record_failure(&task_token, outcome);
+        Ok(RespondWorkflowTaskFailedResponse::default())
+    }
+
+    async fn get_workflow_execution_history(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        page_token: Vec<u8>,
+    ) -> Result<GetWorkflowExecutionHistoryResponse> {
+        let events = run_id
+            .as_ref()
+            .and_then(|rid| {
+                self.state.runs.lock().by_run_id.get_mut(rid).map(|r| {
+                    r.pages_fetched += 1;
+                    r.events.clone()
+                })
+            })
+            .ok_or_else(|| {
+                tonic::Status::not_found(format!(
+                    "No history is being replayed for workflow {} run {:?}",
+                    workflow_id, run_id
+                ))
+            })?;
+        let from = if page_token.is_empty() {
+            0
+        } else {
+            std::str::from_utf8(&page_token)
+                .ok()
+                .and_then(|t| t.parse::<usize>().ok())
+                .filter(|from| *from <= events.len())
+                .ok_or_else(|| tonic::Status::invalid_argument("Invalid history page token"))?
+        };
+        let (page, next_page_token) = self.page_of(&events, from);
+        Ok(GetWorkflowExecutionHistoryResponse {
+            history: Some(History { events: page }),
+            next_page_token,
+            ..Default::default()
+        })
+    }
+
+    async fn respond_legacy_query(
+        &self,
+        _task_token: TaskToken,
+        _query_result: QueryResult,
+    ) -> Result<RespondQueryTaskCompletedResponse> {
+        Ok(RespondQueryTaskCompletedResponse::default())
+    }
+}
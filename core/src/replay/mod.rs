@@ -2,70 +2,258 @@
 //! to replay canned histories. It should be used by Lang SDKs to provide replay capabilities to
 //! users during testing.
 
-use crate::{worker::client::mocks::mock_manual_workflow_client, WorkerClientBag};
-use futures::FutureExt;
-use std::{
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    time::Duration,
-};
-use temporal_sdk_core_protos::temporal::api::{
-    common::v1::WorkflowExecution,
-    history::v1::History,
-    workflowservice::v1::{
-        RespondWorkflowTaskCompletedResponse, RespondWorkflowTaskFailedResponse,
-    },
+mod client;
+
+pub(crate) use client::ReplayWorkerClient;
+
+use crate::{telemetry::metrics::MetricsContext, Worker, WorkerClientBag, WorkerConfig};
+use futures::{stream, Stream, StreamExt};
+use prost::Message;
+use std::{path::Path, sync::Arc};
+use temporal_client::WorkflowClientTrait;
+use temporal_sdk_core_api::{worker::NondeterminismPolicy, Worker as WorkerTrait};
+use temporal_sdk_core_protos::{
+    coresdk::workflow_activation::remove_from_cache::EvictionReason,
+    temporal::api::{command::v1::Command, common::v1::WorkflowExecution, history::v1::History},
 };
 pub use temporal_sdk_core_protos::{
     default_wes_attribs, HistoryInfo, TestHistoryBuilder, DEFAULT_WORKFLOW_TYPE,
 };
 
-/// Create a mock client which can be used by a replay worker to serve up canned history.
-/// It will return the entire history in one workflow task, after that it will return default
-/// responses (with a 10s wait). If a workflow task failure is sent to the mock, it will send
-/// the complete response again.
-pub(crate) fn mock_client_from_history(
-    history: &History,
-    task_queue: impl Into<String>,
-) -> WorkerClientBag {
-    let mut mg = mock_manual_workflow_client();
-
-    let hist_info = HistoryInfo::new_from_history(history, None).unwrap();
-    let wf = WorkflowExecution {
-        workflow_id: "fake_wf_id".to_string(),
-        run_id: hist_info.orig_run_id().to_string(),
-    };
-
-    let did_send = Arc::new(AtomicBool::new(false));
-    let did_send_clone = did_send.clone();
-    let tq = task_queue.into();
-    mg.expect_poll_workflow_task().returning(move |_, _| {
-        let hist_info = hist_info.clone();
-        let wf = wf.clone();
-        let did_send_clone = did_send_clone.clone();
-        let tq = tq.clone();
-        async move {
-            if !did_send_clone.swap(true, Ordering::AcqRel) {
-                let mut resp = hist_info.as_poll_wft_response(tq);
-                resp.workflow_execution = Some(wf.clone());
-                Ok(resp)
-            } else {
-                tokio::time::sleep(Duration::from_secs(10)).await;
-                Ok(Default::default())
-            }
+/// Workflow id given to histories replayed by [init_replay_worker](crate::init_replay_worker),
+/// which does not ask for one
+pub(crate) const DEFAULT_REPLAY_WORKFLOW_ID: &str = "fake_wf_id";
+
+/// A history to be replayed by a [Replayer], along with the id of the workflow it belongs to
+#[derive(Debug, Clone)]
+pub struct HistoryForReplay {
+    /// The full history of a single workflow run
+    pub hist: History,
+    /// The id of the workflow the history belongs to
+    pub workflow_id: String,
+}
+
+impl HistoryForReplay {
+    /// Create a new history for replay
+    pub fn new(hist: History, workflow_id: impl Into<String>) -> Self {
+        Self {
+            hist,
+            workflow_id: workflow_id.into(),
         }
-        .boxed()
-    });
+    }
+}
+
+/// How replaying a single history went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayOutcome {
+    /// Every event in the history was replayed without the workflow diverging from it
+    Success,
+    /// The workflow's behavior did not match its history
+    Nondeterminism {
+        /// Description of where the workflow diverged
+        details: String,
+    },
+    /// Replay failed for some other reason, ex: the workflow code failed an activation
+    Failed {
+        /// Description of the failure
+        details: String,
+    },
+}
+
+/// The outcome of replaying a single history
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkflowReplayResult {
+    /// The id of the replayed workflow
+    pub workflow_id: String,
+    /// The run id of the replayed history
+    pub run_id: String,
+    /// How replay went
+    pub outcome: ReplayOutcome,
+    /// Commands the worker sent when completing workflow tasks for the run, which may be compared
+    /// against those recorded in its history
+    pub commands: Vec<Command>,
+}
+
+/// Outcomes of every history fed to a [Replayer], in the order they were replayed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayReport {
+    /// One result per history
+    pub results: Vec<WorkflowReplayResult>,
+}
+
+impl ReplayReport {
+    /// Returns true if every history replayed successfully
+    pub fn all_succeeded(&self) -> bool {
+        self.results
+            .iter()
+            .all(|r| r.outcome == ReplayOutcome::Success)
+    }
 
-    mg.expect_complete_workflow_task()
-        .returning(|_| async move { Ok(RespondWorkflowTaskCompletedResponse::default()) }.boxed());
-    mg.expect_fail_workflow_task().returning(move |_, _, _| {
-        // We'll need to re-send the history if WFT fails
-        did_send.store(false, Ordering::Release);
-        async move { Ok(RespondWorkflowTaskFailedResponse {}) }.boxed()
+    /// Returns the results for histories which were not replayed successfully
+    pub fn failures(&self) -> impl Iterator<Item = &WorkflowReplayResult> {
+        self.results
+            .iter()
+            .filter(|r| r.outcome != ReplayOutcome::Success)
+    }
+}
+
+/// Replays many histories through a single worker, concurrently up to the worker's cache size, and
+/// reports on the outcome of each.
+///
+/// Lang drives [Replayer::worker] like any other worker, until polling returns
+/// [PollWfError::ShutDown](crate::api::errors::PollWfError::ShutDown), which happens once every
+/// history has been replayed. Afterwards [Replayer::report] describes how each one went. Histories
+/// must have distinct run ids.
+pub struct Replayer {
+    worker: Arc<Worker>,
+    client: ReplayWorkerClient,
+}
+
+impl Replayer {
+    /// Create a replayer which will replay every history produced by the provided stream. Remote
+    /// activities are disabled, and nondeterminism always fails the workflow task so it can be
+    /// reported.
+    pub async fn new<S>(config: WorkerConfig, histories: S) -> Result<Self, anyhow::Error>
+    where
+        S: Stream<Item = HistoryForReplay> + Send + 'static,
+    {
+        info!(
+            task_queue = config.task_queue.as_str(),
+            "Registering replayer"
+        );
+        let client = ReplayWorkerClient::new(histories.boxed());
+        let worker = replay_worker(config, client.clone());
+        if client.histories_exhausted().await {
+            worker.initiate_shutdown();
+        }
+        Ok(Self {
+            worker: Arc::new(worker),
+            client,
+        })
+    }
+
+    /// The worker lang should poll to perform the replay
+    pub fn worker(&self) -> Arc<Worker> {
+        self.worker.clone()
+    }
+
+    /// Describes the outcome of each history which has been handed to the worker so far. Histories
+    /// which have not finished replaying are reported as failed.
+    pub fn report(&self) -> ReplayReport {
+        self.client.report()
+    }
+}
+
+/// Create a worker which replays the histories served by the provided client, evicting each run
+/// once it has processed its whole history, and shutting down once every run has been resolved
+pub(crate) fn replay_worker(mut config: WorkerConfig, client: ReplayWorkerClient) -> Worker {
+    // Runs must stay cached for the whole of their replay, since their history is only sent
+    // once.
+    config.max_cached_workflows = config.max_cached_workflows.max(1);
+    config.no_remote_activities = true;
+    config.nondeterminism_policy = NondeterminismPolicy::FailWorkflowTask;
+    config.nondeterminism_policy_by_type.clear();
+    let namespace = config.namespace.clone();
+    let mut worker = Worker::new(
+        config,
+        None,
+        None,
+        Arc::new(WorkerClientBag::new(Box::new(client.clone()), namespace)),
+        MetricsContext::default(),
+    );
+    worker.set_post_activate_hook(move |worker| {
+        let finished =
+            client.runs_reached_end(|run_id| worker.most_recently_processed_event(run_id));
+        if client.is_done() {
+            worker.initiate_shutdown();
+            return;
+        }
+        // Make room in the cache for the histories still to come
+        for run_id in finished {
+            worker.request_wf_eviction(
+                &run_id,
+                "Replay of history complete",
+                EvictionReason::Unspecified,
+            );
+        }
     });
+    worker
+}
+
+/// Create a client serving a single history, as used by
+/// [init_replay_worker](crate::init_replay_worker)
+pub(crate) fn client_for_history(history: &History) -> ReplayWorkerClient {
+    ReplayWorkerClient::new(
+        stream::iter([HistoryForReplay::new(
+            history.clone(),
+            DEFAULT_REPLAY_WORKFLOW_ID,
+        )])
+        .boxed(),
+    )
+}
+
+/// Reads every `.bin` file in the provided directory as a protobuf serialized history, as written
+/// by `histfetch`. The workflow id is taken from the file name, minus any `_history` suffix.
+pub async fn histories_from_dir(
+    dir: impl AsRef<Path>,
+) -> Result<Vec<HistoryForReplay>, anyhow::Error> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut histories = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension() != Some("bin".as_ref()) {
+            continue;
+        }
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let wf_id = stem.strip_suffix("_history").unwrap_or(&stem).to_string();
+        let bytes = tokio::fs::read(&path).await?;
+        histories.push(HistoryForReplay::new(History::decode(&*bytes)?, wf_id));
+    }
+    Ok(histories)
+}
 
-    WorkerClientBag::new(Box::new(mg), "fake_namespace".to_string())
+/// Fetches the full history of each provided workflow execution from the server, ex: those
+/// found by a visibility query, for replay. Executions whose history cannot be fetched are logged
+/// and skipped.
+pub fn histories_from_server<C, S>(
+    client: Arc<C>,
+    executions: S,
+) -> impl Stream<Item = HistoryForReplay>
+where
+    C: WorkflowClientTrait + Send + Sync + 'static,
+    S: Stream<Item = WorkflowExecution> + Send + 'static,
+{
+    executions.filter_map(move |we| {
+        let client = client.clone();
+        async move {
+            let mut events = vec![];
+            let mut page_token = vec![];
+            loop {
+                let resp = match client
+                    .get_workflow_execution_history(
+                        we.workflow_id.clone(),
+                        Some(we.run_id.clone()).filter(|r| !r.is_empty()),
+                        page_token,
+                    )
+                    .await
+                {
+                    Ok(r) => r,
+                    Err(e) => {
+                        warn!(workflow_id = %we.workflow_id, run_id = %we.run_id, error = ?e,
+                              "Could not fetch history for replay, skipping it");
+                        return None;
+                    }
+                };
+                events.extend(resp.history.into_iter().flat_map(|h| h.events));
+                if resp.next_page_token.is_empty() {
+                    break;
+                }
+                page_token = resp.next_page_token;
+            }
+            Some(HistoryForReplay::new(History { events }, we.workflow_id))
+        }
+    })
 }
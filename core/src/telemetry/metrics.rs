@@ -9,6 +9,7 @@ use opentelemetry::{
     KeyValue,
 };
 use std::{borrow::Cow, sync::Arc, time::Duration};
+use temporal_sdk_core_protos::coresdk::workflow_activation::remove_from_cache::EvictionReason;
 
 /// Used to track context associated with metrics, and record/update them
 ///
@@ -36,6 +37,19 @@ impl MetricsContext {
         self
     }
 
+    /// Same context, but attributed to a different task queue. Used by workers which poll more
+    /// than one task queue.
+    pub(crate) fn for_task_q(&self, tq: String) -> Self {
+        let mut kvs: Vec<_> = self
+            .kvs
+            .iter()
+            .filter(|kv| kv.key.as_str() != KEY_TASK_QUEUE)
+            .cloned()
+            .collect();
+        kvs.push(task_queue(tq));
+        Self::new(kvs)
+    }
+
     /// Extend an existing metrics context with new attributes
     pub(crate) fn with_new_attrs(&self, new_kvs: impl IntoIterator<Item = KeyValue>) -> Self {
         let mut kvs = self.kvs.clone();
@@ -58,6 +72,11 @@ impl MetricsContext {
         WF_TASK_EXECUTION_FAILURE_COUNTER.add(1, &self.kvs);
     }
 
+    /// A workflow run was quarantined after behaving nondeterministically
+    pub(crate) fn wf_quarantined(&self) {
+        WF_QUARANTINED_COUNTER.add(1, &self.kvs);
+    }
+
     /// A workflow completed successfully
     pub(crate) fn wf_completed(&self) {
         WF_COMPLETED_COUNTER.add(1, &self.kvs);
@@ -83,6 +102,12 @@ impl MetricsContext {
         WF_E2E_LATENCY.record(dur.as_millis() as u64, &self.kvs);
     }
 
+    /// A workflow task was completed early, without waiting for local activities to finish, to
+    /// keep the workflow task from timing out
+    pub(crate) fn wf_task_forced_heartbeat(&self) {
+        WF_TASK_FORCED_HEARTBEAT_COUNTER.add(1, &self.kvs);
+    }
+
     /// Record workflow task schedule to start time in millis
     pub(crate) fn wf_task_sched_to_start_latency(&self, dur: Duration) {
         WF_TASK_SCHED_TO_START_LATENCY.record(dur.as_millis() as u64, &self.kvs);
@@ -108,6 +133,17 @@ impl MetricsContext {
         ACT_EXECUTION_FAILED.add(1, &self.kvs);
     }
 
+    /// A local activity was promoted to a normal activity
+    pub(crate) fn la_promoted(&self) {
+        LA_PROMOTED_COUNTER.add(1, &self.kvs);
+    }
+
+    /// A local activity was resolved with the result of an earlier execution, rather than being
+    /// executed again, after the workflow task which would have recorded it failed
+    pub(crate) fn la_result_reused(&self) {
+        LA_RESULT_REUSED_COUNTER.add(1, &self.kvs);
+    }
+
     /// Record activity task schedule to start time in millis
     pub(crate) fn act_sched_to_start_latency(&self, dur: Duration) {
         ACT_SCHED_TO_START_LATENCY.record(dur.as_millis() as u64, &self.kvs);
@@ -119,6 +155,12 @@ impl MetricsContext {
         ACT_EXEC_LATENCY.record(dur.as_millis() as u64, &self.kvs);
     }
 
+    /// Record how long a worker held back an activity (remote or local) to respect its own rate
+    /// limits, in millis
+    pub(crate) fn act_throttled_latency(&self, dur: Duration) {
+        ACT_THROTTLED_LATENCY.record(dur.as_millis() as u64, &self.kvs);
+    }
+
     /// A worker was registered
     pub(crate) fn worker_registered(&self) {
         WORKER_REGISTERED.add(1, &self.kvs);
@@ -134,6 +176,12 @@ impl MetricsContext {
         NUM_POLLERS.record(num as u64, &self.kvs);
     }
 
+    /// A poller received a (non-empty) task from its task queue. Context should include poller
+    /// type / task queue tag.
+    pub(crate) fn task_queue_task_received(&self) {
+        TASK_QUEUE_TASK_RECEIVED.add(1, &self.kvs);
+    }
+
     /// A workflow task found a cached workflow to run against
     pub(crate) fn sticky_cache_hit(&self) {
         STICKY_CACHE_HIT.add(1, &self.kvs);
@@ -148,6 +196,18 @@ impl MetricsContext {
     pub(crate) fn cache_size(&self, size: u64) {
         STICKY_CACHE_SIZE.record(size, &self.kvs);
     }
+
+    /// Record current approximate cache size in bytes
+    pub(crate) fn cache_size_bytes(&self, size: u64) {
+        STICKY_CACHE_SIZE_BYTES.record(size, &self.kvs);
+    }
+
+    /// A workflow was chosen for eviction by the cache in order to respect its limits
+    pub(crate) fn forced_cache_eviction(&self, reason: EvictionReason) {
+        let mut kvs = (*self.kvs).clone();
+        kvs.push(eviction_reason(reason));
+        STICKY_CACHE_FORCED_EVICTION.add(1, &kvs);
+    }
 }
 
 lazy_static::lazy_static! {
@@ -185,6 +245,7 @@ const KEY_TASK_QUEUE: &str = "task_queue";
 const KEY_ACT_TYPE: &str = "activity_type";
 const KEY_POLLER_TYPE: &str = "poller_type";
 const KEY_WORKER_TYPE: &str = "worker_type";
+const KEY_EVICTION_REASON: &str = "eviction_reason";
 
 pub(crate) fn workflow_poller() -> KeyValue {
     KeyValue::new(KEY_POLLER_TYPE, "workflow_task")
@@ -204,6 +265,9 @@ pub(crate) fn activity_type(ty: String) -> KeyValue {
 pub(crate) fn workflow_type(ty: String) -> KeyValue {
     KeyValue::new(KEY_WF_TYPE, ty)
 }
+pub(crate) fn eviction_reason(reason: EvictionReason) -> KeyValue {
+    KeyValue::new(KEY_EVICTION_REASON, format!("{:?}", reason))
+}
 pub(crate) const fn workflow_worker_type() -> KeyValue {
     KeyValue {
         key: opentelemetry::Key::from_static_str(KEY_WORKER_TYPE),
@@ -227,6 +291,7 @@ tm!(ctr, WF_COMPLETED_COUNTER, "workflow_completed");
 tm!(ctr, WF_CANCELED_COUNTER, "workflow_canceled");
 tm!(ctr, WF_FAILED_COUNTER, "workflow_failed");
 tm!(ctr, WF_CONT_COUNTER, "workflow_continue_as_new");
+tm!(ctr, WF_QUARANTINED_COUNTER, "workflow_quarantined");
 const WF_E2E_LATENCY_NAME: &str = "workflow_endtoend_latency";
 tm!(vr_u64, WF_E2E_LATENCY, WF_E2E_LATENCY_NAME);
 
@@ -245,6 +310,11 @@ tm!(
     WF_TASK_EXECUTION_FAILURE_COUNTER,
     "workflow_task_execution_failed"
 );
+tm!(
+    ctr,
+    WF_TASK_FORCED_HEARTBEAT_COUNTER,
+    "workflow_task_forced_heartbeat"
+);
 const WF_TASK_SCHED_TO_START_LATENCY_NAME: &str = "workflow_task_schedule_to_start_latency";
 tm!(
     vr_u64,
@@ -262,6 +332,12 @@ tm!(
 
 tm!(ctr, ACT_POLL_NO_TASK, "activity_poll_no_task");
 tm!(ctr, ACT_EXECUTION_FAILED, "activity_execution_failed");
+tm!(ctr, LA_PROMOTED_COUNTER, "local_activity_promoted");
+tm!(
+    ctr,
+    LA_RESULT_REUSED_COUNTER,
+    "local_activity_result_reused"
+);
 // Act task unregistered can't be known by core right now since it's not well defined as an
 // activity result. We could add a flag to the failed activity result if desired.
 const ACT_SCHED_TO_START_LATENCY_NAME: &str = "activity_schedule_to_start_latency";
@@ -272,11 +348,14 @@ tm!(
 );
 const ACT_EXEC_LATENCY_NAME: &str = "activity_execution_latency";
 tm!(vr_u64, ACT_EXEC_LATENCY, ACT_EXEC_LATENCY_NAME);
+const ACT_THROTTLED_LATENCY_NAME: &str = "activity_rate_limit_throttled_latency";
+tm!(vr_u64, ACT_THROTTLED_LATENCY, ACT_THROTTLED_LATENCY_NAME);
 
 // name kept as worker start for compat with old sdk / what users expect
 tm!(ctr, WORKER_REGISTERED, "worker_start");
 const NUM_POLLERS_NAME: &str = "num_pollers";
 tm!(vr_u64, NUM_POLLERS, NUM_POLLERS_NAME);
+tm!(ctr, TASK_QUEUE_TASK_RECEIVED, "task_queue_task_received");
 const TASK_SLOTS_AVAILABLE_NAME: &str = "worker_task_slots_available";
 tm!(vr_u64, TASK_SLOTS_AVAILABLE, TASK_SLOTS_AVAILABLE_NAME);
 
@@ -284,6 +363,17 @@ tm!(ctr, STICKY_CACHE_HIT, "sticky_cache_hit");
 tm!(ctr, STICKY_CACHE_MISS, "sticky_cache_miss");
 const STICKY_CACHE_SIZE_NAME: &str = "sticky_cache_size";
 tm!(vr_u64, STICKY_CACHE_SIZE, STICKY_CACHE_SIZE_NAME);
+const STICKY_CACHE_SIZE_BYTES_NAME: &str = "sticky_cache_size_bytes";
+tm!(
+    vr_u64,
+    STICKY_CACHE_SIZE_BYTES,
+    STICKY_CACHE_SIZE_BYTES_NAME
+);
+tm!(
+    ctr,
+    STICKY_CACHE_FORCED_EVICTION,
+    "sticky_cache_total_forced_eviction"
+);
 
 /// Artisanal, handcrafted latency buckets for workflow e2e latency which should expose a useful
 /// set of buckets for < 1 day runtime workflows. Beyond that, this metric probably isn't very
@@ -336,9 +426,10 @@ impl AggregatorSelector for SDKAggSelector {
         if *descriptor.instrument_kind() == InstrumentKind::ValueRecorder {
             // Some recorders are just gauges
             match descriptor.name() {
-                STICKY_CACHE_SIZE_NAME | NUM_POLLERS_NAME | TASK_SLOTS_AVAILABLE_NAME => {
-                    return Some(Arc::new(last_value()))
-                }
+                STICKY_CACHE_SIZE_NAME
+                | STICKY_CACHE_SIZE_BYTES_NAME
+                | NUM_POLLERS_NAME
+                | TASK_SLOTS_AVAILABLE_NAME => return Some(Arc::new(last_value())),
                 _ => (),
             }
 
@@ -346,9 +437,9 @@ impl AggregatorSelector for SDKAggSelector {
             let buckets = match descriptor.name() {
                 WF_E2E_LATENCY_NAME => WF_LATENCY_MS_BUCKETS,
                 WF_TASK_EXECUTION_LATENCY_NAME | WF_TASK_REPLAY_LATENCY_NAME => WF_TASK_MS_BUCKETS,
-                WF_TASK_SCHED_TO_START_LATENCY_NAME | ACT_SCHED_TO_START_LATENCY_NAME => {
-                    TASK_SCHED_TO_START_MS_BUCKETS
-                }
+                WF_TASK_SCHED_TO_START_LATENCY_NAME
+                | ACT_SCHED_TO_START_LATENCY_NAME
+                | ACT_THROTTLED_LATENCY_NAME => TASK_SCHED_TO_START_MS_BUCKETS,
                 ACT_EXEC_LATENCY_NAME => ACT_EXE_MS_BUCKETS,
                 _ => DEFAULT_MS_BUCKETS,
             };
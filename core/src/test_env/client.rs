@@ -0,0 +1,1111 @@
+//! A client which stands in for the server, generating each workflow's history from the commands
+//! the worker sends, on a virtual clock
+
+use super::{ActivityStub, ActivityStubCall, TestWorkflowResult};
+use crate::worker::client::WorkerClient;
+use parking_lot::Mutex;
+use rand::{thread_rng, Rng};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use temporal_client::WorkflowTaskCompletion;
+use temporal_sdk_core_protos::{
+    coresdk::{common::Payload, workflow_commands::QueryResult, IntoPayloadsExt},
+    temporal::api::{
+        command::v1::{command::Attributes as CmdAttribs, Command},
+        common::v1::{Payloads, WorkflowExecution, WorkflowType},
+        enums::v1::{EventType, RetryState, TaskQueueKind, TimeoutType, WorkflowTaskFailedCause},
+        failure::v1::{failure::FailureInfo, Failure},
+        history::v1::{history_event::Attributes, *},
+        taskqueue::v1::TaskQueue,
+        workflowservice::v1::*,
+    },
+    HistoryInfo, TaskToken, TestHistoryBuilder,
+};
+use tokio::sync::watch;
+
+type Result<T, E = tonic::Status> = std::result::Result<T, E>;
+
+/// How long the workflow tasks recorded in generated histories are allowed to take
+const WFT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Acts as the server for a [TestWorkflowEnvironment](super::TestWorkflowEnvironment).
+///
+/// Workflow tasks are handed out with the full history of their run. Whenever no workflow or
+/// activity task is ready or outstanding, the virtual clock jumps ahead to the next pending timer
+/// and fires it.
+#[derive(Clone)]
+pub(crate) struct TestEnvClient {
+    state: Arc<Mutex<EnvState>>,
+    /// Signalled whenever the state changes in a way which might unblock a poll
+    changed: Arc<watch::Sender<()>>,
+}
+
+struct EnvState {
+    task_queue: String,
+    now: SystemTime,
+    workflows: HashMap<String, TestWorkflow>,
+    /// Run ids, in order, of workflows which have a workflow task waiting to be polled
+    wft_queue: VecDeque<String>,
+    /// Run ids of workflows whose task has been handed to the worker, by task token
+    outstanding_wfts: HashMap<TaskToken, String>,
+    /// Activities which have been scheduled but not yet polled
+    activity_queue: VecDeque<TestActivity>,
+    outstanding_activities: HashMap<TaskToken, TestActivity>,
+    stubs: HashMap<String, ActivityStub>,
+}
+
+struct TestWorkflow {
+    workflow_id: String,
+    workflow_type: String,
+    history: TestHistoryBuilder,
+    wft: WftState,
+    /// Changes to the history which happened while a workflow task was outstanding, and will be
+    /// applied once it is completed, as the server would
+    buffered: Vec<HistoryChange>,
+    /// Pending timers by id, with when they fire and the id of their started event
+    timers: HashMap<String, (SystemTime, i64)>,
+    /// Activities waiting to be retried, with when their next attempt starts
+    retries: Vec<(SystemTime, TestActivity)>,
+    last_wft_completed_id: i64,
+    result: Option<TestWorkflowResult>,
+}
+
+#[derive(Clone, Copy)]
+enum WftState {
+    None,
+    Scheduled(i64),
+    Started { scheduled: i64, started: i64 },
+}
+
+type HistoryChange = Box<dyn FnOnce(&mut TestHistoryBuilder) + Send>;
+
+#[derive(Clone)]
+struct TestActivity {
+    run_id: String,
+    task_token: TaskToken,
+    scheduled_event_id: i64,
+    attrs: ActivityTaskScheduledEventAttributes,
+    scheduled_time: SystemTime,
+    /// Starting at 1, the attempt which is next to run or running
+    attempt: u32,
+    /// Id of the cancel requested event, if the workflow asked for the activity to be cancelled
+    cancel_requested_event_id: Option<i64>,
+}
+
+impl TestWorkflow {
+    fn is_closed(&self) -> bool {
+        self.result.is_some()
+    }
+
+    fn close(&mut self, result: TestWorkflowResult) {
+        self.result = Some(result);
+        self.timers.clear();
+        self.retries.clear();
+    }
+
+    fn add_event(&mut self, event_type: EventType, attribs: impl Into<Attributes>) -> i64 {
+        self.history
+            .add_get_event_id(event_type, Some(attribs.into()))
+    }
+
+    /// Applies the change to the history right away, unless a workflow task is outstanding, in
+    /// which case it is buffered until that task is completed. A workflow task is scheduled to
+    /// deliver the change if there isn't one already.
+    fn change_history(&mut self, now: SystemTime, change: HistoryChange) -> bool {
+        if self.is_closed() {
+            return false;
+        }
+        if let WftState::Started { .. } = self.wft {
+            self.buffered.push(change);
+            return false;
+        }
+        self.history.set_event_time(now);
+        change(&mut self.history);
+        self.schedule_wft(now)
+    }
+
+    /// Returns true if a new workflow task was scheduled
+    fn schedule_wft(&mut self, now: SystemTime) -> bool {
+        if self.is_closed() || !matches!(self.wft, WftState::None) {
+            return false;
+        }
+        self.history.set_event_time(now);
+        let id = self.add_event(
+            EventType::WorkflowTaskScheduled,
+            WorkflowTaskScheduledEventAttributes {
+                start_to_close_timeout: Some(WFT_TIMEOUT.into()),
+                attempt: 1,
+                ..Default::default()
+            },
+        );
+        self.wft = WftState::Scheduled(id);
+        true
+    }
+}
+
+impl TestEnvClient {
+    pub(crate) fn new(task_queue: String) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(EnvState {
+                task_queue,
+                now: SystemTime::now(),
+                workflows: Default::default(),
+                wft_queue: Default::default(),
+                outstanding_wfts: Default::default(),
+                activity_queue: Default::default(),
+                outstanding_activities: Default::default(),
+                stubs: Default::default(),
+            })),
+            changed: Arc::new(watch::channel(()).0),
+        }
+    }
+
+    pub(crate) fn now(&self) -> SystemTime {
+        self.state.lock().now
+    }
+
+    pub(crate) fn register_stub(&self, activity_type: String, stub: ActivityStub) {
+        self.state.lock().stubs.insert(activity_type, stub);
+    }
+
+    pub(crate) fn start_workflow(
+        &self,
+        workflow_id: String,
+        workflow_type: String,
+        input: Vec<Payload>,
+    ) -> String {
+        let run_id = uuid::Uuid::new_v4().to_string();
+        let mut st = self.state.lock();
+        let mut history = TestHistoryBuilder::default();
+        history.set_event_time(st.now);
+        history.add(
+            EventType::WorkflowExecutionStarted,
+            WorkflowExecutionStartedEventAttributes {
+                workflow_type: Some(WorkflowType {
+                    name: workflow_type.clone(),
+                }),
+                task_queue: Some(TaskQueue {
+                    name: st.task_queue.clone(),
+                    kind: TaskQueueKind::Normal as i32,
+                }),
+                input: input.into_payloads(),
+                workflow_task_timeout: Some(WFT_TIMEOUT.into()),
+                original_execution_run_id: run_id.clone(),
+                first_execution_run_id: run_id.clone(),
+                attempt: 1,
+                ..Default::default()
+            }
+            .into(),
+        );
+        let mut wf = TestWorkflow {
+            workflow_id,
+            workflow_type,
+            history,
+            wft: WftState::None,
+            buffered: vec![],
+            timers: Default::default(),
+            retries: vec![],
+            last_wft_completed_id: 0,
+            result: None,
+        };
+        wf.schedule_wft(st.now);
+        st.workflows.insert(run_id.clone(), wf);
+        st.wft_queue.push_back(run_id.clone());
+        drop(st);
+        self.changed.send_replace(());
+        run_id
+    }
+
+    pub(crate) fn workflow_result(&self, run_id: &str) -> Option<TestWorkflowResult> {
+        self.state
+            .lock()
+            .workflows
+            .get(run_id)
+            .and_then(|wf| wf.result.clone())
+    }
+
+    pub(crate) fn history(&self, run_id: &str) -> Option<History> {
+        self.state
+            .lock()
+            .workflows
+            .get(run_id)
+            .and_then(|wf| wf.history.get_full_history_info().ok())
+            .map(Into::into)
+    }
+
+    /// Returns true if every workflow started so far has closed
+    pub(crate) fn all_workflows_closed(&self) -> bool {
+        self.state
+            .lock()
+            .workflows
+            .values()
+            .all(|wf| wf.is_closed())
+    }
+
+    /// Waits until the provided function produces something from the state, skipping time ahead
+    /// whenever everything is blocked on timers
+    async fn wait_for<T>(&self, mut take: impl FnMut(&mut EnvState) -> Option<T>) -> T {
+        let mut changed = self.changed.subscribe();
+        loop {
+            {
+                let mut st = self.state.lock();
+                if let Some(t) = take(&mut st) {
+                    drop(st);
+                    self.changed.send_replace(());
+                    return t;
+                }
+                if st.skip_to_next_timer() {
+                    drop(st);
+                    self.changed.send_replace(());
+                    continue;
+                }
+            }
+            // The sender lives as long as self, so this cannot fail
+            let _ = changed.changed().await;
+        }
+    }
+
+    fn with_state<T>(&self, f: impl FnOnce(&mut EnvState) -> T) -> T {
+        let res = f(&mut self.state.lock());
+        self.changed.send_replace(());
+        res
+    }
+}
+
+impl EnvState {
+    /// If nothing can make progress except by time passing, moves the clock to the earliest pending
+    /// timer or activity retry, and fires every timer and starts every retry due by then. Returns
+    /// true if time was skipped.
+    fn skip_to_next_timer(&mut self) -> bool {
+        if !self.wft_queue.is_empty()
+            || !self.outstanding_wfts.is_empty()
+            || !self.activity_queue.is_empty()
+            || !self.outstanding_activities.is_empty()
+        {
+            return false;
+        }
+        let next_fire = self
+            .workflows
+            .values()
+            .filter(|wf| !wf.is_closed())
+            .flat_map(|wf| {
+                let timers = wf.timers.values().map(|(fire_at, _)| *fire_at);
+                timers.chain(wf.retries.iter().map(|(retry_at, _)| *retry_at))
+            })
+            .min();
+        let next_fire = match next_fire {
+            Some(t) => t,
+            None => return false,
+        };
+        self.now = self.now.max(next_fire);
+        let now = self.now;
+        let mut due_retries = vec![];
+        for (run_id, wf) in self.workflows.iter_mut() {
+            let (due, waiting) = std::mem::take(&mut wf.retries)
+                .into_iter()
+                .partition(|(retry_at, _)| *retry_at <= now);
+            wf.retries = waiting;
+            due_retries.extend(due.into_iter().map(|(_, act)| act));
+
+            let mut due: Vec<_> = wf
+                .timers
+                .iter()
+                .filter(|(_, (fire_at, _))| *fire_at <= now)
+                .map(|(id, (fire_at, started_id))| (*fire_at, *started_id, id.clone()))
+                .collect();
+            if due.is_empty() {
+                continue;
+            }
+            due.sort();
+            for (_, started_id, timer_id) in due {
+                wf.timers.remove(&timer_id);
+                if wf.change_history(
+                    now,
+                    Box::new(move |h| h.add_timer_fired(started_id, timer_id)),
+                ) {
+                    self.wft_queue.push_back(run_id.clone());
+                }
+            }
+        }
+        for act in due_retries {
+            self.attempt_activity(act);
+        }
+        true
+    }
+
+    /// Runs the activity's current attempt with its stub if there is one, or queues it for the
+    /// worker
+    fn attempt_activity(&mut self, act: TestActivity) {
+        let activity_type = act
+            .attrs
+            .activity_type
+            .as_ref()
+            .map(|at| at.name.clone())
+            .unwrap_or_default();
+        let stub = match self.stubs.get(&activity_type) {
+            Some(s) => s.clone(),
+            None => {
+                self.activity_queue.push_back(act);
+                return;
+            }
+        };
+        let call = ActivityStubCall {
+            run_id: act.run_id.clone(),
+            activity_id: act.attrs.activity_id.clone(),
+            activity_type,
+            input: act
+                .attrs
+                .input
+                .clone()
+                .map(|p| p.payloads.into_iter().map(Into::into).collect())
+                .unwrap_or_default(),
+            attempt: act.attempt,
+        };
+        match stub(&call) {
+            Ok(result) => {
+                let scheduled_event_id = act.scheduled_event_id;
+                let attempt = act.attempt;
+                self.change_history(
+                    &act.run_id,
+                    Box::new(move |h| {
+                        resolve_activity(
+                            h,
+                            scheduled_event_id,
+                            attempt,
+                            ActivityOutcome::Completed(Some(result.into())),
+                        )
+                    }),
+                );
+            }
+            Err(failure) => self.activity_attempt_failed(act, failure),
+        }
+    }
+
+    /// Schedules the activity's next attempt if its retry policy allows, otherwise resolves it
+    /// with the failure
+    fn activity_attempt_failed(&mut self, mut act: TestActivity, failure: Failure) {
+        let now = self.now;
+        match retry_backoff(&act, &failure, now) {
+            Ok(backoff) => {
+                if let Some(wf) = self.workflows.get_mut(&act.run_id) {
+                    if !wf.is_closed() {
+                        act.attempt += 1;
+                        wf.retries.push((now + backoff, act));
+                    }
+                }
+            }
+            Err(retry_state) => {
+                let scheduled_event_id = act.scheduled_event_id;
+                let attempt = act.attempt;
+                self.change_history(
+                    &act.run_id,
+                    Box::new(move |h| {
+                        resolve_activity(
+                            h,
+                            scheduled_event_id,
+                            attempt,
+                            ActivityOutcome::Failed(failure, retry_state),
+                        )
+                    }),
+                );
+            }
+        }
+    }
+
+    fn change_history(&mut self, run_id: &str, change: HistoryChange) {
+        let now = self.now;
+        if let Some(wf) = self.workflows.get_mut(run_id) {
+            if wf.change_history(now, change) {
+                self.wft_queue.push_back(run_id.to_string());
+            }
+        }
+    }
+
+    fn take_wft(&mut self) -> Option<PollWorkflowTaskQueueResponse> {
+        let run_id = self.wft_queue.pop_front()?;
+        let now = self.now;
+        let task_queue = self.task_queue.clone();
+        let wf = self.workflows.get_mut(&run_id)?;
+        let scheduled = match wf.wft {
+            WftState::Scheduled(id) => id,
+            _ => return None,
+        };
+        wf.history.set_event_time(now);
+        let started = wf.add_event(
+            EventType::WorkflowTaskStarted,
+            WorkflowTaskStartedEventAttributes {
+                scheduled_event_id: scheduled,
+                ..Default::default()
+            },
+        );
+        wf.wft = WftState::Started { scheduled, started };
+        let hist_info = wf
+            .history
+            .get_full_history_info()
+            .expect("Generated histories are well formed");
+        let mut resp = hist_info.as_poll_wft_response(task_queue);
+        resp.workflow_execution = Some(WorkflowExecution {
+            workflow_id: wf.workflow_id.clone(),
+            run_id: run_id.clone(),
+        });
+        self.outstanding_wfts
+            .insert(TaskToken(resp.task_token.clone()), run_id);
+        Some(resp)
+    }
+
+    fn take_activity(&mut self) -> Option<PollActivityTaskQueueResponse> {
+        let act = self.activity_queue.pop_front()?;
+        let wf = self.workflows.get(&act.run_id)?;
+        let resp = PollActivityTaskQueueResponse {
+            task_token: act.task_token.0.clone(),
+            workflow_type: Some(WorkflowType {
+                name: wf.workflow_type.clone(),
+            }),
+            workflow_execution: Some(WorkflowExecution {
+                workflow_id: wf.workflow_id.clone(),
+                run_id: act.run_id.clone(),
+            }),
+            activity_type: act.attrs.activity_type.clone(),
+            activity_id: act.attrs.activity_id.clone(),
+            header: act.attrs.header.clone(),
+            input: act.attrs.input.clone(),
+            scheduled_time: Some(act.scheduled_time.into()),
+            current_attempt_scheduled_time: Some(self.now.into()),
+            started_time: Some(self.now.into()),
+            attempt: act.attempt as i32,
+            schedule_to_close_timeout: act.attrs.schedule_to_close_timeout.clone(),
+            start_to_close_timeout: act.attrs.start_to_close_timeout.clone(),
+            heartbeat_timeout: act.attrs.heartbeat_timeout.clone(),
+            retry_policy: act.attrs.retry_policy.clone(),
+            ..Default::default()
+        };
+        self.outstanding_activities
+            .insert(act.task_token.clone(), act);
+        Some(resp)
+    }
+
+    fn complete_wft(&mut self, request: WorkflowTaskCompletion) -> Result<()> {
+        // Rejected before anything is applied, so an unsupported command leaves the task and the
+        // workflow untouched
+        for command in &request.commands {
+            check_supported(command)?;
+        }
+        let run_id = self
+            .outstanding_wfts
+            .remove(&request.task_token)
+            .ok_or_else(|| tonic::Status::not_found("Workflow task not found"))?;
+        let now = self.now;
+        let wf = self
+            .workflows
+            .get_mut(&run_id)
+            .expect("Workflows with outstanding tasks exist");
+        let (scheduled, started) = match wf.wft {
+            WftState::Started { scheduled, started } => (scheduled, started),
+            _ => unreachable!("Outstanding workflow tasks are always started"),
+        };
+        wf.history.set_event_time(now);
+        wf.last_wft_completed_id = wf.add_event(
+            EventType::WorkflowTaskCompleted,
+            WorkflowTaskCompletedEventAttributes {
+                scheduled_event_id: scheduled,
+                started_event_id: started,
+                sdk_metadata: Some(request.sdk_metadata),
+                ..Default::default()
+            },
+        );
+        wf.wft = WftState::None;
+        let mut scheduled_activities = vec![];
+        for command in request.commands {
+            scheduled_activities.extend(self.handle_command(&run_id, command));
+        }
+        let wf = self
+            .workflows
+            .get_mut(&run_id)
+            .expect("Workflows with outstanding tasks exist");
+        let mut scheduled_wft = false;
+        for change in std::mem::take(&mut wf.buffered) {
+            scheduled_wft |= wf.change_history(now, change);
+        }
+        if request.force_create_new_workflow_task {
+            scheduled_wft |= wf.schedule_wft(now);
+        }
+        if scheduled_wft {
+            self.wft_queue.push_back(run_id);
+        }
+        for act in scheduled_activities {
+            self.attempt_activity(act);
+        }
+        Ok(())
+    }
+
+    /// Records the command, which must have passed [check_supported], in the workflow's history.
+    /// Returns the activity it scheduled, if any, which must be attempted once every command in the
+    /// task has been handled.
+    fn handle_command(&mut self, run_id: &str, command: Command) -> Option<TestActivity> {
+        let now = self.now;
+        let wf = match self.workflows.get_mut(run_id) {
+            Some(wf) if !wf.is_closed() => wf,
+            _ => return None,
+        };
+        let mut scheduled_activity = None;
+        let wft_completed_id = wf.last_wft_completed_id;
+        match command.attributes {
+            Some(CmdAttribs::ScheduleActivityTaskCommandAttributes(a)) => {
+                let attrs = ActivityTaskScheduledEventAttributes {
+                    activity_id: a.activity_id,
+                    activity_type: a.activity_type,
+                    task_queue: a.task_queue,
+                    header: a.header,
+                    input: a.input,
+                    schedule_to_close_timeout: a.schedule_to_close_timeout,
+                    schedule_to_start_timeout: a.schedule_to_start_timeout,
+                    start_to_close_timeout: a.start_to_close_timeout,
+                    heartbeat_timeout: a.heartbeat_timeout,
+                    workflow_task_completed_event_id: wft_completed_id,
+                    retry_policy: a.retry_policy,
+                    ..Default::default()
+                };
+                let scheduled_event_id =
+                    wf.add_event(EventType::ActivityTaskScheduled, attrs.clone());
+                scheduled_activity = Some(TestActivity {
+                    run_id: run_id.to_string(),
+                    task_token: TaskToken(thread_rng().gen::<[u8; 16]>().to_vec()),
+                    scheduled_event_id,
+                    attrs,
+                    scheduled_time: now,
+                    attempt: 1,
+                    cancel_requested_event_id: None,
+                });
+            }
+            Some(CmdAttribs::RequestCancelActivityTaskCommandAttributes(a)) => {
+                let cancel_requested_id = wf.add_event(
+                    EventType::ActivityTaskCancelRequested,
+                    ActivityTaskCancelRequestedEventAttributes {
+                        scheduled_event_id: a.scheduled_event_id,
+                        workflow_task_completed_event_id: wft_completed_id,
+                    },
+                );
+                let is_target = |act: &TestActivity| {
+                    act.run_id == run_id && act.scheduled_event_id == a.scheduled_event_id
+                };
+                let queued_ix = self.activity_queue.iter().position(is_target);
+                let retry_ix = wf.retries.iter().position(|(_, act)| is_target(act));
+                if queued_ix.is_some() || retry_ix.is_some() {
+                    // Not running anywhere, so it can be cancelled right away
+                    if let Some(ix) = queued_ix {
+                        self.activity_queue.remove(ix);
+                    }
+                    if let Some(ix) = retry_ix {
+                        wf.retries.remove(ix);
+                    }
+                    // Applied after the rest of the task's commands
+                    wf.buffered.push(Box::new(move |h| {
+                        h.add(
+                            EventType::ActivityTaskCanceled,
+                            ActivityTaskCanceledEventAttributes {
+                                scheduled_event_id: a.scheduled_event_id,
+                                latest_cancel_requested_event_id: cancel_requested_id,
+                                ..Default::default()
+                            }
+                            .into(),
+                        )
+                    }));
+                } else if let Some(act) = self
+                    .outstanding_activities
+                    .values_mut()
+                    .find(|act| is_target(act))
+                {
+                    act.cancel_requested_event_id = Some(cancel_requested_id);
+                }
+            }
+            Some(CmdAttribs::StartTimerCommandAttributes(a)) => {
+                let fire_after = a
+                    .start_to_fire_timeout
+                    .clone()
+                    .and_then(|d| Duration::try_from(d).ok())
+                    .unwrap_or_default();
+                let started_id = wf.add_event(
+                    EventType::TimerStarted,
+                    TimerStartedEventAttributes {
+                        timer_id: a.timer_id.clone(),
+                        start_to_fire_timeout: a.start_to_fire_timeout,
+                        workflow_task_completed_event_id: wft_completed_id,
+                    },
+                );
+                wf.timers.insert(a.timer_id, (now + fire_after, started_id));
+            }
+            Some(CmdAttribs::CancelTimerCommandAttributes(a)) => {
+                if let Some((_, started_event_id)) = wf.timers.remove(&a.timer_id) {
+                    wf.add_event(
+                        EventType::TimerCanceled,
+                        TimerCanceledEventAttributes {
+                            timer_id: a.timer_id,
+                            started_event_id,
+                            workflow_task_completed_event_id: wft_completed_id,
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+            Some(CmdAttribs::RecordMarkerCommandAttributes(a)) => {
+                wf.add_event(
+                    EventType::MarkerRecorded,
+                    MarkerRecordedEventAttributes {
+                        marker_name: a.marker_name,
+                        details: a.details,
+                        workflow_task_completed_event_id: wft_completed_id,
+                        header: a.header,
+                        failure: a.failure,
+                    },
+                );
+            }
+            Some(CmdAttribs::UpsertWorkflowSearchAttributesCommandAttributes(a)) => {
+                wf.add_event(
+                    EventType::UpsertWorkflowSearchAttributes,
+                    UpsertWorkflowSearchAttributesEventAttributes {
+                        workflow_task_completed_event_id: wft_completed_id,
+                        search_attributes: a.search_attributes,
+                    },
+                );
+            }
+            Some(CmdAttribs::CompleteWorkflowExecutionCommandAttributes(a)) => {
+                wf.add_event(
+                    EventType::WorkflowExecutionCompleted,
+                    WorkflowExecutionCompletedEventAttributes {
+                        result: a.result.clone(),
+                        workflow_task_completed_event_id: wft_completed_id,
+                        ..Default::default()
+                    },
+                );
+                wf.close(TestWorkflowResult::Completed(
+                    a.result
+                        .map(|p| p.payloads.into_iter().map(Into::into).collect())
+                        .unwrap_or_default(),
+                ));
+            }
+            Some(CmdAttribs::FailWorkflowExecutionCommandAttributes(a)) => {
+                wf.add_event(
+                    EventType::WorkflowExecutionFailed,
+                    WorkflowExecutionFailedEventAttributes {
+                        failure: a.failure.clone(),
+                        workflow_task_completed_event_id: wft_completed_id,
+                        ..Default::default()
+                    },
+                );
+                wf.close(TestWorkflowResult::Failed(a.failure.unwrap_or_default()));
+            }
+            Some(CmdAttribs::CancelWorkflowExecutionCommandAttributes(a)) => {
+                wf.add_event(
+                    EventType::WorkflowExecutionCanceled,
+                    WorkflowExecutionCanceledEventAttributes {
+                        details: a.details,
+                        workflow_task_completed_event_id: wft_completed_id,
+                    },
+                );
+                wf.close(TestWorkflowResult::Cancelled);
+            }
+            Some(CmdAttribs::ContinueAsNewWorkflowExecutionCommandAttributes(a)) => {
+                wf.add_event(
+                    EventType::WorkflowExecutionContinuedAsNew,
+                    WorkflowExecutionContinuedAsNewEventAttributes {
+                        workflow_type: a.workflow_type,
+                        task_queue: a.task_queue,
+                        input: a.input,
+                        workflow_task_completed_event_id: wft_completed_id,
+                        ..Default::default()
+                    },
+                );
+                wf.close(TestWorkflowResult::ContinuedAsNew);
+            }
+            _ => unreachable!("Unsupported commands are rejected before the task is applied"),
+        }
+        if wf.is_closed() {
+            self.activity_queue.retain(|act| act.run_id != run_id);
+            return None;
+        }
+        scheduled_activity
+    }
+
+    fn fail_wft(
+        &mut self,
+        task_token: &TaskToken,
+        cause: WorkflowTaskFailedCause,
+        failure: Failure,
+    ) {
+        let run_id = match self.outstanding_wfts.remove(task_token) {
+            Some(r) => r,
+            None => return,
+        };
+        let now = self.now;
+        if let Some(wf) = self.workflows.get_mut(&run_id) {
+            if let WftState::Started { scheduled, started } = wf.wft {
+                wf.history.set_event_time(now);
+                wf.add_event(
+                    EventType::WorkflowTaskFailed,
+                    WorkflowTaskFailedEventAttributes {
+                        scheduled_event_id: scheduled,
+                        started_event_id: started,
+                        cause: cause as i32,
+                        failure: Some(failure.clone()),
+                        ..Default::default()
+                    },
+                );
+            }
+            wf.wft = WftState::None;
+            // The server would retry the task forever, but that would never get anywhere in a test
+            wf.close(TestWorkflowResult::WorkflowTaskFailed(failure));
+            self.activity_queue.retain(|act| act.run_id != run_id);
+        }
+    }
+
+    fn resolve_activity(&mut self, task_token: &TaskToken, outcome: ActivityOutcome) -> Result<()> {
+        let act = self
+            .outstanding_activities
+            .remove(task_token)
+            .ok_or_else(|| tonic::Status::not_found("Activity task not found"))?;
+        let (scheduled_event_id, attempt) = (act.scheduled_event_id, act.attempt);
+        let outcome = match (outcome, act.cancel_requested_event_id) {
+            (ActivityOutcome::Cancelled(details, _), Some(cancel_requested_id)) => {
+                ActivityOutcome::Cancelled(details, cancel_requested_id)
+            }
+            (ActivityOutcome::Failed(failure, _), _) => {
+                self.activity_attempt_failed(act, failure);
+                return Ok(());
+            }
+            (o, _) => o,
+        };
+        self.change_history(
+            &act.run_id,
+            Box::new(move |h| resolve_activity(h, scheduled_event_id, attempt, outcome)),
+        );
+        Ok(())
+    }
+}
+
+enum ActivityOutcome {
+    Completed(Option<Payloads>),
+    /// The final failure, and why it was not retried
+    Failed(Failure, RetryState),
+    /// Details, and the id of the event which requested cancellation
+    Cancelled(Option<Payloads>, i64),
+}
+
+/// Returns an error if the test environment can't apply the command
+fn check_supported(command: &Command) -> Result<()> {
+    match command.attributes {
+        Some(CmdAttribs::ScheduleActivityTaskCommandAttributes(_))
+        | Some(CmdAttribs::RequestCancelActivityTaskCommandAttributes(_))
+        | Some(CmdAttribs::StartTimerCommandAttributes(_))
+        | Some(CmdAttribs::CancelTimerCommandAttributes(_))
+        | Some(CmdAttribs::RecordMarkerCommandAttributes(_))
+        | Some(CmdAttribs::UpsertWorkflowSearchAttributesCommandAttributes(_))
+        | Some(CmdAttribs::CompleteWorkflowExecutionCommandAttributes(_))
+        | Some(CmdAttribs::FailWorkflowExecutionCommandAttributes(_))
+        | Some(CmdAttribs::CancelWorkflowExecutionCommandAttributes(_))
+        | Some(CmdAttribs::ContinueAsNewWorkflowExecutionCommandAttributes(_)) => Ok(()),
+        _ => Err(tonic::Status::unimplemented(format!(
+            "The test environment does not support {:?} commands",
+            command.command_type()
+        ))),
+    }
+}
+
+/// Returns how long to wait before the activity's next attempt after it failed with the provided
+/// failure, or why it should not be retried, following its retry policy like the server would
+fn retry_backoff(
+    act: &TestActivity,
+    failure: &Failure,
+    now: SystemTime,
+) -> Result<Duration, RetryState> {
+    let policy = act.attrs.retry_policy.clone().unwrap_or_default();
+    let non_retryable = match &failure.failure_info {
+        Some(FailureInfo::ApplicationFailureInfo(af)) => {
+            af.non_retryable || policy.non_retryable_error_types.contains(&af.r#type)
+        }
+        Some(FailureInfo::TimeoutFailureInfo(tf)) => {
+            tf.timeout_type() != TimeoutType::StartToClose
+                && tf.timeout_type() != TimeoutType::Heartbeat
+        }
+        _ => false,
+    };
+    if non_retryable {
+        return Err(RetryState::NonRetryableFailure);
+    }
+    if policy.maximum_attempts > 0 && act.attempt >= policy.maximum_attempts as u32 {
+        return Err(RetryState::MaximumAttemptsReached);
+    }
+    let initial = policy
+        .initial_interval
+        .and_then(|d| Duration::try_from(d).ok())
+        .filter(|d| !d.is_zero())
+        .unwrap_or_else(|| Duration::from_secs(1));
+    let coefficient = if policy.backoff_coefficient >= 1.0 {
+        policy.backoff_coefficient
+    } else {
+        2.0
+    };
+    let maximum = policy
+        .maximum_interval
+        .and_then(|d| Duration::try_from(d).ok())
+        .filter(|d| !d.is_zero())
+        .unwrap_or(initial * 100);
+    let backoff = initial
+        .mul_f64(coefficient.powi(act.attempt as i32 - 1))
+        .min(maximum);
+    let deadline = act
+        .attrs
+        .schedule_to_close_timeout
+        .clone()
+        .and_then(|d| Duration::try_from(d).ok())
+        .filter(|d| !d.is_zero())
+        .map(|d| act.scheduled_time + d);
+    if deadline.map_or(false, |d| now + backoff >= d) {
+        return Err(RetryState::Timeout);
+    }
+    Ok(backoff)
+}
+
+/// Records the final attempt of an activity being started and resolved with the provided outcome
+fn resolve_activity(
+    h: &mut TestHistoryBuilder,
+    scheduled_event_id: i64,
+    attempt: u32,
+    outcome: ActivityOutcome,
+) {
+    let started_event_id = h.add_get_event_id(
+        EventType::ActivityTaskStarted,
+        Some(
+            ActivityTaskStartedEventAttributes {
+                scheduled_event_id,
+                attempt: attempt as i32,
+                ..Default::default()
+            }
+            .into(),
+        ),
+    );
+    let (event_type, attrs): (_, Attributes) = match outcome {
+        ActivityOutcome::Completed(result) => (
+            EventType::ActivityTaskCompleted,
+            ActivityTaskCompletedEventAttributes {
+                result,
+                scheduled_event_id,
+                started_event_id,
+                ..Default::default()
+            }
+            .into(),
+        ),
+        ActivityOutcome::Failed(failure, retry_state) if failure.is_timeout() => (
+            EventType::ActivityTaskTimedOut,
+            ActivityTaskTimedOutEventAttributes {
+                failure: Some(failure),
+                scheduled_event_id,
+                started_event_id,
+                retry_state: retry_state as i32,
+            }
+            .into(),
+        ),
+        ActivityOutcome::Failed(failure, retry_state) => (
+            EventType::ActivityTaskFailed,
+            ActivityTaskFailedEventAttributes {
+                failure: Some(failure),
+                scheduled_event_id,
+                started_event_id,
+                retry_state: retry_state as i32,
+                ..Default::default()
+            }
+            .into(),
+        ),
+        ActivityOutcome::Cancelled(details, latest_cancel_requested_event_id) => (
+            EventType::ActivityTaskCanceled,
+            ActivityTaskCanceledEventAttributes {
+                details,
+                latest_cancel_requested_event_id,
+                scheduled_event_id,
+                started_event_id,
+                ..Default::default()
+            }
+            .into(),
+        ),
+    };
+    h.add(event_type, attrs);
+}
+
+#[async_trait::async_trait]
+impl WorkerClient for TestEnvClient {
+    async fn poll_workflow_task(
+        &self,
+        _task_queue: String,
+        _is_sticky: bool,
+    ) -> Result<PollWorkflowTaskQueueResponse> {
+        Ok(self.wait_for(EnvState::take_wft).await)
+    }
+
+    async fn poll_activity_task(
+        &self,
+        _task_queue: String,
+        _max_tasks_per_sec: Option<f64>,
+    ) -> Result<PollActivityTaskQueueResponse> {
+        Ok(self.wait_for(EnvState::take_activity).await)
+    }
+
+    async fn complete_workflow_task(
+        &self,
+        request: WorkflowTaskCompletion,
+    ) -> Result<RespondWorkflowTaskCompletedResponse> {
+        self.with_state(|st| st.complete_wft(request))?;
+        Ok(RespondWorkflowTaskCompletedResponse::default())
+    }
+
+    async fn complete_activity_task(
+        &self,
+        task_token: TaskToken,
+        result: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCompletedResponse> {
+        self.with_state(|st| st.resolve_activity(&task_token, ActivityOutcome::Completed(result)))?;
+        Ok(RespondActivityTaskCompletedResponse::default())
+    }
+
+    async fn record_activity_heartbeat(
+        &self,
+        task_token: TaskToken,
+        _details: Option<Payloads>,
+    ) -> Result<RecordActivityTaskHeartbeatResponse> {
+        let st = self.state.lock();
+        let act = st
+            .outstanding_activities
+            .get(&task_token)
+            .ok_or_else(|| tonic::Status::not_found("Activity task not found"))?;
+        let workflow_closed = st
+            .workflows
+            .get(&act.run_id)
+            .map_or(true, |wf| wf.is_closed());
+        Ok(RecordActivityTaskHeartbeatResponse {
+            cancel_requested: act.cancel_requested_event_id.is_some() || workflow_closed,
+        })
+    }
+
+    async fn cancel_activity_task(
+        &self,
+        task_token: TaskToken,
+        details: Option<Payloads>,
+    ) -> Result<RespondActivityTaskCanceledResponse> {
+        self.with_state(|st| {
+            st.resolve_activity(&task_token, ActivityOutcome::Cancelled(details, 0))
+        })?;
+        Ok(RespondActivityTaskCanceledResponse::default())
+    }
+
+    async fn fail_activity_task(
+        &self,
+        task_token: TaskToken,
+        failure: Option<Failure>,
+    ) -> Result<RespondActivityTaskFailedResponse> {
+        self.with_state(|st| {
+            st.resolve_activity(
+                &task_token,
+                ActivityOutcome::Failed(failure.unwrap_or_default(), RetryState::Unspecified),
+            )
+        })?;
+        Ok(RespondActivityTaskFailedResponse::default())
+    }
+
+    async fn fail_workflow_task(
+        &self,
+        task_token: TaskToken,
+        cause: WorkflowTaskFailedCause,
+        failure: Option<Failure>,
+    ) -> Result<RespondWorkflowTaskFailedResponse> {
+        self.with_state(|st| st.fail_wft(&task_token, cause, failure.unwrap_or_default()));
+        Ok(RespondWorkflowTaskFailedResponse::default())
+    }
+
+    async fn get_workflow_execution_history(
+        &self,
+        workflow_id: String,
+        run_id: Option<String>,
+        _page_token: Vec<u8>,
+    ) -> Result<GetWorkflowExecutionHistoryResponse> {
+        let st = self.state.lock();
+        let wf = run_id
+            .as_ref()
+            .and_then(|rid| st.workflows.get(rid))
+            .or_else(|| {
+                st.workflows
+                    .values()
+                    .find(|wf| wf.workflow_id == workflow_id)
+            })
+            .ok_or_else(|| tonic::Status::not_found("Workflow not found"))?;
+        let hist_info: HistoryInfo = wf
+            .history
+            .get_full_history_info()
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+        Ok(hist_info.into())
+    }
+
+    async fn respond_legacy_query(
+        &self,
+        _task_token: TaskToken,
+        _query_result: QueryResult,
+    ) -> Result<RespondQueryTaskCompletedResponse> {
+        Ok(RespondQueryTaskCompletedResponse::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temporal_sdk_core_protos::temporal::api::{
+        command::v1::StartTimerCommandAttributes, enums::v1::CommandType,
+    };
+
+    #[tokio::test]
+    async fn unsupported_commands_leave_the_task_outstanding() {
+        let client = TestEnvClient::new("q".to_string());
+        let run_id = client.start_workflow("wf".to_string(), "wf_type".to_string(), vec![]);
+        let wft = client
+            .poll_workflow_task("q".to_string(), false)
+            .await
+            .unwrap();
+        let completion = |commands| WorkflowTaskCompletion {
+            task_token: TaskToken(wft.task_token.clone()),
+            commands,
+            sticky_attributes: None,
+            query_responses: vec![],
+            return_new_workflow_task: false,
+            force_create_new_workflow_task: false,
+            sdk_metadata: Default::default(),
+        };
+        let timer: Command = CmdAttribs::StartTimerCommandAttributes(StartTimerCommandAttributes {
+            timer_id: "1".to_string(),
+            start_to_fire_timeout: Some(Duration::from_secs(1).into()),
+        })
+        .into();
+        let unsupported = Command {
+            command_type: CommandType::RequestCancelExternalWorkflowExecution as i32,
+            attributes: Some(
+                CmdAttribs::RequestCancelExternalWorkflowExecutionCommandAttributes(
+                    Default::default(),
+                ),
+            ),
+        };
+        let history_before = client.history(&run_id).unwrap();
+
+        let err = client
+            .complete_workflow_task(completion(vec![timer.clone(), unsupported]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unimplemented);
+        assert_eq!(client.history(&run_id).unwrap(), history_before);
+
+        // The same task can still be completed
+        client
+            .complete_workflow_task(completion(vec![timer]))
+            .await
+            .unwrap();
+        let events = client.history(&run_id).unwrap().events;
+        assert_eq!(events.last().unwrap().event_type(), EventType::TimerStarted);
+    }
+}
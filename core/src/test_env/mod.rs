@@ -0,0 +1,144 @@
+//! This module implements an in-process stand-in for the Temporal server which workflows can be
+//! run against in tests. Time only passes when everything is waiting on a timer, at which point it
+//! skips straight to the next one firing, so workflows which sleep for days complete in
+//! milliseconds.
+
+mod client;
+
+pub(crate) use client::TestEnvClient;
+
+use crate::{telemetry::metrics::MetricsContext, Worker, WorkerClientBag, WorkerConfig};
+use std::{sync::Arc, time::SystemTime};
+use temporal_sdk_core_api::Worker as WorkerTrait;
+use temporal_sdk_core_protos::{
+    coresdk::common::Payload,
+    temporal::api::{failure::v1::Failure, history::v1::History},
+};
+
+/// Stands in for an activity when the environment schedules one of its type. Called once per
+/// attempt, which resolves immediately.
+pub(crate) type ActivityStub =
+    Arc<dyn Fn(&ActivityStubCall) -> Result<Payload, Failure> + Send + Sync>;
+
+/// One attempt at running a stubbed activity
+#[derive(Debug, Clone)]
+pub struct ActivityStubCall {
+    /// The run id of the workflow which scheduled the activity
+    pub run_id: String,
+    /// The activity's id within its workflow
+    pub activity_id: String,
+    /// The activity's type
+    pub activity_type: String,
+    /// The input the activity was scheduled with
+    pub input: Vec<Payload>,
+    /// Which attempt this is, starting from 1
+    pub attempt: u32,
+}
+
+/// How a workflow run by a [TestWorkflowEnvironment] ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestWorkflowResult {
+    /// The workflow completed with the provided result
+    Completed(Vec<Payload>),
+    /// The workflow failed
+    Failed(Failure),
+    /// The workflow was cancelled
+    Cancelled,
+    /// The workflow continued as new. The new run is not started by the environment.
+    ContinuedAsNew,
+    /// A workflow task failed, ex: because of nondeterminism or a panic in workflow code. Where the
+    /// server would retry the task forever, the environment gives up on the workflow instead.
+    WorkflowTaskFailed(Failure),
+}
+
+/// Runs workflows against an in-process, time-skipping stand-in for the server, so they can be
+/// tested without one.
+///
+/// Histories are generated from the commands the worker sends, one event at a time, as the server
+/// would. Activities are dispatched to the worker, unless a stub has been registered for their type
+/// with [TestWorkflowEnvironment::register_activity_stub]. The environment keeps a virtual clock,
+/// which starts at the current time and only moves when every started workflow is blocked on a
+/// timer, at which point it jumps to the next timer due to fire.
+///
+/// Lang drives [TestWorkflowEnvironment::worker] like any other worker, after starting workflows
+/// with [TestWorkflowEnvironment::start_workflow]. The worker shuts itself down once every started
+/// workflow has closed. Child workflows, signals, and cancelling external workflows are not
+/// supported.
+pub struct TestWorkflowEnvironment {
+    worker: Arc<Worker>,
+    client: TestEnvClient,
+}
+
+impl TestWorkflowEnvironment {
+    /// Create an environment which runs workflows on a worker with the provided configuration
+    pub fn new(config: WorkerConfig) -> Self {
+        info!(
+            task_queue = config.task_queue.as_str(),
+            "Registering time-skipping test environment"
+        );
+        let client = TestEnvClient::new(config.task_queue.clone());
+        let namespace = config.namespace.clone();
+        let mut worker = Worker::new(
+            config,
+            None,
+            None,
+            Arc::new(WorkerClientBag::new(Box::new(client.clone()), namespace)),
+            MetricsContext::default(),
+        );
+        let hook_client = client.clone();
+        worker.set_post_activate_hook(move |worker| {
+            if hook_client.all_workflows_closed() {
+                worker.initiate_shutdown();
+            }
+        });
+        Self {
+            worker: Arc::new(worker),
+            client,
+        }
+    }
+
+    /// The worker lang should poll to run the environment's workflows
+    pub fn worker(&self) -> Arc<Worker> {
+        self.worker.clone()
+    }
+
+    /// Start a workflow of the provided type with the provided input, returning its run id.
+    /// Workflows must be started before the worker stops, which it does as soon as every workflow
+    /// started so far has closed.
+    pub fn start_workflow(
+        &self,
+        workflow_id: impl Into<String>,
+        workflow_type: impl Into<String>,
+        input: Vec<Payload>,
+    ) -> String {
+        self.client
+            .start_workflow(workflow_id.into(), workflow_type.into(), input)
+    }
+
+    /// Resolve every activity of the provided type with the result of calling `stub`, rather than
+    /// dispatching it to the worker. Failed attempts are retried according to the activity's retry
+    /// policy, with backoffs elapsing on the virtual clock.
+    pub fn register_activity_stub(
+        &self,
+        activity_type: impl Into<String>,
+        stub: impl Fn(&ActivityStubCall) -> Result<Payload, Failure> + Send + Sync + 'static,
+    ) {
+        self.client
+            .register_stub(activity_type.into(), Arc::new(stub));
+    }
+
+    /// The current time on the environment's virtual clock
+    pub fn now(&self) -> SystemTime {
+        self.client.now()
+    }
+
+    /// How the workflow run with the provided id ended, or `None` if it is still running
+    pub fn workflow_result(&self, run_id: &str) -> Option<TestWorkflowResult> {
+        self.client.workflow_result(run_id)
+    }
+
+    /// The history generated so far for the workflow run with the provided id
+    pub fn history(&self, run_id: &str) -> Option<History> {
+        self.client.history(run_id)
+    }
+}
@@ -3,7 +3,7 @@ pub(crate) use temporal_sdk_core_test_utils::canned_histories;
 use crate::{
     pollers::{BoxedActPoller, BoxedPoller, BoxedWFPoller, MockManualPoller, MockPoller},
     replay::TestHistoryBuilder,
-    sticky_q_name_for_worker,
+    session_q_name_for_worker, sticky_q_name_for_worker,
     worker::client::{mocks::mock_workflow_client, MockWorkerClient, WorkerClient},
     workflow::WorkflowCachingPolicy,
     TaskToken, Worker, WorkerClientBag, WorkerConfig, WorkerConfigBuilder,
@@ -96,9 +96,11 @@ pub(crate) fn build_fake_worker(
 
 pub(crate) fn mock_worker(mocks: MocksHolder) -> Worker {
     let sticky_q = sticky_q_name_for_worker("unit-test", &mocks.mock_worker.config);
+    let session_q = session_q_name_for_worker("unit-test", &mocks.mock_worker.config);
     Worker::new_with_pollers(
         mocks.mock_worker.config,
         sticky_q,
+        session_q,
         Arc::new(mocks.client_bag),
         mocks.mock_worker.wf_poller,
         mocks.mock_worker.act_poller,
@@ -1,15 +1,23 @@
 mod activity_heartbeat_manager;
+mod heartbeat_checkpoints;
 mod local_activities;
+mod rate_limits;
+mod sessions;
+
+pub use heartbeat_checkpoints::FileHeartbeatCheckpointStore;
+pub(crate) use rate_limits::ActivityRateLimiter;
+pub(crate) use sessions::SessionManager;
 
 pub(crate) use local_activities::{
     DispatchOrTimeoutLA, ExecutingLAId, LACompleteAction, LocalActRequest,
-    LocalActivityExecutionResult, LocalActivityManager, LocalActivityResolution,
-    LocalInFlightActInfo, NewLocalAct,
+    LocalActivityExecutionResult, LocalActivityManager, LocalActivityManagerOptions,
+    LocalActivityResolution, LocalInFlightActInfo, NewLocalAct,
 };
 
 use crate::{
     abstractions::MeteredSemaphore,
     pollers::BoxedActPoller,
+    retry_logic::RetryPolicyExt,
     telemetry::metrics::{activity_type, activity_worker_type, workflow_type, MetricsContext},
     worker::{
         activities::activity_heartbeat_manager::ActivityHeartbeatError,
@@ -22,20 +30,31 @@ use dashmap::DashMap;
 use std::{
     convert::TryInto,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
+};
+use temporal_sdk_core_api::worker::{
+    HeartbeatCheckpoint, HeartbeatCheckpointKey, HeartbeatCheckpointStore, WorkerConfig,
+    SESSION_CREATION_ACTIVITY_TYPE, SESSION_KEEPALIVE_ACTIVITY_TYPE,
 };
 use temporal_sdk_core_protos::{
     coresdk::{
         activity_result::{self as ar, activity_execution_result as aer},
         activity_task::{ActivityCancelReason, ActivityTask},
-        ActivityHeartbeat,
+        common::RetryPolicy,
+        ActivityHeartbeat, IntoPayloadsExt,
     },
     temporal::api::{
         failure::v1::{failure::FailureInfo, CanceledFailureInfo, Failure},
         workflowservice::v1::PollActivityTaskQueueResponse,
     },
 };
-use tokio::sync::Notify;
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex, Notify, Semaphore,
+    },
+    task::JoinHandle,
+};
 
 #[derive(Debug, derive_more::Constructor)]
 struct PendingActivityCancel {
@@ -63,6 +82,13 @@ struct RemoteInFlightActInfo {
     /// we have learned from heartbeating and issued a cancel task, in which case we may simply
     /// discard the reply.
     pub known_not_found: bool,
+    /// Set if this is a session keepalive activity, which core runs itself rather than lang
+    pub session_id: Option<String>,
+    /// Set if the worker checkpoints heartbeats, along with the attempt being run
+    pub checkpoint_key: Option<(HeartbeatCheckpointKey, u32)>,
+    /// Used to determine if a failed attempt was the activity's last, and so its checkpoint
+    /// can be dropped
+    pub retry_policy: Option<RetryPolicy>,
 }
 impl RemoteInFlightActInfo {
     fn new(
@@ -79,13 +105,26 @@ impl RemoteInFlightActInfo {
             heartbeat_timeout,
             issued_cancel_to_lang: false,
             known_not_found: false,
+            session_id: None,
+            checkpoint_key: None,
+            retry_policy: None,
         }
     }
+
+    /// How long lang may go without heartbeating before we locally cancel the activity. `None`
+    /// if the activity has no (or a zero, see [WorkerActivityTasks::record_heartbeat]) heartbeat
+    /// timeout.
+    fn heartbeat_deadline(&self) -> Option<Duration> {
+        self.heartbeat_timeout
+            .clone()
+            .and_then(|d| d.try_into().ok())
+            .filter(|d: &Duration| !d.is_zero())
+    }
 }
 
 pub(crate) struct WorkerActivityTasks {
     /// Centralizes management of heartbeat issuing / throttling
-    heartbeat_manager: ActivityHeartbeatManager,
+    heartbeat_manager: Arc<ActivityHeartbeatManager>,
     /// Activities that have been issued to lang but not yet completed
     outstanding_activity_tasks: DashMap<TaskToken, RemoteInFlightActInfo>,
     /// Buffers activity task polling in the event we need to return a cancellation while a poll is
@@ -95,6 +134,26 @@ pub(crate) struct WorkerActivityTasks {
     activities_semaphore: MeteredSemaphore,
     /// Wakes every time an activity is removed from the outstanding map
     complete_notify: Notify,
+    /// Task tokens lang heartbeated which we are not tracking. Lang is told about these with
+    /// `NotFound` cancels, rather than the heartbeat being silently dropped.
+    unknown_heartbeats_tx: UnboundedSender<TaskToken>,
+    unknown_heartbeats_rx: Mutex<UnboundedReceiver<TaskToken>>,
+    /// Manages activity sessions hosted by this worker, if it hosts any
+    sessions: Option<Arc<SessionManager>>,
+    /// Heartbeats session keepalives, independently of whether lang is polling for activities
+    session_keepalive_task: Option<JoinHandle<()>>,
+    /// Enforces this worker's own limits on how quickly it starts activities
+    rate_limiter: ActivityRateLimiter,
+    /// Tasks which were held back by a rate limit. They do not hold an activity slot while waiting,
+    /// and their tokens are sent to `throttled_tasks_tx` once they may start.
+    throttled_tasks: DashMap<TaskToken, PollActivityTaskQueueResponse>,
+    /// Bounds how many tasks may be held back at once, since they don't hold activity slots
+    throttled_capacity: Semaphore,
+    throttled_tasks_tx: UnboundedSender<TaskToken>,
+    throttled_tasks_rx: Mutex<UnboundedReceiver<TaskToken>>,
+    client: Arc<WorkerClientBag>,
+    /// Persists the latest heartbeat details of activities, if configured
+    checkpoint_store: Option<Arc<dyn HeartbeatCheckpointStore>>,
 
     metrics: MetricsContext,
 
@@ -104,26 +163,60 @@ pub(crate) struct WorkerActivityTasks {
 
 impl WorkerActivityTasks {
     pub(crate) fn new(
-        max_activity_tasks: usize,
+        config: &WorkerConfig,
         poller: BoxedActPoller,
         client: Arc<WorkerClientBag>,
         metrics: MetricsContext,
-        max_heartbeat_throttle_interval: Duration,
-        default_heartbeat_throttle_interval: Duration,
+        sessions: Option<SessionManager>,
     ) -> Self {
+        let (unknown_heartbeats_tx, unknown_heartbeats_rx) = unbounded_channel();
+        let (throttled_tasks_tx, throttled_tasks_rx) = unbounded_channel();
+        let heartbeat_manager = Arc::new(ActivityHeartbeatManager::new(client.clone()));
+        let sessions = sessions.map(Arc::new);
+        let session_keepalive_task = sessions.clone().map(|sessions| {
+            let heartbeat_manager = heartbeat_manager.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (task_token, interval) = sessions.next_heartbeat_due().await;
+                    let hb = ActivityHeartbeat {
+                        task_token: task_token.0,
+                        details: vec![],
+                    };
+                    match heartbeat_manager.record(hb, interval) {
+                        Err(ActivityHeartbeatError::ShuttingDown) => break,
+                        Err(e) => warn!(error = ?e, "Failed to heartbeat session keepalive"),
+                        Ok(_) => {}
+                    }
+                }
+            })
+        });
         Self {
-            heartbeat_manager: ActivityHeartbeatManager::new(client),
+            heartbeat_manager,
             outstanding_activity_tasks: Default::default(),
             poller,
             activities_semaphore: MeteredSemaphore::new(
-                max_activity_tasks,
+                config.max_outstanding_activities,
                 metrics.with_new_attrs([activity_worker_type()]),
                 MetricsContext::available_task_slots,
             ),
             complete_notify: Notify::new(),
+            unknown_heartbeats_tx,
+            unknown_heartbeats_rx: Mutex::new(unknown_heartbeats_rx),
+            sessions,
+            session_keepalive_task,
+            rate_limiter: ActivityRateLimiter::new(
+                config.max_worker_activities_per_second,
+                &config.max_activities_per_second_by_type,
+            ),
+            throttled_tasks: Default::default(),
+            throttled_capacity: Semaphore::new(config.max_outstanding_activities),
+            throttled_tasks_tx,
+            throttled_tasks_rx: Mutex::new(throttled_tasks_rx),
+            client,
+            checkpoint_store: config.heartbeat_checkpoint_store.clone(),
             metrics,
-            max_heartbeat_throttle_interval,
-            default_heartbeat_throttle_interval,
+            max_heartbeat_throttle_interval: config.max_heartbeat_throttle_interval,
+            default_heartbeat_throttle_interval: config.default_heartbeat_throttle_interval,
         }
     }
 
@@ -131,16 +224,49 @@ impl WorkerActivityTasks {
         self.poller.notify_shutdown();
     }
 
-    /// Wait for all outstanding activity tasks to finish
+    /// Wait for all outstanding activity tasks to finish. Sessions hosted by this worker are
+    /// ended, since nothing would otherwise ever finish their keepalive activities. Tasks still
+    /// held back by a rate limit are failed, so that the server may retry them elsewhere.
+    ///
+    /// Throttled heartbeats are flushed first, so that the server has the latest details of
+    /// activities which are still running should the process be stopped before they finish.
     pub(crate) async fn wait_all_finished(&self) {
+        self.heartbeat_manager.flush_pending().await;
+        self.fail_throttled_tasks().await;
+        if let Some(sessions) = self.sessions.as_ref() {
+            for task_token in sessions.keepalive_tokens() {
+                let failure =
+                    Failure::application_failure("Worker is shutting down".to_string(), true);
+                if let Err(e) = self
+                    .complete(
+                        task_token,
+                        aer::Status::Failed(ar::Failure {
+                            failure: Some(failure),
+                        }),
+                        &**self.client,
+                    )
+                    .await
+                {
+                    warn!(error = ?e, "Failed to end session while shutting down");
+                }
+            }
+        }
         while !self.outstanding_activity_tasks.is_empty() {
             self.complete_notify.notified().await
         }
     }
 
     pub(crate) async fn shutdown(self) {
+        if let Some(task) = self.session_keepalive_task {
+            task.abort();
+        }
         self.poller.shutdown_box().await;
         self.heartbeat_manager.shutdown().await;
+        if let Some(store) = self.checkpoint_store.as_ref() {
+            if let Err(e) = store.flush() {
+                warn!(error = ?e, "Failed to flush activity heartbeat checkpoints");
+            }
+        }
     }
 
     /// Wait until not at the outstanding activity limit, and then poll for an activity task.
@@ -156,7 +282,23 @@ impl WorkerActivityTasks {
                 .acquire()
                 .await
                 .expect("outstanding activity semaphore not closed");
-            (self.poller.poll().await, sem)
+            // Tasks held back by a rate limit gave up their slot while waiting, and now take one
+            // back. New tasks are only polled for once the overall limit would let them start.
+            let work = tokio::select! {
+                biased;
+
+                Some(work) = self.next_throttled_task() => Ok(work),
+                work = async {
+                    let capacity = self
+                        .throttled_capacity
+                        .acquire()
+                        .await
+                        .expect("throttled task semaphore not closed");
+                    self.rate_limiter.overall_available().await;
+                    (self.poller.poll().await, capacity)
+                } => Err(work),
+            };
+            (work, sem)
         };
 
         tokio::select! {
@@ -165,7 +307,18 @@ impl WorkerActivityTasks {
             cancel_task = self.next_pending_cancel_task() => {
                 cancel_task
             }
+            Some(task_token) = self.next_unknown_heartbeat() => {
+                Ok(Some(ActivityTask::cancel_from_ids(task_token.0, ActivityCancelReason::NotFound)))
+            }
             (work, sem) = poll_with_semaphore => {
+                let work = match work {
+                    Ok(throttled) => {
+                        sem.forget();
+                        return Ok(Some(self.start_activity(throttled)));
+                    }
+                    Err(polled) => polled,
+                };
+                let (work, throttled_capacity) = work;
                 match work {
                     Some(Ok(work)) => {
                         if work == PollActivityTaskQueueResponse::default() {
@@ -179,17 +332,50 @@ impl WorkerActivityTasks {
                                 .act_sched_to_start_latency(dur);
                         }
 
-                        self.outstanding_activity_tasks.insert(
-                            work.task_token.clone().into(),
-                            RemoteInFlightActInfo::new(
-                                work.activity_type.clone().unwrap_or_default().name,
-                                work.workflow_type.clone().unwrap_or_default().name,
-                                work.heartbeat_timeout.clone()
-                            ),
-                        );
+                        match work.activity_type.as_ref().map(|at| at.name.as_str()) {
+                            Some(SESSION_CREATION_ACTIVITY_TYPE) => {
+                                // Dropping the permit, since core is done with this task right away
+                                self.create_session(work).await;
+                                return Ok(None);
+                            }
+                            Some(SESSION_KEEPALIVE_ACTIVITY_TYPE) => {
+                                // Sessions are limited separately, they do not take activity slots
+                                self.start_session_keepalive(work).await;
+                                return Ok(None);
+                            }
+                            _ => {}
+                        }
+
+                        // Rate limits are only charged for real tasks, once they've been received.
+                        // The server has already started the task, so it is given back rather
+                        // than held for longer than it could possibly wait.
+                        let act_type = work.activity_type.clone().unwrap_or_default().name;
+                        let throttle_for =
+                            match self.rate_limiter.try_reserve(&act_type, max_throttle_wait(&work)) {
+                                Some(throttle_for) => throttle_for,
+                                None => {
+                                    self.give_back_throttled_task(work).await;
+                                    return Ok(None);
+                                }
+                            };
+                        if !throttle_for.is_zero() {
+                            self.metrics
+                                .with_new_attrs([activity_type(act_type)])
+                                .act_throttled_latency(throttle_for);
+                            // The slot is returned (by dropping the permit) while waiting
+                            let task_token = TaskToken(work.task_token.clone());
+                            self.throttled_tasks.insert(task_token.clone(), work);
+                            throttled_capacity.forget();
+                            let tx = self.throttled_tasks_tx.clone();
+                            tokio::spawn(async move {
+                                tokio::time::sleep(throttle_for).await;
+                                let _ = tx.send(task_token);
+                            });
+                            return Ok(None);
+                        }
                         // Only permanently take a permit in the event the poll finished properly
                         sem.forget();
-                        Ok(Some(ActivityTask::start_from_poll_resp(work)))
+                        Ok(Some(self.start_activity(work)))
                     }
                     None => {
                         Err(PollActivityError::ShutDown)
@@ -212,9 +398,43 @@ impl WorkerActivityTasks {
                 workflow_type(act_info.base.workflow_type.clone()),
             ]);
             act_metrics.act_execution_latency(act_info.base.start_time.elapsed());
-            self.activities_semaphore.add_permit();
+            if let Some(session_id) = act_info.session_id.as_ref() {
+                if let Some(sessions) = self.sessions.as_ref() {
+                    sessions.end(session_id);
+                }
+            } else {
+                self.activities_semaphore.add_permit();
+            }
             self.heartbeat_manager.evict(task_token.clone()).await;
             let known_not_found = act_info.known_not_found;
+            // Checkpoints are kept for failed activities which may be retried. Ones which time out
+            // or are retried elsewhere are left for the store to expire.
+            if let (Some(store), Some((key, attempt))) = (
+                self.checkpoint_store.as_ref(),
+                act_info.checkpoint_key.as_ref(),
+            ) {
+                let will_run_again = match &status {
+                    aer::Status::Completed(_) | aer::Status::Cancelled(_) => false,
+                    aer::Status::Failed(ar::Failure { failure }) => {
+                        match act_info.retry_policy.as_ref() {
+                            Some(rp) => rp
+                                .should_retry(
+                                    *attempt as usize,
+                                    failure.as_ref().and_then(|f| f.maybe_application_failure()),
+                                )
+                                .is_some(),
+                            // Without the policy we can't know, so leave it for the store
+                            None => true,
+                        }
+                    }
+                    aer::Status::WillCompleteAsync(_) => true,
+                };
+                if known_not_found || !will_run_again {
+                    if let Err(e) = store.remove(key) {
+                        warn!(error = ?e, "Failed to remove activity heartbeat checkpoint");
+                    }
+                }
+            }
             drop(act_info); // TODO: Get rid of dashmap. If we hold ref across await, bad stuff.
             self.complete_notify.notify_waiters();
 
@@ -277,11 +497,17 @@ impl WorkerActivityTasks {
         &self,
         details: ActivityHeartbeat,
     ) -> Result<(), ActivityHeartbeatError> {
-        // TODO: Propagate these back as cancels. Silent fails is too nonobvious
-        let heartbeat_timeout: Duration = self
-            .outstanding_activity_tasks
-            .get(&TaskToken(details.task_token.clone()))
-            .ok_or(ActivityHeartbeatError::UnknownActivity)?
+        let task_token = TaskToken(details.task_token.clone());
+        let act_info = match self.outstanding_activity_tasks.get(&task_token) {
+            Some(i) => i,
+            None => {
+                // Let lang know the activity is gone, so it doesn't keep running it for nothing
+                let _ = self.unknown_heartbeats_tx.send(task_token);
+                return Err(ActivityHeartbeatError::UnknownActivity);
+            }
+        };
+        let deadline = act_info.heartbeat_deadline();
+        let heartbeat_timeout: Duration = act_info
             .heartbeat_timeout
             .clone()
             // We treat None as 0 (even though heartbeat_timeout is never set to None by the server)
@@ -301,7 +527,217 @@ impl WorkerActivityTasks {
         };
         let throttle_interval =
             std::cmp::min(throttle_interval, self.max_heartbeat_throttle_interval);
-        self.heartbeat_manager.record(details, throttle_interval)
+        let checkpoint_key = act_info.checkpoint_key.clone();
+        drop(act_info);
+        if let (Some(store), Some((key, attempt))) =
+            (self.checkpoint_store.as_ref(), checkpoint_key)
+        {
+            let checkpoint = HeartbeatCheckpoint {
+                attempt,
+                details: details.details.clone(),
+            };
+            if let Err(e) = store.save(&key, &checkpoint) {
+                warn!(error = ?e, "Failed to checkpoint activity heartbeat");
+            }
+        }
+        self.heartbeat_manager.record(details, throttle_interval)?;
+        if let Some(deadline) = deadline {
+            self.heartbeat_manager.reset_deadline(task_token, deadline);
+        }
+        Ok(())
+    }
+
+    /// Reserve a session slot in response to a session creation activity, completing the activity
+    /// with the name of this worker's session task queue. If this worker can't host the session,
+    /// the activity is failed so that the server may retry it on another worker.
+    async fn create_session(&self, work: PollActivityTaskQueueResponse) {
+        let task_token = TaskToken(work.task_token);
+        let run_id = work.workflow_execution.unwrap_or_default().run_id;
+        let res = match self.sessions.as_ref() {
+            Some(sessions)
+                if sessions.try_reserve(SessionManager::session_id(&run_id, &work.activity_id)) =>
+            {
+                self.client
+                    .complete_activity_task(task_token, Some(sessions.task_queue().into()))
+                    .await
+                    .map(|_| ())
+            }
+            Some(_) => self
+                .client
+                .fail_activity_task(
+                    task_token,
+                    Some(Failure::application_failure(
+                        "Worker is hosting the maximum number of sessions".to_string(),
+                        false,
+                    )),
+                )
+                .await
+                .map(|_| ()),
+            None => self
+                .client
+                .fail_activity_task(
+                    task_token,
+                    Some(Failure::application_failure(
+                        "Worker does not host sessions".to_string(),
+                        false,
+                    )),
+                )
+                .await
+                .map(|_| ()),
+        };
+        if let Err(e) = res {
+            warn!(error = ?e, "Failed to respond to session creation activity");
+        }
+    }
+
+    /// Attach a session keepalive activity to its previously reserved session and start tracking
+    /// it. Core heartbeats it from here on.
+    async fn start_session_keepalive(&self, work: PollActivityTaskQueueResponse) {
+        let task_token: TaskToken = work.task_token.clone().into();
+        let run_id = work.workflow_execution.clone().unwrap_or_default().run_id;
+        let creation_id = work
+            .input
+            .as_ref()
+            .and_then(|i| i.payloads.first())
+            .map(|p| String::from_utf8_lossy(&p.data).to_string())
+            .unwrap_or_default();
+        let session_id = SessionManager::session_id(&run_id, &creation_id);
+        let mut act_info = RemoteInFlightActInfo::new(
+            SESSION_KEEPALIVE_ACTIVITY_TYPE.to_string(),
+            work.workflow_type.unwrap_or_default().name,
+            work.heartbeat_timeout.clone(),
+        );
+        let started = self.sessions.as_ref().map_or(false, |sessions| {
+            sessions.start_keepalive(
+                &session_id,
+                task_token.clone(),
+                work.heartbeat_timeout.and_then(|d| d.try_into().ok()),
+            )
+        });
+        if started {
+            act_info.session_id = Some(session_id);
+            self.outstanding_activity_tasks.insert(task_token, act_info);
+        } else if let Err(e) = self
+            .client
+            .fail_activity_task(
+                task_token,
+                Some(Failure::application_failure(
+                    "Session does not exist on this worker".to_string(),
+                    true,
+                )),
+            )
+            .await
+        {
+            warn!(error = ?e, "Failed to fail unknown session keepalive activity");
+        }
+    }
+
+    /// Begins tracking a polled activity which has taken a slot, and produces its start task
+    fn start_activity(&self, mut work: PollActivityTaskQueueResponse) -> ActivityTask {
+        let task_token: TaskToken = work.task_token.clone().into();
+        let mut act_info = RemoteInFlightActInfo::new(
+            work.activity_type.clone().unwrap_or_default().name,
+            work.workflow_type.clone().unwrap_or_default().name,
+            work.heartbeat_timeout.clone(),
+        );
+        if let Some(store) = self.checkpoint_store.as_ref() {
+            let key = HeartbeatCheckpointKey {
+                namespace: work.workflow_namespace.clone(),
+                workflow_id: work
+                    .workflow_execution
+                    .clone()
+                    .unwrap_or_default()
+                    .workflow_id,
+                run_id: work.workflow_execution.clone().unwrap_or_default().run_id,
+                activity_id: work.activity_id.clone(),
+            };
+            let attempt = work.attempt as u32;
+            match store.load(&key) {
+                // A checkpoint from an older attempt may be staler than what the server has, if
+                // another worker ran the attempts in between
+                Ok(Some(checkpoint)) if checkpoint.attempt + 1 >= attempt => {
+                    debug!(activity_id = %work.activity_id, attempt,
+                           "Resuming activity from its heartbeat checkpoint");
+                    work.heartbeat_details = checkpoint.details.into_payloads();
+                }
+                Ok(_) => {}
+                Err(e) => warn!(error = ?e, "Failed to load activity heartbeat checkpoint"),
+            }
+            act_info.checkpoint_key = Some((key, attempt));
+            act_info.retry_policy = work.retry_policy.clone().map(Into::into);
+        }
+        if let Some(hb_timeout) = act_info.heartbeat_deadline() {
+            self.heartbeat_manager
+                .reset_deadline(task_token.clone(), hb_timeout);
+        }
+        self.outstanding_activity_tasks.insert(task_token, act_info);
+        ActivityTask::start_from_poll_resp(work)
+    }
+
+    /// Resolves with throttled tasks once their rate limit allows them to start. Tasks which were
+    /// failed while throttled are skipped.
+    async fn next_throttled_task(&self) -> Option<PollActivityTaskQueueResponse> {
+        let mut rx = self.throttled_tasks_rx.lock().await;
+        loop {
+            let task_token = rx.recv().await?;
+            if let Some((_, work)) = self.throttled_tasks.remove(&task_token) {
+                self.throttled_capacity.add_permits(1);
+                return Some(work);
+            }
+        }
+    }
+
+    /// Fails a task which a rate limit would hold back for longer than its timeouts allow back to
+    /// the server as retryable, so that it may be retried later or elsewhere
+    async fn give_back_throttled_task(&self, work: PollActivityTaskQueueResponse) {
+        if let Err(e) = self
+            .client
+            .fail_activity_task(
+                TaskToken(work.task_token),
+                Some(Failure::application_failure(
+                    "Activity was rate limited by the worker for longer than its timeouts allow"
+                        .to_string(),
+                    false,
+                )),
+            )
+            .await
+        {
+            warn!(error = ?e, "Failed to give back rate limited activity");
+        }
+    }
+
+    /// Fail all tasks still held back by a rate limit back to the server
+    async fn fail_throttled_tasks(&self) {
+        let task_tokens: Vec<_> = self
+            .throttled_tasks
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        for task_token in task_tokens {
+            if self.throttled_tasks.remove(&task_token).is_none() {
+                // Started in the meantime
+                continue;
+            }
+            self.throttled_capacity.add_permits(1);
+            if let Err(e) = self
+                .client
+                .fail_activity_task(
+                    task_token,
+                    Some(Failure::application_failure(
+                        "Worker shut down before the activity could start".to_string(),
+                        false,
+                    )),
+                )
+                .await
+            {
+                warn!(error = ?e, "Failed to fail rate limited activity while shutting down");
+            }
+        }
+    }
+
+    /// Resolves with task tokens of activities lang heartbeated but which we are not tracking
+    async fn next_unknown_heartbeat(&self) -> Option<TaskToken> {
+        self.unknown_heartbeats_rx.lock().await.recv().await
     }
 
     async fn next_pending_cancel_task(&self) -> Result<Option<ActivityTask>, PollActivityError> {
@@ -322,6 +758,22 @@ impl WorkerActivityTasks {
                 if reason == ActivityCancelReason::NotFound {
                     details.known_not_found = true;
                 }
+                if details.session_id.is_some() {
+                    drop(details);
+                    // The workflow has ended the session (or the server no longer knows about it),
+                    // which core handles itself.
+                    self.complete(
+                        task_token,
+                        aer::Status::Cancelled(ar::Cancellation::from_details(None)),
+                        &**self.client,
+                    )
+                    .await
+                    .map_err(|e| {
+                        warn!(error = ?e, "Failed to complete cancelled session keepalive");
+                    })
+                    .ok();
+                    return Ok(None);
+                }
                 Ok(Some(ActivityTask::cancel_from_ids(task_token.0, reason)))
             } else {
                 debug!(task_token = ?task_token, "Unknown activity task when issuing cancel");
@@ -341,3 +793,30 @@ impl WorkerActivityTasks {
         self.activities_semaphore.sem.available_permits()
     }
 }
+
+/// The longest a polled task may be held back by a rate limit: it must not be kept waiting past
+/// any of its (already running) timeouts. `None` if the task has no timeouts.
+fn max_throttle_wait(work: &PollActivityTaskQueueResponse) -> Option<Duration> {
+    let as_std = |d: &Option<prost_types::Duration>| -> Option<Duration> {
+        d.clone()
+            .and_then(|d| d.try_into().ok())
+            .filter(|d: &Duration| !d.is_zero())
+    };
+    let sched_to_close_left = as_std(&work.schedule_to_close_timeout).map(|timeout| {
+        let elapsed = work
+            .scheduled_time
+            .clone()
+            .and_then(|t| SystemTime::try_from(t).ok())
+            .and_then(|t| t.elapsed().ok())
+            .unwrap_or_default();
+        timeout.saturating_sub(elapsed)
+    });
+    [
+        as_std(&work.start_to_close_timeout),
+        as_std(&work.heartbeat_timeout),
+        sched_to_close_left,
+    ]
+    .into_iter()
+    .flatten()
+    .min()
+}
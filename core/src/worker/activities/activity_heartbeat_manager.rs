@@ -4,7 +4,7 @@ use crate::{
 };
 use futures::StreamExt;
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
     sync::Arc,
     time::{self, Duration, Instant},
 };
@@ -42,6 +42,14 @@ enum HeartbeatAction {
     },
     CompleteReport(TaskToken),
     CompleteThrottle(TaskToken),
+    FlushPending {
+        on_complete: Arc<Notify>,
+    },
+    ResetDeadline {
+        token: TaskToken,
+        heartbeat_timeout: Duration,
+    },
+    DeadlineLapsed(TaskToken),
 }
 
 #[derive(Debug)]
@@ -60,6 +68,13 @@ enum HeartbeatExecutorAction {
         task_token: TaskToken,
         details: Vec<common::Payload>,
     },
+    /// Report heartbeats to the server without waiting out their throttle intervals
+    ReportAll {
+        reports: Vec<(TaskToken, Vec<common::Payload>)>,
+    },
+    /// Lang must heartbeat this task token within the duration, otherwise the activity is
+    /// cancelled locally. The timer is abandoned if the token is cancelled.
+    Deadline(TaskToken, Duration, CancellationToken),
 }
 
 /// Errors thrown when heartbeating
@@ -107,6 +122,22 @@ impl ActivityHeartbeatManager {
         Ok(())
     }
 
+    /// (Re)starts the local heartbeat deadline timer for the activity. If the timer lapses before
+    /// it is reset again or the activity is evicted, a cancel with
+    /// [ActivityCancelReason::HeartbeatTimeout] is dispatched. Once that has happened, further
+    /// resets for the activity are ignored, so the cancel is only ever issued once.
+    ///
+    /// The same caveat as [Self::record] applies - this must not be called after [Self::evict].
+    pub(super) fn reset_deadline(&self, task_token: TaskToken, heartbeat_timeout: Duration) {
+        if self.shutdown_token.is_cancelled() {
+            return;
+        }
+        let _ = self.heartbeat_tx.send(HeartbeatAction::ResetDeadline {
+            token: task_token,
+            heartbeat_timeout,
+        });
+    }
+
     /// Tell the heartbeat manager we are done forever with a certain task, so it may be forgotten.
     /// This will also force-flush the most recently provided details.
     /// Record *should* not be called with the same TaskToken after calling this.
@@ -125,6 +156,24 @@ impl ActivityHeartbeatManager {
         self.incoming_cancels.lock().await.recv().await
     }
 
+    /// Immediately sends the latest details of every activity whose heartbeats are currently
+    /// being throttled, resolving once they have been sent. Details recorded while a heartbeat is
+    /// already in flight are sent as soon as it completes, and are waited on as well.
+    pub(super) async fn flush_pending(&self) {
+        if self.shutdown_token.is_cancelled() {
+            return;
+        }
+        let completed = Arc::new(Notify::new());
+        let _ = self.heartbeat_tx.send(HeartbeatAction::FlushPending {
+            on_complete: completed.clone(),
+        });
+        // Follow-up reports are never sent once the manager shuts down
+        tokio::select! {
+            _ = completed.notified() => (),
+            _ = self.shutdown_token.cancelled() => (),
+        }
+    }
+
     // TODO: Can own self now!
     /// Initiates shutdown procedure by stopping lifecycle loop and awaiting for all in-flight
     /// heartbeat requests to be flushed to the server.
@@ -146,6 +195,9 @@ struct ActivityHeartbeatState {
     last_send_requested: Instant,
     throttle_interval: Duration,
     throttled_cancellation_token: Option<CancellationToken>,
+    /// If set, details recorded while a request is in flight are sent without throttling once it
+    /// completes
+    flush_after_report: bool,
 }
 
 impl ActivityHeartbeatState {
@@ -162,10 +214,23 @@ impl ActivityHeartbeatState {
     }
 }
 
+/// A request to flush pending heartbeats which is waiting on reports to complete
+#[derive(Debug)]
+struct PendingFlush {
+    /// How many more reports must complete for each task token before its details are flushed
+    remaining_reports: HashMap<TaskToken, u8>,
+    on_complete: Arc<Notify>,
+}
+
 #[derive(Debug)]
 struct HeartbeatStreamState {
     tt_to_state: HashMap<TaskToken, ActivityHeartbeatState>,
     tt_needs_flush: HashMap<TaskToken, Arc<Notify>>,
+    pending_flushes: Vec<PendingFlush>,
+    /// Cancellation tokens for the currently running heartbeat deadline timers
+    tt_deadlines: HashMap<TaskToken, CancellationToken>,
+    /// Activities which missed their heartbeat deadline and have already been cancelled
+    tt_deadline_lapsed: HashSet<TaskToken>,
     incoming_hbs: UnboundedReceiver<HeartbeatAction>,
     /// Token that can be used to cancel the entire stream.
     /// Requests to the server are not cancelled with this token.
@@ -181,6 +246,9 @@ impl HeartbeatStreamState {
                 cancellation_token: cancellation_token.clone(),
                 tt_to_state: Default::default(),
                 tt_needs_flush: Default::default(),
+                pending_flushes: Default::default(),
+                tt_deadlines: Default::default(),
+                tt_deadline_lapsed: Default::default(),
                 incoming_hbs,
             },
             heartbeat_tx,
@@ -201,6 +269,7 @@ impl HeartbeatStreamState {
                     last_recorded_details: None,
                     is_record_in_flight: true,
                     throttled_cancellation_token: None,
+                    flush_after_report: false,
                 };
                 e.insert(state);
                 Some(HeartbeatExecutorAction::Report {
@@ -221,14 +290,20 @@ impl HeartbeatStreamState {
         if let Some(not) = self.tt_needs_flush.remove(&tt) {
             not.notify_one();
         }
+        self.report_completed_for_flushes(&tt);
         if let Some(st) = self.tt_to_state.get_mut(&tt) {
             st.is_record_in_flight = false;
             let cancellation_token = self.cancellation_token.child_token();
             st.throttled_cancellation_token = Some(cancellation_token.clone());
+            let sleep_for = if std::mem::take(&mut st.flush_after_report) {
+                Duration::ZERO
+            } else {
+                st.get_throttle_sleep_duration()
+            };
             // Always sleep for simplicity even if the duration is 0
             Some(HeartbeatExecutorAction::Sleep(
                 tt.clone(),
-                st.get_throttle_sleep_duration(),
+                sleep_for,
                 cancellation_token,
             ))
         } else {
@@ -261,7 +336,103 @@ impl HeartbeatStreamState {
         }
     }
 
-    /// Activity should not be tracked anymore, cancel throttle timer if running.
+    /// Stop throttling every activity with details waiting to be sent, and report them all.
+    /// `on_complete` is notified once those reports, and the ones following reports which are
+    /// currently in flight, have completed.
+    fn flush_pending(&mut self, on_complete: Arc<Notify>) -> Option<HeartbeatExecutorAction> {
+        let mut reports = vec![];
+        let mut remaining_reports = HashMap::new();
+        for (tt, state) in self.tt_to_state.iter_mut() {
+            if state.last_recorded_details.is_none() {
+                continue;
+            }
+            if state.is_record_in_flight {
+                // Wait for the in flight report, and then the one sending the newer details
+                state.flush_after_report = true;
+                remaining_reports.insert(tt.clone(), 2);
+                continue;
+            }
+            remaining_reports.insert(tt.clone(), 1);
+            if let Some(cancel_tok) = state.throttled_cancellation_token.take() {
+                cancel_tok.cancel();
+            }
+            state.last_send_requested = Instant::now();
+            state.is_record_in_flight = true;
+            reports.push((
+                tt.clone(),
+                state
+                    .last_recorded_details
+                    .take()
+                    .expect("Details were just checked"),
+            ));
+        }
+        if remaining_reports.is_empty() {
+            on_complete.notify_one();
+        } else {
+            self.pending_flushes.push(PendingFlush {
+                remaining_reports,
+                on_complete,
+            });
+        }
+        if reports.is_empty() {
+            return None;
+        }
+        Some(HeartbeatExecutorAction::ReportAll { reports })
+    }
+
+    /// A report for the task token completed, notify any flushes no longer waiting on anything
+    fn report_completed_for_flushes(&mut self, tt: &TaskToken) {
+        let mut i = 0;
+        while i < self.pending_flushes.len() {
+            let flush = &mut self.pending_flushes[i];
+            if let Entry::Occupied(mut remaining) = flush.remaining_reports.entry(tt.clone()) {
+                *remaining.get_mut() -= 1;
+                if *remaining.get() == 0 {
+                    remaining.remove();
+                }
+            }
+            if flush.remaining_reports.is_empty() {
+                self.pending_flushes.swap_remove(i).on_complete.notify_one();
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Lang heartbeated (or started) an activity, restart its heartbeat deadline timer
+    fn reset_deadline(
+        &mut self,
+        tt: TaskToken,
+        heartbeat_timeout: Duration,
+    ) -> Option<HeartbeatExecutorAction> {
+        if self.tt_deadline_lapsed.contains(&tt) {
+            return None;
+        }
+        let cancellation_token = self.cancellation_token.child_token();
+        if let Some(old) = self
+            .tt_deadlines
+            .insert(tt.clone(), cancellation_token.clone())
+        {
+            old.cancel();
+        }
+        Some(HeartbeatExecutorAction::Deadline(
+            tt,
+            heartbeat_timeout,
+            cancellation_token,
+        ))
+    }
+
+    /// An activity's deadline timer lapsed and it has been cancelled. Stop tracking its deadline,
+    /// abandoning any timer a heartbeat raced to restart.
+    fn deadline_lapsed(&mut self, tt: TaskToken) -> Option<HeartbeatExecutorAction> {
+        if let Some(deadline) = self.tt_deadlines.remove(&tt) {
+            deadline.cancel();
+        }
+        self.tt_deadline_lapsed.insert(tt);
+        None
+    }
+
+    /// Activity should not be tracked anymore, cancel throttle and deadline timers if running.
     ///
     /// Will return a report action if there are recorded details present, to ensure we flush the
     /// latest details before we cease tracking this activity.
@@ -270,6 +441,10 @@ impl HeartbeatStreamState {
         tt: TaskToken,
         on_complete: Arc<Notify>,
     ) -> Option<HeartbeatExecutorAction> {
+        if let Some(deadline) = self.tt_deadlines.remove(&tt) {
+            deadline.cancel();
+        }
+        self.tt_deadline_lapsed.remove(&tt);
         if let Some(state) = self.tt_to_state.remove(&tt) {
             if let Some(cancel_tok) = state.throttled_cancellation_token {
                 let _ = cancel_tok.cancel();
@@ -323,7 +498,11 @@ impl ActivityHeartbeatManager {
                             HeartbeatAction::SendHeartbeat(hb) => hb_states.record(hb),
                             HeartbeatAction::CompleteReport(tt) => hb_states.handle_report_completed(tt),
                             HeartbeatAction::CompleteThrottle(tt) => hb_states.handle_throttle_completed(tt),
+                            HeartbeatAction::FlushPending{ on_complete } => hb_states.flush_pending(on_complete),
                             HeartbeatAction::Evict{ token, on_complete } => hb_states.evict(token, on_complete),
+                            HeartbeatAction::ResetDeadline{ token, heartbeat_timeout } =>
+                                hb_states.reset_deadline(token, heartbeat_timeout),
+                            HeartbeatAction::DeadlineLapsed(tt) => hb_states.deadline_lapsed(tt),
                         },
                         hb_states,
                     ))
@@ -345,41 +524,31 @@ impl ActivityHeartbeatManager {
                                 },
                             };
                         }
-                        HeartbeatExecutorAction::Report { task_token: tt, details } => {
-                            match sg
-                                .record_activity_heartbeat(tt.clone(), details.into_payloads())
-                                .await
-                            {
-                                Ok(RecordActivityTaskHeartbeatResponse { cancel_requested }) => {
-                                    if cancel_requested {
-                                        cancels_tx
-                                            .send(PendingActivityCancel::new(
-                                                tt.clone(),
-                                                ActivityCancelReason::Cancelled,
-                                            ))
-                                            .expect(
-                                                "Receive half of heartbeat cancels not blocked",
-                                            );
-                                    }
-                                }
-                                // Send cancels for any activity that learns its workflow already
-                                // finished (which is one thing not found implies - other reasons
-                                // would seem equally valid).
-                                Err(s) if s.code() == tonic::Code::NotFound => {
+                        HeartbeatExecutorAction::Deadline(tt, duration, cancellation_token) => {
+                            tokio::select! {
+                                _ = cancellation_token.cancelled() => (),
+                                _ = tokio::time::sleep(duration) => {
                                     debug!(task_token = %tt,
-                                           "Activity not found when recording heartbeat");
+                                           "Activity missed its heartbeat deadline");
+                                    let _ = heartbeat_tx
+                                        .send(HeartbeatAction::DeadlineLapsed(tt.clone()));
                                     cancels_tx
                                         .send(PendingActivityCancel::new(
-                                            tt.clone(),
-                                            ActivityCancelReason::NotFound,
+                                            tt,
+                                            ActivityCancelReason::HeartbeatTimeout,
                                         ))
                                         .expect("Receive half of heartbeat cancels not blocked");
-                                }
-                                Err(e) => {
-                                    warn!("Error when recording heartbeat: {:?}", e);
-                                }
+                                },
                             };
-                            let _ = heartbeat_tx.send(HeartbeatAction::CompleteReport(tt));
+                        }
+                        HeartbeatExecutorAction::Report { task_token, details } => {
+                            report(&sg, &heartbeat_tx, &cancels_tx, task_token, details).await;
+                        }
+                        HeartbeatExecutorAction::ReportAll { reports } => {
+                            futures::future::join_all(reports.into_iter().map(|(tt, details)| {
+                                report(&sg, &heartbeat_tx, &cancels_tx, tt, details)
+                            }))
+                            .await;
                         }
                     }
                 }
@@ -395,11 +564,53 @@ impl ActivityHeartbeatManager {
     }
 }
 
+/// Record a heartbeat with the server, queueing a cancel if it tells us the activity was cancelled
+/// or no longer exists
+async fn report(
+    client: &WorkerClientBag,
+    heartbeat_tx: &UnboundedSender<HeartbeatAction>,
+    cancels_tx: &UnboundedSender<PendingActivityCancel>,
+    tt: TaskToken,
+    details: Vec<common::Payload>,
+) {
+    match client
+        .record_activity_heartbeat(tt.clone(), details.into_payloads())
+        .await
+    {
+        Ok(RecordActivityTaskHeartbeatResponse { cancel_requested }) => {
+            if cancel_requested {
+                cancels_tx
+                    .send(PendingActivityCancel::new(
+                        tt.clone(),
+                        ActivityCancelReason::Cancelled,
+                    ))
+                    .expect("Receive half of heartbeat cancels not blocked");
+            }
+        }
+        // Send cancels for any activity that learns its workflow already finished (which is one
+        // thing not found implies - other reasons would seem equally valid).
+        Err(s) if s.code() == tonic::Code::NotFound => {
+            debug!(task_token = %tt, "Activity not found when recording heartbeat");
+            cancels_tx
+                .send(PendingActivityCancel::new(
+                    tt.clone(),
+                    ActivityCancelReason::NotFound,
+                ))
+                .expect("Receive half of heartbeat cancels not blocked");
+        }
+        Err(e) => {
+            warn!("Error when recording heartbeat: {:?}", e);
+        }
+    };
+    let _ = heartbeat_tx.send(HeartbeatAction::CompleteReport(tt));
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
-    use crate::worker::client::mocks::mock_workflow_client;
+    use crate::worker::client::mocks::{mock_manual_workflow_client, mock_workflow_client};
+    use futures::FutureExt;
     use std::time::Duration;
     use temporal_sdk_core_protos::{
         coresdk::common::Payload,
@@ -468,6 +679,56 @@ mod test {
         hm.shutdown().await;
     }
 
+    /// Throttled details are sent right away when pending heartbeats are flushed, including those
+    /// recorded while a heartbeat is in flight, and the flush waits for all of them to be sent
+    #[tokio::test]
+    async fn flush_pending_sends_throttled_details() {
+        let (sent_tx, mut sent_rx) = unbounded_channel();
+        let release_first = Arc::new(Notify::new());
+        let mut mock_client = mock_manual_workflow_client();
+        let release = release_first.clone();
+        mock_client
+            .expect_record_activity_heartbeat()
+            .returning(move |tt, details| {
+                let data = details.unwrap().payloads[0].data[0];
+                // The first report for the first token stays in flight until released
+                let hold = tt == vec![0].into() && data == 1;
+                sent_tx.send((tt, data)).unwrap();
+                let release = release.clone();
+                async move {
+                    if hold {
+                        release.notified().await;
+                    }
+                    Ok(RecordActivityTaskHeartbeatResponse::default())
+                }
+                .boxed()
+            })
+            .times(4);
+        let hm = ActivityHeartbeatManager::new(Arc::new(mock_client.into()));
+        for i in 0_u8..2 {
+            record_heartbeat(&hm, vec![i], 1, Duration::from_secs(100));
+            sent_rx.recv().await.unwrap();
+        }
+        // The first token's report is still in flight, the second's is in flight or throttled
+        record_heartbeat(&hm, vec![0], 2, Duration::from_secs(100));
+        record_heartbeat(&hm, vec![1], 2, Duration::from_secs(100));
+
+        let flush = hm.flush_pending();
+        tokio::pin!(flush);
+        assert!(futures::poll!(&mut flush).is_pending());
+        release_first.notify_one();
+        tokio::time::timeout(Duration::from_secs(1), flush)
+            .await
+            .expect("Flush must not wait out the throttle interval");
+        let mut flushed = vec![];
+        while let Ok(sent) = sent_rx.try_recv() {
+            flushed.push(sent);
+        }
+        flushed.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        assert_eq!(flushed, vec![(vec![0].into(), 2), (vec![1].into(), 2)]);
+        hm.shutdown().await;
+    }
+
     /// This test reports one heartbeat and waits for the throttle_interval to elapse before sending another
     #[tokio::test]
     async fn report_heartbeat_after_timeout() {
@@ -520,6 +781,55 @@ mod test {
         hm.shutdown().await;
     }
 
+    #[tokio::test]
+    async fn deadline_lapse_issues_cancel() {
+        let mut mock_client = mock_workflow_client();
+        mock_client.expect_record_activity_heartbeat().times(0);
+        let hm = ActivityHeartbeatManager::new(Arc::new(mock_client.into()));
+        let fake_task_token: TaskToken = vec![1, 2, 3].into();
+        hm.reset_deadline(fake_task_token.clone(), Duration::from_millis(10));
+        let pc = hm.next_pending_cancel().await.unwrap();
+        assert_eq!(pc.task_token, fake_task_token);
+        assert_eq!(pc.reason, ActivityCancelReason::HeartbeatTimeout);
+        hm.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn heartbeat_after_deadline_lapse_does_not_cancel_again() {
+        let mut mock_client = mock_workflow_client();
+        mock_client.expect_record_activity_heartbeat().times(0);
+        let hm = ActivityHeartbeatManager::new(Arc::new(mock_client.into()));
+        let fake_task_token: TaskToken = vec![1, 2, 3].into();
+        hm.reset_deadline(fake_task_token.clone(), Duration::from_millis(10));
+        let pc = hm.next_pending_cancel().await.unwrap();
+        assert_eq!(pc.reason, ActivityCancelReason::HeartbeatTimeout);
+        // Lang heartbeating before it processes the cancel must not re-arm the deadline
+        hm.reset_deadline(fake_task_token, Duration::from_millis(10));
+        let next = tokio::time::timeout(Duration::from_millis(100), hm.next_pending_cancel());
+        assert!(next.await.is_err());
+        hm.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn heartbeats_keep_deadline_from_lapsing() {
+        let mut mock_client = mock_workflow_client();
+        mock_client
+            .expect_record_activity_heartbeat()
+            .returning(|_, _| Ok(RecordActivityTaskHeartbeatResponse::default()));
+        let hm = ActivityHeartbeatManager::new(Arc::new(mock_client.into()));
+        let fake_task_token = vec![1, 2, 3];
+        for i in 0_u8..5 {
+            record_heartbeat(&hm, fake_task_token.clone(), i, Duration::from_millis(10));
+            hm.reset_deadline(fake_task_token.clone().into(), Duration::from_millis(50));
+            sleep(Duration::from_millis(20)).await;
+        }
+        hm.evict(fake_task_token.into()).await;
+        // Evicting abandons the deadline, so nothing may be dispatched
+        let next = tokio::time::timeout(Duration::from_millis(100), hm.next_pending_cancel());
+        assert!(next.await.is_err());
+        hm.shutdown().await;
+    }
+
     /// Recording new heartbeats after shutdown is not allowed, and will result in error.
     #[tokio::test]
     async fn record_after_shutdown() {
@@ -0,0 +1,215 @@
+use anyhow::anyhow;
+use prost::Message;
+use siphasher::sip128::{Hasher128, SipHasher13};
+use std::{
+    fs,
+    hash::Hasher,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use temporal_sdk_core_api::worker::{
+    HeartbeatCheckpoint, HeartbeatCheckpointKey, HeartbeatCheckpointStore,
+};
+use temporal_sdk_core_protos::{
+    coresdk::{FromPayloadsExt, IntoPayloadsExt},
+    temporal::api::common::v1::Payloads,
+};
+
+/// A [HeartbeatCheckpointStore] which keeps each activity's checkpoint in its own file within a
+/// directory. Checkpoints are written to a temporary file and synced before being renamed over
+/// the previous one, so a crash never leaves a partially written checkpoint behind.
+///
+/// Checkpoints which have not been written for longer than the store's max age are ignored, and
+/// deleted when the store is created or flushed.
+#[derive(Debug, Clone)]
+pub struct FileHeartbeatCheckpointStore {
+    dir: PathBuf,
+    max_age: Duration,
+}
+
+impl FileHeartbeatCheckpointStore {
+    /// Keep checkpoints in the provided directory, creating it if it does not exist. Checkpoints
+    /// expire after a week.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        Self::with_max_age(dir, Duration::from_secs(60 * 60 * 24 * 7))
+    }
+
+    /// Like [FileHeartbeatCheckpointStore::new], but checkpoints expire once they have not been
+    /// written for `max_age`
+    pub fn with_max_age(dir: impl Into<PathBuf>, max_age: Duration) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let store = Self { dir, max_age };
+        store.remove_expired()?;
+        Ok(store)
+    }
+
+    fn is_expired(&self, path: &Path) -> io::Result<bool> {
+        let modified = fs::metadata(path)?.modified()?;
+        // Clocks going backwards leave a checkpoint looking fresh, which is the safe direction
+        Ok(matches!(SystemTime::now().duration_since(modified), Ok(age) if age >= self.max_age))
+    }
+
+    fn remove_expired(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension() == Some("checkpoint".as_ref()) && self.is_expired(&path)? {
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn path_for(&self, key: &HeartbeatCheckpointKey) -> PathBuf {
+        // Ids may be long and contain any characters, so files are named by a hash of the key.
+        // The hash must be stable across releases, since checkpoints outlive the process.
+        let mut hasher = SipHasher13::new();
+        for part in [
+            &key.namespace,
+            &key.workflow_id,
+            &key.run_id,
+            &key.activity_id,
+        ] {
+            hasher.write(&(part.len() as u64).to_le_bytes());
+            hasher.write(part.as_bytes());
+        }
+        self.dir
+            .join(format!("{:032x}.checkpoint", hasher.finish128().as_u128()))
+    }
+}
+
+impl HeartbeatCheckpointStore for FileHeartbeatCheckpointStore {
+    fn save(
+        &self,
+        key: &HeartbeatCheckpointKey,
+        checkpoint: &HeartbeatCheckpoint,
+    ) -> Result<(), anyhow::Error> {
+        let path = self.path_for(key);
+        let tmp_path = path.with_extension("tmp");
+        let mut contents = checkpoint.attempt.to_le_bytes().to_vec();
+        checkpoint
+            .details
+            .clone()
+            .into_payloads()
+            .unwrap_or_default()
+            .encode(&mut contents)?;
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn load(
+        &self,
+        key: &HeartbeatCheckpointKey,
+    ) -> Result<Option<HeartbeatCheckpoint>, anyhow::Error> {
+        let path = self.path_for(key);
+        let expired = match self.is_expired(&path) {
+            Ok(expired) => expired,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if expired {
+            return Ok(None);
+        }
+        let contents = match fs::read(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if contents.len() < 4 {
+            return Err(anyhow!("Heartbeat checkpoint file is truncated"));
+        }
+        let (attempt, details) = contents.split_at(4);
+        Ok(Some(HeartbeatCheckpoint {
+            attempt: u32::from_le_bytes(attempt.try_into()?),
+            details: Vec::from_payloads(Some(Payloads::decode(details)?)),
+        }))
+    }
+
+    fn remove(&self, key: &HeartbeatCheckpointKey) -> Result<(), anyhow::Error> {
+        match fs::remove_file(self.path_for(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn flush(&self) -> Result<(), anyhow::Error> {
+        self.remove_expired()?;
+        // Checkpoint files are synced as they are written, but the renames are only durable once
+        // the directory is
+        fs::File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temporal_sdk_core_protos::coresdk::common::Payload;
+
+    fn key(activity_id: &str) -> HeartbeatCheckpointKey {
+        HeartbeatCheckpointKey {
+            namespace: "ns".to_string(),
+            workflow_id: "wf".to_string(),
+            run_id: "run".to_string(),
+            activity_id: activity_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn saves_loads_and_removes_checkpoints() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let store = FileHeartbeatCheckpointStore::new(&dir).unwrap();
+        assert_eq!(store.load(&key("1")).unwrap(), None);
+
+        let checkpoint = |attempt, data: &[u8]| HeartbeatCheckpoint {
+            attempt,
+            details: vec![Payload {
+                metadata: Default::default(),
+                data: data.to_vec(),
+            }],
+        };
+        store.save(&key("1"), &checkpoint(1, b"first")).unwrap();
+        store.save(&key("1"), &checkpoint(2, b"second")).unwrap();
+        store.save(&key("2"), &checkpoint(1, b"other")).unwrap();
+        store.flush().unwrap();
+        assert_eq!(
+            store.load(&key("1")).unwrap(),
+            Some(checkpoint(2, b"second"))
+        );
+        // Checkpoints survive the store being recreated, as they would a worker restart
+        let store = FileHeartbeatCheckpointStore::new(&dir).unwrap();
+        assert_eq!(
+            store.load(&key("2")).unwrap(),
+            Some(checkpoint(1, b"other"))
+        );
+
+        store.remove(&key("1")).unwrap();
+        store.remove(&key("1")).unwrap();
+        assert_eq!(store.load(&key("1")).unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expired_checkpoints_are_ignored_and_removed() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let store = FileHeartbeatCheckpointStore::new(&dir).unwrap();
+        let checkpoint = HeartbeatCheckpoint {
+            attempt: 1,
+            details: vec![],
+        };
+        store.save(&key("1"), &checkpoint).unwrap();
+        assert_eq!(store.load(&key("1")).unwrap(), Some(checkpoint));
+
+        let store = FileHeartbeatCheckpointStore::with_max_age(&dir, Duration::ZERO).unwrap();
+        assert_eq!(store.load(&key("1")).unwrap(), None);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
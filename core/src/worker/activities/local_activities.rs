@@ -1,11 +1,14 @@
 use crate::{
     abstractions::MeteredSemaphore, protosext::ValidScheduleLA, retry_logic::RetryPolicyExt,
-    MetricsContext, TaskToken,
+    telemetry::metrics::activity_type, worker::activities::ActivityRateLimiter, MetricsContext,
+    TaskToken,
 };
+use lru::LruCache;
 use parking_lot::Mutex;
 use std::{
-    collections::HashMap,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
     fmt::{Debug, Formatter},
+    hash::{Hash, Hasher},
     time::{Duration, Instant, SystemTime},
 };
 use temporal_sdk_core_protos::{
@@ -14,7 +17,7 @@ use temporal_sdk_core_protos::{
         activity_task::{activity_task, ActivityCancelReason, ActivityTask, Cancel, Start},
         common::WorkflowExecution,
     },
-    temporal::api::enums::v1::TimeoutType,
+    temporal::api::{enums::v1::TimeoutType, failure::v1::Failure},
 };
 use tokio::{
     sync::{
@@ -31,7 +34,8 @@ use tokio_util::sync::CancellationToken;
 pub(crate) enum DispatchOrTimeoutLA {
     /// Send the activity task to lang
     Dispatch(ActivityTask),
-    /// Notify the machines (and maybe lang) that this LA has timed out
+    /// Notify the machines (and maybe lang) that this LA has timed out, or that it was resolved as
+    /// cancelled without lang acknowledging the cancellation
     Timeout {
         run_id: String,
         resolution: LocalActivityResolution,
@@ -60,6 +64,16 @@ impl LocalActivityExecutionResult {
     pub(crate) fn timeout(tt: TimeoutType) -> Self {
         Self::TimedOut(ActFail::timeout(tt))
     }
+    /// The result recorded for a local activity which was promoted to a normal activity while it
+    /// was running or backing off, and so has no result of its own
+    pub(crate) fn promoted() -> Self {
+        Self::Failed(ActFail {
+            failure: Some(Failure::application_failure(
+                "Local activity was promoted to a normal activity".to_string(),
+                false,
+            )),
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -70,6 +84,8 @@ pub(crate) struct LocalActivityResolution {
     pub attempt: u32,
     pub backoff: Option<prost_types::Duration>,
     pub original_schedule_time: Option<SystemTime>,
+    /// Set if the activity was promoted to a normal activity rather than being resolved locally
+    pub promoted: bool,
 }
 
 #[derive(Clone)]
@@ -94,6 +110,12 @@ impl Debug for NewLocalAct {
 pub(crate) enum LocalActRequest {
     New(NewLocalAct),
     Cancel(ExecutingLAId),
+    /// Promote all of the run's local activities which are running or backing off to normal
+    /// activities
+    #[from(ignore)]
+    PromoteAll {
+        run_id: String,
+    },
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -107,6 +129,8 @@ pub(crate) struct LocalActivityManager {
     namespace: String,
     /// Constrains number of currently executing local activities
     semaphore: MeteredSemaphore,
+    /// Constrains how quickly local activities are started
+    rate_limiter: ActivityRateLimiter,
     /// Sink for new activity execution requests
     act_req_tx: UnboundedSender<NewOrRetry>,
     /// Cancels need a different queue since they should be taken first, and don't take a permit
@@ -117,6 +141,32 @@ pub(crate) struct LocalActivityManager {
     rcvs: tokio::sync::Mutex<RcvChans>,
     shutdown_complete_tok: CancellationToken,
     dat: Mutex<LAMData>,
+    metrics: MetricsContext,
+    options: LocalActivityManagerOptions,
+}
+
+/// Options tuning how a [LocalActivityManager] runs activities
+#[derive(Debug, Clone)]
+pub(crate) struct LocalActivityManagerOptions {
+    /// If set, activities whose retry backoff exceeds their local retry threshold are promoted to
+    /// normal activities rather than backing off with a timer
+    pub promote_past_retry_threshold: bool,
+    /// How many results of completed activities are kept for reuse if the run is reprocessed.
+    /// Zero disables reuse.
+    pub max_cached_results: usize,
+    /// How long lang has to acknowledge the cancellation of a running activity before it is
+    /// resolved as cancelled regardless
+    pub cancel_grace_period: Duration,
+}
+
+impl Default for LocalActivityManagerOptions {
+    fn default() -> Self {
+        Self {
+            promote_past_retry_threshold: false,
+            max_cached_results: 0,
+            cancel_grace_period: Duration::from_secs(10),
+        }
+    }
 }
 
 struct LAMData {
@@ -124,24 +174,104 @@ struct LAMData {
     outstanding_activity_tasks: HashMap<TaskToken, LocalInFlightActInfo>,
     id_to_tt: HashMap<ExecutingLAId, TaskToken>,
     /// Tasks for activities which are currently backing off. May be used to cancel retrying them.
-    backing_off_tasks: HashMap<ExecutingLAId, JoinHandle<()>>,
+    backing_off_tasks: HashMap<ExecutingLAId, BackingOffTask>,
     /// Tasks for timing out activities which are currently in the queue or dispatched.
     timeout_tasks: HashMap<ExecutingLAId, TimeoutBag>,
+    /// Tasks for force-resolving running activities lang has been asked to cancel, should it not
+    /// acknowledge the cancellation in time.
+    cancel_grace_tasks: HashMap<ExecutingLAId, JoinHandle<()>>,
+    /// Running activities lang has been asked to cancel so that they can be promoted to normal
+    /// activities. They are promoted once lang acknowledges the cancel, or the grace period ends.
+    promoting: HashSet<ExecutingLAId>,
+    /// Results of activities lang finished executing, kept so that if the workflow task which
+    /// would have recorded them fails, they are not executed again when the run is reprocessed.
+    /// `None` if caching is disabled.
+    completed_results: Option<LruCache<ExecutingLAId, CompletedLA>>,
     next_tt_num: u32,
 }
 
+/// What a kept result was produced by, so that it is only reused for the same request: the same
+/// activity and input, on the attempt it was scheduled with (which is greater than one when
+/// backing off with a timer). A reprocessed run may schedule something else at the same seq.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct CompletedLARequest {
+    activity_type: String,
+    activity_id: String,
+    attempt: u32,
+    input_hash: u64,
+}
+impl CompletedLARequest {
+    fn new(la: &NewLocalAct) -> Self {
+        let mut hasher = DefaultHasher::new();
+        for arg in &la.schedule_cmd.arguments {
+            let mut metadata: Vec<_> = arg.metadata.iter().collect();
+            metadata.sort();
+            metadata.hash(&mut hasher);
+            arg.data.hash(&mut hasher);
+        }
+        Self {
+            activity_type: la.schedule_cmd.activity_type.clone(),
+            activity_id: la.schedule_cmd.activity_id.clone(),
+            attempt: la.schedule_cmd.attempt.max(1),
+            input_hash: hasher.finish(),
+        }
+    }
+}
+
+/// Waits to send an activity's next attempt, after it failed or was held back by rate limiting
+struct BackingOffTask {
+    handle: JoinHandle<()>,
+    /// The attempt which will be made once the task fires
+    next_attempt: u32,
+    original_schedule_time: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+struct CompletedLA {
+    request: CompletedLARequest,
+    result: LocalActivityExecutionResult,
+    runtime: Duration,
+    /// The attempt which produced the result
+    attempt: u32,
+    original_schedule_time: SystemTime,
+}
+
 impl LAMData {
     fn gen_next_token(&mut self) -> TaskToken {
         self.next_tt_num += 1;
         TaskToken::new_local_activity_token(self.next_tt_num.to_le_bytes())
     }
+
+    fn remember_result(
+        &mut self,
+        info: &LocalInFlightActInfo,
+        result: &LocalActivityExecutionResult,
+    ) {
+        if let Some(cache) = self.completed_results.as_mut() {
+            cache.put(
+                ExecutingLAId {
+                    run_id: info.la_info.workflow_exec_info.run_id.clone(),
+                    seq_num: info.la_info.schedule_cmd.seq,
+                },
+                CompletedLA {
+                    request: CompletedLARequest::new(&info.la_info),
+                    result: result.clone(),
+                    runtime: info.dispatch_time.elapsed(),
+                    attempt: info.attempt,
+                    original_schedule_time: info.la_info.schedule_time,
+                },
+            );
+        }
+    }
 }
 
 impl LocalActivityManager {
     pub(crate) fn new(
         max_concurrent: usize,
         namespace: String,
+        rate_limiter: ActivityRateLimiter,
         metrics_context: MetricsContext,
+        options: LocalActivityManagerOptions,
     ) -> Self {
         let (act_req_tx, act_req_rx) = unbounded_channel();
         let (cancels_req_tx, cancels_req_rx) = unbounded_channel();
@@ -150,9 +280,10 @@ impl LocalActivityManager {
             namespace,
             semaphore: MeteredSemaphore::new(
                 max_concurrent,
-                metrics_context,
+                metrics_context.clone(),
                 MetricsContext::available_task_slots,
             ),
+            rate_limiter,
             act_req_tx,
             cancels_req_tx,
             complete_notify: Notify::new(),
@@ -167,17 +298,30 @@ impl LocalActivityManager {
                 id_to_tt: Default::default(),
                 backing_off_tasks: Default::default(),
                 timeout_tasks: Default::default(),
+                cancel_grace_tasks: Default::default(),
+                promoting: Default::default(),
+                completed_results: (options.max_cached_results > 0)
+                    .then(|| LruCache::new(options.max_cached_results)),
                 next_tt_num: 0,
             }),
+            metrics: metrics_context,
+            options,
         }
     }
 
     #[cfg(test)]
     fn test(max_concurrent: usize) -> Self {
+        Self::test_with(max_concurrent, Default::default())
+    }
+
+    #[cfg(test)]
+    fn test_with(max_concurrent: usize, options: LocalActivityManagerOptions) -> Self {
         Self::new(
             max_concurrent,
             "fake_ns".to_string(),
+            ActivityRateLimiter::new(None, &Default::default()),
             MetricsContext::default(),
+            options,
         )
     }
 
@@ -210,6 +354,45 @@ impl LocalActivityManager {
                         debug!("Tried to queue already-executing local activity {:?}", &id);
                         continue;
                     }
+                    // If lang already finished executing this activity, but the workflow task
+                    // which would have recorded its marker failed, reuse the result.
+                    let reusable =
+                        dlock
+                            .completed_results
+                            .as_mut()
+                            .and_then(|c| match c.get(&id) {
+                                Some(done) if done.request == CompletedLARequest::new(&act) => {
+                                    Some(done.clone())
+                                }
+                                Some(_) => {
+                                    debug!(
+                                    "Dropping result kept for a different local activity at {:?}",
+                                    &id
+                                );
+                                    c.pop(&id);
+                                    None
+                                }
+                                None => None,
+                            });
+                    if let Some(done) = reusable {
+                        debug!(
+                            "Reusing result of already-executed local activity {:?}",
+                            &id
+                        );
+                        self.metrics
+                            .with_new_attrs([activity_type(act.schedule_cmd.activity_type.clone())])
+                            .la_result_reused();
+                        immediate_resolutions.push(LocalActivityResolution {
+                            seq: id.seq_num,
+                            result: done.result,
+                            runtime: done.runtime,
+                            attempt: done.attempt,
+                            backoff: None,
+                            original_schedule_time: Some(done.original_schedule_time),
+                            promoted: false,
+                        });
+                        continue;
+                    }
                     // Pre-generate and insert the task token now, before we may or may not dispatch
                     // the activity, so we can enforce idempotency. Prevents two identical LAs
                     // ending up in the queue at once.
@@ -234,30 +417,63 @@ impl LocalActivityManager {
                     // First check if this ID is currently backing off, if so abort the backoff
                     // task
                     if let Some(t) = dlock.backing_off_tasks.remove(&id) {
-                        t.abort();
+                        t.handle.abort();
+                        dlock.id_to_tt.remove(&id);
+                        dlock.timeout_tasks.remove(&id);
                         immediate_resolutions.push(LocalActivityResolution {
                             seq: id.seq_num,
-                            result: LocalActivityExecutionResult::Cancelled(
-                                Cancellation::from_details(None),
-                            ),
+                            result: LocalActivityExecutionResult::empty_cancel(),
                             runtime: Duration::from_secs(0),
                             attempt: 0,
                             backoff: None,
                             original_schedule_time: None,
+                            promoted: false,
                         });
                         continue;
                     }
 
-                    if let Some(tt) = dlock.id_to_tt.get(&id) {
-                        self.cancels_req_tx
-                            .send(CancelOrTimeout::Cancel(ActivityTask {
-                                task_token: tt.0.clone(),
-                                variant: Some(activity_task::Variant::Cancel(Cancel {
-                                    reason: ActivityCancelReason::Cancelled as i32,
-                                })),
-                            }))
-                            .expect("Receive half of LA cancel channel cannot be dropped");
+                    let tt = match dlock.id_to_tt.get(&id) {
+                        Some(tt) => tt.clone(),
+                        None => continue,
+                    };
+                    // Activities still waiting in the queue have never been seen by lang, so they
+                    // can be resolved now, and will be dropped rather than dispatched.
+                    if !dlock.outstanding_activity_tasks.contains_key(&tt) {
+                        dlock.id_to_tt.remove(&id);
+                        dlock.timeout_tasks.remove(&id);
+                        immediate_resolutions.push(LocalActivityResolution {
+                            seq: id.seq_num,
+                            result: LocalActivityExecutionResult::empty_cancel(),
+                            runtime: Duration::from_secs(0),
+                            attempt: 0,
+                            backoff: None,
+                            original_schedule_time: None,
+                            promoted: false,
+                        });
+                        continue;
+                    }
+                    // Lang was already asked to stop running it. If that was to promote it, the
+                    // workflow no longer wants it run at all, so it resolves as cancelled.
+                    if dlock.cancel_grace_tasks.contains_key(&id) {
+                        dlock.promoting.remove(&id);
+                        continue;
                     }
+
+                    self.cancels_req_tx
+                        .send(CancelOrTimeout::Cancel(ActivityTask {
+                            task_token: tt.0,
+                            variant: Some(activity_task::Variant::Cancel(Cancel {
+                                reason: ActivityCancelReason::Cancelled as i32,
+                            })),
+                        }))
+                        .expect("Receive half of LA cancel channel cannot be dropped");
+                    // The workflow is resolved once lang acknowledges the cancel by completing the
+                    // activity. If it doesn't do so in time, resolve it as cancelled anyway.
+                    let jh = self.force_cancel_after_grace_period(id.clone());
+                    dlock.cancel_grace_tasks.insert(id, jh);
+                }
+                LocalActRequest::PromoteAll { run_id } => {
+                    immediate_resolutions.extend(self.promote_all(&run_id));
                 }
             }
         }
@@ -267,10 +483,21 @@ impl LocalActivityManager {
     /// Returns the next pending local-activity related action, or None if shutdown has initiated
     /// and there are no more remaining actions to take.
     pub(crate) async fn next_pending(&self) -> Option<DispatchOrTimeoutLA> {
+        loop {
+            if let Some(res) = self.next_pending_or_throttled().await? {
+                return Some(res);
+            }
+        }
+    }
+
+    /// Like [LocalActivityManager::next_pending], but returns `Some(None)` if the next new
+    /// activity had to be held back to respect rate limits.
+    async fn next_pending_or_throttled(&self) -> Option<Option<DispatchOrTimeoutLA>> {
         let new_or_retry = match self.rcvs.lock().await.next(&self.semaphore).await? {
             NewOrCancel::Cancel(c) => {
                 return match c {
-                    CancelOrTimeout::Cancel(c) => Some(DispatchOrTimeoutLA::Dispatch(c)),
+                    CancelOrTimeout::Cancel(c) => Some(Some(DispatchOrTimeoutLA::Dispatch(c))),
+                    CancelOrTimeout::ForceCancel(id) => Some(self.force_cancel(id)),
                     CancelOrTimeout::Timeout {
                         run_id,
                         resolution,
@@ -300,11 +527,11 @@ impl LocalActivityManager {
                         } else {
                             None
                         };
-                        Some(DispatchOrTimeoutLA::Timeout {
+                        Some(Some(DispatchOrTimeoutLA::Timeout {
                             run_id,
                             resolution,
                             task,
-                        })
+                        }))
                     }
                 };
             }
@@ -313,12 +540,14 @@ impl LocalActivityManager {
 
         // It is important that there are no await points after receiving from the channel, as
         // it would mean dropping this future would cause us to drop the activity request.
+        let was_throttled = matches!(new_or_retry, NewOrRetry::Throttled { .. });
         let (new_la, attempt) = match new_or_retry {
             NewOrRetry::New(n) => {
                 let explicit_attempt_num_or_1 = n.schedule_cmd.attempt.max(1);
                 (n, explicit_attempt_num_or_1)
             }
             NewOrRetry::Retry { in_flight, attempt } => (in_flight, attempt),
+            NewOrRetry::Throttled { in_flight, attempt } => (in_flight, attempt),
         };
         let orig = new_la.clone();
         let id = ExecutingLAId {
@@ -333,11 +562,21 @@ impl LocalActivityManager {
         // meaningful value.
         dat.backing_off_tasks.remove(&id);
 
+        // The activity was cancelled while it waited in the queue
+        if !dat.id_to_tt.contains_key(&id) {
+            debug!(
+                "Dropping local activity {:?} cancelled before dispatch",
+                &id
+            );
+            self.semaphore.add_permit();
+            return Some(None);
+        }
+
         // If this task sat in the queue for too long, return a timeout for it instead
         if let Some(s2s) = sa.schedule_to_start_timeout.as_ref() {
             let sat_for = new_la.schedule_time.elapsed().unwrap_or_default();
             if sat_for > *s2s {
-                return Some(DispatchOrTimeoutLA::Timeout {
+                return Some(Some(DispatchOrTimeoutLA::Timeout {
                     run_id: new_la.workflow_exec_info.run_id,
                     resolution: LocalActivityResolution {
                         seq: sa.seq,
@@ -346,9 +585,42 @@ impl LocalActivityManager {
                         attempt,
                         backoff: None,
                         original_schedule_time: Some(new_la.schedule_time),
+                        promoted: false,
                     },
                     task: None,
+                }));
+            }
+        }
+
+        // Activities coming back from being throttled already hold their rate limit reservation
+        if !was_throttled {
+            let throttle_for = self.rate_limiter.reserve(&sa.activity_type);
+            if !throttle_for.is_zero() {
+                self.metrics
+                    .with_new_attrs([activity_type(sa.activity_type.clone())])
+                    .act_throttled_latency(throttle_for);
+                // Give back the permit, it will be taken again once the activity may start
+                self.semaphore.add_permit();
+                let original_schedule_time = orig.schedule_time;
+                let send_chan = self.act_req_tx.clone();
+                let jh = tokio::spawn(async move {
+                    tokio::time::sleep(throttle_for).await;
+                    send_chan
+                        .send(NewOrRetry::Throttled {
+                            in_flight: orig,
+                            attempt,
+                        })
+                        .expect("Receive half of LA request channel cannot be dropped");
                 });
+                dat.backing_off_tasks.insert(
+                    id,
+                    BackingOffTask {
+                        handle: jh,
+                        next_attempt: attempt,
+                        original_schedule_time,
+                    },
+                );
+                return Some(None);
             }
         }
 
@@ -370,7 +642,7 @@ impl LocalActivityManager {
         }
 
         let (schedule_to_close, start_to_close) = sa.close_timeouts.into_sched_and_start();
-        Some(DispatchOrTimeoutLA::Dispatch(ActivityTask {
+        Some(Some(DispatchOrTimeoutLA::Dispatch(ActivityTask {
             task_token: tt.0,
             variant: Some(activity_task::Variant::Start(Start {
                 workflow_namespace: self.namespace.clone(),
@@ -391,7 +663,7 @@ impl LocalActivityManager {
                 retry_policy: Some(sa.retry_policy),
                 is_local: true,
             })),
-        }))
+        })))
     }
 
     /// Mark a local activity as having completed (pass, fail, or cancelled)
@@ -408,22 +680,44 @@ impl LocalActivityManager {
             };
             dlock.id_to_tt.remove(&exec_id);
             self.semaphore.add_permit();
+            let cancel_requested = dlock
+                .cancel_grace_tasks
+                .remove(&exec_id)
+                .map(|t| t.abort())
+                .is_some();
+            // Lang stopped running an activity being promoted. Unless it managed to finish first,
+            // its slot is only now free, and the normal activity can run in its place.
+            if dlock.promoting.remove(&exec_id)
+                && !matches!(status, LocalActivityExecutionResult::Completed(_))
+            {
+                self.complete_notify.notify_one();
+                return LACompleteAction::Promote(info);
+            }
 
             match status {
                 LocalActivityExecutionResult::Completed(_)
                 | LocalActivityExecutionResult::TimedOut(_)
                 | LocalActivityExecutionResult::Cancelled { .. } => {
                     // Timeouts are included in this branch since they are not retried
+                    if matches!(status, LocalActivityExecutionResult::Completed(_)) {
+                        dlock.remember_result(&info, status);
+                    }
                     self.complete_notify.notify_one();
                     LACompleteAction::Report(info)
                 }
                 LocalActivityExecutionResult::Failed(f) => {
-                    if let Some(backoff_dur) = info.la_info.schedule_cmd.retry_policy.should_retry(
-                        info.attempt as usize,
-                        f.failure
-                            .as_ref()
-                            .and_then(|f| f.maybe_application_failure()),
-                    ) {
+                    // Activities which fail after being asked to cancel are not retried
+                    let retry_after = if cancel_requested {
+                        None
+                    } else {
+                        info.la_info.schedule_cmd.retry_policy.should_retry(
+                            info.attempt as usize,
+                            f.failure
+                                .as_ref()
+                                .and_then(|f| f.maybe_application_failure()),
+                        )
+                    };
+                    if let Some(backoff_dur) = retry_after {
                         let will_use_timer =
                             backoff_dur > info.la_info.schedule_cmd.local_retry_threshold;
                         debug!(run_id = %info.la_info.workflow_exec_info.run_id,
@@ -433,6 +727,9 @@ impl LocalActivityManager {
                             "Local activity failed, will retry after backing off for {:?}",
                              backoff_dur
                         );
+                        if will_use_timer && self.options.promote_past_retry_threshold {
+                            return LACompleteAction::Promote(info);
+                        }
                         if will_use_timer {
                             // We want this to be reported, as the workflow will mark this
                             // failure down, then start a timer for backoff.
@@ -446,6 +743,8 @@ impl LocalActivityManager {
                         dlock.id_to_tt.insert(exec_id.clone(), tt);
 
                         // Send the retry request after waiting the backoff duration
+                        let next_attempt = info.attempt + 1;
+                        let original_schedule_time = info.la_info.schedule_time;
                         let send_chan = self.act_req_tx.clone();
                         let jh = tokio::spawn(async move {
                             tokio::time::sleep(backoff_dur).await;
@@ -453,14 +752,22 @@ impl LocalActivityManager {
                             send_chan
                                 .send(NewOrRetry::Retry {
                                     in_flight: info.la_info,
-                                    attempt: info.attempt + 1,
+                                    attempt: next_attempt,
                                 })
                                 .expect("Receive half of LA request channel cannot be dropped");
                         });
-                        dlock.backing_off_tasks.insert(exec_id, jh);
+                        dlock.backing_off_tasks.insert(
+                            exec_id,
+                            BackingOffTask {
+                                handle: jh,
+                                next_attempt,
+                                original_schedule_time,
+                            },
+                        );
 
                         LACompleteAction::WillBeRetried
                     } else {
+                        dlock.remember_result(&info, status);
                         LACompleteAction::Report(info)
                     }
                 }
@@ -470,6 +777,145 @@ impl LocalActivityManager {
         }
     }
 
+    /// Resolve a running activity whose cancellation lang did not acknowledge within the grace
+    /// period as cancelled, and stop tracking it. Returns `None` if lang has since completed it.
+    fn force_cancel(&self, id: ExecutingLAId) -> Option<DispatchOrTimeoutLA> {
+        let tt = self.dat.lock().id_to_tt.get(&id).cloned()?;
+        let result = LocalActivityExecutionResult::empty_cancel();
+        match self.complete(&tt, &result) {
+            LACompleteAction::Promote(info) => {
+                warn!(run_id = %id.run_id, seq_num = %id.seq_num,
+                      "Lang did not stop running local activity within the grace period, \
+                       promoting it anyway");
+                Some(DispatchOrTimeoutLA::Timeout {
+                    run_id: id.run_id,
+                    resolution: LocalActivityResolution {
+                        seq: id.seq_num,
+                        result: LocalActivityExecutionResult::promoted(),
+                        runtime: info.dispatch_time.elapsed(),
+                        attempt: info.attempt,
+                        backoff: None,
+                        original_schedule_time: Some(info.la_info.schedule_time),
+                        promoted: true,
+                    },
+                    task: None,
+                })
+            }
+            LACompleteAction::Report(info) => {
+                warn!(run_id = %id.run_id, seq_num = %id.seq_num,
+                      "Lang did not acknowledge local activity cancellation within the grace \
+                       period, resolving it as cancelled");
+                Some(DispatchOrTimeoutLA::Timeout {
+                    run_id: id.run_id,
+                    resolution: LocalActivityResolution {
+                        seq: id.seq_num,
+                        result,
+                        runtime: info.dispatch_time.elapsed(),
+                        attempt: info.attempt,
+                        backoff: None,
+                        original_schedule_time: Some(info.la_info.schedule_time),
+                        promoted: false,
+                    },
+                    task: None,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Drop the results kept for the run's local activities with the provided seqs. Should be
+    /// called once a workflow task recording their markers has been completed, since they will
+    /// never need to be reused.
+    pub(crate) fn forget_results(&self, run_id: &str, seqs: impl IntoIterator<Item = u32>) {
+        if let Some(cache) = self.dat.lock().completed_results.as_mut() {
+            for seq_num in seqs {
+                cache.pop(&ExecutingLAId {
+                    run_id: run_id.to_string(),
+                    seq_num,
+                });
+            }
+        }
+    }
+
+    /// Promotes the run's local activities which are running or backing off to normal
+    /// activities. Those backing off are stopped and resolutions promoting them are returned. Lang
+    /// is asked to cancel those which are running, and they are promoted once it has stopped
+    /// running them (or the cancel grace period elapses), so that the normal activity never runs
+    /// at the same time as the local one. Activities still waiting to be dispatched are left alone.
+    fn promote_all(&self, run_id: &str) -> Vec<LocalActivityResolution> {
+        let mut dlock = self.dat.lock();
+        let mut resolutions = vec![];
+
+        let backing_off: Vec<_> = dlock
+            .backing_off_tasks
+            .keys()
+            .filter(|id| id.run_id == run_id)
+            .cloned()
+            .collect();
+        for id in backing_off {
+            let task = dlock
+                .backing_off_tasks
+                .remove(&id)
+                .expect("Backing off activity was just found");
+            task.handle.abort();
+            dlock.id_to_tt.remove(&id);
+            dlock.timeout_tasks.remove(&id);
+            resolutions.push(LocalActivityResolution {
+                seq: id.seq_num,
+                result: LocalActivityExecutionResult::promoted(),
+                runtime: Duration::from_secs(0),
+                attempt: task.next_attempt - 1,
+                backoff: None,
+                original_schedule_time: Some(task.original_schedule_time),
+                promoted: true,
+            });
+        }
+
+        let running: Vec<_> = dlock
+            .outstanding_activity_tasks
+            .iter()
+            .filter(|(_, info)| info.la_info.workflow_exec_info.run_id == run_id)
+            .map(|(tt, info)| {
+                let id = ExecutingLAId {
+                    run_id: run_id.to_string(),
+                    seq_num: info.la_info.schedule_cmd.seq,
+                };
+                (tt.clone(), id)
+            })
+            .collect();
+        for (tt, id) in running {
+            if !dlock.promoting.insert(id.clone()) {
+                continue;
+            }
+            // Any cancel already underway is superseded, lang is told again with the new reason
+            if let Some(t) = dlock.cancel_grace_tasks.remove(&id) {
+                t.abort();
+            }
+            self.cancels_req_tx
+                .send(CancelOrTimeout::Cancel(ActivityTask {
+                    task_token: tt.0,
+                    variant: Some(activity_task::Variant::Cancel(Cancel {
+                        reason: ActivityCancelReason::Promoted as i32,
+                    })),
+                }))
+                .expect("Receive half of LA cancel channel cannot be dropped");
+            let jh = self.force_cancel_after_grace_period(id.clone());
+            dlock.cancel_grace_tasks.insert(id, jh);
+        }
+        resolutions
+    }
+
+    fn force_cancel_after_grace_period(&self, id: ExecutingLAId) -> JoinHandle<()> {
+        let grace_period = self.options.cancel_grace_period;
+        let cancel_chan = self.cancels_req_tx.clone();
+        tokio::spawn(async move {
+            sleep(grace_period).await;
+            cancel_chan
+                .send(CancelOrTimeout::ForceCancel(id))
+                .expect("Receive half of LA cancel channel cannot be dropped");
+        })
+    }
+
     pub(crate) async fn shutdown_and_wait_all_finished(&self) {
         while !self.dat.lock().outstanding_activity_tasks.is_empty() {
             self.complete_notify.notified().await;
@@ -485,6 +931,8 @@ pub(crate) enum LACompleteAction {
     Report(LocalInFlightActInfo),
     /// Lang needs to be told to do the schedule-a-timer-then-rerun hack
     LangDoesTimerBackoff(prost_types::Duration, LocalInFlightActInfo),
+    /// Lang needs to be told to run the activity as a normal activity from now on
+    Promote(LocalInFlightActInfo),
     /// The activity will be re-enqueued for another attempt (and so status should not be reported
     /// to the workflow)
     WillBeRetried,
@@ -499,6 +947,11 @@ enum NewOrRetry {
         in_flight: NewLocalAct,
         attempt: u32,
     },
+    /// An activity which was held back by rate limiting, and may now start
+    Throttled {
+        in_flight: NewLocalAct,
+        attempt: u32,
+    },
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -510,6 +963,8 @@ enum CancelOrTimeout {
         resolution: LocalActivityResolution,
         dispatch_cancel: bool,
     },
+    /// The grace period given to lang to acknowledge cancelling the activity has elapsed
+    ForceCancel(ExecutingLAId),
 }
 
 enum NewOrCancel {
@@ -570,6 +1025,7 @@ impl TimeoutBag {
             attempt: new_la.schedule_cmd.attempt,
             backoff: None,
             original_schedule_time: Some(new_la.schedule_time),
+            promoted: false,
         };
         // Remove any time already elapsed since the scheduling time
         let schedule_to_close = schedule_to_close
@@ -637,7 +1093,7 @@ mod tests {
     use super::*;
     use crate::protosext::LACloseTimeouts;
     use temporal_sdk_core_protos::{
-        coresdk::common::RetryPolicy,
+        coresdk::common::{Payload, RetryPolicy},
         temporal::api::failure::v1::{failure::FailureInfo, ApplicationFailureInfo, Failure},
     };
     use tokio::{sync::mpsc::error::TryRecvError, task::yield_now};
@@ -725,6 +1181,88 @@ mod tests {
         };
     }
 
+    #[tokio::test]
+    async fn rate_limits_hold_back_activities() {
+        let lam = LocalActivityManager::new(
+            10,
+            "fake_ns".to_string(),
+            ActivityRateLimiter::new(Some(100.0), &HashMap::from([("slow".to_string(), 5.0)])),
+            MetricsContext::default(),
+            Default::default(),
+        );
+        lam.enqueue((1..=6).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    activity_type: "slow".to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: Default::default(),
+                schedule_time: SystemTime::now(),
+            }
+            .into()
+        }));
+        let start = Instant::now();
+        // The first five fit in the bucket, the sixth must wait for a token to refill
+        for _ in 1..=5 {
+            lam.next_pending().await.unwrap().unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+        let sixth = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            sixth.variant.unwrap(),
+            activity_task::Variant::Start(Start {activity_id, ..}) if activity_id == "6"
+        );
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn can_cancel_throttled() {
+        let lam = LocalActivityManager::new(
+            5,
+            "fake_ns".to_string(),
+            ActivityRateLimiter::new(Some(0.1), &Default::default()),
+            MetricsContext::default(),
+            Default::default(),
+        );
+        lam.enqueue((1..=2).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+            }
+            .into()
+        }));
+        lam.next_pending().await.unwrap().unwrap();
+        // The second activity is held back for ten seconds
+        tokio::select! {
+            _ = lam.next_pending() => panic!("Throttled activity must not be dispatched"),
+            _ = sleep(Duration::from_millis(50)) => {}
+        }
+        let res = lam.enqueue([LocalActRequest::Cancel(ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 2,
+        })]);
+        assert_matches!(
+            res.as_slice(),
+            [LocalActivityResolution {
+                seq: 2,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            }]
+        );
+    }
+
     #[tokio::test]
     async fn can_cancel_in_flight() {
         let lam = LocalActivityManager::test(5);
@@ -752,6 +1290,136 @@ mod tests {
         assert_matches!(next.variant.unwrap(), activity_task::Variant::Cancel(_));
     }
 
+    fn cancellable_la(seq: u32) -> LocalActRequest {
+        NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq,
+                activity_id: seq.to_string(),
+                retry_policy: RetryPolicy {
+                    maximum_attempts: 5,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+        }
+        .into()
+    }
+
+    fn cancel_req(seq: u32) -> LocalActRequest {
+        LocalActRequest::Cancel(ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: seq,
+        })
+    }
+
+    #[tokio::test]
+    async fn cancel_resolves_only_once_lang_acknowledges() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue([cancellable_la(1)]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+
+        assert!(lam.enqueue([cancel_req(1)]).is_empty());
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(next.variant.unwrap(), activity_task::Variant::Cancel(_));
+        // Cancelling again does not dispatch another cancel
+        assert!(lam.enqueue([cancel_req(1)]).is_empty());
+        tokio::select! {
+            _ = lam.next_pending() => panic!("Nothing else should be dispatched"),
+            _ = sleep(Duration::from_millis(50)) => {}
+        }
+
+        let res = lam.complete(&tt, &LocalActivityExecutionResult::empty_cancel());
+        assert_matches!(res, LACompleteAction::Report(_));
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_activity_is_force_resolved_after_grace_period() {
+        let lam = LocalActivityManager::test_with(
+            5,
+            LocalActivityManagerOptions {
+                cancel_grace_period: Duration::from_millis(100),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([cancellable_la(1)]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        lam.enqueue([cancel_req(1)]);
+        lam.next_pending().await.unwrap().unwrap();
+
+        let start = Instant::now();
+        let next = lam.next_pending().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert_matches!(
+            next,
+            DispatchOrTimeoutLA::Timeout {
+                resolution: LocalActivityResolution {
+                    seq: 1,
+                    result: LocalActivityExecutionResult::Cancelled(_),
+                    ..
+                },
+                task: None,
+                ..
+            }
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+        // Lang acknowledging afterward is ignored
+        let res = lam.complete(&tt, &LocalActivityExecutionResult::empty_cancel());
+        assert_matches!(res, LACompleteAction::Untracked);
+    }
+
+    #[tokio::test]
+    async fn activity_failing_after_cancel_is_not_retried() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue([cancellable_la(1)]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        lam.enqueue([cancel_req(1)]);
+        lam.next_pending().await.unwrap().unwrap();
+
+        let res = lam.complete(
+            &tt,
+            &LocalActivityExecutionResult::Failed(ActFail {
+                failure: Some(Failure::application_failure("cancelled".to_string(), false)),
+            }),
+        );
+        assert_matches!(res, LACompleteAction::Report(_));
+        assert_eq!(lam.num_in_backoff(), 0);
+    }
+
+    #[tokio::test]
+    async fn cancel_of_queued_activity_resolves_immediately() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([cancellable_la(1), cancellable_la(2)]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+
+        let res = lam.enqueue([cancel_req(2)]);
+        assert_matches!(
+            res.as_slice(),
+            [LocalActivityResolution {
+                seq: 2,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            }]
+        );
+        lam.complete(
+            &tt,
+            &LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        // The cancelled activity is skipped rather than dispatched, and gives its slot back
+        lam.enqueue([cancellable_la(3)]);
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            next.variant.unwrap(),
+            activity_task::Variant::Start(Start {activity_id, ..}) if activity_id == "3"
+        );
+    }
+
     #[tokio::test]
     async fn respects_timer_backoff_threshold() {
         let lam = LocalActivityManager::test(1);
@@ -787,6 +1455,221 @@ mod tests {
         )
     }
 
+    #[tokio::test]
+    async fn promotes_past_timer_backoff_threshold() {
+        let lam = LocalActivityManager::test_with(
+            1,
+            LocalActivityManagerOptions {
+                promote_past_retry_threshold: true,
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                attempt: 5,
+                retry_policy: RetryPolicy {
+                    initial_interval: Some(Duration::from_secs(1).into()),
+                    backoff_coefficient: 10.0,
+                    maximum_interval: Some(Duration::from_secs(10).into()),
+                    maximum_attempts: 10,
+                    non_retryable_error_types: vec![],
+                },
+                local_retry_threshold: Duration::from_secs(5),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: Default::default(),
+            schedule_time: SystemTime::now(),
+        }
+        .into()]);
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let tt = TaskToken(next.task_token);
+        let res = lam.complete(
+            &tt,
+            &LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        assert_matches!(res, LACompleteAction::Promote(info) if info.attempt == 5)
+    }
+
+    #[tokio::test]
+    async fn can_promote_in_flight_and_backing_off() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue((1..=2).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    retry_policy: RetryPolicy {
+                        initial_interval: Some(Duration::from_secs(10).into()),
+                        backoff_coefficient: 1.0,
+                        maximum_attempts: 10,
+                        ..Default::default()
+                    },
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+            }
+            .into()
+        }));
+        let first = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        let second = lam.next_pending().await.unwrap().unwrap();
+        // The second activity fails and starts backing off locally
+        lam.complete(
+            &TaskToken(second.task_token),
+            &LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        assert_eq!(lam.num_in_backoff(), 1);
+
+        // Only the one backing off is promoted right away
+        let res = lam.enqueue([LocalActRequest::PromoteAll {
+            run_id: "run_id".to_string(),
+        }]);
+        assert_matches!(
+            res.as_slice(),
+            [LocalActivityResolution {
+                seq: 2,
+                attempt: 1,
+                original_schedule_time: Some(_),
+                promoted: true,
+                ..
+            }]
+        );
+        assert_eq!(lam.num_in_backoff(), 0);
+        // Lang is asked to stop running the first, which keeps its slot until it has
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            next.variant.unwrap(),
+            activity_task::Variant::Cancel(Cancel { reason })
+                if reason == ActivityCancelReason::Promoted as i32
+        );
+        assert_eq!(lam.num_outstanding(), 1);
+        // Asking again doesn't ask lang again
+        assert!(lam
+            .enqueue([LocalActRequest::PromoteAll {
+                run_id: "run_id".to_string(),
+            }])
+            .is_empty());
+
+        let res = lam.complete(&first, &LocalActivityExecutionResult::empty_cancel());
+        assert_matches!(res, LACompleteAction::Promote(info) if info.attempt == 1);
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn running_activity_is_promoted_after_grace_period() {
+        let lam = LocalActivityManager::test_with(
+            5,
+            LocalActivityManagerOptions {
+                cancel_grace_period: Duration::from_millis(100),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([cancellable_la(1)]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        assert!(lam
+            .enqueue([LocalActRequest::PromoteAll {
+                run_id: "run_id".to_string(),
+            }])
+            .is_empty());
+        lam.next_pending().await.unwrap().unwrap();
+
+        let next = lam.next_pending().await.unwrap();
+        assert_matches!(
+            next,
+            DispatchOrTimeoutLA::Timeout {
+                resolution: LocalActivityResolution {
+                    seq: 1,
+                    promoted: true,
+                    ..
+                },
+                task: None,
+                ..
+            }
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+        let res = lam.complete(&tt, &LocalActivityExecutionResult::empty_cancel());
+        assert_matches!(res, LACompleteAction::Untracked);
+    }
+
+    #[tokio::test]
+    async fn reuses_results_of_completed_activities() {
+        let lam = LocalActivityManager::test_with(
+            5,
+            LocalActivityManagerOptions {
+                max_cached_results: 10,
+                ..Default::default()
+            },
+        );
+        let new_la = |seq: u32, activity_type: &str| -> LocalActRequest {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    activity_type: activity_type.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1, "echo"), new_la(2, "echo")]);
+        for _ in 1..=2 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            lam.complete(
+                &TaskToken(next.task_token),
+                &LocalActivityExecutionResult::Completed(Success {
+                    result: Some(Payload::default()),
+                }),
+            );
+        }
+
+        // The same activity is requested again, ex: because the workflow task failed
+        let res = lam.enqueue([new_la(1, "echo")]);
+        assert_matches!(
+            res.as_slice(),
+            [LocalActivityResolution {
+                seq: 1,
+                result: LocalActivityExecutionResult::Completed(_),
+                attempt: 1,
+                ..
+            }]
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+
+        // A different activity at the same seq, ex: if the workflow changed, runs
+        assert!(lam.enqueue([new_la(2, "other")]).is_empty());
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(lam.num_outstanding(), 1);
+        lam.complete(
+            &TaskToken(next.task_token),
+            &LocalActivityExecutionResult::Completed(Success {
+                result: Some(Payload::default()),
+            }),
+        );
+
+        // Once a result is recorded, the activity would run again if requested
+        lam.forget_results("run_id", [1]);
+        assert!(lam.enqueue([new_la(1, "echo")]).is_empty());
+        lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(lam.num_outstanding(), 1);
+        // Results which weren't recorded are kept
+        assert_eq!(lam.enqueue([new_la(2, "other")]).len(), 1);
+    }
+
     #[tokio::test]
     async fn respects_non_retryable_error_types() {
         let lam = LocalActivityManager::test(1);
@@ -0,0 +1,74 @@
+use crate::abstractions::TokenBucket;
+use std::{collections::HashMap, time::Duration};
+
+/// Client-side limits on how quickly a worker starts activities, either overall or for specific
+/// activity types
+pub(crate) struct ActivityRateLimiter {
+    overall: Option<TokenBucket>,
+    by_type: HashMap<String, TokenBucket>,
+}
+
+impl ActivityRateLimiter {
+    pub(crate) fn new(overall: Option<f64>, by_type: &HashMap<String, f64>) -> Self {
+        Self {
+            overall: overall.map(TokenBucket::new),
+            by_type: by_type
+                .iter()
+                .map(|(act_type, rate)| (act_type.clone(), TokenBucket::new(*rate)))
+                .collect(),
+        }
+    }
+
+    /// Reserves a start of an activity of the provided type, returning how long the caller must
+    /// wait before starting it
+    fn reserve_type(&self, act_type: &str) -> Duration {
+        self.by_type
+            .get(act_type)
+            .map(TokenBucket::reserve)
+            .unwrap_or_default()
+    }
+
+    /// Reserves a start against both the overall and the per-type limits, returning how long the
+    /// caller must wait before starting it
+    pub(crate) fn reserve(&self, act_type: &str) -> Duration {
+        let overall = self
+            .overall
+            .as_ref()
+            .map(TokenBucket::reserve)
+            .unwrap_or_default();
+        overall.max(self.reserve_type(act_type))
+    }
+
+    /// Like [Self::reserve], but nothing is reserved (and `None` is returned) if the caller would
+    /// have to wait longer than `max_wait`
+    pub(crate) fn try_reserve(
+        &self,
+        act_type: &str,
+        max_wait: Option<Duration>,
+    ) -> Option<Duration> {
+        let overall = match self.overall.as_ref() {
+            Some(bucket) => bucket.try_reserve(max_wait)?,
+            None => Duration::ZERO,
+        };
+        let by_type = match self.by_type.get(act_type) {
+            Some(bucket) => match bucket.try_reserve(max_wait) {
+                Some(wait) => wait,
+                None => {
+                    if let Some(bucket) = self.overall.as_ref() {
+                        bucket.unreserve();
+                    }
+                    return None;
+                }
+            },
+            None => Duration::ZERO,
+        };
+        Some(overall.max(by_type))
+    }
+
+    /// Resolves once the overall limit would allow starting an activity right away
+    pub(crate) async fn overall_available(&self) {
+        if let Some(bucket) = self.overall.as_ref() {
+            bucket.available().await;
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::TaskToken;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// How long a reserved session slot is held for a keepalive activity which never shows up, EX:
+/// because the workflow which created it was terminated.
+const RESERVATION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Heartbeat interval for keepalives which were scheduled without a heartbeat timeout
+const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tracks the activity sessions hosted by this worker. A session's slot is reserved when its
+/// creation activity arrives on the worker's normal task queue, and is then held by a keepalive
+/// activity on the worker's session task queue, which core heartbeats on its own until the
+/// workflow cancels it.
+pub(crate) struct SessionManager {
+    /// The task queue only this worker polls, which session activities are routed to
+    task_queue: String,
+    max_sessions: usize,
+    sessions: Mutex<HashMap<String, SessionState>>,
+    /// Notified whenever a keepalive starts or a session ends
+    keepalives_changed: Notify,
+}
+
+#[derive(Debug)]
+struct SessionState {
+    reserved_at: Instant,
+    keepalive: Option<Keepalive>,
+}
+
+#[derive(Debug)]
+struct Keepalive {
+    task_token: TaskToken,
+    interval: Duration,
+    next_beat: Instant,
+}
+
+impl SessionManager {
+    pub(crate) fn new(task_queue: String, max_sessions: usize) -> Self {
+        Self {
+            task_queue,
+            max_sessions,
+            sessions: Default::default(),
+            keepalives_changed: Notify::new(),
+        }
+    }
+
+    pub(super) fn task_queue(&self) -> &str {
+        &self.task_queue
+    }
+
+    /// Session ids are derived from the run and the id of the creation activity, both of which are
+    /// known to core when the creation and keepalive activities are polled.
+    pub(super) fn session_id(run_id: &str, creation_activity_id: &str) -> String {
+        format!("{}/{}", run_id, creation_activity_id)
+    }
+
+    /// Attempt to reserve a slot for a new session. Returns false if the worker is at capacity.
+    pub(super) fn try_reserve(&self, session_id: String) -> bool {
+        let mut sessions = self.sessions.lock();
+        sessions
+            .retain(|_, s| s.keepalive.is_some() || s.reserved_at.elapsed() < RESERVATION_TIMEOUT);
+        if sessions.len() >= self.max_sessions {
+            return false;
+        }
+        sessions.insert(
+            session_id,
+            SessionState {
+                reserved_at: Instant::now(),
+                keepalive: None,
+            },
+        );
+        true
+    }
+
+    /// Attach a keepalive activity to a reserved session. Returns false if there is no such
+    /// reservation.
+    pub(super) fn start_keepalive(
+        &self,
+        session_id: &str,
+        task_token: TaskToken,
+        heartbeat_timeout: Option<Duration>,
+    ) -> bool {
+        let mut sessions = self.sessions.lock();
+        match sessions.get_mut(session_id) {
+            Some(s) if s.keepalive.is_none() => {
+                let interval = heartbeat_timeout
+                    .filter(|d| !d.is_zero())
+                    .map(|d| d / 2)
+                    .unwrap_or(DEFAULT_KEEPALIVE_INTERVAL);
+                s.keepalive = Some(Keepalive {
+                    task_token,
+                    interval,
+                    next_beat: Instant::now() + interval,
+                });
+                self.keepalives_changed.notify_waiters();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget about a session, freeing its slot
+    pub(super) fn end(&self, session_id: &str) {
+        self.sessions.lock().remove(session_id);
+        self.keepalives_changed.notify_waiters();
+    }
+
+    /// Task tokens of all the keepalive activities currently running
+    pub(super) fn keepalive_tokens(&self) -> Vec<TaskToken> {
+        self.sessions
+            .lock()
+            .values()
+            .filter_map(|s| s.keepalive.as_ref().map(|k| k.task_token.clone()))
+            .collect()
+    }
+
+    /// Resolves with the task token and heartbeat interval of the next keepalive which must be
+    /// heartbeated. Keepalives which start or end while waiting are accounted for.
+    pub(super) async fn next_heartbeat_due(&self) -> (TaskToken, Duration) {
+        loop {
+            // Created before looking at the sessions, so no change made after that is missed
+            let changed = self.keepalives_changed.notified();
+            let next = self
+                .sessions
+                .lock()
+                .values()
+                .filter_map(|s| s.keepalive.as_ref())
+                .min_by_key(|k| k.next_beat)
+                .map(|k| (k.next_beat, k.task_token.clone()));
+            let (at, task_token) = match next {
+                Some(n) => n,
+                None => {
+                    changed.await;
+                    continue;
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep_until(at.into()) => {}
+                _ = changed => continue,
+            }
+            if let Some(k) = self
+                .sessions
+                .lock()
+                .values_mut()
+                .filter_map(|s| s.keepalive.as_mut())
+                .find(|k| k.task_token == task_token)
+            {
+                k.next_beat = Instant::now() + k.interval;
+                return (task_token, k.interval);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservations_respect_capacity() {
+        let sm = SessionManager::new("sessq".to_string(), 1);
+        assert!(sm.try_reserve("a".to_string()));
+        assert!(!sm.try_reserve("b".to_string()));
+        sm.end("a");
+        assert!(sm.try_reserve("b".to_string()));
+    }
+
+    #[test]
+    fn keepalive_requires_reservation() {
+        let sm = SessionManager::new("sessq".to_string(), 1);
+        assert!(!sm.start_keepalive("a", vec![1].into(), None));
+        assert!(sm.try_reserve("a".to_string()));
+        assert!(sm.start_keepalive("a", vec![1].into(), None));
+        // Can't start a second keepalive for the same session
+        assert!(!sm.start_keepalive("a", vec![2].into(), None));
+        assert_eq!(sm.keepalive_tokens(), vec![vec![1].into()]);
+    }
+
+    #[tokio::test]
+    async fn heartbeats_come_due() {
+        let sm = SessionManager::new("sessq".to_string(), 2);
+        sm.try_reserve("a".to_string());
+        sm.start_keepalive("a", vec![1].into(), Some(Duration::from_millis(20)));
+        let (tt, interval) = tokio::time::timeout(Duration::from_secs(1), sm.next_heartbeat_due())
+            .await
+            .unwrap();
+        assert_eq!(tt, vec![1].into());
+        assert_eq!(interval, Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn waiting_for_heartbeats_notices_new_keepalives() {
+        let sm = SessionManager::new("sessq".to_string(), 2);
+        let next = sm.next_heartbeat_due();
+        tokio::pin!(next);
+        // Nothing to heartbeat yet
+        assert!(tokio::time::timeout(Duration::from_millis(20), &mut next)
+            .await
+            .is_err());
+        sm.try_reserve("a".to_string());
+        sm.start_keepalive("a", vec![1].into(), Some(Duration::from_millis(20)));
+        let (tt, _) = tokio::time::timeout(Duration::from_secs(1), next)
+            .await
+            .unwrap();
+        assert_eq!(tt, vec![1].into());
+    }
+}
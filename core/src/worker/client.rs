@@ -1,5 +1,6 @@
 //! Worker-specific client needs
 
+#[cfg(test)]
 pub(crate) mod mocks;
 
 use std::{
@@ -1,7 +1,6 @@
 use super::*;
 use futures::Future;
 
-#[cfg(test)]
 /// Create a mock client primed with basic necessary expectations
 pub(crate) fn mock_workflow_client() -> MockWorkerClient {
     MockWorkerClient::new()
@@ -2,8 +2,11 @@ mod activities;
 pub(crate) mod client;
 mod wft_delivery;
 
-pub use temporal_sdk_core_api::worker::{WorkerConfig, WorkerConfigBuilder};
+pub use temporal_sdk_core_api::worker::{
+    WorkerConfig, WorkerConfigBuilder, STACK_TRACE_QUERY_TYPE,
+};
 
+pub use activities::FileHeartbeatCheckpointStore;
 pub(crate) use activities::{
     ExecutingLAId, LocalActRequest, LocalActivityExecutionResult, LocalActivityResolution,
     NewLocalAct,
@@ -12,9 +15,10 @@ pub(crate) use activities::{
 use crate::{
     abstractions::MeteredSemaphore,
     errors::CompleteWfError,
+    pending_activations::EvictionCause,
     pollers::{
-        new_activity_task_buffer, new_workflow_task_buffer, BoxedActPoller, BoxedWFPoller, Poller,
-        WorkflowTaskPoller,
+        new_activity_task_buffer, new_workflow_task_buffer, BoxedActPoller, BoxedWFPoller,
+        MultiQueuePoller, Poller, WorkflowTaskPoller,
     },
     protosext::{legacy_query_failure, ValidPollWFTQResponse},
     telemetry::{
@@ -25,7 +29,10 @@ use crate::{
         VecDisplayer,
     },
     worker::{
-        activities::{DispatchOrTimeoutLA, LACompleteAction, LocalActivityManager},
+        activities::{
+            DispatchOrTimeoutLA, LACompleteAction, LocalActivityManager,
+            LocalActivityManagerOptions,
+        },
         client::WorkerClientBag,
         wft_delivery::WFTSource,
     },
@@ -34,25 +41,36 @@ use crate::{
             ActivationAction, FailedActivationOutcome, NewWfTaskOutcome,
             ServerCommandsWithWorkflowInfo, WorkflowTaskManager,
         },
-        EmptyWorkflowCommandErr, LocalResolution, WFMachinesError, WorkflowCachingPolicy,
+        EmptyWorkflowCommandErr, LocalResolution, WorkflowCachingPolicy, LEGACY_QUERY_ID,
     },
     ActivityHeartbeat, CompleteActivityError, PollActivityError, PollWfError, WorkerTrait,
 };
-use activities::{LocalInFlightActInfo, WorkerActivityTasks};
-use futures::{Future, TryFutureExt};
-use std::{convert::TryInto, future, sync::Arc};
+use activities::{ActivityRateLimiter, LocalInFlightActInfo, SessionManager, WorkerActivityTasks};
+use futures::{future::join_all, Future, TryFutureExt};
+use parking_lot::Mutex;
+use std::{collections::HashMap, convert::TryInto, future, sync::Arc};
 use temporal_client::WorkflowTaskCompletion;
 use temporal_sdk_core_protos::{
+    constants::LOCAL_ACTIVITY_MARKER_NAME,
     coresdk::{
         activity_result::activity_execution_result,
         activity_task::ActivityTask,
-        workflow_activation::{remove_from_cache::EvictionReason, WorkflowActivation},
+        common::extract_local_activity_marker_data,
+        workflow_activation::{
+            remove_from_cache::EvictionReason, workflow_activation_job, WorkflowActivation,
+        },
+        workflow_commands::{
+            query_result, workflow_command, FailWorkflowExecution, QueryResult, QuerySuccess,
+            WorkflowCommand,
+        },
         workflow_completion::{self, workflow_activation_completion, WorkflowActivationCompletion},
-        ActivityTaskCompletion,
+        ActivityTaskCompletion, AsJsonPayloadExt,
     },
     temporal::api::{
-        enums::v1::{TaskQueueKind, WorkflowTaskFailedCause},
-        failure::v1::Failure,
+        command::v1::{command::Attributes, Command as ProtoCommand},
+        common::v1::Payloads,
+        enums::v1::{CommandType, TaskQueueKind, WorkflowTaskFailedCause},
+        failure::v1::{failure::FailureInfo, Failure},
         taskqueue::v1::{StickyExecutionAttributes, TaskQueue},
         workflowservice::v1::{PollActivityTaskQueueResponse, PollWorkflowTaskQueueResponse},
     },
@@ -96,6 +114,9 @@ pub struct Worker {
     shutdown_token: CancellationToken,
     /// Will be called at the end of each activation completion
     post_activate_hook: Option<Box<dyn Fn(&Self) + Send + Sync>>,
+    /// Answers to core-handled queries, by run id, to be sent along with lang's completion of the
+    /// activation they were removed from
+    core_query_answers: Mutex<HashMap<String, Vec<QueryResult>>>,
 
     metrics: MetricsContext,
 }
@@ -106,6 +127,13 @@ impl WorkerTrait for Worker {
         self.next_workflow_activation().await
     }
 
+    async fn poll_workflow_activations(
+        &self,
+        max: usize,
+    ) -> Result<Vec<WorkflowActivation>, PollWfError> {
+        self.next_workflow_activations(max).await
+    }
+
     #[instrument(level = "debug", skip(self))]
     async fn poll_activity_task(&self) -> Result<ActivityTask, PollActivityError> {
         loop {
@@ -126,6 +154,18 @@ impl WorkerTrait for Worker {
         self.complete_workflow_activation(completion).await
     }
 
+    async fn complete_workflow_activations(
+        &self,
+        completions: Vec<WorkflowActivationCompletion>,
+    ) -> Vec<Result<(), CompleteWfError>> {
+        join_all(
+            completions
+                .into_iter()
+                .map(|c| self.complete_workflow_activation(c)),
+        )
+        .await
+    }
+
     #[instrument(level = "debug", skip(self, completion),
     fields(completion=%&completion))]
     async fn complete_activity_task(
@@ -186,10 +226,12 @@ impl Worker {
     pub(crate) fn new(
         config: WorkerConfig,
         sticky_queue_name: Option<String>,
+        session_queue_name: Option<String>,
         client: Arc<WorkerClientBag>,
         metrics: MetricsContext,
     ) -> Self {
-        info!(task_queue = %config.task_queue, "Initializing worker");
+        info!(task_queue = %config.task_queue,
+              additional_task_queues = ?config.additional_task_queues, "Initializing worker");
         metrics.worker_registered();
 
         let max_nonsticky_polls = if sticky_queue_name.is_some() {
@@ -198,15 +240,27 @@ impl Worker {
             config.max_concurrent_wft_polls
         };
         let max_sticky_polls = config.max_sticky_polls();
-        let wft_metrics = metrics.with_new_attrs([workflow_poller()]);
-        let mut wf_task_poll_buffer = new_workflow_task_buffer(
-            client.clone(),
-            config.task_queue.clone(),
-            false,
-            max_nonsticky_polls,
-            max_nonsticky_polls * 2,
+        let wf_task_poll_buffer = MultiQueuePoller::new(
+            config
+                .pollers_per_task_queue(max_nonsticky_polls)
+                .into_iter()
+                .map(|(tq, num_pollers)| {
+                    let wft_metrics = metrics
+                        .for_task_q(tq.clone())
+                        .with_new_attrs([workflow_poller()]);
+                    let mut wp = new_workflow_task_buffer(
+                        client.clone(),
+                        tq,
+                        false,
+                        num_pollers,
+                        num_pollers * 2,
+                    );
+                    let np_metrics = wft_metrics.clone();
+                    wp.set_num_pollers_handler(move |np| np_metrics.record_num_pollers(np));
+                    (wp, wft_metrics)
+                })
+                .collect(),
         );
-        wf_task_poll_buffer.set_num_pollers_handler(move |np| wft_metrics.record_num_pollers(np));
         let sticky_queue_poller = sticky_queue_name.as_ref().map(|sqn| {
             let sticky_metrics = metrics.with_new_attrs([workflow_sticky_poller()]);
             let mut sp = new_workflow_task_buffer(
@@ -222,15 +276,30 @@ impl Worker {
         let act_poll_buffer = if config.no_remote_activities {
             None
         } else {
-            let mut ap = new_activity_task_buffer(
-                client.clone(),
-                config.task_queue.clone(),
-                config.max_concurrent_at_polls,
-                config.max_concurrent_at_polls * 2,
-                config.max_task_queue_activities_per_second,
+            // The session queue only sees a few tasks per session, so one poller suffices
+            let session_queue = session_queue_name.clone().map(|sqn| (sqn, 1));
+            let ap = MultiQueuePoller::new(
+                config
+                    .pollers_per_task_queue(config.max_concurrent_at_polls)
+                    .into_iter()
+                    .chain(session_queue)
+                    .map(|(tq, num_pollers)| {
+                        let act_metrics = metrics
+                            .for_task_q(tq.clone())
+                            .with_new_attrs([activity_poller()]);
+                        let mut ap = new_activity_task_buffer(
+                            client.clone(),
+                            tq,
+                            num_pollers,
+                            num_pollers * 2,
+                            config.max_task_queue_activities_per_second,
+                        );
+                        let np_metrics = act_metrics.clone();
+                        ap.set_num_pollers_handler(move |np| np_metrics.record_num_pollers(np));
+                        (ap, act_metrics)
+                    })
+                    .collect(),
             );
-            let act_metrics = metrics.with_new_attrs([activity_poller()]);
-            ap.set_num_pollers_handler(move |np| act_metrics.record_num_pollers(np));
             Some(Box::from(ap)
                 as Box<
                     dyn Poller<PollActivityTaskQueueResponse> + Send + Sync,
@@ -243,6 +312,7 @@ impl Worker {
         Self::new_with_pollers(
             config,
             sticky_queue_name,
+            session_queue_name,
             client,
             wf_task_poll_buffer,
             act_poll_buffer,
@@ -252,7 +322,13 @@ impl Worker {
 
     #[cfg(test)]
     pub(crate) fn new_test(config: WorkerConfig, client: impl WorkerClient + 'static) -> Self {
-        Self::new(config, None, Arc::new(client.into()), Default::default())
+        Self::new(
+            config,
+            None,
+            None,
+            Arc::new(client.into()),
+            Default::default(),
+        )
     }
 
     /// Returns number of currently cached workflows
@@ -263,6 +339,7 @@ impl Worker {
     pub(crate) fn new_with_pollers(
         config: WorkerConfig,
         sticky_queue_name: Option<String>,
+        session_queue_name: Option<String>,
         client: Arc<WorkerClientBag>,
         wft_poller: BoxedWFPoller,
         act_poller: Option<BoxedActPoller>,
@@ -281,21 +358,36 @@ impl Worker {
             wf_client: client.clone(),
             sticky_name: sticky_queue_name,
             wf_task_source: WFTSource::new(wft_poller),
-            wft_manager: WorkflowTaskManager::new(pa_notif.clone(), cache_policy, metrics.clone()),
+            wft_manager: WorkflowTaskManager::new(
+                pa_notif.clone(),
+                cache_policy,
+                &config,
+                metrics.clone(),
+            ),
             at_task_mgr: act_poller.map(|ap| {
                 WorkerActivityTasks::new(
-                    config.max_outstanding_activities,
+                    &config,
                     ap,
                     client.clone(),
                     metrics.clone(),
-                    config.max_heartbeat_throttle_interval,
-                    config.default_heartbeat_throttle_interval,
+                    session_queue_name
+                        .map(|tq| SessionManager::new(tq, config.max_concurrent_sessions)),
                 )
             }),
             local_act_mgr: LocalActivityManager::new(
                 config.max_outstanding_local_activities,
                 config.namespace.clone(),
+                ActivityRateLimiter::new(
+                    config.max_local_activities_per_second,
+                    &config.max_activities_per_second_by_type,
+                ),
                 metrics.with_new_attrs([local_activity_worker_type()]),
+                LocalActivityManagerOptions {
+                    promote_past_retry_threshold: config
+                        .promote_local_activities_past_retry_threshold,
+                    max_cached_results: config.max_cached_local_activity_results,
+                    cancel_grace_period: config.local_activity_cancel_grace_period,
+                },
             ),
             workflows_semaphore: MeteredSemaphore::new(
                 config.max_outstanding_workflow_tasks,
@@ -307,6 +399,7 @@ impl Worker {
             post_activate_hook: None,
             pending_activations_notify: pa_notif,
             wfts_drained_notify,
+            core_query_answers: Default::default(),
             metrics,
         }
     }
@@ -349,6 +442,13 @@ impl Worker {
         self.workflows_semaphore.sem.available_permits()
     }
 
+    #[cfg(test)]
+    pub(crate) fn available_activity_permits(&self) -> Option<usize> {
+        self.at_task_mgr
+            .as_ref()
+            .map(|atm| atm.remaining_activity_capacity())
+    }
+
     /// Get new activity tasks (may be local or nonlocal). Local activities are returned first
     /// before polling the server if there are any.
     ///
@@ -371,6 +471,9 @@ impl Worker {
                 match r {
                     Some(DispatchOrTimeoutLA::Dispatch(r)) => Ok(Some(r)),
                     Some(DispatchOrTimeoutLA::Timeout { run_id, resolution, task }) => {
+                        if resolution.promoted {
+                            self.metrics.la_promoted();
+                        }
                         self.notify_local_result(
                             &run_id, LocalResolution::LocalActivity(resolution)).await;
                         Ok(task)
@@ -406,7 +509,7 @@ impl Worker {
             let as_la_res: LocalActivityExecutionResult = status.try_into()?;
             match self.local_act_mgr.complete(&task_token, &as_la_res) {
                 LACompleteAction::Report(info) => {
-                    self.complete_local_act(as_la_res, info, None).await
+                    self.complete_local_act(as_la_res, info, None, false).await
                 }
                 LACompleteAction::LangDoesTimerBackoff(backoff, info) => {
                     // This la needs to write a failure marker, and then we will tell lang how
@@ -414,9 +517,20 @@ impl Worker {
                     // no other situations where core generates "internal" commands so it is much
                     // simpler for lang to reply with the timer / next LA command than to do it
                     // internally. Plus, this backoff hack we'd like to eliminate eventually.
-                    self.complete_local_act(as_la_res, info, Some(backoff))
+                    self.complete_local_act(as_la_res, info, Some(backoff), false)
                         .await
                 }
+                LACompleteAction::Promote(info) => {
+                    // Like timer backoff, this writes a marker with the failure, then lang
+                    // schedules a normal activity to run in place of the local one. Activities
+                    // lang stopped running so they could be promoted have no failure of their own.
+                    self.metrics.la_promoted();
+                    let la_res = match as_la_res {
+                        LocalActivityExecutionResult::Failed(_) => as_la_res,
+                        _ => LocalActivityExecutionResult::promoted(),
+                    };
+                    self.complete_local_act(la_res, info, None, true).await
+                }
                 LACompleteAction::WillBeRetried => {
                     // Nothing to do here
                 }
@@ -440,6 +554,15 @@ impl Worker {
 
     #[instrument(level = "debug", skip(self), fields(run_id))]
     pub(crate) async fn next_workflow_activation(&self) -> Result<WorkflowActivation, PollWfError> {
+        loop {
+            let act = self.next_activation_for_run().await?;
+            if let Some(act) = self.answer_core_queries(act).await {
+                return Ok(act);
+            }
+        }
+    }
+
+    async fn next_activation_for_run(&self) -> Result<WorkflowActivation, PollWfError> {
         // The poll needs to be in a loop because we can't guarantee tail call optimization in Rust
         // (simply) and we really, really need that for long-poll retries.
         loop {
@@ -499,6 +622,89 @@ impl Worker {
         }
     }
 
+    /// Waits for one activation, then drains up to `max` total from those which are already
+    /// pending, so they can be handed to lang together
+    pub(crate) async fn next_workflow_activations(
+        &self,
+        max: usize,
+    ) -> Result<Vec<WorkflowActivation>, PollWfError> {
+        let mut acts = vec![self.next_workflow_activation().await?];
+        while acts.len() < max {
+            match self.wft_manager.next_pending_activation() {
+                Some(pa) => {
+                    if let Some(pa) = self.answer_core_queries(pa).await {
+                        debug!(activation=%pa, "Sending pending activation to lang");
+                        acts.push(pa);
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(acts)
+    }
+
+    /// Removes queries which core answers itself (see [STACK_TRACE_QUERY_TYPE]) from the
+    /// activation. If nothing is left for lang, core completes the activation with the answers
+    /// and `None` is returned. Otherwise the answers are held until lang completes the activation.
+    async fn answer_core_queries(&self, mut act: WorkflowActivation) -> Option<WorkflowActivation> {
+        let (core_queries, lang_jobs): (Vec<_>, Vec<_>) = act.jobs.into_iter().partition(|j| {
+            matches!(&j.variant, Some(workflow_activation_job::Variant::QueryWorkflow(q))
+                                 if q.query_type == STACK_TRACE_QUERY_TYPE)
+        });
+        act.jobs = lang_jobs;
+        if core_queries.is_empty() {
+            return Some(act);
+        }
+        let answers: Vec<_> = core_queries
+            .into_iter()
+            .filter_map(|j| match j.variant {
+                Some(workflow_activation_job::Variant::QueryWorkflow(q)) => {
+                    Some(self.answer_stack_trace_query(&act.run_id, q.query_id))
+                }
+                _ => None,
+            })
+            .collect();
+        if act.jobs.is_empty() {
+            let completion = WorkflowActivationCompletion::from_cmds(
+                act.run_id,
+                answers.into_iter().map(Into::into).collect(),
+            );
+            if let Err(e) = self.complete_workflow_activation(completion).await {
+                warn!(error=?e, "Failed to complete activation answering core-handled queries");
+            }
+            None
+        } else {
+            self.core_query_answers
+                .lock()
+                .entry(act.run_id.clone())
+                .or_default()
+                .extend(answers);
+            Some(act)
+        }
+    }
+
+    fn answer_stack_trace_query(&self, run_id: &str, query_id: String) -> QueryResult {
+        let answer = self
+            .wft_manager
+            .pending_commands(run_id)
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|pending| pending.as_json_payload().map_err(|e| e.to_string()));
+        let variant = match answer {
+            Ok(payload) => QuerySuccess {
+                response: Some(payload),
+            }
+            .into(),
+            Err(message) => query_result::Variant::Failed(Failure {
+                message,
+                ..Default::default()
+            }),
+        };
+        QueryResult {
+            query_id,
+            variant: Some(variant),
+        }
+    }
+
     #[instrument(level = "debug", skip(self, completion),
     fields(completion=%&completion, run_id=%completion.run_id))]
     pub(crate) async fn complete_workflow_activation(
@@ -506,8 +712,19 @@ impl Worker {
         completion: WorkflowActivationCompletion,
     ) -> Result<(), CompleteWfError> {
         let wfstatus = completion.status;
+        let core_answers = self.core_query_answers.lock().remove(&completion.run_id);
         let report_outcome = match wfstatus {
-            Some(workflow_activation_completion::Status::Successful(success)) => {
+            Some(workflow_activation_completion::Status::Successful(mut success)) => {
+                success
+                    .commands
+                    .extend(
+                        core_answers
+                            .into_iter()
+                            .flatten()
+                            .map(|qr| WorkflowCommand {
+                                variant: Some(qr.into()),
+                            }),
+                    );
                 self.wf_activation_success(&completion.run_id, success)
                     .await
             }
@@ -516,8 +733,9 @@ impl Worker {
                 self.wf_activation_failed(
                     &completion.run_id,
                     WorkflowTaskFailedCause::Unspecified,
-                    EvictionReason::LangFail,
+                    EvictionReason::LangFail.into(),
                     failure,
+                    core_answers.unwrap_or_default(),
                 )
                 .await
             }
@@ -556,15 +774,23 @@ impl Worker {
         &self,
         run_id: &str,
         message: impl Into<String>,
-        reason: EvictionReason,
+        cause: impl Into<EvictionCause>,
     ) -> bool {
-        match self.wft_manager.request_eviction(run_id, message, reason) {
+        match self.wft_manager.request_eviction(run_id, message, cause) {
             EvictionRequestResult::EvictionRequested(_) => true,
             EvictionRequestResult::NotFound => false,
             EvictionRequestResult::EvictionAlreadyRequested(_) => false,
         }
     }
 
+    /// Returns the event id of the most recently processed event for the provided run, or zero if
+    /// the run is not cached
+    pub(crate) fn most_recently_processed_event(&self, run_id: &str) -> i64 {
+        self.wft_manager
+            .most_recently_processed_event(run_id)
+            .unwrap_or_default()
+    }
+
     /// Sets a function to be called at the end of each activation completion
     pub(crate) fn set_post_activate_hook(
         &mut self,
@@ -573,21 +799,6 @@ impl Worker {
         self.post_activate_hook = Some(Box::new(callback))
     }
 
-    /// Used for replay workers - causes the worker to shutdown when the given run reaches the
-    /// given event number
-    pub(crate) fn set_shutdown_on_run_reaches_event(&mut self, run_id: String, last_event: i64) {
-        self.set_post_activate_hook(move |worker| {
-            if worker
-                .wft_manager
-                .most_recently_processed_event(&run_id)
-                .unwrap_or_default()
-                >= last_event
-            {
-                worker.initiate_shutdown();
-            }
-        });
-    }
-
     /// Resolves with WFT poll response or `PollWfError::ShutDown` if WFTs have been drained
     async fn workflow_poll_or_wfts_drained(
         &self,
@@ -725,12 +936,42 @@ impl Worker {
                 .await?;
                 None
             }
+            NewWfTaskOutcome::Quarantined => {
+                // The task is dropped, so the server will time it out. Nothing is outstanding for
+                // lang, so the permit can be returned immediately.
+                debug!(run_id=%we.run_id, "Dropping workflow task for quarantined run");
+                self.return_workflow_task_permit();
+                None
+            }
+            NewWfTaskOutcome::FailWorkflow { error, task_token } => {
+                warn!(error=?error, run_id=%we.run_id,
+                      "Failing workflow after nondeterminism error while applying poll response");
+                let did_issue_eviction = self.request_wf_eviction(
+                    &we.run_id,
+                    format!(
+                        "Error while applying poll response to workflow: {:?}",
+                        error
+                    ),
+                    error.eviction_cause(),
+                );
+                self.fail_nondeterministic_workflow(
+                    &we.run_id,
+                    task_token,
+                    Failure::application_failure(error.source.to_string(), false),
+                    vec![],
+                )
+                .await?;
+                if !did_issue_eviction {
+                    self.return_workflow_task_permit();
+                }
+                None
+            }
             NewWfTaskOutcome::Evict(e) => {
                 warn!(error=?e, run_id=%we.run_id, "Error while applying poll response to workflow");
                 let did_issue_eviction = self.request_wf_eviction(
                     &we.run_id,
                     format!("Error while applying poll response to workflow: {:?}", e),
-                    e.evict_reason(),
+                    e.eviction_cause(),
                 );
                 // If we didn't actually need to issue an eviction, then return the WFT permit.
                 // EX: The workflow we tried to evict wasn't in the cache.
@@ -742,6 +983,44 @@ impl Worker {
         })
     }
 
+    /// Complete a workflow task with a command failing the workflow execution, because its run
+    /// behaved nondeterministically and its nondeterminism policy says to fail the workflow. Any
+    /// answers to queries which came with the task are sent along with it.
+    async fn fail_nondeterministic_workflow(
+        &self,
+        run_id: &str,
+        task_token: TaskToken,
+        mut failure: Failure,
+        query_responses: Vec<QueryResult>,
+    ) -> Result<(), CompleteWfError> {
+        if let Some(FailureInfo::ApplicationFailureInfo(ai)) = failure.failure_info.as_mut() {
+            ai.r#type = "NonDeterministicError".to_string();
+        }
+        let fail_cmd = ProtoCommand {
+            command_type: CommandType::FailWorkflowExecution as i32,
+            attributes: Some(
+                FailWorkflowExecution {
+                    failure: Some(failure),
+                }
+                .into(),
+            ),
+        };
+        self.handle_wft_reporting_errs(run_id, || async {
+            self.wf_client
+                .complete_workflow_task(WorkflowTaskCompletion {
+                    task_token,
+                    commands: vec![fail_cmd],
+                    sticky_attributes: None,
+                    query_responses,
+                    return_new_workflow_task: false,
+                    force_create_new_workflow_task: false,
+                    sdk_metadata: Default::default(),
+                })
+                .await
+        })
+        .await
+    }
+
     /// Handle a successful workflow activation
     ///
     /// Returns true if we actually reported WFT completion to server (success or failure)
@@ -750,6 +1029,20 @@ impl Worker {
         run_id: &str,
         success: workflow_completion::Success,
     ) -> Result<WFTReportOutcome, CompleteWfError> {
+        // Kept in case the activation can't be applied, so the queries can still be answered if
+        // the workflow is failed
+        let query_responses: Vec<_> = success
+            .commands
+            .iter()
+            .filter_map(|c| match &c.variant {
+                Some(workflow_command::Variant::RespondToQuery(qr))
+                    if qr.query_id != LEGACY_QUERY_ID =>
+                {
+                    Some(qr.clone())
+                }
+                _ => None,
+            })
+            .collect();
         // Convert to wf commands
         let cmds = success
             .commands
@@ -775,6 +1068,7 @@ impl Worker {
                         commands,
                         query_responses,
                         force_new_wft,
+                        sdk_metadata,
                     },
             })) => {
                 debug!("Sending commands to server: {}", commands.display());
@@ -791,6 +1085,7 @@ impl Worker {
                     sticky_attributes: None,
                     return_new_workflow_task: true,
                     force_create_new_workflow_task: force_new_wft,
+                    sdk_metadata,
                 };
                 let sticky_attrs = self.get_sticky_attrs();
                 // Do not return new WFT if we would not cache, because returned new WFTs are always
@@ -800,6 +1095,20 @@ impl Worker {
                 }
                 completion.sticky_attributes = sticky_attrs;
 
+                // Local activities whose markers are being recorded, and so whose kept results
+                // will not be needed once this completes
+                let recorded_las: Vec<u32> = completion
+                    .commands
+                    .iter()
+                    .filter_map(|c| match &c.attributes {
+                        Some(Attributes::RecordMarkerCommandAttributes(m))
+                            if m.marker_name == LOCAL_ACTIVITY_MARKER_NAME =>
+                        {
+                            extract_local_activity_marker_data(&m.details).map(|d| d.seq)
+                        }
+                        _ => None,
+                    })
+                    .collect();
                 self.handle_wft_reporting_errs(run_id, || async {
                     let maybe_wft = self
                         .wf_client
@@ -812,6 +1121,7 @@ impl Worker {
                     Ok(())
                 })
                 .await?;
+                self.local_act_mgr.forget_results(run_id, recorded_las);
                 Ok(WFTReportOutcome {
                     reported_to_server: true,
                     failed: false,
@@ -836,18 +1146,33 @@ impl Worker {
             }),
             Err(update_err) => {
                 // Automatically fail the workflow task in the event we couldn't update machines
-                let fail_cause = if matches!(&update_err.source, WFMachinesError::Nondeterminism(_))
+                let eviction = update_err.eviction_cause();
+                let (fail_cause, failure) = if let Some(details) = eviction.nondeterminism.as_ref()
                 {
-                    WorkflowTaskFailedCause::NonDeterministicError
+                    // Include the readable diff in the message, and the structured report as
+                    // failure details
+                    let mut failure =
+                        Failure::application_failure(update_err.source.to_string(), false);
+                    if let Some(FailureInfo::ApplicationFailureInfo(ai)) =
+                        failure.failure_info.as_mut()
+                    {
+                        ai.details = Some(Payloads {
+                            payloads: vec![details.as_payload().into()],
+                        });
+                    }
+                    (WorkflowTaskFailedCause::NonDeterministicError, failure)
                 } else {
-                    WorkflowTaskFailedCause::Unspecified
+                    (
+                        WorkflowTaskFailedCause::Unspecified,
+                        Failure::application_failure(format!("{:?}", update_err), false),
+                    )
                 };
-                let wft_fail_str = format!("{:?}", update_err);
                 self.wf_activation_failed(
                     run_id,
                     fail_cause,
-                    update_err.evict_reason(),
-                    Failure::application_failure(wft_fail_str.clone(), false).into(),
+                    eviction,
+                    failure.into(),
+                    query_responses,
                 )
                 .await
             }
@@ -861,17 +1186,26 @@ impl Worker {
         &self,
         run_id: &str,
         cause: WorkflowTaskFailedCause,
-        reason: EvictionReason,
+        eviction: EvictionCause,
         failure: workflow_completion::Failure,
+        query_responses: Vec<QueryResult>,
     ) -> Result<WFTReportOutcome, CompleteWfError> {
         Ok(
             match self.wft_manager.failed_activation(
                 run_id,
-                reason,
+                eviction,
                 format!("Workflow activation completion failed: {:?}", failure),
             ) {
                 FailedActivationOutcome::Report(tt) => {
                     warn!(run_id, failure=?failure, "Failing workflow activation");
+                    if !query_responses.is_empty() {
+                        // Failing a task can't carry query answers. The server keeps the queries
+                        // and delivers them again with the retried task, which answers them.
+                        debug!(
+                            run_id,
+                            "Queries will be answered by the retried workflow task"
+                        );
+                    }
                     self.handle_wft_reporting_errs(run_id, || async {
                         self.wf_client
                             .fail_workflow_task(tt, cause, failure.failure.map(Into::into))
@@ -893,6 +1227,20 @@ impl Worker {
                         failed: true,
                     }
                 }
+                FailedActivationOutcome::FailWorkflow(tt) => {
+                    warn!(run_id, failure=?failure, "Failing workflow after nondeterminism error");
+                    self.fail_nondeterministic_workflow(
+                        run_id,
+                        tt,
+                        failure.failure.unwrap_or_default(),
+                        query_responses,
+                    )
+                    .await?;
+                    WFTReportOutcome {
+                        reported_to_server: true,
+                        failed: true,
+                    }
+                }
                 FailedActivationOutcome::NoReport => WFTReportOutcome {
                     reported_to_server: false,
                     failed: true,
@@ -943,6 +1291,7 @@ impl Worker {
         la_res: LocalActivityExecutionResult,
         info: LocalInFlightActInfo,
         backoff: Option<prost_types::Duration>,
+        promoted: bool,
     ) {
         self.notify_local_result(
             &info.la_info.workflow_exec_info.run_id,
@@ -953,6 +1302,7 @@ impl Worker {
                 attempt: info.attempt,
                 backoff,
                 original_schedule_time: Some(info.la_info.schedule_time),
+                promoted,
             }),
         )
         .await
@@ -967,7 +1317,7 @@ impl Worker {
             self.request_wf_eviction(
                 run_id,
                 "Issue while processing local resolution",
-                e.evict_reason(),
+                e.eviction_cause(),
             );
         }
     }
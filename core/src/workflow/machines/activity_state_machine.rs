@@ -224,10 +224,9 @@ impl TryFrom<HistoryEvent> for ActivityMachineEvents {
                 }
             }
             _ => {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "Activity machine does not handle this event: {}",
-                    e
-                )))
+                return Err(WFMachinesError::Nondeterminism(
+                    format!("Activity machine does not handle this event: {}", e).into(),
+                ))
             }
         })
     }
@@ -168,7 +168,7 @@ impl TryFrom<HistoryEvent> for CancelExternalMachineEvents {
                 return Err(WFMachinesError::Nondeterminism(format!(
                     "Cancel external WF machine does not handle this event: {}",
                     e
-                )))
+                ).into()))
             }
         })
     }
@@ -79,10 +79,9 @@ impl TryFrom<HistoryEvent> for CancelWorkflowMachineEvents {
         Ok(match EventType::from_i32(e.event_type) {
             Some(EventType::WorkflowExecutionCanceled) => Self::WorkflowExecutionCanceled,
             _ => {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "Cancel workflow machine does not handle this event: {}",
-                    e
-                )))
+                return Err(WFMachinesError::Nondeterminism(
+                    format!("Cancel workflow machine does not handle this event: {}", e).into(),
+                ))
             }
         })
     }
@@ -622,12 +622,18 @@ mod test {
     use anyhow::anyhow;
     use rstest::{fixture, rstest};
     use std::mem::discriminant;
+    use std::time::Duration;
     use temporal_sdk::{
-        CancellableFuture, ChildWorkflowOptions, WfContext, WorkflowFunction, WorkflowResult,
+        CancellableFuture, ChildWorkflowFailureKind, ChildWorkflowOptions, ChildWorkflowStartError,
+        WfContext, WorkflowFunction, WorkflowResult,
     };
-    use temporal_sdk_core_protos::coresdk::{
-        child_workflow::child_workflow_result,
-        workflow_activation::resolve_child_workflow_execution_start::Status as StartStatus,
+    use temporal_sdk_core_protos::{
+        coresdk::{
+            child_workflow::{child_workflow_result, ParentClosePolicy},
+            common::RetryPolicy,
+            workflow_activation::resolve_child_workflow_execution_start::Status as StartStatus,
+        },
+        temporal::api::command::v1::command,
     };
 
     #[derive(Clone, Copy)]
@@ -635,6 +641,8 @@ mod test {
         Success,
         Failure,
         StartFailure,
+        Terminated,
+        TimedOut,
     }
 
     impl Expectation {
@@ -643,6 +651,8 @@ mod test {
                 0 => Self::Success,
                 1 => Self::Failure,
                 2 => Self::StartFailure,
+                3 => Self::Terminated,
+                4 => Self::TimedOut,
                 _ => return None,
             })
         }
@@ -664,6 +674,22 @@ mod test {
         ManagedWFFunc::new(t, func, vec![[Expectation::Failure as u8].into()])
     }
 
+    #[fixture]
+    fn child_workflow_terminated_hist() -> ManagedWFFunc {
+        let func = WorkflowFunction::new(parent_wf);
+        let t = canned_histories::single_child_workflow_terminated("child-id-1");
+        assert_eq!(3, t.get_full_history_info().unwrap().wf_task_count());
+        ManagedWFFunc::new(t, func, vec![[Expectation::Terminated as u8].into()])
+    }
+
+    #[fixture]
+    fn child_workflow_timed_out_hist() -> ManagedWFFunc {
+        let func = WorkflowFunction::new(parent_wf);
+        let t = canned_histories::single_child_workflow_timed_out("child-id-1");
+        assert_eq!(3, t.get_full_history_info().unwrap().wf_task_count());
+        ManagedWFFunc::new(t, func, vec![[Expectation::TimedOut as u8].into()])
+    }
+
     #[fixture]
     fn child_workflow_start_fail_hist() -> ManagedWFFunc {
         let func = WorkflowFunction::new(parent_wf);
@@ -680,28 +706,40 @@ mod test {
             ..Default::default()
         });
 
-        let start_res = child.start(&ctx).await;
-        match (expectation, &start_res.status) {
-            (Expectation::Success | Expectation::Failure, StartStatus::Succeeded(_)) => {}
-            (Expectation::StartFailure, StartStatus::Failed(_)) => return Ok(().into()),
-            _ => return Err(anyhow!("Unexpected start status")),
-        };
-        match (
-            expectation,
-            start_res.into_started().unwrap().result().await.status,
-        ) {
-            (Expectation::Success, Some(child_workflow_result::Status::Completed(_))) => {
-                Ok(().into())
+        let started = match (expectation, child.start(&ctx).await.try_into_started()) {
+            (
+                Expectation::StartFailure,
+                Err(ChildWorkflowStartError::WorkflowAlreadyStarted { workflow_id, .. }),
+            ) if workflow_id == "child-id-1" => return Ok(().into()),
+            (Expectation::StartFailure, _) | (_, Err(_)) => {
+                return Err(anyhow!("Unexpected start status"))
             }
-            (Expectation::Failure, _) => Ok(().into()),
-            _ => Err(anyhow!("Unexpected child WF status")),
+            (_, Ok(started)) => started,
+        };
+        if let Expectation::Success = expectation {
+            return match started.result().await.status {
+                Some(child_workflow_result::Status::Completed(_)) => Ok(().into()),
+                _ => Err(anyhow!("Unexpected child WF status")),
+            };
+        }
+        let failure = started
+            .typed_result()
+            .await
+            .expect_err("Child should not have succeeded");
+        match (expectation, failure.kind) {
+            (Expectation::Failure, ChildWorkflowFailureKind::Failed)
+            | (Expectation::Terminated, ChildWorkflowFailureKind::Terminated)
+            | (Expectation::TimedOut, ChildWorkflowFailureKind::TimedOut) => Ok(().into()),
+            _ => Err(anyhow!("Unexpected child WF failure {}", failure)),
         }
     }
 
     #[rstest(
         wfm,
         case::success(child_workflow_happy_hist()),
-        case::failure(child_workflow_fail_hist())
+        case::failure(child_workflow_fail_hist()),
+        case::terminated(child_workflow_terminated_hist()),
+        case::timed_out(child_workflow_timed_out_hist())
     )]
     #[tokio::test]
     async fn single_child_workflow_until_completion(mut wfm: ManagedWFFunc) {
@@ -750,6 +788,50 @@ mod test {
         wfm.shutdown().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn child_workflow_options_sent_in_command() {
+        let func = WorkflowFunction::new(|ctx: WfContext| async move {
+            ctx.child_workflow(ChildWorkflowOptions {
+                workflow_id: "child-id-1".to_string(),
+                workflow_type: "child".to_string(),
+                task_queue: Some("child-tq".to_string()),
+                run_timeout: Some(Duration::from_secs(10)),
+                parent_close_policy: ParentClosePolicy::Abandon,
+                retry_policy: Some(RetryPolicy {
+                    maximum_attempts: 3,
+                    ..Default::default()
+                }),
+                cron_schedule: Some("@hourly".to_string()),
+                ..Default::default()
+            })
+            .start(&ctx)
+            .await;
+            Ok(().into())
+        });
+        let mut t = TestHistoryBuilder::default();
+        t.add_by_type(EventType::WorkflowExecutionStarted);
+        t.add_workflow_task_scheduled_and_started();
+        let mut wfm = ManagedWFFunc::new(t, func, vec![]);
+
+        wfm.get_next_activation().await.unwrap();
+        let commands = wfm.get_server_commands().commands;
+        assert_eq!(commands.len(), 1);
+        match &commands[0].attributes {
+            Some(command::Attributes::StartChildWorkflowExecutionCommandAttributes(a)) => {
+                assert_eq!(a.task_queue.as_ref().unwrap().name, "child-tq");
+                assert_eq!(
+                    a.workflow_run_timeout.clone().unwrap(),
+                    Duration::from_secs(10).into()
+                );
+                assert_eq!(a.parent_close_policy, ParentClosePolicy::Abandon as i32);
+                assert_eq!(a.retry_policy.as_ref().unwrap().maximum_attempts, 3);
+                assert_eq!(a.cron_schedule, "@hourly");
+            }
+            _ => panic!("Expected a start child workflow command"),
+        }
+        wfm.shutdown().await.unwrap();
+    }
+
     async fn cancel_before_send_wf(ctx: WfContext) -> WorkflowResult<()> {
         let workflow_id = "child-id-1";
         let child = ctx.child_workflow(ChildWorkflowOptions {
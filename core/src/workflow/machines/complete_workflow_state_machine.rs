@@ -68,10 +68,13 @@ impl TryFrom<HistoryEvent> for CompleteWorkflowMachineEvents {
         Ok(match e.event_type() {
             EventType::WorkflowExecutionCompleted => Self::WorkflowExecutionCompleted,
             _ => {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "Complete workflow machine does not handle this event: {}",
-                    e
-                )))
+                return Err(WFMachinesError::Nondeterminism(
+                    format!(
+                        "Complete workflow machine does not handle this event: {}",
+                        e
+                    )
+                    .into(),
+                ))
             }
         })
     }
@@ -76,10 +76,13 @@ impl TryFrom<HistoryEvent> for ContinueAsNewWorkflowMachineEvents {
         Ok(match e.event_type() {
             EventType::WorkflowExecutionContinuedAsNew => Self::WorkflowExecutionContinuedAsNew,
             _ => {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "Continue as new workflow machine does not handle this event: {}",
-                    e
-                )))
+                return Err(WFMachinesError::Nondeterminism(
+                    format!(
+                        "Continue as new workflow machine does not handle this event: {}",
+                        e
+                    )
+                    .into(),
+                ))
             }
         })
     }
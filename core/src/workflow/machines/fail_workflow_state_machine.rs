@@ -92,10 +92,9 @@ impl TryFrom<HistoryEvent> for FailWorkflowMachineEvents {
         Ok(match e.event_type() {
             EventType::WorkflowExecutionFailed => Self::WorkflowExecutionFailed,
             _ => {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "Fail workflow machine does not handle this event: {}",
-                    e
-                )))
+                return Err(WFMachinesError::Nondeterminism(
+                    format!("Fail workflow machine does not handle this event: {}", e).into(),
+                ))
             }
         })
     }
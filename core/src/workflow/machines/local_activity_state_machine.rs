@@ -15,7 +15,7 @@ use temporal_sdk_core_protos::{
     constants::LOCAL_ACTIVITY_MARKER_NAME,
     coresdk::{
         activity_result::{
-            ActivityResolution, Cancellation, DoBackoff, Failure as ActFail, Success,
+            ActivityResolution, Cancellation, DoBackoff, DoPromote, Failure as ActFail, Success,
         },
         common::build_local_activity_marker_details,
         external_data::LocalActivityMarkerData,
@@ -99,6 +99,7 @@ pub(super) struct ResolveDat {
     pub(super) attempt: u32,
     pub(super) backoff: Option<prost_types::Duration>,
     pub(super) original_schedule_time: Option<SystemTime>,
+    pub(super) promoted: bool,
 }
 
 impl From<CompleteLocalActivityData> for ResolveDat {
@@ -124,6 +125,7 @@ impl From<CompleteLocalActivityData> for ResolveDat {
             attempt: d.marker_dat.attempt,
             backoff: d.marker_dat.backoff,
             original_schedule_time: d.marker_dat.original_schedule_time.try_into_or_none(),
+            promoted: d.marker_dat.promoted,
         }
     }
 }
@@ -147,7 +149,7 @@ pub(super) fn new_local_activity(
     } else {
         if maybe_pre_resolved.is_some() {
             return Err(WFMachinesError::Nondeterminism(
-                "Local activity cannot be created as pre-resolved while not replaying".to_string(),
+                "Local activity cannot be created as pre-resolved while not replaying".into(),
             ));
         }
         Executing {}.into()
@@ -232,6 +234,7 @@ impl LocalActivityMachine {
         attempt: u32,
         backoff: Option<prost_types::Duration>,
         original_schedule_time: Option<SystemTime>,
+        promoted: bool,
     ) -> Result<Vec<MachineResponse>, WFMachinesError> {
         self.try_resolve_with_dat(ResolveDat {
             result,
@@ -239,6 +242,7 @@ impl LocalActivityMachine {
             attempt,
             backoff,
             original_schedule_time,
+            promoted,
         })
     }
     /// Attempt to resolve the local activity with already known data, ex pre-resolved data
@@ -284,6 +288,7 @@ impl SharedState {
             attempt: self.attrs.attempt,
             backoff: None,
             original_schedule_time: self.attrs.original_schedule_time,
+            promoted: false,
         }
     }
 }
@@ -466,16 +471,22 @@ impl ResultNotified {
         dat: CompleteLocalActivityData,
     ) -> LocalActivityMachineTransition<MarkerCommandRecorded> {
         if self.result_type == ResultType::Completed && dat.result.is_err() {
-            return TransitionResult::Err(WFMachinesError::Nondeterminism(format!(
-                "Local activity (seq {}) completed successfully locally, but history said \
+            return TransitionResult::Err(WFMachinesError::Nondeterminism(
+                format!(
+                    "Local activity (seq {}) completed successfully locally, but history said \
                  it failed!",
-                shared.attrs.seq
-            )));
+                    shared.attrs.seq
+                )
+                .into(),
+            ));
         } else if self.result_type == ResultType::Failed && dat.result.is_ok() {
-            return TransitionResult::Err(WFMachinesError::Nondeterminism(format!(
-                "Local activity (seq {}) failed locally, but history said it completed!",
-                shared.attrs.seq
-            )));
+            return TransitionResult::Err(WFMachinesError::Nondeterminism(
+                format!(
+                    "Local activity (seq {}) failed locally, but history said it completed!",
+                    shared.attrs.seq
+                )
+                .into(),
+            ));
         }
         verify_marker_dat!(&shared, &dat, TransitionResult::default())
     }
@@ -611,6 +622,7 @@ impl WFMachinesAdapter for LocalActivityMachine {
                 attempt,
                 backoff,
                 original_schedule_time,
+                promoted,
             }) => {
                 let mut maybe_ok_result = None;
                 let mut maybe_failure = None;
@@ -630,7 +642,17 @@ impl WFMachinesAdapter for LocalActivityMachine {
                         maybe_failure = failure;
                     }
                 };
-                let resolution = if let Some(b) = backoff.as_ref() {
+                let resolution = if promoted {
+                    ActivityResolution {
+                        status: Some(
+                            DoPromote {
+                                attempt,
+                                original_schedule_time: original_schedule_time.map(Into::into),
+                            }
+                            .into(),
+                        ),
+                    }
+                } else if let Some(b) = backoff.as_ref() {
                     ActivityResolution {
                         status: Some(
                             DoBackoff {
@@ -680,6 +702,7 @@ impl WFMachinesAdapter for LocalActivityMachine {
                                 complete_time: complete_time.map(Into::into),
                                 backoff,
                                 original_schedule_time: original_schedule_time.map(Into::into),
+                                promoted,
                             },
                             maybe_ok_result,
                         ),
@@ -732,16 +755,15 @@ impl TryFrom<HistoryEvent> for LocalActivityMachineEvents {
 
     fn try_from(e: HistoryEvent) -> Result<Self, Self::Error> {
         if e.event_type() != EventType::MarkerRecorded {
-            return Err(WFMachinesError::Nondeterminism(format!(
-                "Local activity machine cannot handle this event: {}",
-                e
-            )));
+            return Err(WFMachinesError::Nondeterminism(
+                format!("Local activity machine cannot handle this event: {}", e).into(),
+            ));
         }
 
         match e.into_local_activity_marker_details() {
             Some(marker_dat) => Ok(LocalActivityMachineEvents::MarkerRecorded(marker_dat)),
             _ => Err(WFMachinesError::Nondeterminism(
-                "Local activity machine encountered an unparsable marker".to_string(),
+                "Local activity machine encountered an unparsable marker".into(),
             )),
         }
     }
@@ -752,11 +774,14 @@ fn verify_marker_data_matches(
     dat: &CompleteLocalActivityData,
 ) -> Result<(), WFMachinesError> {
     if shared.attrs.seq != dat.marker_dat.seq {
-        return Err(WFMachinesError::Nondeterminism(format!(
-            "Local activity marker data has sequence number {} but matched against LA \
+        return Err(WFMachinesError::Nondeterminism(
+            format!(
+                "Local activity marker data has sequence number {} but matched against LA \
             command with sequence number {}",
-            dat.marker_dat.seq, shared.attrs.seq
-        )));
+                dat.marker_dat.seq, shared.attrs.seq
+            )
+            .into(),
+        ));
     }
 
     Ok(())
@@ -784,8 +809,8 @@ impl From<LocalActivityExecutionResult> for ActivityResolution {
 mod tests {
     use super::*;
     use crate::{
-        replay::TestHistoryBuilder, test_help::canned_histories,
-        workflow::managed_wf::ManagedWFFunc,
+        internal_flags::CoreInternalFlags, replay::TestHistoryBuilder, test_help::canned_histories,
+        worker::LocalActRequest, workflow::managed_wf::ManagedWFFunc,
     };
     use rstest::rstest;
     use std::time::Duration;
@@ -798,8 +823,10 @@ mod tests {
             workflow_activation::{workflow_activation_job, WorkflowActivationJob},
             workflow_commands::ActivityCancellationType::WaitCancellationCompleted,
         },
+        default_wes_attribs,
         temporal::api::{
             command::v1::command, enums::v1::WorkflowTaskFailedCause, failure::v1::Failure,
+            history::v1::WorkflowExecutionStartedEventAttributes,
         },
     };
 
@@ -916,6 +943,45 @@ mod tests {
         wfm.shutdown().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn la_timeouts_capped_at_run_deadline() {
+        let func = WorkflowFunction::new(|ctx: WfContext| async move {
+            ctx.local_activity(LocalActivityOptions {
+                start_to_close_timeout: Some(Duration::from_secs(60)),
+                ..Default::default()
+            })
+            .await;
+            Ok(().into())
+        });
+        let mut t = TestHistoryBuilder::default();
+        t.add(
+            EventType::WorkflowExecutionStarted,
+            WorkflowExecutionStartedEventAttributes {
+                workflow_run_timeout: Some(Duration::from_secs(10).into()),
+                ..default_wes_attribs()
+            }
+            .into(),
+        );
+        t.add_workflow_task_scheduled_and_started();
+        let mut wfm = ManagedWFFunc::new(t, func, vec![]);
+
+        wfm.get_next_activation().await.unwrap();
+        let mut las = wfm.drain_queued_local_activities();
+        assert_eq!(las.len(), 1);
+        let timeouts = match las.pop().unwrap() {
+            LocalActRequest::New(la) => la.schedule_cmd.close_timeouts,
+            other => panic!("Expected a new local activity request, got {:?}", other),
+        };
+        let (sched, start) = timeouts.into_sched_and_start();
+        assert!(sched.unwrap() <= Duration::from_secs(10));
+        assert!(start.unwrap() <= Duration::from_secs(10));
+        assert_eq!(
+            wfm.get_server_commands().sdk_metadata.core_used_flags,
+            vec![CoreInternalFlags::CapTimeoutsAtRunDeadline as u32]
+        );
+        wfm.shutdown().await.unwrap();
+    }
+
     async fn two_la_wf(ctx: WfContext) -> WorkflowResult<()> {
         ctx.local_activity(LocalActivityOptions::default()).await;
         ctx.local_activity(LocalActivityOptions::default()).await;
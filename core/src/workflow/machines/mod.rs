@@ -8,6 +8,7 @@ mod complete_workflow_state_machine;
 mod continue_as_new_workflow_state_machine;
 mod fail_workflow_state_machine;
 mod local_activity_state_machine;
+mod modify_workflow_properties_state_machine;
 #[allow(unused)]
 mod mutable_side_effect_state_machine;
 mod patch_state_machine;
@@ -32,6 +33,7 @@ use complete_workflow_state_machine::CompleteWorkflowMachine;
 use continue_as_new_workflow_state_machine::ContinueAsNewWorkflowMachine;
 use fail_workflow_state_machine::FailWorkflowMachine;
 use local_activity_state_machine::LocalActivityMachine;
+use modify_workflow_properties_state_machine::ModifyWorkflowPropertiesMachine;
 use patch_state_machine::PatchMachine;
 use prost::alloc::fmt::Formatter;
 use rustfsm::{MachineError, StateMachine};
@@ -68,6 +70,7 @@ enum MachineKind {
     CancelExternalWorkflow,
     LocalActivity,
     UpsertSearchAttributes,
+    ModifyWorkflowProperties,
 }
 
 #[enum_dispatch::enum_dispatch]
@@ -86,6 +89,7 @@ enum Machines {
     TimerMachine,
     WorkflowTaskMachine,
     UpsertSearchAttributesMachine,
+    ModifyWorkflowPropertiesMachine,
 }
 
 /// Extends [rustfsm::StateMachine] with some functionality specific to the temporal SDK.
@@ -121,6 +125,9 @@ trait TemporalStateMachine: Send {
 
     /// Returns true if the state machine is in a final state
     fn is_final_state(&self) -> bool;
+
+    /// Describes the state the machine is currently in
+    fn current_state(&self) -> String;
 }
 
 impl<SM> TemporalStateMachine for SM
@@ -149,20 +156,20 @@ where
         if let Ok(converted_command) = command_type.try_into() {
             match OnEventWrapper::on_event_mut(self, converted_command) {
                 Ok(c) => process_machine_commands(self, c, None),
-                Err(MachineError::InvalidTransition) => {
-                    Err(WFMachinesError::Nondeterminism(format!(
+                Err(MachineError::InvalidTransition) => Err(WFMachinesError::Nondeterminism(
+                    format!(
                         "Unexpected command producing an invalid transition {:?} in state {}",
                         command_type,
                         self.state()
-                    )))
-                }
+                    )
+                    .into(),
+                )),
                 Err(MachineError::Underlying(e)) => Err(e.into()),
             }
         } else {
-            Err(WFMachinesError::Nondeterminism(format!(
-                "Unexpected command {:?}",
-                command_type
-            )))
+            Err(WFMachinesError::Nondeterminism(
+                format!("Unexpected command {:?}", command_type).into(),
+            ))
         }
     }
 
@@ -219,6 +226,10 @@ where
     fn is_final_state(&self) -> bool {
         self.has_reached_final_state()
     }
+
+    fn current_state(&self) -> String {
+        self.state().to_string()
+    }
 }
 
 fn process_machine_commands<SM>(
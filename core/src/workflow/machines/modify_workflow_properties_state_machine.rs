@@ -0,0 +1,396 @@
+use super::{
+    workflow_machines::{MachineResponse, WFMachinesError},
+    NewMachineWithCommand,
+};
+use crate::workflow::machines::{Cancellable, EventInfo, MachineKind, WFMachinesAdapter};
+use rustfsm::{fsm, TransitionResult};
+use temporal_sdk_core_protos::{
+    coresdk::workflow_commands::ModifyWorkflowProperties,
+    temporal::api::{
+        command::v1::Command,
+        enums::v1::{CommandType, EventType},
+        history::v1::HistoryEvent,
+    },
+};
+
+fsm! {
+    pub(super) name ModifyWorkflowPropertiesMachine;
+    command ModifyWorkflowPropertiesMachineCommand;
+    error WFMachinesError;
+    shared_state SharedState;
+
+    // Like upserting search attributes, modifying workflow properties is fire-and-forget. The
+    // machine moves to CommandIssued once its command is looped back as CommandScheduled, and is
+    // done once the server records the corresponding event.
+    Created --(CommandScheduled) --> CommandIssued;
+    CommandIssued --(CommandRecorded) --> Done;
+}
+
+/// Instantiates a ModifyWorkflowPropertiesMachine and packs it together with an initial command
+/// to apply the provided property changes.
+pub(super) fn modify_workflow_properties(
+    attribs: ModifyWorkflowProperties,
+) -> NewMachineWithCommand {
+    let sm = ModifyWorkflowPropertiesMachine::new();
+    let cmd = Command {
+        command_type: CommandType::ModifyWorkflowProperties as i32,
+        attributes: Some(attribs.into()),
+    };
+    NewMachineWithCommand {
+        command: cmd,
+        machine: sm.into(),
+    }
+}
+
+/// Unused but must exist
+type SharedState = ();
+
+/// This machine emits no commands besides the one it is created with
+#[derive(Debug, derive_more::Display)]
+pub(super) enum ModifyWorkflowPropertiesMachineCommand {}
+
+#[derive(Debug, Default, Clone, derive_more::Display)]
+pub(super) struct Created {}
+
+#[derive(Debug, Default, Clone, derive_more::Display)]
+pub(super) struct CommandIssued {}
+
+#[derive(Debug, Default, Clone, derive_more::Display)]
+pub(super) struct Done {}
+
+impl ModifyWorkflowPropertiesMachine {
+    fn new() -> Self {
+        Self {
+            state: Created {}.into(),
+            shared_state: (),
+        }
+    }
+}
+
+impl WFMachinesAdapter for ModifyWorkflowPropertiesMachine {
+    fn adapt_response(
+        &self,
+        _my_command: Self::Command,
+        _event_info: Option<EventInfo>,
+    ) -> Result<Vec<MachineResponse>, Self::Error> {
+        Err(Self::Error::Nondeterminism(
+            "ModifyWorkflowPropertiesMachine does not use commands".into(),
+        ))
+    }
+
+    fn matches_event(&self, event: &HistoryEvent) -> bool {
+        matches!(event.event_type(), EventType::WorkflowPropertiesModified)
+    }
+
+    fn kind(&self) -> MachineKind {
+        MachineKind::ModifyWorkflowProperties
+    }
+}
+
+impl Cancellable for ModifyWorkflowPropertiesMachine {}
+
+impl TryFrom<HistoryEvent> for ModifyWorkflowPropertiesMachineEvents {
+    type Error = WFMachinesError;
+
+    fn try_from(e: HistoryEvent) -> Result<Self, Self::Error> {
+        match e.event_type() {
+            EventType::WorkflowPropertiesModified => {
+                Ok(ModifyWorkflowPropertiesMachineEvents::CommandRecorded)
+            }
+            _ => Err(Self::Error::Nondeterminism(
+                format!("ModifyWorkflowPropertiesMachine does not handle {e}").into(),
+            )),
+        }
+    }
+}
+
+impl TryFrom<CommandType> for ModifyWorkflowPropertiesMachineEvents {
+    type Error = WFMachinesError;
+
+    fn try_from(c: CommandType) -> Result<Self, Self::Error> {
+        match c {
+            CommandType::ModifyWorkflowProperties => {
+                Ok(ModifyWorkflowPropertiesMachineEvents::CommandScheduled)
+            }
+            _ => Err(Self::Error::Nondeterminism(
+                format!("ModifyWorkflowPropertiesMachine does not handle command type {c:?}")
+                    .into(),
+            )),
+        }
+    }
+}
+
+impl From<CommandIssued> for Done {
+    fn from(_: CommandIssued) -> Self {
+        Self {}
+    }
+}
+
+impl From<Created> for CommandIssued {
+    fn from(_: Created) -> Self {
+        Self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::OnEventWrapper, *};
+    use crate::{replay::TestHistoryBuilder, workflow::managed_wf::ManagedWFFunc};
+    use futures::StreamExt;
+    use parking_lot::Mutex;
+    use rustfsm::StateMachine;
+    use std::{collections::HashMap, sync::Arc};
+    use temporal_sdk::{WfContext, WorkflowFunction};
+    use temporal_sdk_core_protos::{
+        coresdk::{common::Payload, workflow_activation::WorkflowProperties},
+        default_wes_attribs,
+        temporal::api::{
+            command::v1::command::Attributes,
+            common::v1::{Memo, SearchAttributes},
+            history::v1::{
+                UpsertWorkflowSearchAttributesEventAttributes,
+                WorkflowExecutionStartedEventAttributes, WorkflowPropertiesModifiedEventAttributes,
+            },
+        },
+    };
+
+    #[tokio::test]
+    async fn upsert_memo_from_workflow() {
+        let mut t = TestHistoryBuilder::default();
+        t.add_by_type(EventType::WorkflowExecutionStarted);
+        t.add_full_wf_task();
+        t.add_workflow_execution_completed();
+
+        let wff = WorkflowFunction::new(|ctx: WfContext| async move {
+            ctx.upsert_memo([(
+                "foo".to_string(),
+                Payload {
+                    data: vec![0x01],
+                    ..Default::default()
+                },
+            )]);
+            assert_eq!(ctx.memo().get("foo").unwrap().data, vec![0x01]);
+            Ok(().into())
+        });
+        let mut wfm = ManagedWFFunc::new(t, wff, vec![]);
+
+        wfm.get_next_activation().await.unwrap();
+        let commands = wfm.get_server_commands().commands;
+        assert_eq!(
+            commands[0].command_type,
+            CommandType::ModifyWorkflowProperties as i32
+        );
+        assert_matches!(
+            commands[0].attributes.clone().unwrap(),
+            Attributes::ModifyWorkflowPropertiesCommandAttributes(msg) => {
+                let fields = msg.upserted_memo.unwrap().fields;
+                assert_eq!(fields.get("foo").unwrap().data, vec![0x01]);
+                assert_eq!(fields.len(), 1);
+            }
+        );
+        wfm.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replays_with_properties_modified_event() {
+        let mut t = TestHistoryBuilder::default();
+        t.add_by_type(EventType::WorkflowExecutionStarted);
+        t.add_full_wf_task();
+        t.add(
+            EventType::WorkflowPropertiesModified,
+            WorkflowPropertiesModifiedEventAttributes {
+                workflow_task_completed_event_id: 4,
+                upserted_memo: Some(Memo::default()),
+            }
+            .into(),
+        );
+        t.add_workflow_execution_completed();
+
+        let wff = WorkflowFunction::new(|ctx: WfContext| async move {
+            ctx.upsert_memo([]);
+            Ok(().into())
+        });
+        let mut wfm = ManagedWFFunc::new(t, wff, vec![]);
+        wfm.get_next_activation().await.unwrap();
+        let commands = wfm.get_server_commands().commands;
+        assert_eq!(
+            commands[0].command_type,
+            CommandType::ModifyWorkflowProperties as i32
+        );
+        // Applying the recorded event must match it up with the command
+        wfm.get_next_activation().await.unwrap();
+        assert!(wfm.get_server_commands().commands.is_empty());
+        wfm.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn memo_from_start_reflects_upserts_during_replay() {
+        let mut t = TestHistoryBuilder::default();
+        t.add(
+            EventType::WorkflowExecutionStarted,
+            WorkflowExecutionStartedEventAttributes {
+                memo: Some(Memo {
+                    fields: HashMap::from([("old".to_string(), b"1".into())]),
+                }),
+                ..default_wes_attribs()
+            }
+            .into(),
+        );
+        t.add_full_wf_task();
+        t.add(
+            EventType::WorkflowPropertiesModified,
+            WorkflowPropertiesModifiedEventAttributes {
+                workflow_task_completed_event_id: 4,
+                upserted_memo: Some(Memo {
+                    fields: HashMap::from([
+                        ("old".to_string(), Default::default()),
+                        ("new".to_string(), b"2".into()),
+                    ]),
+                }),
+            }
+            .into(),
+        );
+        t.add_workflow_execution_completed();
+
+        let seen_memos = Arc::new(Mutex::new(vec![]));
+        let seen_memos_wf = seen_memos.clone();
+        let wff = WorkflowFunction::new(move |ctx: WfContext| {
+            let seen_memos = seen_memos_wf.clone();
+            async move {
+                let keys = |ctx: &WfContext| {
+                    let mut keys: Vec<_> = ctx.memo().into_keys().collect();
+                    keys.sort();
+                    seen_memos.lock().push(keys);
+                };
+                keys(&ctx);
+                ctx.upsert_memo([
+                    ("old".to_string(), Payload::default()),
+                    (
+                        "new".to_string(),
+                        Payload {
+                            data: b"2".to_vec(),
+                            ..Default::default()
+                        },
+                    ),
+                ]);
+                keys(&ctx);
+                Ok(().into())
+            }
+        });
+        let mut wfm = ManagedWFFunc::new(t, wff, vec![]);
+        wfm.process_all_activations().await.unwrap();
+        wfm.shutdown().await.unwrap();
+        assert_eq!(
+            *seen_memos.lock(),
+            vec![vec!["old".to_string()], vec!["new".to_string()]]
+        );
+    }
+
+    #[tokio::test]
+    async fn activations_carry_current_workflow_properties() {
+        let mut t = TestHistoryBuilder::default();
+        t.add(
+            EventType::WorkflowExecutionStarted,
+            WorkflowExecutionStartedEventAttributes {
+                memo: Some(Memo {
+                    fields: HashMap::from([("old".to_string(), b"1".into())]),
+                }),
+                search_attributes: Some(SearchAttributes {
+                    indexed_fields: HashMap::from([("sa".to_string(), b"1".into())]),
+                }),
+                ..default_wes_attribs()
+            }
+            .into(),
+        );
+        t.add_full_wf_task();
+        t.add(
+            EventType::WorkflowPropertiesModified,
+            WorkflowPropertiesModifiedEventAttributes {
+                workflow_task_completed_event_id: 4,
+                upserted_memo: Some(Memo {
+                    fields: HashMap::from([
+                        ("old".to_string(), Default::default()),
+                        ("new".to_string(), b"2".into()),
+                    ]),
+                }),
+            }
+            .into(),
+        );
+        t.add(
+            EventType::UpsertWorkflowSearchAttributes,
+            UpsertWorkflowSearchAttributesEventAttributes {
+                workflow_task_completed_event_id: 4,
+                search_attributes: Some(SearchAttributes {
+                    indexed_fields: HashMap::from([("sa".to_string(), b"2".into())]),
+                }),
+            }
+            .into(),
+        );
+        t.add_we_signaled("go", vec![]);
+        t.add_full_wf_task();
+        t.add_workflow_execution_completed();
+
+        let wff = WorkflowFunction::new(|ctx: WfContext| async move {
+            ctx.upsert_memo([
+                ("old".to_string(), Payload::default()),
+                ("new".to_string(), b"2".into()),
+            ]);
+            ctx.upsert_search_attributes([("sa".to_string(), b"2".into())]);
+            ctx.make_signal_channel("go").next().await;
+            Ok(().into())
+        });
+        let mut wfm = ManagedWFFunc::new(t, wff, vec![]);
+
+        let keys_and_data = |props: WorkflowProperties| {
+            let sorted = |map: HashMap<String, Payload>| {
+                let mut fields: Vec<_> = map.into_iter().map(|(k, v)| (k, v.data)).collect();
+                fields.sort();
+                fields
+            };
+            (sorted(props.memo), sorted(props.search_attributes))
+        };
+        // Core reports the properties the workflow started with, and then those left by upserts
+        let act = wfm.get_next_activation().await.unwrap();
+        assert_eq!(
+            keys_and_data(act.workflow_properties.unwrap()),
+            (
+                vec![("old".to_string(), b"1".to_vec())],
+                vec![("sa".to_string(), b"1".to_vec())]
+            )
+        );
+        let act = wfm.get_next_activation().await.unwrap();
+        assert_eq!(
+            keys_and_data(act.workflow_properties.unwrap()),
+            (
+                vec![("new".to_string(), b"2".to_vec())],
+                vec![("sa".to_string(), b"2".to_vec())]
+            )
+        );
+        // Nothing changed since, so they are not sent again
+        let act = wfm.get_next_activation().await.unwrap();
+        assert!(act.workflow_properties.is_none());
+        wfm.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn modify_workflow_properties_sm() {
+        let mut sm = ModifyWorkflowPropertiesMachine::new();
+        assert_eq!(Created {}.to_string(), sm.state().to_string());
+
+        let cmd_scheduled_sm_event = CommandType::ModifyWorkflowProperties.try_into().unwrap();
+        let recorded_history_event = HistoryEvent {
+            event_type: EventType::WorkflowPropertiesModified as i32,
+            ..Default::default()
+        };
+        assert!(sm.matches_event(&recorded_history_event));
+        let cmd_recorded_sm_event = recorded_history_event.try_into().unwrap();
+
+        OnEventWrapper::on_event_mut(&mut sm, cmd_scheduled_sm_event)
+            .expect("CommandScheduled should transition Created -> CommandIssued");
+        assert_eq!(CommandIssued {}.to_string(), sm.state().to_string());
+
+        OnEventWrapper::on_event_mut(&mut sm, cmd_recorded_sm_event)
+            .expect("CommandRecorded should transition CommandIssued -> Done");
+        assert_eq!(Done {}.to_string(), sm.state().to_string());
+    }
+}
@@ -165,10 +165,13 @@ impl Notified {
         id: String,
     ) -> PatchMachineTransition<MarkerCommandRecorded> {
         if id != dat.patch_id {
-            return TransitionResult::Err(WFMachinesError::Nondeterminism(format!(
-                "Change id {} does not match expected id {}",
-                id, dat.patch_id
-            )));
+            return TransitionResult::Err(WFMachinesError::Nondeterminism(
+                format!(
+                    "Change id {} does not match expected id {}",
+                    id, dat.patch_id
+                )
+                .into(),
+            ));
         }
         TransitionResult::default()
     }
@@ -211,10 +214,9 @@ impl TryFrom<HistoryEvent> for PatchMachineEvents {
     fn try_from(e: HistoryEvent) -> Result<Self, Self::Error> {
         match e.get_patch_marker_details() {
             Some((id, _)) => Ok(Self::MarkerRecorded(id)),
-            _ => Err(WFMachinesError::Nondeterminism(format!(
-                "Change machine cannot handle this event: {}",
-                e
-            ))),
+            _ => Err(WFMachinesError::Nondeterminism(
+                format!("Change machine cannot handle this event: {}", e).into(),
+            )),
         }
     }
 }
@@ -208,10 +208,13 @@ impl TryFrom<HistoryEvent> for SignalExternalMachineEvents {
                 }
             }
             _ => {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "Signal external WF machine does not handle this event: {}",
-                    e
-                )))
+                return Err(WFMachinesError::Nondeterminism(
+                    format!(
+                        "Signal external WF machine does not handle this event: {}",
+                        e
+                    )
+                    .into(),
+                ))
             }
         })
     }
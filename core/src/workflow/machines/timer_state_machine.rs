@@ -113,10 +113,9 @@ impl TryFrom<HistoryEvent> for TimerMachineEvents {
                 }
             }
             _ => {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "Timer machine does not handle this event: {}",
-                    e
-                )))
+                return Err(WFMachinesError::Nondeterminism(
+                    format!("Timer machine does not handle this event: {}", e).into(),
+                ))
             }
         })
     }
@@ -274,12 +273,23 @@ impl Cancellable for TimerMachine {
 mod test {
     use super::*;
     use crate::{
-        replay::TestHistoryBuilder, test_help::canned_histories,
+        internal_flags::CoreInternalFlags, replay::TestHistoryBuilder, test_help::canned_histories,
         workflow::managed_wf::ManagedWFFunc,
     };
     use rstest::{fixture, rstest};
     use std::{mem::discriminant, time::Duration};
     use temporal_sdk::{CancellableFuture, WfContext, WorkflowFunction};
+    use temporal_sdk_core_protos::{
+        default_wes_attribs,
+        temporal::api::{
+            command::v1::command,
+            history::v1::{
+                WorkflowExecutionStartedEventAttributes, WorkflowTaskCompletedEventAttributes,
+            },
+            sdk::v1::WorkflowTaskCompletedMetadata,
+        },
+        utilities::TryIntoOrNone,
+    };
 
     #[fixture]
     fn happy_wfm() -> ManagedWFFunc {
@@ -437,4 +447,98 @@ mod test {
             assert_eq!(discriminant(&state), discriminant(&s.state));
         }
     }
+
+    #[tokio::test]
+    async fn timers_capped_at_run_deadline() {
+        let func = WorkflowFunction::new(|ctx: WfContext| async move {
+            ctx.timer(Duration::from_secs(60)).await;
+            Ok(().into())
+        });
+        let mut t = TestHistoryBuilder::default();
+        t.add(
+            EventType::WorkflowExecutionStarted,
+            WorkflowExecutionStartedEventAttributes {
+                workflow_run_timeout: Some(Duration::from_secs(10).into()),
+                ..default_wes_attribs()
+            }
+            .into(),
+        );
+        t.add_workflow_task_scheduled_and_started();
+        let mut wfm = ManagedWFFunc::new(t, func, vec![]);
+
+        let act = wfm.get_next_activation().await.unwrap();
+        assert!(act.run_deadline.is_some());
+        let commands = wfm.get_server_commands().commands;
+        assert_eq!(commands.len(), 1);
+        let fire_after: Duration = match &commands[0].attributes {
+            Some(command::Attributes::StartTimerCommandAttributes(a)) => {
+                a.start_to_fire_timeout.clone().try_into_or_none().unwrap()
+            }
+            _ => panic!("Expected a start timer command"),
+        };
+        assert!(fire_after <= Duration::from_secs(10));
+        assert_eq!(
+            wfm.get_server_commands().sdk_metadata.core_used_flags,
+            vec![CoreInternalFlags::CapTimeoutsAtRunDeadline as u32]
+        );
+        wfm.shutdown().await.unwrap();
+    }
+
+    #[rstest]
+    #[case::flag_recorded(true)]
+    #[case::flag_not_recorded(false)]
+    #[tokio::test]
+    async fn timers_capped_during_replay_only_if_flag_recorded(#[case] flag_recorded: bool) {
+        let func = WorkflowFunction::new(|ctx: WfContext| async move {
+            ctx.timer(Duration::from_secs(60)).await;
+            Ok(().into())
+        });
+        let mut t = TestHistoryBuilder::default();
+        t.add(
+            EventType::WorkflowExecutionStarted,
+            WorkflowExecutionStartedEventAttributes {
+                workflow_run_timeout: Some(Duration::from_secs(10).into()),
+                ..default_wes_attribs()
+            }
+            .into(),
+        );
+        t.add_workflow_task_scheduled_and_started();
+        let core_used_flags = if flag_recorded {
+            vec![CoreInternalFlags::CapTimeoutsAtRunDeadline as u32]
+        } else {
+            vec![]
+        };
+        t.add(
+            EventType::WorkflowTaskCompleted,
+            WorkflowTaskCompletedEventAttributes {
+                scheduled_event_id: 2,
+                sdk_metadata: Some(WorkflowTaskCompletedMetadata {
+                    core_used_flags,
+                    lang_used_flags: vec![],
+                }),
+                ..Default::default()
+            }
+            .into(),
+        );
+        let timer_started_event_id = t.add_get_event_id(EventType::TimerStarted, None);
+        t.add_timer_fired(timer_started_event_id, "1".to_string());
+        t.add_workflow_task_scheduled_and_started();
+        let mut wfm = ManagedWFFunc::new(t, func, vec![]);
+
+        wfm.get_next_activation().await.unwrap();
+        let commands = wfm.get_server_commands();
+        assert!(commands.replaying);
+        let fire_after: Duration = match &commands.commands[0].attributes {
+            Some(command::Attributes::StartTimerCommandAttributes(a)) => {
+                a.start_to_fire_timeout.clone().try_into_or_none().unwrap()
+            }
+            _ => panic!("Expected a start timer command"),
+        };
+        assert_eq!(
+            fire_after <= Duration::from_secs(10),
+            flag_recorded,
+            "Timer should only be capped if history says the flag was used"
+        );
+        wfm.shutdown().await.unwrap();
+    }
 }
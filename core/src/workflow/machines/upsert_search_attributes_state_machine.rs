@@ -91,7 +91,7 @@ impl WFMachinesAdapter for UpsertSearchAttributesMachine {
     ) -> Result<Vec<MachineResponse>, Self::Error> {
         // No implementation needed until this state machine emits state machine commands
         Err(Self::Error::Nondeterminism(
-            "UpsertWorkflowSearchAttributesMachine does not use commands".to_string(),
+            "UpsertWorkflowSearchAttributesMachine does not use commands".into(),
         ))
     }
 
@@ -121,9 +121,9 @@ impl TryFrom<HistoryEvent> for UpsertSearchAttributesMachineEvents {
             EventType::UpsertWorkflowSearchAttributes => {
                 Ok(UpsertSearchAttributesMachineEvents::CommandRecorded)
             }
-            _ => Err(Self::Error::Nondeterminism(format!(
-                "UpsertWorkflowSearchAttributesMachine does not handle {e}"
-            ))),
+            _ => Err(Self::Error::Nondeterminism(
+                format!("UpsertWorkflowSearchAttributesMachine does not handle {e}").into(),
+            )),
         }
     }
 }
@@ -138,9 +138,10 @@ impl TryFrom<CommandType> for UpsertSearchAttributesMachineEvents {
             CommandType::UpsertWorkflowSearchAttributes => {
                 Ok(UpsertSearchAttributesMachineEvents::CommandScheduled)
             }
-            _ => Err(Self::Error::Nondeterminism(format!(
-                "UpsertWorkflowSearchAttributesMachine does not handle command type {c:?}"
-            ))),
+            _ => Err(Self::Error::Nondeterminism(
+                format!("UpsertWorkflowSearchAttributesMachine does not handle command type {c:?}")
+                    .into(),
+            )),
         }
     }
 }
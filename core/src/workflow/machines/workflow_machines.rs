@@ -9,6 +9,7 @@ use super::{
     complete_workflow_state_machine::complete_workflow,
     continue_as_new_workflow_state_machine::continue_as_new,
     fail_workflow_state_machine::fail_workflow, local_activity_state_machine::new_local_activity,
+    modify_workflow_properties_state_machine::modify_workflow_properties,
     patch_state_machine::has_change, signal_external_state_machine::new_external_signal,
     timer_state_machine::new_timer, upsert_search_attributes_state_machine::upsert_search_attrs,
     workflow_machines::local_acts::LocalActivityData,
@@ -16,43 +17,101 @@ use super::{
     TemporalStateMachine,
 };
 use crate::{
+    internal_flags::{CoreInternalFlags, InternalFlags},
     protosext::{HistoryEventExt, ValidScheduleLA},
     telemetry::{metrics::MetricsContext, VecDisplayer},
     worker::{
         ExecutingLAId, LocalActRequest, LocalActivityExecutionResult, LocalActivityResolution,
     },
     workflow::{
-        CommandID, DrivenWorkflow, HistoryUpdate, LocalResolution, WFCommand, WorkflowFetcher,
-        WorkflowStartedInfo,
+        CommandID, DrivenWorkflow, HistoryUpdate, LocalResolution, PendingCommand, WFCommand,
+        WorkflowFetcher, WorkflowStartedInfo,
     },
 };
+use prost::Message;
 use siphasher::sip::SipHasher13;
 use slotmap::SlotMap;
 use std::{
     borrow::{Borrow, BorrowMut},
     collections::{HashMap, VecDeque},
     convert::TryInto,
+    fmt::{Display, Formatter},
     hash::{Hash, Hasher},
+    mem,
     time::{Duration, Instant, SystemTime},
 };
 use temporal_sdk_core_protos::{
     coresdk::{
         common::NamespacedWorkflowExecution,
         workflow_activation::{
+            resolve_child_workflow_execution_start,
             workflow_activation_job::{self, Variant},
-            NotifyHasPatch, UpdateRandomSeed, WorkflowActivation,
+            NotifyHasPatch, UpdateRandomSeed, WorkflowActivation, WorkflowActivationJob,
+            WorkflowProperties,
         },
         workflow_commands::request_cancel_external_workflow_execution as cancel_we,
     },
     temporal::api::{
-        command::v1::Command as ProtoCommand,
+        command::v1::{command, Command as ProtoCommand},
         enums::v1::EventType,
         history::v1::{history_event, HistoryEvent},
+        sdk::v1::WorkflowTaskCompletedMetadata,
     },
+    utilities::TryIntoOrNone,
 };
 
 type Result<T, E = WFMachinesError> = std::result::Result<T, E>;
 
+/// Just enough of an activation job to describe it in a nondeterminism report, so that jobs need
+/// only be formatted if one is actually produced
+#[derive(Clone, Copy)]
+struct RecentJob {
+    kind: &'static str,
+    seq: Option<u32>,
+}
+
+impl From<&WorkflowActivationJob> for RecentJob {
+    fn from(job: &WorkflowActivationJob) -> Self {
+        let (kind, seq) = match job.variant.as_ref() {
+            None => ("empty", None),
+            Some(Variant::StartWorkflow(_)) => ("StartWorkflow", None),
+            Some(Variant::FireTimer(t)) => ("FireTimer", Some(t.seq)),
+            Some(Variant::UpdateRandomSeed(_)) => ("UpdateRandomSeed", None),
+            Some(Variant::QueryWorkflow(_)) => ("QueryWorkflow", None),
+            Some(Variant::CancelWorkflow(_)) => ("CancelWorkflow", None),
+            Some(Variant::SignalWorkflow(_)) => ("SignalWorkflow", None),
+            Some(Variant::ResolveActivity(r)) => ("ResolveActivity", Some(r.seq)),
+            Some(Variant::NotifyHasPatch(_)) => ("NotifyHasPatch", None),
+            Some(Variant::ResolveChildWorkflowExecutionStart(_)) => {
+                ("ResolveChildWorkflowExecutionStart", None)
+            }
+            Some(Variant::ResolveChildWorkflowExecution(_)) => {
+                ("ResolveChildWorkflowExecution", None)
+            }
+            Some(Variant::ResolveSignalExternalWorkflow(_)) => {
+                ("ResolveSignalExternalWorkflow", None)
+            }
+            Some(Variant::RemoveFromCache(_)) => ("RemoveFromCache", None),
+            Some(Variant::ResolveRequestCancelExternalWorkflow(_)) => {
+                ("ResolveRequestCancelExternalWorkflow", None)
+            }
+        };
+        Self { kind, seq }
+    }
+}
+
+impl Display for RecentJob {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.seq {
+            Some(seq) => write!(f, "{}({})", self.kind, seq),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// How many of a run's most recent activation jobs are included in nondeterminism reports
+const RECENT_JOBS_KEPT: usize = 10;
+
 slotmap::new_key_type! { struct MachineKey; }
 /// Handles all the logic for driving a workflow. It orchestrates many state machines that together
 /// comprise the logic of an executing workflow. One instance will exist per currently executing
@@ -92,6 +151,12 @@ pub(crate) struct WorkflowMachines {
     /// The current workflow time if it has been established. This may differ from the WFT start
     /// time since local activities may advance the clock
     current_wf_time: Option<SystemTime>,
+    /// The time by which this run must complete, given its run and execution timeouts
+    run_deadline: Option<SystemTime>,
+    /// The workflow's memo and search attributes as of its start, plus any upserted since
+    workflow_properties: WorkflowProperties,
+    /// Set when `workflow_properties` changed since they were last sent to lang
+    workflow_properties_changed: bool,
 
     all_machines: SlotMap<MachineKey, Machines>,
 
@@ -101,6 +166,9 @@ pub(crate) struct WorkflowMachines {
 
     /// Maps command ids as created by workflow authors to their associated machines.
     id_to_machine: HashMap<CommandID, MachineKey>,
+    /// Commands lang has issued which have not yet been resolved, along with the workflow time
+    /// at which they were issued
+    unresolved_commands: HashMap<MachineKey, (CommandID, Option<SystemTime>)>,
 
     /// Queued commands which have been produced by machines and await processing / being sent to
     /// the server.
@@ -122,6 +190,15 @@ pub(crate) struct WorkflowMachines {
     /// re-applying the final workflow task.
     pub have_seen_terminal_event: bool,
 
+    /// Total encoded size of all history events which have been applied to these machines
+    processed_history_bytes: usize,
+    /// Number of workflow task started events which have been applied to these machines
+    workflow_task_number: u32,
+    /// Internal flags in effect for this run, see [crate::internal_flags]
+    internal_flags: InternalFlags,
+    /// The jobs in the most recent activations, used when reporting nondeterminism errors
+    recent_jobs: VecDeque<RecentJob>,
+
     /// Metrics context
     pub metrics: MetricsContext,
 }
@@ -219,14 +296,22 @@ impl WorkflowMachines {
             workflow_end_time: None,
             wft_start_time: None,
             current_wf_time: None,
+            run_deadline: None,
+            workflow_properties: Default::default(),
+            workflow_properties_changed: false,
             all_machines: Default::default(),
             machines_by_event_id: Default::default(),
             id_to_machine: Default::default(),
+            unresolved_commands: Default::default(),
             commands: Default::default(),
             current_wf_task_commands: Default::default(),
             encountered_change_markers: Default::default(),
             local_activity_data: LocalActivityData::default(),
             have_seen_terminal_event: false,
+            processed_history_bytes: 0,
+            workflow_task_number: 0,
+            internal_flags: Default::default(),
+            recent_jobs: Default::default(),
         }
     }
 
@@ -265,23 +350,40 @@ impl WorkflowMachines {
                 attempt,
                 backoff,
                 original_schedule_time,
+                promoted,
             }) => {
                 let act_id = CommandID::LocalActivity(seq);
                 let mk = self.get_machine_key(act_id)?;
                 let mach = self.machine_mut(mk);
                 if let Machines::LocalActivityMachine(ref mut lam) = *mach {
-                    let resps =
-                        lam.try_resolve(result, runtime, attempt, backoff, original_schedule_time)?;
+                    let resps = lam
+                        .try_resolve(
+                            result,
+                            runtime,
+                            attempt,
+                            backoff,
+                            original_schedule_time,
+                            promoted,
+                        )
+                        .map_err(|e| self.nondeterminism_context(e, None, None, Some(mk)))?;
                     if resps.is_empty() {
                         result_important = false;
                     }
                     self.process_machine_responses(mk, resps)?;
                 } else {
-                    return Err(WFMachinesError::Nondeterminism(format!(
-                        "Command matching activity with seq num {} existed but was not a \
-                        local activity!",
-                        seq
-                    )));
+                    return Err(self.nondeterminism_context(
+                        WFMachinesError::Nondeterminism(
+                            format!(
+                                "Command matching activity with seq num {} existed but was not \
+                                 a local activity!",
+                                seq
+                            )
+                            .into(),
+                        ),
+                        None,
+                        None,
+                        Some(mk),
+                    ));
                 }
                 self.local_activity_data.done_executing(seq);
             }
@@ -300,6 +402,14 @@ impl WorkflowMachines {
         self.local_activity_data.outstanding_la_count()
     }
 
+    /// Returns an approximation of the memory used by this run, which is the size of all history it
+    /// has processed plus the size of its state machines.
+    pub(crate) fn approx_size_bytes(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.processed_history_bytes
+            + self.all_machines.len() * mem::size_of::<Machines>()
+    }
+
     /// Returns start info for the workflow if it has started
     pub(crate) fn get_started_info(&self) -> Option<&WorkflowStartedInfo> {
         self.drive_me.get_started_info()
@@ -318,6 +428,13 @@ impl WorkflowMachines {
         if event.is_final_wf_execution_event() {
             self.have_seen_terminal_event = true;
         }
+        if let Some(history_event::Attributes::WorkflowTaskCompletedEventAttributes(attrs)) =
+            event.attributes.as_ref()
+        {
+            self.internal_flags
+                .add_from_complete(attrs)
+                .map_err(unknown_flag_err)?;
+        }
 
         if event.is_command_event() {
             self.handle_command_event(event)?;
@@ -346,11 +463,12 @@ impl WorkflowMachines {
                         }
                     }
                     None => {
-                        return Err(WFMachinesError::Nondeterminism(format!(
+                        let err = WFMachinesError::Nondeterminism(
                             "During event handling, this event had an initial command ID but we \
-                            could not find a matching command for it: {:?}",
-                            event
-                        )));
+                             could not find a matching command for it"
+                                .into(),
+                        );
+                        return Err(self.nondeterminism_context(err, Some(&event), None, None));
                     }
                 }
             }
@@ -363,6 +481,9 @@ impl WorkflowMachines {
     /// Called when a workflow task started event has triggered. Ensures we are tracking the ID
     /// of the current started event as well as workflow time properly.
     fn task_started(&mut self, task_started_event_id: i64, time: SystemTime) -> Result<()> {
+        if self.current_started_event_id != task_started_event_id {
+            self.workflow_task_number += 1;
+        }
         self.current_started_event_id = task_started_event_id;
         self.wft_start_time = Some(time);
         self.set_current_time(time);
@@ -433,10 +554,10 @@ impl WorkflowMachines {
             let command = if let Some(c) = maybe_command {
                 c
             } else {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "No command scheduled for event {}",
-                    event
-                )));
+                let err = WFMachinesError::Nondeterminism(
+                    format!("No command scheduled for event {}", event).into(),
+                );
+                return Err(self.nondeterminism_context(err, Some(&event), None, None));
             };
 
             let canceled_before_sent = self
@@ -444,8 +565,22 @@ impl WorkflowMachines {
                 .was_cancelled_before_sent_to_server();
 
             if !canceled_before_sent {
+                if let MachineAssociatedCommand::Real(cmd) = &command.command {
+                    if let Some(mismatch) = self.id_and_type_mismatch(cmd, &event) {
+                        let err = WFMachinesError::Nondeterminism(mismatch.into());
+                        return Err(self.nondeterminism_context(
+                            err,
+                            Some(&event),
+                            Some(&command.command),
+                            Some(command.machine),
+                        ));
+                    }
+                }
                 // Feed the machine the event
-                self.submachine_handle_event(command.machine, event, true)?;
+                self.submachine_handle_event(command.machine, event, true)
+                    .map_err(|e| {
+                        self.nondeterminism_context(e, None, Some(&command.command), None)
+                    })?;
                 break command;
             }
         };
@@ -482,6 +617,28 @@ impl WorkflowMachines {
                         // workflow time set.
                         self.set_current_time(as_systime);
                     }
+                    let run_timeout_at = self
+                        .workflow_start_time
+                        .zip(attrs.workflow_run_timeout.clone().try_into_or_none());
+                    self.run_deadline = run_timeout_at
+                        .map(|(start, timeout): (_, Duration)| start + timeout)
+                        .into_iter()
+                        .chain(
+                            attrs
+                                .workflow_execution_expiration_time
+                                .clone()
+                                .try_into_or_none(),
+                        )
+                        .min();
+                    self.workflow_properties = WorkflowProperties {
+                        memo: attrs.memo.clone().map(Into::into).unwrap_or_default(),
+                        search_attributes: attrs
+                            .search_attributes
+                            .clone()
+                            .map(Into::into)
+                            .unwrap_or_default(),
+                    };
+                    self.workflow_properties_changed = true;
                     // Notify the lang sdk that it's time to kick off a workflow
                     self.drive_me.start(
                         self.workflow_id.clone(),
@@ -560,14 +717,38 @@ impl WorkflowMachines {
     /// "no work" situation. Possibly, it may know about some work the machines don't, like queries.
     pub(crate) fn get_wf_activation(&mut self) -> WorkflowActivation {
         let jobs = self.drive_me.drain_jobs();
+        if !jobs.is_empty() {
+            self.recent_jobs.extend(jobs.iter().map(RecentJob::from));
+            let excess = self.recent_jobs.len().saturating_sub(RECENT_JOBS_KEPT);
+            self.recent_jobs.drain(..excess);
+        }
+        // Activations without jobs may never reach lang, so changes are held for the next one
+        let workflow_properties = if !jobs.is_empty() && self.workflow_properties_changed {
+            self.workflow_properties_changed = false;
+            Some(self.workflow_properties.clone())
+        } else {
+            None
+        };
         WorkflowActivation {
             timestamp: self.current_wf_time.map(Into::into),
             is_replaying: self.replaying,
             run_id: self.run_id.clone(),
             jobs,
+            history_length: self.last_processed_event as u32,
+            history_size_bytes: self.processed_history_bytes as u64,
+            continue_as_new_suggested: false,
+            run_deadline: self.run_deadline.map(Into::into),
+            workflow_properties,
         }
     }
 
+    /// Returns how much workflow time remains before the run deadline, if there is one
+    fn time_remaining(&self) -> Option<Duration> {
+        let now = self.current_wf_time?;
+        self.run_deadline
+            .map(|deadline| deadline.duration_since(now).unwrap_or_default())
+    }
+
     pub(crate) fn has_pending_jobs(&self) -> bool {
         self.drive_me.has_pending_jobs()
     }
@@ -584,7 +765,9 @@ impl WorkflowMachines {
     /// the workflow code, handling them, and preparing them to be sent off to the server.
     pub(crate) async fn iterate_machines(&mut self) -> Result<()> {
         let results = self.drive_me.fetch_workflow_iteration_output().await;
-        let jobs = self.handle_driven_results(results)?;
+        let jobs = self
+            .handle_driven_results(results)
+            .map_err(|e| self.nondeterminism_context(e, None, None, None))?;
         for job in jobs {
             self.drive_me.send_job(job);
         }
@@ -647,6 +830,7 @@ impl WorkflowMachines {
             let next_event = history.peek();
             let eid = event.event_id;
             let etype = event.event_type;
+            self.processed_history_bytes += event.encoded_len();
             self.handle_event(event, next_event.is_some())?;
             self.last_processed_event = eid;
             if etype == EventType::WorkflowTaskStarted as i32 && next_event.is_none() {
@@ -671,6 +855,14 @@ impl WorkflowMachines {
                     ));
             } else if e.is_local_activity_marker() {
                 self.local_activity_data.process_peekahead_marker(e)?;
+            } else if let Some(history_event::Attributes::WorkflowTaskCompletedEventAttributes(
+                attrs,
+            )) = e.attributes.as_ref()
+            {
+                // The commands lang is about to produce were recorded under these flags
+                self.internal_flags
+                    .load_from_complete(attrs)
+                    .map_err(unknown_flag_err)?;
             }
         }
 
@@ -689,11 +881,145 @@ impl WorkflowMachines {
         event: HistoryEvent,
         has_next_event: bool,
     ) -> Result<()> {
-        let machine_responses = self.machine_mut(sm).handle_event(event, has_next_event)?;
+        // Nondeterminism errors describe the event which caused them, but copying its attributes
+        // for every event would be wasteful, so only its header is kept
+        let event_header = HistoryEvent {
+            event_id: event.event_id,
+            event_time: event.event_time.clone(),
+            event_type: event.event_type,
+            version: event.version,
+            task_id: event.task_id,
+            attributes: None,
+        };
+        let machine_responses = self
+            .machine_mut(sm)
+            .handle_event(event, has_next_event)
+            .map_err(|e| self.nondeterminism_context(e, Some(&event_header), None, Some(sm)))?;
         self.process_machine_responses(sm, machine_responses)?;
         Ok(())
     }
 
+    /// Checks that the ids and types in events recording activity or child workflow commands match
+    /// the commands, if [CoreInternalFlags::IdAndTypeDeterminismChecks] is in effect. Returns a
+    /// description of the mismatch, if there is one.
+    fn id_and_type_mismatch(&mut self, cmd: &ProtoCommand, event: &HistoryEvent) -> Option<String> {
+        let (cmd_id, cmd_type, evt_id, evt_type) = match (&cmd.attributes, &event.attributes) {
+            (
+                Some(command::Attributes::ScheduleActivityTaskCommandAttributes(c)),
+                Some(history_event::Attributes::ActivityTaskScheduledEventAttributes(e)),
+            ) => (
+                &c.activity_id,
+                c.activity_type.as_ref().map(|t| &t.name),
+                &e.activity_id,
+                e.activity_type.as_ref().map(|t| &t.name),
+            ),
+            (
+                Some(command::Attributes::StartChildWorkflowExecutionCommandAttributes(c)),
+                Some(
+                    history_event::Attributes::StartChildWorkflowExecutionInitiatedEventAttributes(
+                        e,
+                    ),
+                ),
+            ) => (
+                &c.workflow_id,
+                c.workflow_type.as_ref().map(|t| &t.name),
+                &e.workflow_id,
+                e.workflow_type.as_ref().map(|t| &t.name),
+            ),
+            _ => return None,
+        };
+        if !self.internal_flags.try_use(
+            CoreInternalFlags::IdAndTypeDeterminismChecks,
+            !self.replaying,
+        ) {
+            return None;
+        }
+        if cmd_id != evt_id {
+            Some(format!(
+                "Command has id {cmd_id} but the event recording it has id {evt_id}"
+            ))
+        } else if cmd_type != evt_type {
+            Some(format!(
+                "Command has type {} but the event recording it has type {}",
+                cmd_type.map(String::as_str).unwrap_or_default(),
+                evt_type.map(String::as_str).unwrap_or_default()
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Describes every command the workflow has issued which has not yet resolved, ordered by
+    /// when they were issued
+    pub(crate) fn pending_commands(&self) -> Vec<PendingCommand> {
+        let mut pending: Vec<_> = self
+            .unresolved_commands
+            .iter()
+            .map(|(mk, (id, issued_at))| {
+                let machine = self.machine(*mk);
+                PendingCommand {
+                    seq: id.seq(),
+                    command_type: machine.kind().to_string(),
+                    state: machine.current_state(),
+                    scheduled_time_ms: issued_at
+                        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                        .map(|d| d.as_millis() as u64),
+                }
+            })
+            .collect();
+        pending.sort_by(|a, b| {
+            (a.scheduled_time_ms, &a.command_type, a.seq).cmp(&(
+                b.scheduled_time_ms,
+                &b.command_type,
+                b.seq,
+            ))
+        });
+        pending
+    }
+
+    /// Returns the metadata to record when completing the current workflow task
+    pub(crate) fn get_sdk_metadata(&self) -> WorkflowTaskCompletedMetadata {
+        self.internal_flags.gather_for_wft_complete()
+    }
+
+    /// Attaches whatever context these machines have to a nondeterminism error, leaving any other
+    /// kind of error untouched. Context which is already present is kept, so that the innermost
+    /// (most specific) caller wins.
+    fn nondeterminism_context(
+        &self,
+        err: WFMachinesError,
+        event: Option<&HistoryEvent>,
+        command: Option<&MachineAssociatedCommand>,
+        machine: Option<MachineKey>,
+    ) -> WFMachinesError {
+        let mut report = match err {
+            WFMachinesError::Nondeterminism(report) => report,
+            other => return other,
+        };
+        if report.event.is_none() {
+            report.event = event.map(|e| Box::new(e.clone()));
+        }
+        if report.command.is_none() {
+            report.command = command.map(|c| match c {
+                MachineAssociatedCommand::Real(cmd) => format!("{} {:?}", cmd, cmd.attributes),
+                fake => fake.to_string(),
+            });
+        }
+        if report.machine_type.is_none() {
+            if let Some(m) = machine.and_then(|mk| self.all_machines.get(mk)) {
+                report.machine_type = Some(format!("{:?}", m.kind()));
+                report.machine_state = Some(m.current_state());
+            }
+        }
+        if report.workflow_task_number.is_none() {
+            report.workflow_task_number = Some(self.workflow_task_number);
+        }
+        if report.recent_jobs.is_empty() {
+            report.recent_jobs = self.recent_jobs.iter().map(ToString::to_string).collect();
+        }
+        WFMachinesError::Nondeterminism(report)
+    }
+
     /// Transfer commands from `current_wf_task_commands` to `commands`, so they may be sent off
     /// to the server. While doing so, [TemporalStateMachine::handle_command] is called on the
     /// machine associated with the command.
@@ -708,7 +1034,15 @@ impl WorkflowMachines {
                     MachineAssociatedCommand::Real(cmd) => {
                         let machine_responses = self
                             .machine_mut(c.machine)
-                            .handle_command(cmd.command_type())?;
+                            .handle_command(cmd.command_type())
+                            .map_err(|e| {
+                                self.nondeterminism_context(
+                                    e,
+                                    None,
+                                    Some(&c.command),
+                                    Some(c.machine),
+                                )
+                            })?;
                         self.process_machine_responses(c.machine, machine_responses)?;
                     }
                     MachineAssociatedCommand::FakeLocalActivityMarker(_) => {}
@@ -735,6 +1069,9 @@ impl WorkflowMachines {
         for response in machine_responses {
             match response {
                 MachineResponse::PushWFJob(a) => {
+                    if job_resolves_command(&a) {
+                        self.unresolved_commands.remove(&smk);
+                    }
                     self.drive_me.send_job(a);
                 }
                 MachineResponse::TriggerWFTaskStarted {
@@ -800,17 +1137,50 @@ impl WorkflowMachines {
         let mut jobs = vec![];
         for cmd in results {
             match cmd {
-                WFCommand::AddTimer(attrs) => {
+                WFCommand::AddTimer(mut attrs) => {
                     let seq = attrs.seq;
+                    let requested: Option<Duration> =
+                        attrs.start_to_fire_timeout.clone().try_into_or_none();
+                    if let Some((requested, remaining)) = requested.zip(self.time_remaining()) {
+                        if requested > remaining
+                            && self.internal_flags.try_use(
+                                CoreInternalFlags::CapTimeoutsAtRunDeadline,
+                                !self.replaying,
+                            )
+                        {
+                            warn!(seq, ?requested, ?remaining, run_id = %self.run_id,
+                                  "Timer would fire after the workflow run deadline, capping it");
+                            // The server rejects timers which aren't positive
+                            attrs.start_to_fire_timeout =
+                                Some(remaining.max(Duration::from_millis(1)).into());
+                        }
+                    }
                     self.add_cmd_to_wf_task(new_timer(attrs), Some(CommandID::Timer(seq)));
                 }
                 WFCommand::UpsertSearchAttributes(attrs) => {
                     let seq = attrs.seq;
+                    self.workflow_properties
+                        .search_attributes
+                        .extend(attrs.search_attributes.clone());
+                    self.workflow_properties_changed = true;
                     self.add_cmd_to_wf_task(
                         upsert_search_attrs(attrs),
                         Some(CommandID::Timer(seq)),
                     );
                 }
+                WFCommand::ModifyWorkflowProperties(attrs) => {
+                    let memo = &mut self.workflow_properties.memo;
+                    for (k, v) in attrs.upserted_memo.iter() {
+                        // Upserting an empty payload removes the field
+                        if v.data.is_empty() && v.metadata.is_empty() {
+                            memo.remove(k);
+                        } else {
+                            memo.insert(k.clone(), v.clone());
+                        }
+                    }
+                    self.workflow_properties_changed = true;
+                    self.add_cmd_to_wf_task(modify_workflow_properties(attrs), None);
+                }
                 WFCommand::CancelTimer(attrs) => {
                     jobs.extend(self.process_cancellation(CommandID::Timer(attrs.seq))?);
                 }
@@ -820,7 +1190,7 @@ impl WorkflowMachines {
                 }
                 WFCommand::AddLocalActivity(attrs) => {
                     let seq = attrs.seq;
-                    let attrs: ValidScheduleLA = ValidScheduleLA::from_schedule_la(
+                    let mut attrs: ValidScheduleLA = ValidScheduleLA::from_schedule_la(
                         attrs,
                         self.get_started_info()
                             .as_ref()
@@ -832,6 +1202,26 @@ impl WorkflowMachines {
                             seq, e
                         ))
                     })?;
+                    let timeouts_from = attrs.original_schedule_time.or(self.current_wf_time);
+                    if let Some((deadline, from)) = self.run_deadline.zip(timeouts_from) {
+                        let remaining = deadline.duration_since(from).unwrap_or_default();
+                        let mut capped = attrs.close_timeouts;
+                        let exceeded = capped.cap_at(remaining);
+                        if capped.into_sched_and_start()
+                            != attrs.close_timeouts.into_sched_and_start()
+                            && self.internal_flags.try_use(
+                                CoreInternalFlags::CapTimeoutsAtRunDeadline,
+                                !self.replaying,
+                            )
+                        {
+                            if exceeded {
+                                warn!(seq, ?remaining, run_id = %self.run_id,
+                                      "Local activity timeouts extend past the workflow run \
+                                       deadline, capping them");
+                            }
+                            attrs.close_timeouts = capped;
+                        }
+                    }
                     let (la, mach_resp) = new_local_activity(
                         attrs,
                         self.replaying,
@@ -839,8 +1229,7 @@ impl WorkflowMachines {
                         self.current_wf_time,
                     )?;
                     let machkey = self.all_machines.insert(la.into());
-                    self.id_to_machine
-                        .insert(CommandID::LocalActivity(seq), machkey);
+                    self.record_command_id(CommandID::LocalActivity(seq), machkey);
                     self.process_machine_responses(machkey, mach_resp)?;
                 }
                 WFCommand::RequestCancelActivity(attrs) => {
@@ -946,6 +1335,10 @@ impl WorkflowMachines {
     fn process_cancellation(&mut self, id: CommandID) -> Result<Vec<Variant>> {
         let mut jobs = vec![];
         let m_key = self.get_machine_key(id)?;
+        // Timers are resolved as far as lang is concerned as soon as it cancels them
+        if matches!(id, CommandID::Timer(_)) {
+            self.unresolved_commands.remove(&m_key);
+        }
         let machine_resps = self.machine_mut(m_key).cancel()?;
         debug!(machine_responses = %machine_resps.display(), cmd_id = ?id,
                "Cancel request responses");
@@ -958,6 +1351,9 @@ impl WorkflowMachines {
                     });
                 }
                 MachineResponse::PushWFJob(j) => {
+                    if job_resolves_command(&j) {
+                        self.unresolved_commands.remove(&m_key);
+                    }
                     jobs.push(j);
                 }
                 MachineResponse::RequestCancelLocalActivity(seq) => {
@@ -985,6 +1381,7 @@ impl WorkflowMachines {
                                 removed_act.attempt,
                                 None,
                                 None,
+                                false,
                             )?;
                             self.process_machine_responses(m_key, more_responses)?;
                         } else {
@@ -1030,11 +1427,17 @@ impl WorkflowMachines {
     fn add_cmd_to_wf_task(&mut self, machine: NewMachineWithCommand, id: Option<CommandID>) {
         let mach = self.add_new_command_machine(machine);
         if let Some(id) = id {
-            self.id_to_machine.insert(id, mach.machine);
+            self.record_command_id(id, mach.machine);
         }
         self.current_wf_task_commands.push_back(mach);
     }
 
+    fn record_command_id(&mut self, id: CommandID, machine: MachineKey) {
+        self.id_to_machine.insert(id, machine);
+        self.unresolved_commands
+            .insert(machine, (id, self.current_wf_time));
+    }
+
     fn add_new_command_machine(&mut self, machine: NewMachineWithCommand) -> CommandAndMachine {
         let k = self.all_machines.insert(machine.machine);
         CommandAndMachine {
@@ -1058,6 +1461,13 @@ impl WorkflowMachines {
     }
 }
 
+fn unknown_flag_err(flag: u32) -> WFMachinesError {
+    WFMachinesError::Fatal(format!(
+        "History uses internal flag {flag}, which this version of core does not know about. The \
+         workflow must be processed by a newer version."
+    ))
+}
+
 fn str_to_randomness_seed(run_id: &str) -> u64 {
     // This was originally `DefaultHasher` but that is potentially unstable across Rust releases.
     // This must forever be `SipHasher13` now or we risk breaking history compat.
@@ -1066,6 +1476,23 @@ fn str_to_randomness_seed(run_id: &str) -> u64 {
     s.finish()
 }
 
+/// Returns true if the job tells lang the command it was waiting on has finished, one way or
+/// another. A child workflow which started successfully is still outstanding.
+fn job_resolves_command(job: &Variant) -> bool {
+    match job {
+        Variant::FireTimer(_)
+        | Variant::ResolveActivity(_)
+        | Variant::ResolveChildWorkflowExecution(_)
+        | Variant::ResolveSignalExternalWorkflow(_)
+        | Variant::ResolveRequestCancelExternalWorkflow(_) => true,
+        Variant::ResolveChildWorkflowExecutionStart(r) => !matches!(
+            r.status,
+            Some(resolve_child_workflow_execution_start::Status::Succeeded(_))
+        ),
+        _ => false,
+    }
+}
+
 enum ChangeMarkerOutcome {
     SkipEvent,
     SkipCommand,
@@ -1087,11 +1514,14 @@ fn change_marker_handling(
                 debug!("Deprecated patch marker tried against wrong machine, skipping.");
                 return Ok(ChangeMarkerOutcome::SkipEvent);
             }
-            return Err(WFMachinesError::Nondeterminism(format!(
-                "Non-deprecated patch marker encountered for change {}, \
+            return Err(WFMachinesError::Nondeterminism(
+                format!(
+                    "Non-deprecated patch marker encountered for change {}, \
                             but there is no corresponding change command!",
-                patch_name
-            )));
+                    patch_name
+                )
+                .into(),
+            ));
         }
         // Version machines themselves may also not *have* matching markers, where non-deprecated
         // calls take the old path, and deprecated calls assume history is produced by a new-code
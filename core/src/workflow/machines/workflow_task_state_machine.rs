@@ -161,10 +161,9 @@ impl TryFrom<HistoryEvent> for WorkflowTaskMachineEvents {
                 }
             }
             _ => {
-                return Err(WFMachinesError::Nondeterminism(format!(
-                    "Event does not apply to a wf task machine: {}",
-                    e
-                )))
+                return Err(WFMachinesError::Nondeterminism(
+                    format!("Event does not apply to a wf task machine: {}", e).into(),
+                ))
             }
         })
     }
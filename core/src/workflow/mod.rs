@@ -18,7 +18,7 @@ use machines::WorkflowMachines;
 use std::{result, sync::mpsc::Sender, time::Duration};
 use temporal_sdk_core_protos::{
     coresdk::{workflow_activation::WorkflowActivation, workflow_commands::*},
-    temporal::api::command::v1::Command as ProtoCommand,
+    temporal::api::{command::v1::Command as ProtoCommand, sdk::v1::WorkflowTaskCompletedMetadata},
 };
 
 pub(crate) const LEGACY_QUERY_ID: &str = "legacy_query";
@@ -74,6 +74,8 @@ impl WorkflowManager {
 pub struct OutgoingServerCommands {
     pub commands: Vec<ProtoCommand>,
     pub replaying: bool,
+    /// Metadata to record with the workflow task completion
+    pub sdk_metadata: WorkflowTaskCompletedMetadata,
 }
 
 #[derive(Debug)]
@@ -147,6 +149,7 @@ impl WorkflowManager {
         OutgoingServerCommands {
             commands: self.machines.get_commands(),
             replaying: self.machines.replaying,
+            sdk_metadata: self.machines.get_sdk_metadata(),
         }
     }
 
@@ -172,8 +175,9 @@ impl WorkflowManager {
 /// Determines when workflows are kept in the cache or evicted
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum WorkflowCachingPolicy {
-    /// Workflows are cached until evicted explicitly or the cache size limit is reached, in which
-    /// case they are evicted by least-recently-used ordering.
+    /// Workflows are cached until evicted explicitly or the cache size or memory limit is reached,
+    /// in which case runs are evicted according to the worker's configured
+    /// [WorkflowCacheEvictionPolicy](temporal_sdk_core_api::worker::WorkflowCacheEvictionPolicy).
     Sticky {
         /// The maximum number of workflows that will be kept in the cache
         max_cached_workflows: usize,
@@ -217,6 +221,7 @@ pub enum WFCommand {
     SignalExternalWorkflow(SignalExternalWorkflowExecution),
     CancelSignalWorkflow(CancelSignalWorkflow),
     UpsertSearchAttributes(UpsertWorkflowSearchAttributes),
+    ModifyWorkflowProperties(ModifyWorkflowProperties),
 }
 
 impl TryFrom<WorkflowCommand> for WFCommand {
@@ -260,6 +265,9 @@ impl TryFrom<WorkflowCommand> for WFCommand {
             workflow_command::Variant::UpsertWorkflowSearchAttributesCommandAttributes(s) => {
                 Ok(Self::UpsertSearchAttributes(s))
             }
+            workflow_command::Variant::ModifyWorkflowProperties(s) => {
+                Ok(Self::ModifyWorkflowProperties(s))
+            }
         }
     }
 }
@@ -274,6 +282,33 @@ enum CommandID {
     CancelExternal(u32),
 }
 
+impl CommandID {
+    const fn seq(&self) -> u32 {
+        match *self {
+            CommandID::Timer(seq)
+            | CommandID::Activity(seq)
+            | CommandID::LocalActivity(seq)
+            | CommandID::ChildWorkflowStart(seq)
+            | CommandID::SignalExternal(seq)
+            | CommandID::CancelExternal(seq) => seq,
+        }
+    }
+}
+
+/// A command issued by the workflow which has not yet resolved. A list of these is the answer to
+/// the built-in stack trace query, which core serves without involving lang.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub(crate) struct PendingCommand {
+    /// The sequence number lang assigned to the command
+    pub seq: u32,
+    /// The kind of command, ex: `Timer` or `Activity`
+    pub command_type: String,
+    /// The current state of the command's state machine
+    pub state: String,
+    /// Workflow time at which the command was issued, in milliseconds since the epoch, if known
+    pub scheduled_time_ms: Option<u64>,
+}
+
 /// Details remembered from the workflow execution started event that we may need to recall later.
 /// Is a subset of `WorkflowExecutionStartedEventAttributes`, but avoids holding on to huge fields.
 #[derive(Debug, Clone)]
@@ -426,6 +461,7 @@ pub mod managed_wf {
                     backoff: None,
                     // Tests at this level don't use the LA dispatcher, so this is irrelevant
                     original_schedule_time: None,
+                    promoted: false,
                 }))
         }
 
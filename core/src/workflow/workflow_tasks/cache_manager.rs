@@ -1,19 +1,140 @@
 use crate::{telemetry::metrics::MetricsContext, workflow::WorkflowCachingPolicy};
 use lru::LruCache;
 use std::{
+    collections::HashMap,
+    fmt::Debug,
     future::Future,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
 };
+use temporal_sdk_core_api::worker::WorkflowCacheEvictionPolicy;
+use temporal_sdk_core_protos::coresdk::workflow_activation::remove_from_cache::EvictionReason;
 use tokio::sync::Notify;
 
-/// Helps to maintain an LRU ordering in which workflow runs have been accessed so that old runs may
-/// be evicted once we reach the cap.
+/// Decides which cached workflow run should be evicted when the cache must make room. Policies only
+/// track usage, the [WorkflowCacheManager] is responsible for tracking membership and sizes.
+pub(crate) trait EvictionStrategy: Debug + Send {
+    /// Called whenever a run is inserted into the cache, or an already-cached run is used again
+    fn record_access(&mut self, run_id: &str);
+    /// Called whenever a run leaves the cache, for whatever reason
+    fn record_removal(&mut self, run_id: &str);
+    /// Choose a run to evict from among `cached`, which maps run ids to their approximate size in
+    /// bytes. The run identified by `exclude` must not be chosen.
+    fn choose_victim(&self, cached: &HashMap<String, usize>, exclude: &str) -> Option<String>;
+}
+
+impl dyn EvictionStrategy {
+    fn from_config(policy: WorkflowCacheEvictionPolicy) -> Box<Self> {
+        match policy {
+            WorkflowCacheEvictionPolicy::Lru => Box::new(LruPolicy::default()),
+            WorkflowCacheEvictionPolicy::Lfu => Box::new(LfuPolicy::default()),
+            WorkflowCacheEvictionPolicy::SizeAware => Box::new(SizeAwarePolicy::default()),
+        }
+    }
+}
+
+/// Evicts the least recently used run
+#[derive(Debug)]
+pub(crate) struct LruPolicy {
+    order: LruCache<String, ()>,
+}
+
+impl Default for LruPolicy {
+    fn default() -> Self {
+        Self {
+            order: LruCache::unbounded(),
+        }
+    }
+}
+
+impl EvictionStrategy for LruPolicy {
+    fn record_access(&mut self, run_id: &str) {
+        self.order.put(run_id.to_owned(), ());
+    }
+
+    fn record_removal(&mut self, run_id: &str) {
+        self.order.pop(run_id);
+    }
+
+    fn choose_victim(&self, _: &HashMap<String, usize>, exclude: &str) -> Option<String> {
+        self.order
+            .iter()
+            .rev()
+            .map(|(rid, _)| rid)
+            .find(|rid| *rid != exclude)
+            .cloned()
+    }
+}
+
+/// Evicts the least frequently used run. Ties are broken by evicting the least recently used.
+#[derive(Debug, Default)]
+pub(crate) struct LfuPolicy {
+    /// Maps run id -> (number of uses, tick of last use)
+    uses: HashMap<String, (u64, u64)>,
+    tick: u64,
+}
+
+impl EvictionStrategy for LfuPolicy {
+    fn record_access(&mut self, run_id: &str) {
+        self.tick += 1;
+        let entry = self.uses.entry(run_id.to_owned()).or_default();
+        entry.0 += 1;
+        entry.1 = self.tick;
+    }
+
+    fn record_removal(&mut self, run_id: &str) {
+        self.uses.remove(run_id);
+    }
+
+    fn choose_victim(&self, _: &HashMap<String, usize>, exclude: &str) -> Option<String> {
+        self.uses
+            .iter()
+            .filter(|(rid, _)| *rid != exclude)
+            .min_by_key(|(_, uses)| **uses)
+            .map(|(rid, _)| rid.clone())
+    }
+}
+
+/// Evicts the run using the most memory. Ties are broken by evicting the least recently used.
+#[derive(Debug, Default)]
+pub(crate) struct SizeAwarePolicy {
+    lru: LruPolicy,
+}
+
+impl EvictionStrategy for SizeAwarePolicy {
+    fn record_access(&mut self, run_id: &str) {
+        self.lru.record_access(run_id);
+    }
+
+    fn record_removal(&mut self, run_id: &str) {
+        self.lru.record_removal(run_id);
+    }
+
+    fn choose_victim(&self, cached: &HashMap<String, usize>, exclude: &str) -> Option<String> {
+        // Iteration goes from most to least recently used, and `max_by_key` returns the last
+        // maximum element, so ties go to the least recently used run.
+        self.lru
+            .order
+            .iter()
+            .map(|(rid, _)| rid)
+            .filter(|rid| *rid != exclude)
+            .max_by_key(|rid| cached.get(*rid).copied().unwrap_or_default())
+            .cloned()
+    }
+}
+
+/// Tracks which workflow runs are cached, and how big they are, so that runs may be evicted once
+/// we reach the configured limits. Which run is evicted is decided by a [EvictionStrategy].
 #[derive(Debug)]
 pub(crate) struct WorkflowCacheManager {
-    cache: LruCache<String, ()>,
+    /// Maps run ids to their last known approximate size in bytes
+    cached: HashMap<String, usize>,
+    strategy: Box<dyn EvictionStrategy>,
+    max_runs: usize,
+    max_bytes: Option<usize>,
+    total_bytes: usize,
     metrics: MetricsContext,
     cap_notify: Arc<Notify>,
     cache_size: Arc<AtomicUsize>,
@@ -21,15 +142,24 @@ pub(crate) struct WorkflowCacheManager {
 }
 
 impl WorkflowCacheManager {
-    pub fn new(policy: WorkflowCachingPolicy, metrics: MetricsContext) -> Self {
-        let cap = match policy {
+    pub fn new(
+        policy: WorkflowCachingPolicy,
+        eviction_policy: WorkflowCacheEvictionPolicy,
+        max_bytes: Option<usize>,
+        metrics: MetricsContext,
+    ) -> Self {
+        let max_runs = match policy {
             WorkflowCachingPolicy::Sticky {
                 max_cached_workflows,
             } => max_cached_workflows,
             _ => 0,
         };
         Self {
-            cache: LruCache::new(cap),
+            cached: Default::default(),
+            strategy: <dyn EvictionStrategy>::from_config(eviction_policy),
+            max_runs,
+            max_bytes,
+            total_bytes: 0,
             metrics,
             cap_notify: Arc::new(Notify::new()),
             cache_size: Arc::new(AtomicUsize::new(0)),
@@ -39,7 +169,12 @@ impl WorkflowCacheManager {
 
     #[cfg(test)]
     fn new_test(policy: WorkflowCachingPolicy) -> Self {
-        Self::new(policy, Default::default())
+        Self::new(
+            policy,
+            WorkflowCacheEvictionPolicy::Lru,
+            None,
+            Default::default(),
+        )
     }
 
     /// Resolves once there is an open slot in the cache. The passed in closure can be used to
@@ -50,13 +185,13 @@ impl WorkflowCacheManager {
     where
         Fun: Fn() -> bool,
     {
-        if self.cache.cap() == 0 {
+        if self.max_runs == 0 {
             return None;
         }
 
         let size = self.cache_size.clone();
         let notify = self.cap_notify.clone();
-        let cap = self.cache.cap();
+        let cap = self.max_runs;
         let mx = self.cap_mutex.clone();
         Some(async move {
             let _l = mx.lock().await;
@@ -66,20 +201,24 @@ impl WorkflowCacheManager {
         })
     }
 
-    /// Inserts a record associated with the run id into the lru cache.
+    /// Inserts a record associated with the run id into the cache.
     /// Once cache reaches capacity, overflow records will be returned back to the caller.
     pub fn insert(&mut self, run_id: &str) -> Option<String> {
-        let res = if self.cache.len() < self.cache.cap() {
-            // Blindly add a record into the cache, since it still has capacity.
-            self.cache.put(run_id.to_owned(), ());
+        let res = if self.cached.contains_key(run_id) {
+            self.strategy.record_access(run_id);
             None
-        } else if self.cache.cap() == 0 {
+        } else if self.max_runs == 0 {
             // Run id should be evicted right away as cache size is 0.
             Some(run_id.to_owned())
         } else {
-            let maybe_got_evicted = self.cache.peek_lru().map(|r| r.0.clone());
-            let not_cached = self.cache.put(run_id.to_owned(), ()).is_none();
-            not_cached.then(|| maybe_got_evicted).flatten()
+            let evicted = if self.cached.len() >= self.max_runs {
+                self.evict_one(run_id, EvictionReason::CacheFull)
+            } else {
+                None
+            };
+            self.cached.insert(run_id.to_owned(), 0);
+            self.strategy.record_access(run_id);
+            evicted
         };
 
         self.size_changed();
@@ -87,19 +226,62 @@ impl WorkflowCacheManager {
         res
     }
 
-    /// If run id exists in the cache it will be moved to the top of the LRU cache.
+    /// Update the approximate size of a cached run. If doing so puts the cache over its memory
+    /// limit, runs are selected for eviction (never the one just updated) and returned until the
+    /// cache is back within the limit.
+    pub fn update_size(&mut self, run_id: &str, size_bytes: usize) -> Vec<String> {
+        let mut evicted = vec![];
+        if let Some(size) = self.cached.get_mut(run_id) {
+            self.total_bytes = self.total_bytes - *size + size_bytes;
+            *size = size_bytes;
+        } else {
+            return evicted;
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            while self.total_bytes > max_bytes {
+                if let Some(victim) = self.evict_one(run_id, EvictionReason::CacheMemoryLimit) {
+                    evicted.push(victim);
+                } else {
+                    break;
+                }
+            }
+        }
+        self.size_changed();
+        evicted
+    }
+
+    /// If run id exists in the cache it will be marked as used by the policy.
     pub fn touch(&mut self, run_id: &str) {
-        self.cache.get(run_id);
+        if self.cached.contains_key(run_id) {
+            self.strategy.record_access(run_id);
+        }
     }
 
     pub fn remove(&mut self, run_id: &str) {
-        self.cache.pop(run_id);
+        self.forget(run_id);
         self.size_changed();
     }
 
+    /// Have the policy select a run to be evicted, and stop tracking it. The caller is responsible
+    /// for actually evicting it.
+    fn evict_one(&mut self, exclude: &str, reason: EvictionReason) -> Option<String> {
+        let victim = self.strategy.choose_victim(&self.cached, exclude)?;
+        self.forget(&victim);
+        self.metrics.forced_cache_eviction(reason);
+        Some(victim)
+    }
+
+    fn forget(&mut self, run_id: &str) {
+        if let Some(size) = self.cached.remove(run_id) {
+            self.total_bytes -= size;
+        }
+        self.strategy.record_removal(run_id);
+    }
+
     fn size_changed(&self) {
-        let size = self.cache.len();
+        let size = self.cached.len();
         self.metrics.cache_size(size as u64);
+        self.metrics.cache_size_bytes(self.total_bytes as u64);
         self.cache_size.store(size, Ordering::Release);
         self.cap_notify.notify_one();
     }
@@ -191,4 +373,73 @@ mod tests {
             assert_eq!(run_id, "2");
         });
     }
+
+    fn new_with_policy(
+        max_cached_workflows: usize,
+        policy: WorkflowCacheEvictionPolicy,
+        max_bytes: Option<usize>,
+    ) -> WorkflowCacheManager {
+        WorkflowCacheManager::new(
+            WorkflowCachingPolicy::Sticky {
+                max_cached_workflows,
+            },
+            policy,
+            max_bytes,
+            Default::default(),
+        )
+    }
+
+    #[test]
+    fn lfu_evicts_least_used() {
+        let mut wcm = new_with_policy(2, WorkflowCacheEvictionPolicy::Lfu, None);
+        assert_matches!(wcm.insert("1"), None);
+        assert_matches!(wcm.insert("2"), None);
+        wcm.touch("1");
+        wcm.touch("1");
+        wcm.touch("2");
+        // 2 was used more recently, but 1 was used more often
+        assert_matches!(wcm.insert("3"), Some(run_id) => {
+            assert_eq!(run_id, "2");
+        });
+    }
+
+    #[test]
+    fn size_aware_evicts_biggest() {
+        let mut wcm = new_with_policy(3, WorkflowCacheEvictionPolicy::SizeAware, None);
+        assert_matches!(wcm.insert("1"), None);
+        assert_matches!(wcm.insert("2"), None);
+        assert_matches!(wcm.insert("3"), None);
+        assert!(wcm.update_size("1", 10).is_empty());
+        assert!(wcm.update_size("2", 1000).is_empty());
+        assert!(wcm.update_size("3", 10).is_empty());
+        assert_matches!(wcm.insert("4"), Some(run_id) => {
+            assert_eq!(run_id, "2");
+        });
+        // Ties are broken by recency
+        assert_matches!(wcm.insert("5"), Some(run_id) => {
+            assert_eq!(run_id, "1");
+        });
+    }
+
+    #[test]
+    fn memory_limit_evicts_until_under() {
+        let mut wcm = new_with_policy(10, WorkflowCacheEvictionPolicy::Lru, Some(100));
+        assert_matches!(wcm.insert("1"), None);
+        assert_matches!(wcm.insert("2"), None);
+        assert_matches!(wcm.insert("3"), None);
+        assert!(wcm.update_size("1", 40).is_empty());
+        assert!(wcm.update_size("2", 40).is_empty());
+        assert_eq!(wcm.update_size("3", 80), vec!["1", "2"]);
+        // The run which grew is never evicted by its own growth
+        assert!(wcm.update_size("3", 200).is_empty());
+        wcm.remove("3");
+        assert_eq!(wcm.total_bytes, 0);
+    }
+
+    #[test]
+    fn update_size_of_unknown_run_does_nothing() {
+        let mut wcm = new_with_policy(10, WorkflowCacheEvictionPolicy::Lru, Some(100));
+        assert!(wcm.update_size("1", 400).is_empty());
+        assert_eq!(wcm.total_bytes, 0);
+    }
 }
@@ -26,6 +26,7 @@ struct ManagedRun {
     wfm: Arc<Mutex<WorkflowManager>>,
     wft: Option<OutstandingTask>,
     activation: Option<OutstandingActivation>,
+    wf_type: String,
     metrics: MetricsContext,
     /// If set, it indicates there is a buffered poll response from the server that applies to this
     /// run. This can happen when lang takes too long to complete a task and the task times out, for
@@ -35,11 +36,12 @@ struct ManagedRun {
 }
 
 impl ManagedRun {
-    fn new(wfm: WorkflowManager, metrics: MetricsContext) -> Self {
+    fn new(wfm: WorkflowManager, wf_type: String, metrics: MetricsContext) -> Self {
         Self {
             wfm: Arc::new(Mutex::new(wfm)),
             wft: None,
             activation: None,
+            wf_type,
             metrics,
             buffered_resp: None,
         }
@@ -119,6 +121,11 @@ impl WorkflowConcurrencyManager {
         }
     }
 
+    /// Fetch the workflow type of a run
+    pub(crate) fn workflow_type(&self, run_id: &str) -> Option<String> {
+        self.runs.read().get(run_id).map(|r| r.wf_type.clone())
+    }
+
     /// Stores some work if there is any outstanding WFT or activation for the run. If there was
     /// not, returns the work back out inside the option.
     pub fn buffer_resp_if_outstanding_work(
@@ -251,9 +258,10 @@ impl WorkflowConcurrencyManager {
                             "Machines created with no jobs".to_string(),
                         ))
                     } else {
-                        self.runs
-                            .write()
-                            .insert(run_id.to_string(), ManagedRun::new(wfm, metrics));
+                        self.runs.write().insert(
+                            run_id.to_string(),
+                            ManagedRun::new(wfm, wf_type.to_owned(), metrics),
+                        );
                         Ok(activation)
                     }
                 }
@@ -4,7 +4,7 @@ mod cache_manager;
 mod concurrency_manager;
 
 use crate::{
-    pending_activations::PendingActivations,
+    pending_activations::{EvictionCause, PendingActivations},
     protosext::{ValidPollWFTQResponse, WorkflowActivationExt},
     telemetry::metrics::MetricsContext,
     worker::{client::WorkerClientBag, LocalActRequest, LocalActivityResolution},
@@ -14,20 +14,23 @@ use crate::{
         workflow_tasks::{
             cache_manager::WorkflowCacheManager, concurrency_manager::WorkflowConcurrencyManager,
         },
-        HistoryPaginator, HistoryUpdate, LocalResolution, WFCommand, WorkflowCachingPolicy,
-        WorkflowManager, LEGACY_QUERY_ID,
+        HistoryPaginator, HistoryUpdate, LocalResolution, PendingCommand, WFCommand,
+        WorkflowCachingPolicy, WorkflowManager, LEGACY_QUERY_ID,
     },
 };
 use crossbeam::queue::SegQueue;
 use futures::FutureExt;
+use lru::LruCache;
 use parking_lot::Mutex;
 use std::{
+    collections::HashMap,
     fmt::Debug,
     future::Future,
     ops::Add,
     sync::Arc,
     time::{Duration, Instant},
 };
+use temporal_sdk_core_api::worker::{NondeterminismPolicy, WorkerConfig};
 use temporal_sdk_core_protos::{
     coresdk::{
         workflow_activation::{
@@ -36,7 +39,7 @@ use temporal_sdk_core_protos::{
         },
         workflow_commands::QueryResult,
     },
-    temporal::api::command::v1::Command as ProtoCommand,
+    temporal::api::{command::v1::Command as ProtoCommand, sdk::v1::WorkflowTaskCompletedMetadata},
     TaskToken,
 };
 use tokio::{sync::Notify, time::timeout_at};
@@ -44,6 +47,8 @@ use tokio::{sync::Notify, time::timeout_at};
 /// What percentage of a WFT timeout we are willing to wait before sending a WFT heartbeat when
 /// necessary.
 const WFT_HEARTBEAT_TIMEOUT_FRACTION: f32 = 0.8;
+/// How many quarantined runs are remembered. Tasks for runs beyond this are processed again.
+const MAX_QUARANTINED_RUNS: usize = 1000;
 
 /// Centralizes concerns related to applying new workflow tasks and reporting the activations they
 /// produce.
@@ -68,6 +73,21 @@ pub struct WorkflowTaskManager {
     // TODO: Also should be moved inside concurrency manager, but there is some complexity around
     //   how inserts to it happen that requires a little thought (or a custom LRU impl)
     cache_manager: Mutex<WorkflowCacheManager>,
+    /// Applies to runs which behave nondeterministically, unless overridden for their type
+    nondeterminism_policy: NondeterminismPolicy,
+    nondeterminism_policy_by_type: HashMap<String, NondeterminismPolicy>,
+    /// Activations for runs whose history has reached either of these lengths or sizes in bytes
+    /// suggest continuing as new
+    continue_as_new_suggested_length: u32,
+    continue_as_new_suggested_bytes: u64,
+    /// Runs which were quarantined after behaving nondeterministically. New tasks for them are
+    /// dropped rather than applied. Only the most recently quarantined runs are remembered.
+    quarantined_runs: Mutex<LruCache<String, ()>>,
+    /// If set, runs may only keep their workflow task alive with heartbeats for this long while
+    /// waiting on local activities, after which those activities are promoted
+    max_la_wft_heartbeat_duration: Option<Duration>,
+    /// When each run with outstanding local activities started waiting on them
+    waiting_on_las_since: Mutex<HashMap<String, Instant>>,
 
     metrics: MetricsContext,
 }
@@ -135,6 +155,14 @@ pub(crate) enum NewWfTaskOutcome {
     Evict(WorkflowUpdateError),
     /// No action should be taken. Possibly we are waiting for local activities to complete
     LocalActsOutstanding,
+    /// The run has been quarantined and the task was dropped
+    Quarantined,
+    /// The run behaved nondeterministically while applying the task, and the workflow execution
+    /// should be failed by completing the task. The run must also be evicted.
+    FailWorkflow {
+        error: WorkflowUpdateError,
+        task_token: TaskToken,
+    },
 }
 
 #[derive(Debug)]
@@ -142,6 +170,8 @@ pub enum FailedActivationOutcome {
     NoReport,
     Report(TaskToken),
     ReportLegacyQueryFailure(TaskToken),
+    /// The workflow task should be completed with a command failing the workflow execution
+    FailWorkflow(TaskToken),
 }
 
 #[derive(Debug)]
@@ -157,6 +187,7 @@ pub(crate) enum ActivationAction {
         commands: Vec<ProtoCommand>,
         query_responses: Vec<QueryResult>,
         force_new_wft: bool,
+        sdk_metadata: WorkflowTaskCompletedMetadata,
     },
     /// We should respond to a legacy query request
     RespondLegacyQuery { result: QueryResult },
@@ -185,7 +216,8 @@ macro_rules! machine_mut {
 impl WorkflowTaskManager {
     pub(crate) fn new(
         pending_activations_notifier: Arc<Notify>,
-        eviction_policy: WorkflowCachingPolicy,
+        caching_policy: WorkflowCachingPolicy,
+        config: &WorkerConfig,
         metrics: MetricsContext,
     ) -> Self {
         Self {
@@ -194,7 +226,19 @@ impl WorkflowTaskManager {
             pending_queries: Default::default(),
             ready_buffered_wft: Default::default(),
             pending_activations_notifier,
-            cache_manager: Mutex::new(WorkflowCacheManager::new(eviction_policy, metrics.clone())),
+            cache_manager: Mutex::new(WorkflowCacheManager::new(
+                caching_policy,
+                config.workflow_cache_eviction_policy,
+                config.max_cached_workflows_bytes,
+                metrics.clone(),
+            )),
+            nondeterminism_policy: config.nondeterminism_policy,
+            nondeterminism_policy_by_type: config.nondeterminism_policy_by_type.clone(),
+            continue_as_new_suggested_length: config.continue_as_new_suggested_history_length,
+            continue_as_new_suggested_bytes: config.continue_as_new_suggested_history_bytes,
+            quarantined_runs: Mutex::new(LruCache::new(MAX_QUARANTINED_RUNS)),
+            max_la_wft_heartbeat_duration: config.max_local_activity_wft_heartbeat_duration,
+            waiting_on_las_since: Default::default(),
             metrics,
         }
     }
@@ -284,6 +328,7 @@ impl WorkflowTaskManager {
                         }
                     }
                     if !act.jobs.is_empty() {
+                        self.suggest_continue_as_new(&mut act);
                         self.insert_outstanding_activation(&act)?;
                         self.cache_manager.lock().touch(&act.run_id);
                         Ok(Some(act))
@@ -326,6 +371,22 @@ impl WorkflowTaskManager {
             .access_sync(run_id, |wfm| wfm.machines.last_processed_event)
     }
 
+    /// Describes the commands the provided run has issued which have not yet resolved
+    pub(crate) fn pending_commands(
+        &self,
+        run_id: &str,
+    ) -> Result<Vec<PendingCommand>, WorkflowMissingError> {
+        self.workflow_machines
+            .access_sync(run_id, |wfm| wfm.machines.pending_commands())
+    }
+
+    /// Marks the activation as suggesting continue-as-new if the run's history has grown past the
+    /// configured thresholds
+    fn suggest_continue_as_new(&self, act: &mut WorkflowActivation) {
+        act.continue_as_new_suggested = act.history_length >= self.continue_as_new_suggested_length
+            || act.history_size_bytes >= self.continue_as_new_suggested_bytes;
+    }
+
     /// Request a workflow eviction. This will queue up an activation to evict the workflow from
     /// the lang side. Workflow will not *actually* be evicted until lang replies to that activation
     ///
@@ -334,7 +395,7 @@ impl WorkflowTaskManager {
         &self,
         run_id: &str,
         message: impl Into<String>,
-        reason: EvictionReason,
+        cause: impl Into<EvictionCause>,
     ) -> EvictionRequestResult {
         if self.workflow_machines.exists(run_id) {
             let attempts = self
@@ -346,7 +407,7 @@ impl WorkflowTaskManager {
                 debug!(%run_id, %message, "Eviction requested");
                 // Queue up an eviction activation
                 self.pending_activations
-                    .notify_needs_eviction(run_id, message, reason);
+                    .notify_needs_eviction(run_id, message, cause.into());
                 self.pending_activations_notifier.notify_waiters();
                 EvictionRequestResult::EvictionRequested(attempts)
             } else {
@@ -364,6 +425,7 @@ impl WorkflowTaskManager {
         debug!(run_id=%run_id, "Evicting run");
 
         self.cache_manager.lock().remove(run_id);
+        self.waiting_on_las_since.lock().remove(run_id);
         let maybe_buffered = self.workflow_machines.evict(run_id);
         self.pending_activations.remove_all_with_run_id(run_id);
 
@@ -386,6 +448,13 @@ impl WorkflowTaskManager {
         work: ValidPollWFTQResponse,
         client: Arc<WorkerClientBag>,
     ) -> NewWfTaskOutcome {
+        if self
+            .quarantined_runs
+            .lock()
+            .contains(&work.workflow_execution.run_id)
+        {
+            return NewWfTaskOutcome::Quarantined;
+        }
         let mut work = if let Some(w) = self.workflow_machines.buffer_resp_if_outstanding_work(work)
         {
             w
@@ -411,10 +480,24 @@ impl WorkflowTaskManager {
             .take()
             .map(|q| query_to_job(LEGACY_QUERY_ID.to_string(), q));
 
+        let task_token = work.task_token.clone();
+        let wf_type = work.workflow_type.clone();
         let (info, mut next_activation, mut pending_queries) =
             match self.instantiate_or_update_workflow(work, client).await {
                 Ok(res) => res,
                 Err(e) => {
+                    if e.source.evict_reason() == EvictionReason::Nondeterminism {
+                        match self.nondeterminism_policy_for_type(&wf_type) {
+                            NondeterminismPolicy::FailWorkflowTask => {}
+                            NondeterminismPolicy::FailWorkflow => {
+                                return NewWfTaskOutcome::FailWorkflow {
+                                    error: e,
+                                    task_token,
+                                };
+                            }
+                            NondeterminismPolicy::Quarantine => self.quarantine(&e.run_id),
+                        }
+                    }
                     return NewWfTaskOutcome::Evict(e);
                 }
             };
@@ -471,6 +554,7 @@ impl WorkflowTaskManager {
                 NewWfTaskOutcome::Autocomplete
             }
         } else {
+            self.suggest_continue_as_new(&mut next_activation);
             if let Err(wme) = self.insert_outstanding_activation(&next_activation) {
                 return NewWfTaskOutcome::Evict(wme.into());
             }
@@ -484,7 +568,7 @@ impl WorkflowTaskManager {
         &self,
         run_id: &str,
         mut commands: Vec<WFCommand>,
-        local_activity_request_sink: impl FnOnce(Vec<LocalActRequest>) -> Vec<LocalActivityResolution>,
+        local_activity_request_sink: impl Fn(Vec<LocalActRequest>) -> Vec<LocalActivityResolution>,
     ) -> Result<Option<ServerCommandsWithWorkflowInfo>, WorkflowUpdateError> {
         // There used to be code here that would return right away if the run reply had no commands
         // and the activation that was just completed only had an eviction in it. That was bad
@@ -604,8 +688,16 @@ impl WorkflowTaskManager {
             // Wait on local activities to resolve if there are any, or for the WFT timeout to
             // be about to expire, in which case we will need to send a WFT heartbeat.
             let must_heartbeat = self
-                .wait_for_local_acts_or_heartbeat(run_id, wft_heartbeat_deadline)
-                .await;
+                .wait_for_local_acts_or_heartbeat(
+                    run_id,
+                    wft_heartbeat_deadline,
+                    &local_activity_request_sink,
+                )
+                .await
+                .map_err(|source| WorkflowUpdateError {
+                    source,
+                    run_id: run_id.to_owned(),
+                })?;
             let has_query_responses = !query_responses.is_empty();
             let is_query_playback = has_pending_query && !has_query_responses;
 
@@ -624,6 +716,7 @@ impl WorkflowTaskManager {
                     force_new_wft: must_heartbeat,
                     commands: server_cmds.commands,
                     query_responses,
+                    sdk_metadata: server_cmds.sdk_metadata,
                 },
             };
             let should_respond = !(self.pending_activations.has_pending(run_id)
@@ -644,7 +737,7 @@ impl WorkflowTaskManager {
     pub(crate) fn failed_activation(
         &self,
         run_id: &str,
-        reason: EvictionReason,
+        cause: EvictionCause,
         failstr: String,
     ) -> FailedActivationOutcome {
         let tt = if let Some(tt) = self
@@ -669,20 +762,65 @@ impl WorkflowTaskManager {
         {
             FailedActivationOutcome::ReportLegacyQueryFailure(tt)
         } else {
-            // Blow up any cached data associated with the workflow
-            let should_report = match self.request_eviction(run_id, failstr, reason) {
-                EvictionRequestResult::EvictionRequested(Some(attempt))
-                | EvictionRequestResult::EvictionAlreadyRequested(Some(attempt)) => attempt <= 1,
-                _ => false,
-            };
-            if should_report {
-                FailedActivationOutcome::Report(tt)
+            let policy = if cause.reason == EvictionReason::Nondeterminism {
+                self.nondeterminism_policy(run_id)
             } else {
-                FailedActivationOutcome::NoReport
+                NondeterminismPolicy::FailWorkflowTask
+            };
+            // Blow up any cached data associated with the workflow
+            let evict_res = self.request_eviction(run_id, failstr, cause);
+            match policy {
+                NondeterminismPolicy::FailWorkflowTask => {
+                    let should_report = match evict_res {
+                        EvictionRequestResult::EvictionRequested(Some(attempt))
+                        | EvictionRequestResult::EvictionAlreadyRequested(Some(attempt)) => {
+                            attempt <= 1
+                        }
+                        _ => false,
+                    };
+                    if should_report {
+                        FailedActivationOutcome::Report(tt)
+                    } else {
+                        FailedActivationOutcome::NoReport
+                    }
+                }
+                NondeterminismPolicy::FailWorkflow => FailedActivationOutcome::FailWorkflow(tt),
+                NondeterminismPolicy::Quarantine => {
+                    self.quarantine(run_id);
+                    FailedActivationOutcome::NoReport
+                }
             }
         }
     }
 
+    /// Returns the [NondeterminismPolicy] which applies to the provided run
+    fn nondeterminism_policy(&self, run_id: &str) -> NondeterminismPolicy {
+        match self.workflow_machines.workflow_type(run_id) {
+            Some(wf_type) => self.nondeterminism_policy_for_type(&wf_type),
+            None => self.nondeterminism_policy,
+        }
+    }
+
+    fn nondeterminism_policy_for_type(&self, wf_type: &str) -> NondeterminismPolicy {
+        self.nondeterminism_policy_by_type
+            .get(wf_type)
+            .copied()
+            .unwrap_or(self.nondeterminism_policy)
+    }
+
+    /// Stop processing tasks for a run which behaved nondeterministically
+    fn quarantine(&self, run_id: &str) {
+        error!(
+            run_id,
+            "Quarantining workflow run after nondeterminism error. Its workflow tasks will not be \
+             processed by this worker."
+        );
+        if let Some(m) = self.workflow_machines.run_metrics(run_id) {
+            m.wf_quarantined();
+        }
+        self.quarantined_runs.lock().put(run_id.to_owned(), ());
+    }
+
     /// Will create a new workflow manager if needed for the workflow activation, if not, it will
     /// feed the existing manager the updated history we received from the server.
     ///
@@ -817,6 +955,21 @@ impl WorkflowTaskManager {
                     EvictionReason::CacheFull,
                 );
             }
+            // Now that the run has processed everything in this task, its size is up to date.
+            // Growing may have pushed the cache over its memory limit.
+            if let Ok(size) = self
+                .workflow_machines
+                .access_sync(run_id, |wfm| wfm.machines.approx_size_bytes())
+            {
+                let evicted = self.cache_manager.lock().update_size(run_id, size);
+                for evicted_run_id in evicted {
+                    self.request_eviction(
+                        &evicted_run_id,
+                        "Workflow cache exceeded memory limit",
+                        EvictionReason::CacheMemoryLimit,
+                    );
+                }
+            }
 
             // If there was a buffered poll response from the server, it is now ready to
             // be handled.
@@ -927,12 +1080,18 @@ impl WorkflowTaskManager {
     /// Wait for either all local activities to resolve, or for 80% of the WFT timeout, in which
     /// case we will "heartbeat" by completing the WFT, even if there are no commands to send.
     ///
+    /// If the run has been waiting on local activities for longer than the configured maximum, its
+    /// local activities are promoted to normal activities. Those backing off are resolved right
+    /// away, but running ones only once lang has stopped running them, so the task may need to be
+    /// heartbeat until then.
+    ///
     /// Returns true if we must heartbeat
     async fn wait_for_local_acts_or_heartbeat(
         &self,
         run_id: &str,
         wft_heartbeat_deadline: Instant,
-    ) -> bool {
+        local_activity_request_sink: &impl Fn(Vec<LocalActRequest>) -> Vec<LocalActivityResolution>,
+    ) -> Result<bool, WFMachinesError> {
         loop {
             let la_count = self
                 .workflow_machines
@@ -941,10 +1100,49 @@ impl WorkflowTaskManager {
                 })
                 .expect("Workflow cannot go missing while we are waiting on LAs");
             if la_count == 0 {
-                return false;
-            } else if Instant::now() >= wft_heartbeat_deadline {
+                self.waiting_on_las_since.lock().remove(run_id);
+                return Ok(false);
+            }
+            let waiting_since = *self
+                .waiting_on_las_since
+                .lock()
+                .entry(run_id.to_owned())
+                .or_insert_with(Instant::now);
+            if Instant::now() >= wft_heartbeat_deadline {
+                let over_budget = self
+                    .max_la_wft_heartbeat_duration
+                    .map_or(false, |max| waiting_since.elapsed() >= max);
+                if over_budget {
+                    warn!(
+                        run_id,
+                        "Local activities ran for longer than the maximum workflow task heartbeat \
+                         duration, promoting them to normal activities"
+                    );
+                    let promoted = local_activity_request_sink(vec![LocalActRequest::PromoteAll {
+                        run_id: run_id.to_owned(),
+                    }]);
+                    if !promoted.is_empty() {
+                        if let Some(m) = self.workflow_machines.run_metrics(run_id) {
+                            for _ in &promoted {
+                                m.la_promoted();
+                            }
+                        }
+                        for resolution in promoted {
+                            self.notify_of_local_result(
+                                run_id,
+                                LocalResolution::LocalActivity(resolution),
+                            )
+                            .await
+                            .map_err(|e| e.source)?;
+                        }
+                        continue;
+                    }
+                }
                 // We must heartbeat b/c there are still pending local activities
-                return true;
+                if let Some(m) = self.workflow_machines.run_metrics(run_id) {
+                    m.wf_task_forced_heartbeat();
+                }
+                return Ok(true);
             }
             // Since an LA resolution always results in a new pending activation, we can wait on
             // notifications of that to re-check if they're all resolved.
@@ -967,8 +1165,11 @@ pub(crate) struct WorkflowUpdateError {
 }
 
 impl WorkflowUpdateError {
-    pub fn evict_reason(&self) -> EvictionReason {
-        self.source.evict_reason()
+    pub fn eviction_cause(&self) -> EvictionCause {
+        EvictionCause {
+            reason: self.source.evict_reason(),
+            nondeterminism: self.source.nondeterminism_details(),
+        }
     }
 }
 
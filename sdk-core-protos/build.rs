@@ -1,6 +1,134 @@
+use std::{fs, path::Path};
+
+/// Declarations which newer upstream API versions have, but the vendored `api_upstream` does not
+/// yet. The vendored subtree must never be edited, so they are added to a copy of it at build time
+/// instead. Each is `(file, anchor, declaration)`, and the declaration is inserted right after the
+/// anchor. Field and enum numbers are the ones upstream assigned. Entries must be removed once a
+/// subtree pull brings in the declarations they add.
+const UPSTREAM_ADDITIONS: &[(&str, &str, &str)] = &[
+    (
+        "temporal/api/enums/v1/command_type.proto",
+        "    COMMAND_TYPE_UPSERT_WORKFLOW_SEARCH_ATTRIBUTES = 13;\n",
+        "    COMMAND_TYPE_MODIFY_WORKFLOW_PROPERTIES = 16;\n",
+    ),
+    (
+        "temporal/api/enums/v1/event_type.proto",
+        "    EVENT_TYPE_UPSERT_WORKFLOW_SEARCH_ATTRIBUTES = 40;\n",
+        "    // Workflow properties modified by user workflow code\n    \
+         EVENT_TYPE_WORKFLOW_PROPERTIES_MODIFIED = 46;\n",
+    ),
+    (
+        "temporal/api/command/v1/message.proto",
+        "import \"temporal/api/taskqueue/v1/message.proto\";\n",
+        "import \"temporal/api/command/v1/workflow_properties.proto\";\n",
+    ),
+    (
+        "temporal/api/command/v1/message.proto",
+        "upsert_workflow_search_attributes_command_attributes = 14;\n",
+        "        ModifyWorkflowPropertiesCommandAttributes \
+         modify_workflow_properties_command_attributes = 17;\n",
+    ),
+    (
+        "temporal/api/history/v1/message.proto",
+        "import \"temporal/api/taskqueue/v1/message.proto\";\n",
+        "import \"temporal/api/sdk/v1/task_complete_metadata.proto\";\n",
+    ),
+    (
+        "temporal/api/history/v1/message.proto",
+        "import \"temporal/api/taskqueue/v1/message.proto\";\n",
+        "import \"temporal/api/history/v1/workflow_properties.proto\";\n",
+    ),
+    (
+        "temporal/api/history/v1/message.proto",
+        "message WorkflowTaskCompletedEventAttributes {\n",
+        "    // Data the SDK wishes to record for itself, but server need not interpret, and does not\n    \
+         // directly impact workflow state.\n    \
+         temporal.api.sdk.v1.WorkflowTaskCompletedMetadata sdk_metadata = 6;\n",
+    ),
+    (
+        "temporal/api/history/v1/message.proto",
+        "upsert_workflow_search_attributes_event_attributes = 45;\n",
+        "        WorkflowPropertiesModifiedEventAttributes \
+         workflow_properties_modified_event_attributes = 51;\n",
+    ),
+    (
+        "temporal/api/workflowservice/v1/request_response.proto",
+        "import \"temporal/api/version/v1/message.proto\";\n",
+        "import \"temporal/api/sdk/v1/task_complete_metadata.proto\";\n",
+    ),
+    (
+        "temporal/api/workflowservice/v1/request_response.proto",
+        "message RespondWorkflowTaskCompletedRequest {\n",
+        "    // Data the SDK wishes to record for itself, but server need not interpret, and does not\n    \
+         // directly impact workflow state.\n    \
+         temporal.api.sdk.v1.WorkflowTaskCompletedMetadata sdk_metadata = 12;\n",
+    ),
+];
+
+/// Copies the vendored upstream protos to `dest`, adding [UPSTREAM_ADDITIONS] to the copy
+fn copy_upstream_with_additions(src: &Path, dest: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    fn copy_dir(src: &Path, dest: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                copy_dir(&entry.path(), &dest.join(entry.file_name()))?;
+            } else {
+                fs::copy(entry.path(), dest.join(entry.file_name()))?;
+            }
+        }
+        Ok(())
+    }
+
+    if dest.exists() {
+        fs::remove_dir_all(dest)?;
+    }
+    copy_dir(src, dest)?;
+    for (file, anchor, declaration) in UPSTREAM_ADDITIONS {
+        let path = dest.join(file);
+        let contents = fs::read_to_string(&path)?;
+        let declared = declaration
+            .lines()
+            .last()
+            .expect("additions are not empty")
+            .trim();
+        if contents.contains(declared) {
+            return Err(format!(
+                "{} now declares `{}` upstream, remove it from the build script's additions",
+                file, declared
+            )
+            .into());
+        }
+        let at = match contents.matches(anchor).count() {
+            1 => contents.find(anchor).expect("anchor is present") + anchor.len(),
+            n => {
+                return Err(format!(
+                    "Expected to find `{}` once in {}, found it {} times",
+                    anchor.trim(),
+                    file,
+                    n
+                )
+                .into())
+            }
+        };
+        fs::write(
+            &path,
+            format!("{}{}{}", &contents[..at], declaration, &contents[at..]),
+        )?;
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rerun-if-changed=../protos");
+    println!("cargo:rerun-if-changed=build.rs");
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    let api_upstream = out_dir.join("api_upstream");
+    copy_upstream_with_additions(Path::new("../protos/api_upstream"), &api_upstream)?;
+    let descriptor_file = out_dir.join("descriptors.bin");
     tonic_build::configure()
+        // Descriptors drive conversion of messages to and from JSON
+        .file_descriptor_set_path(descriptor_file)
         // We don't actually want to build the grpc definitions - we don't need them (for now).
         // Just build the message structs.
         .build_server(false)
@@ -92,9 +220,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &[
                 "../protos/local/temporal/sdk/core/core_interface.proto",
                 "../protos/local/temporal/sdk/core/bridge/bridge.proto",
-                "../protos/api_upstream/temporal/api/workflowservice/v1/service.proto",
+                &api_upstream
+                    .join("temporal/api/workflowservice/v1/service.proto")
+                    .to_string_lossy(),
             ],
-            &["../protos/api_upstream", "../protos/local"],
+            &[&api_upstream.to_string_lossy(), "../protos/local"],
         )?;
     Ok(())
 }
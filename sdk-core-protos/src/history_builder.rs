@@ -35,6 +35,8 @@ pub struct TestHistoryBuilder {
     final_workflow_task_started_event_id: i64,
     previous_task_completed_id: i64,
     original_run_id: String,
+    /// If set, used as the time of every event added, rather than the current time
+    event_time: Option<SystemTime>,
 }
 
 impl TestHistoryBuilder {
@@ -257,6 +259,7 @@ impl TestHistoryBuilder {
         payload: Option<CorePayload>,
         failure: Option<Failure>,
         complete_time: Option<Timestamp>,
+        promoted: bool,
     ) {
         let attrs = MarkerRecordedEventAttributes {
             marker_name: LOCAL_ACTIVITY_MARKER_NAME.to_string(),
@@ -269,6 +272,7 @@ impl TestHistoryBuilder {
                     complete_time,
                     backoff: None,
                     original_schedule_time: None,
+                    promoted,
                 },
                 payload,
             ),
@@ -285,7 +289,7 @@ impl TestHistoryBuilder {
         activity_id: &str,
         payload: CorePayload,
     ) {
-        self.add_local_activity_marker(seq, activity_id, Some(payload), None, None);
+        self.add_local_activity_marker(seq, activity_id, Some(payload), None, None, false);
     }
 
     pub fn add_local_activity_result_marker_with_time(
@@ -295,7 +299,14 @@ impl TestHistoryBuilder {
         payload: CorePayload,
         complete_time: Timestamp,
     ) {
-        self.add_local_activity_marker(seq, activity_id, Some(payload), None, Some(complete_time));
+        self.add_local_activity_marker(
+            seq,
+            activity_id,
+            Some(payload),
+            None,
+            Some(complete_time),
+            false,
+        );
     }
 
     pub fn add_local_activity_fail_marker(
@@ -304,7 +315,17 @@ impl TestHistoryBuilder {
         activity_id: &str,
         failure: Failure,
     ) {
-        self.add_local_activity_marker(seq, activity_id, None, Some(failure), None);
+        self.add_local_activity_marker(seq, activity_id, None, Some(failure), None, false);
+    }
+
+    /// Records a local activity which was promoted to a normal activity after failing
+    pub fn add_local_activity_promoted_marker(
+        &mut self,
+        seq: u32,
+        activity_id: &str,
+        failure: Failure,
+    ) {
+        self.add_local_activity_marker(seq, activity_id, None, Some(failure), None, true);
     }
 
     pub fn add_local_activity_cancel_marker(&mut self, seq: u32, activity_id: &str) {
@@ -322,6 +343,7 @@ impl TestHistoryBuilder {
                 )),
             }),
             None,
+            false,
         );
     }
 
@@ -404,6 +426,11 @@ impl TestHistoryBuilder {
         );
     }
 
+    /// Use the provided time for events added from now on, rather than the current time
+    pub fn set_event_time(&mut self, time: SystemTime) {
+        self.event_time = Some(time);
+    }
+
     pub fn get_orig_run_id(&self) -> &str {
         &self.original_run_id
     }
@@ -440,7 +467,7 @@ impl TestHistoryBuilder {
         let evt = HistoryEvent {
             event_type: event_type as i32,
             event_id: self.current_event_id,
-            event_time: Some(SystemTime::now().into()),
+            event_time: Some(self.event_time.unwrap_or_else(SystemTime::now).into()),
             attributes: Some(attribs),
             ..Default::default()
         };
@@ -9,6 +9,7 @@ pub mod utilities;
 mod history_builder;
 #[cfg(feature = "history_builders")]
 mod history_info;
+mod proto_json;
 mod task_token;
 
 #[cfg(feature = "history_builders")]
@@ -472,8 +473,10 @@ pub mod coresdk {
                     workflow_activation_job::Variant::RemoveFromCache(RemoveFromCache {
                         message,
                         reason: reason as i32,
+                        nondeterminism_details: None,
                     }),
                 )],
+                ..Default::default()
             }
         }
 
@@ -489,6 +492,7 @@ pub mod coresdk {
                     .into_iter()
                     .map(|qr| workflow_activation_job::Variant::QueryWorkflow(qr).into())
                     .collect(),
+                ..Default::default()
             }
         }
 
@@ -556,6 +560,22 @@ pub mod coresdk {
             }
         }
 
+        impl NondeterminismDetails {
+            /// Encodes these details as a protobuf payload, so they may be attached to failures
+            pub fn as_payload(&self) -> Payload {
+                Payload {
+                    metadata: HashMap::from([
+                        ("encoding".to_string(), b"binary/protobuf".to_vec()),
+                        (
+                            "messageType".to_string(),
+                            b"coresdk.workflow_activation.NondeterminismDetails".to_vec(),
+                        ),
+                    ]),
+                    data: prost::Message::encode_to_vec(self),
+                }
+            }
+        }
+
         impl Display for WorkflowActivation {
             fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
                 write!(f, "WorkflowActivation(")?;
@@ -838,6 +858,17 @@ pub mod coresdk {
             }
         }
 
+        impl Display for ModifyWorkflowProperties {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                write!(
+                    f,
+                    "ModifyWorkflowProperties({}, upserted memo keys: {:?})",
+                    self.seq,
+                    self.upserted_memo.keys()
+                )
+            }
+        }
+
         impl Display for SignalExternalWorkflowExecution {
             fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
                 write!(f, "SignalExternalWorkflowExecution({})", self.seq)
@@ -1501,6 +1532,16 @@ pub mod temporal {
                     }
                 }
 
+                impl From<workflow_commands::ModifyWorkflowProperties> for command::Attributes {
+                    fn from(s: workflow_commands::ModifyWorkflowProperties) -> Self {
+                        Self::ModifyWorkflowPropertiesCommandAttributes(
+                            ModifyWorkflowPropertiesCommandAttributes {
+                                upserted_memo: Some(s.upserted_memo.into()),
+                            },
+                        )
+                    }
+                }
+
                 impl From<workflow_commands::CancelTimer> for command::Attributes {
                     fn from(s: workflow_commands::CancelTimer) -> Self {
                         Self::CancelTimerCommandAttributes(CancelTimerCommandAttributes {
@@ -1669,6 +1710,21 @@ pub mod temporal {
                     }
                 }
 
+                impl From<Memo> for HashMap<String, common::Payload> {
+                    fn from(m: Memo) -> Self {
+                        m.fields.into_iter().map(|(k, v)| (k, v.into())).collect()
+                    }
+                }
+
+                impl From<SearchAttributes> for HashMap<String, common::Payload> {
+                    fn from(s: SearchAttributes) -> Self {
+                        s.indexed_fields
+                            .into_iter()
+                            .map(|(k, v)| (k, v.into()))
+                            .collect()
+                    }
+                }
+
                 impl From<HashMap<String, common::Payload>> for SearchAttributes {
                     fn from(h: HashMap<String, common::Payload>) -> Self {
                         Self {
@@ -1713,6 +1769,8 @@ pub mod temporal {
 
                 tonic::include_proto!("temporal.api.history.v1");
 
+                const HISTORY_TYPE_NAME: &str = "temporal.api.history.v1.History";
+
                 impl History {
                     pub fn extract_run_id_from_start(&self) -> Result<&str, anyhow::Error> {
                         if let Some(
@@ -1730,6 +1788,25 @@ pub mod temporal {
                     pub fn last_event_id(&self) -> i64 {
                         self.events.last().map(|e| e.event_id).unwrap_or_default()
                     }
+
+                    /// Parses a history from JSON, as downloaded from the web UI or exported by
+                    /// tctl. Accepts both the proto3 JSON mapping and the older enum spellings,
+                    /// ex: `WorkflowExecutionStarted` rather than
+                    /// `EVENT_TYPE_WORKFLOW_EXECUTION_STARTED`. A bare list of events is accepted
+                    /// as well.
+                    pub fn from_json(json: &str) -> Result<Self, anyhow::Error> {
+                        let mut value: serde_json::Value = serde_json::from_str(json)?;
+                        if value.is_array() {
+                            value = serde_json::json!({ "events": value });
+                        }
+                        crate::proto_json::message_from_json(HISTORY_TYPE_NAME, value)
+                    }
+
+                    /// Serializes the history as JSON, following the proto3 JSON mapping
+                    pub fn to_json(&self) -> Result<String, anyhow::Error> {
+                        let value = crate::proto_json::message_to_json(HISTORY_TYPE_NAME, self)?;
+                        Ok(serde_json::to_string_pretty(&value)?)
+                    }
                 }
 
                 impl HistoryEvent {
@@ -1745,6 +1822,7 @@ pub mod temporal {
                             | EventType::TimerCanceled
                             | EventType::TimerStarted
                             | EventType::UpsertWorkflowSearchAttributes
+                            | EventType::WorkflowPropertiesModified
                             | EventType::WorkflowExecutionCanceled
                             | EventType::WorkflowExecutionCompleted
                             | EventType::WorkflowExecutionContinuedAsNew
@@ -1836,6 +1914,12 @@ pub mod temporal {
             }
         }
 
+        pub mod sdk {
+            pub mod v1 {
+                tonic::include_proto!("temporal.api.sdk.v1");
+            }
+        }
+
         pub mod taskqueue {
             pub mod v1 {
                 use crate::temporal::api::enums::v1::TaskQueueKind;
@@ -0,0 +1,201 @@
+//! Conversion between protobuf messages and JSON, driven by the descriptors of the protos compiled
+//! into this crate. Output follows the proto3 JSON mapping. Input may also use the enum spellings
+//! tctl and older versions of the web UI produce, ex: `WorkflowExecutionStarted` rather than
+//! `EVENT_TYPE_WORKFLOW_EXECUTION_STARTED`.
+
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use prost::Message;
+use prost_reflect::{
+    DeserializeOptions, DynamicMessage, EnumDescriptor, FileDescriptor, Kind, MessageDescriptor,
+};
+use serde_json::Value;
+
+type Result<T, E = anyhow::Error> = std::result::Result<T, E>;
+
+static DESCRIPTORS: Lazy<FileDescriptor> = Lazy::new(|| {
+    FileDescriptor::decode(&include_bytes!(concat!(env!("OUT_DIR"), "/descriptors.bin"))[..])
+        .expect("Descriptors compiled into the crate must be valid")
+});
+
+/// Parse a message of the given fully qualified type (ex: `temporal.api.history.v1.History`) from
+/// its JSON representation
+pub(crate) fn message_from_json<M: Message + Default>(type_name: &str, json: Value) -> Result<M> {
+    let desc = message_descriptor(type_name)?;
+    let mut json = json;
+    rewrite_legacy_enums(&desc, &mut json);
+    // Histories from newer servers may have fields we don't know about yet
+    let options = DeserializeOptions::new().deny_unknown_fields(false);
+    let msg = DynamicMessage::deserialize_with_options(desc, json, &options)?;
+    Ok(msg.transcode_to()?)
+}
+
+/// Render a message of the given fully qualified type as JSON
+pub(crate) fn message_to_json<M: Message>(type_name: &str, msg: &M) -> Result<Value> {
+    let mut dynamic = DynamicMessage::new(message_descriptor(type_name)?);
+    dynamic.transcode_from(msg)?;
+    Ok(serde_json::to_value(&dynamic)?)
+}
+
+fn message_descriptor(type_name: &str) -> Result<MessageDescriptor> {
+    DESCRIPTORS
+        .get_message_by_name(type_name)
+        .ok_or_else(|| anyhow!("Unknown message type {}", type_name))
+}
+
+/// Replaces enum values spelled the way tctl does with their proto3 JSON names, throughout a JSON
+/// representation of a message of the provided type. Anything which isn't recognized is left for
+/// deserialization to judge.
+fn rewrite_legacy_enums(desc: &MessageDescriptor, json: &mut Value) {
+    let obj = match json.as_object_mut() {
+        Some(o) => o,
+        None => return,
+    };
+    for (key, val) in obj.iter_mut() {
+        let field = match desc
+            .get_field_by_json_name(key)
+            .or_else(|| desc.get_field_by_name(key))
+        {
+            Some(f) => f,
+            None => continue,
+        };
+        let kind = if field.is_map() {
+            match field.kind() {
+                Kind::Message(entry) => entry.map_entry_value_field().kind(),
+                _ => continue,
+            }
+        } else {
+            field.kind()
+        };
+        let vals: Vec<&mut Value> = match val {
+            Value::Array(items) if field.is_list() => items.iter_mut().collect(),
+            Value::Object(entries) if field.is_map() => entries.values_mut().collect(),
+            v => vec![v],
+        };
+        for v in vals {
+            match &kind {
+                Kind::Enum(e) => rewrite_legacy_enum(e, v),
+                Kind::Message(m) => rewrite_legacy_enums(m, v),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// `WorkflowExecutionStarted` -> `EVENT_TYPE_WORKFLOW_EXECUTION_STARTED`, for the `EventType` enum
+fn rewrite_legacy_enum(desc: &EnumDescriptor, json: &mut Value) {
+    let name = match json.as_str() {
+        Some(n) if desc.get_value_by_name(n).is_none() => n,
+        _ => return,
+    };
+    let prefix = format!("{}_", screaming_snake_case(desc.name()));
+    let canonical = desc.values().find(|v| {
+        let unprefixed = v.name().strip_prefix(&prefix).unwrap_or_else(|| v.name());
+        pascal_case(unprefixed) == name
+    });
+    if let Some(v) = canonical {
+        *json = Value::String(v.name().to_string());
+    }
+}
+
+/// `EventType` -> `EVENT_TYPE`
+fn screaming_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_uppercase());
+    }
+    out
+}
+
+/// `WORKFLOW_EXECUTION_STARTED` -> `WorkflowExecutionStarted`
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(|c| c.to_lowercase()))
+                    .collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temporal::api::{
+        enums::v1::{EventType, TaskQueueKind},
+        history::v1::{history_event::Attributes, History},
+    };
+
+    #[test]
+    fn binary_histories_round_trip_through_json() {
+        for bytes in [
+            &include_bytes!("../../histories/timer_workflow_history.bin")[..],
+            &include_bytes!("../../histories/fail_wf_task.bin")[..],
+        ] {
+            let hist = History::decode(bytes).unwrap();
+            let json = hist.to_json().unwrap();
+            assert!(json.contains("\"EVENT_TYPE_WORKFLOW_EXECUTION_STARTED\""));
+            assert_eq!(History::from_json(&json).unwrap(), hist);
+        }
+    }
+
+    #[test]
+    fn parses_tctl_style_json() {
+        let json = r#"{
+          "events": [{
+            "eventId": "1",
+            "eventTime": "2022-01-14T16:59:45.546747610Z",
+            "eventType": "WorkflowExecutionStarted",
+            "taskId": "1048576",
+            "workflowExecutionStartedEventAttributes": {
+              "workflowType": { "name": "timer_wf" },
+              "taskQueue": { "name": "q", "kind": "Normal" },
+              "input": { "payloads": [{
+                "metadata": { "encoding": "anNvbi9wbGFpbg==" },
+                "data": "IkhpIg=="
+              }]},
+              "workflowRunTimeout": "0s",
+              "workflowTaskTimeout": "10s",
+              "attempt": 1,
+              "someFieldFromTheFuture": true
+            }
+          }]
+        }"#;
+        let hist = History::from_json(json).unwrap();
+        let event = &hist.events[0];
+        assert_eq!(event.event_id, 1);
+        assert_eq!(event.event_type(), EventType::WorkflowExecutionStarted);
+        assert_eq!(event.event_time.as_ref().unwrap().nanos, 546_747_610);
+        match event.attributes.as_ref().unwrap() {
+            Attributes::WorkflowExecutionStartedEventAttributes(a) => {
+                assert_eq!(a.workflow_type.as_ref().unwrap().name, "timer_wf");
+                assert_eq!(a.task_queue.as_ref().unwrap().kind(), TaskQueueKind::Normal);
+                let payload = &a.input.as_ref().unwrap().payloads[0];
+                assert_eq!(payload.metadata["encoding"], b"json/plain");
+                assert_eq!(payload.data, b"\"Hi\"");
+                assert_eq!(a.workflow_task_timeout.as_ref().unwrap().seconds, 10);
+                assert_eq!(a.attempt, 1);
+            }
+            other => panic!("Unexpected attributes {:?}", other),
+        }
+
+        // The same history in the proto3 mapping, as a bare list of events, parses identically
+        let canonical = hist.to_json().unwrap();
+        let events: Value = serde_json::from_str::<Value>(&canonical).unwrap()["events"].clone();
+        assert_eq!(History::from_json(&events.to_string()).unwrap(), hist);
+    }
+
+    #[test]
+    fn unrecognized_enum_values_are_errors() {
+        let json = r#"[{ "eventId": "1", "eventType": "NotAnEventType" }]"#;
+        assert!(History::from_json(json).is_err());
+    }
+}
@@ -136,6 +136,8 @@ impl ActContext {
     }
 
     /// Extract heartbeat details from last failed attempt. This is used in combination with retry policy.
+    /// If the worker has a heartbeat checkpoint store, these are the details last checkpointed by
+    /// the previous attempt, which may be fresher than what the server received.
     pub fn get_heartbeat_details(&self) -> &[Payload] {
         &self.heartbeat_details
     }
@@ -43,8 +43,9 @@ mod workflow_future;
 pub use activity_context::ActContext;
 
 pub use workflow_context::{
-    ActivityOptions, CancellableFuture, ChildWorkflow, ChildWorkflowOptions, LocalActivityOptions,
-    Signal, SignalData, SignalWorkflowOptions, WfContext,
+    ActivityOptions, CancellableFuture, ChildWorkflow, ChildWorkflowFailure,
+    ChildWorkflowFailureKind, ChildWorkflowOptions, ChildWorkflowStartError, LocalActivityOptions,
+    Session, SessionError, SessionOptions, Signal, SignalData, SignalWorkflowOptions, WfContext,
 };
 
 use crate::{
@@ -63,10 +64,7 @@ use std::{
 };
 use temporal_client::ClientOptionsBuilder;
 use temporal_sdk_core::Url;
-use temporal_sdk_core_api::{
-    errors::{PollActivityError, PollWfError},
-    Worker as CoreWorker,
-};
+use temporal_sdk_core_api::Worker as CoreWorker;
 use temporal_sdk_core_protos::{
     coresdk::{
         activity_result::{ActivityExecutionResult, ActivityResolution},
@@ -248,13 +246,9 @@ impl Worker {
         tokio::try_join!(
             // Workflow polling loop
             async {
-                loop {
-                    let activation = match common.worker.poll_workflow_activation().await {
-                        Err(PollWfError::ShutDown) => {
-                            break;
-                        }
-                        o => o?,
-                    };
+                let mut activations = common.worker.activation_stream();
+                while let Some(activation) = activations.next().await {
+                    let activation = activation?;
                     if let Some(wf_fut) = wf_half.workflow_activation_handler(
                         common,
                         shutdown_token.clone(),
@@ -281,15 +275,17 @@ impl Worker {
             async {
                 if !act_half.activity_fns.is_empty() {
                     let shutdown_token = shutdown_token.clone();
+                    let mut activities = common.worker.activity_task_stream();
                     loop {
                         tokio::select! {
-                            activity = common.worker.poll_activity_task() => {
-                                if matches!(activity, Err(PollActivityError::ShutDown)) {
-                                    break;
-                                }
+                            activity = activities.next() => {
+                                let activity = match activity {
+                                    Some(a) => a?,
+                                    None => break,
+                                };
                                 act_half.activity_task_handler(common.worker.clone(),
                                                                common.task_queue.clone(),
-                                                               activity?)?;
+                                                               activity)?;
                             },
                             _ = shutdown_token.cancelled() => { break }
                         }
@@ -1,8 +1,8 @@
 mod options;
 
 pub use options::{
-    ActivityOptions, ChildWorkflowOptions, LocalActivityOptions, Signal, SignalData,
-    SignalWorkflowOptions,
+    ActivityOptions, ChildWorkflowOptions, LocalActivityOptions, SessionOptions, Signal,
+    SignalData, SignalWorkflowOptions,
 };
 
 use crate::{
@@ -11,10 +11,16 @@ use crate::{
     SignalExternalWfResult, TimerResult, UnblockEvent, Unblockable,
 };
 use crossbeam::channel::{Receiver, Sender};
-use futures::{task::Context, FutureExt, Stream};
+use futures::{
+    future::{select, BoxFuture, Either, Shared},
+    pin_mut,
+    task::Context,
+    FutureExt, Stream,
+};
 use parking_lot::RwLock;
 use std::{
     collections::HashMap,
+    fmt::{Display, Formatter},
     future::Future,
     marker::PhantomData,
     pin::Pin,
@@ -25,18 +31,24 @@ use std::{
     task::Poll,
     time::{Duration, SystemTime},
 };
+use temporal_sdk_core::api::worker::{
+    SESSION_CREATION_ACTIVITY_TYPE, SESSION_KEEPALIVE_ACTIVITY_TYPE,
+};
 use temporal_sdk_core_protos::coresdk::{
     activity_result::{activity_resolution, ActivityResolution},
-    child_workflow::ChildWorkflowResult,
-    common::{NamespacedWorkflowExecution, Payload},
+    child_workflow::{
+        child_workflow_result, ChildWorkflowResult, StartChildWorkflowExecutionFailedCause,
+    },
+    common::{NamespacedWorkflowExecution, Payload, RetryPolicy},
     workflow_activation::resolve_child_workflow_execution_start::Status as ChildWorkflowStartStatus,
     workflow_commands::{
         request_cancel_external_workflow_execution as cancel_we,
-        signal_external_workflow_execution as sig_we, workflow_command,
-        RequestCancelExternalWorkflowExecution, SetPatchMarker, SignalExternalWorkflowExecution,
-        StartTimer, UpsertWorkflowSearchAttributes,
+        signal_external_workflow_execution as sig_we, workflow_command, ActivityCancellationType,
+        ModifyWorkflowProperties, RequestCancelExternalWorkflowExecution, SetPatchMarker,
+        SignalExternalWorkflowExecution, StartTimer, UpsertWorkflowSearchAttributes,
     },
 };
+use temporal_sdk_core_protos::temporal::api::failure::v1::{failure::FailureInfo, Failure};
 use tokio::sync::{mpsc, oneshot, watch};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
@@ -60,6 +72,7 @@ struct WfCtxProtectedDat {
     next_cancel_external_wf_sequence_number: u32,
     next_signal_external_wf_sequence_number: u32,
     next_upsert_search_attrs_sequence_number: u32,
+    next_modify_wf_props_sequence_number: u32,
 }
 
 impl WfCtxProtectedDat {
@@ -93,6 +106,11 @@ impl WfCtxProtectedDat {
         self.next_upsert_search_attrs_sequence_number += 1;
         seq
     }
+    fn next_modify_wf_props_seq(&mut self) -> u32 {
+        let seq = self.next_modify_wf_props_sequence_number;
+        self.next_modify_wf_props_sequence_number += 1;
+        seq
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -101,6 +119,20 @@ pub struct WfContextSharedData {
     pub changes: HashMap<String, bool>,
     pub is_replaying: bool,
     pub wf_time: Option<SystemTime>,
+    /// The workflow's search attributes as last reported by core, plus any upserted since
+    pub search_attributes: HashMap<String, Payload>,
+    /// The workflow's memo as last reported by core, plus any upserted since
+    pub memo: HashMap<String, Payload>,
+    /// Number of history events processed as of the most recent activation
+    pub history_length: u32,
+    /// Approximate size in bytes of the history processed as of the most recent activation
+    pub history_size_bytes: u64,
+    /// Whether the most recent activation suggested the workflow continue as new, because its
+    /// history has grown past the worker's configured length or size thresholds
+    pub continue_as_new_suggested: bool,
+    /// The time by which this run must complete, given its run and execution timeouts, as of the
+    /// most recent activation
+    pub run_deadline: Option<SystemTime>,
 }
 
 // TODO: Dataconverter type interface to replace Payloads here. Possibly just use serde
@@ -131,6 +163,7 @@ impl WfContext {
                     next_cancel_external_wf_sequence_number: 1,
                     next_signal_external_wf_sequence_number: 1,
                     next_upsert_search_attrs_sequence_number: 1,
+                    next_modify_wf_props_sequence_number: 1,
                 }),
             },
             rx,
@@ -152,6 +185,36 @@ impl WfContext {
         self.shared.read().wf_time
     }
 
+    /// Return the number of events in the workflow's history, as of the most recent activation
+    pub fn history_length(&self) -> u32 {
+        self.shared.read().history_length
+    }
+
+    /// Return the approximate size in bytes of the workflow's history, as of the most recent
+    /// activation
+    pub fn history_size_bytes(&self) -> u64 {
+        self.shared.read().history_size_bytes
+    }
+
+    /// Returns true if the workflow's history has grown large enough that the worker suggests it
+    /// continue as new
+    pub fn continue_as_new_suggested(&self) -> bool {
+        self.shared.read().continue_as_new_suggested
+    }
+
+    /// Return the time by which this run must complete, given its run and execution timeouts, if
+    /// it has either
+    pub fn run_deadline(&self) -> Option<SystemTime> {
+        self.shared.read().run_deadline
+    }
+
+    /// Return how much workflow time remains before [WfContext::run_deadline], if there is one
+    pub fn time_remaining(&self) -> Option<Duration> {
+        let shared = self.shared.read();
+        let (deadline, now) = shared.run_deadline.zip(shared.wf_time)?;
+        Some(deadline.duration_since(now).unwrap_or_default())
+    }
+
     pub(crate) fn get_shared_data(&self) -> Arc<RwLock<WfContextSharedData>> {
         self.shared.clone()
     }
@@ -230,6 +293,59 @@ impl WfContext {
         cmd
     }
 
+    /// Create a session, which runs a series of activities on the same worker. Resolves once a
+    /// worker has reserved capacity for the session, which must later be released with
+    /// [Session::complete].
+    pub async fn create_session(&self, opts: SessionOptions) -> Result<Session, SessionError> {
+        let creation_id = format!(
+            "session-creation-{}",
+            self.seq_nums.read().next_activity_sequence_number
+        );
+        let creation = self
+            .activity(ActivityOptions {
+                activity_id: Some(creation_id.clone()),
+                activity_type: SESSION_CREATION_ACTIVITY_TYPE.to_string(),
+                schedule_to_close_timeout: Some(opts.creation_timeout),
+                ..Default::default()
+            })
+            .await;
+        let task_queue = match creation.status.as_ref() {
+            Some(activity_resolution::Status::Completed(c)) => c
+                .result
+                .as_ref()
+                .map(|p| String::from_utf8_lossy(&p.data).to_string()),
+            _ => None,
+        };
+        let task_queue = match task_queue {
+            Some(tq) => tq,
+            None => return Err(SessionError::CreationFailed(creation)),
+        };
+
+        // The keepalive runs for the life of the session. Its sequence number is needed to cancel
+        // it, which is what ends the session.
+        let keepalive_seq = self.seq_nums.read().next_activity_sequence_number;
+        let keepalive = self.activity(ActivityOptions {
+            activity_type: SESSION_KEEPALIVE_ACTIVITY_TYPE.to_string(),
+            input: creation_id.as_str().into(),
+            task_queue: task_queue.clone(),
+            schedule_to_start_timeout: Some(opts.creation_timeout),
+            start_to_close_timeout: Some(opts.max_duration),
+            heartbeat_timeout: Some(opts.heartbeat_timeout),
+            cancellation_type: ActivityCancellationType::TryCancel,
+            // A lost worker will never come back, so there is no point retrying
+            retry_policy: Some(RetryPolicy {
+                maximum_attempts: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        Ok(Session {
+            task_queue,
+            keepalive_id: CancellableID::Activity(keepalive_seq),
+            keepalive: keepalive.boxed().shared(),
+        })
+    }
+
     /// Creates a child workflow stub with the provided options
     pub fn child_workflow(&self, opts: ChildWorkflowOptions) -> ChildWorkflow {
         ChildWorkflow { opts }
@@ -288,16 +404,53 @@ impl WfContext {
 
     /// Add or create a set of search attributes
     pub fn upsert_search_attributes(&self, attr_iter: impl IntoIterator<Item = (String, Payload)>) {
+        let search_attributes = HashMap::from_iter(attr_iter);
+        self.shared
+            .write()
+            .search_attributes
+            .extend(search_attributes.clone());
         self.send(RustWfCmd::NewNonblockingCmd(
             workflow_command::Variant::UpsertWorkflowSearchAttributesCommandAttributes(
                 UpsertWorkflowSearchAttributes {
                     seq: self.seq_nums.write().next_upsert_search_attrs_wf_seq(),
-                    search_attributes: HashMap::from_iter(attr_iter.into_iter()),
+                    search_attributes,
                 },
             ),
         ))
     }
 
+    /// Add or update fields of the workflow's memo. A field may be removed by upserting it with an
+    /// empty payload.
+    pub fn upsert_memo(&self, memo_iter: impl IntoIterator<Item = (String, Payload)>) {
+        let upserted_memo = HashMap::from_iter(memo_iter);
+        {
+            let mut shared = self.shared.write();
+            for (k, v) in upserted_memo.iter() {
+                if v.data.is_empty() && v.metadata.is_empty() {
+                    shared.memo.remove(k);
+                } else {
+                    shared.memo.insert(k.clone(), v.clone());
+                }
+            }
+        }
+        self.send(RustWfCmd::NewNonblockingCmd(
+            workflow_command::Variant::ModifyWorkflowProperties(ModifyWorkflowProperties {
+                seq: self.seq_nums.write().next_modify_wf_props_seq(),
+                upserted_memo,
+            }),
+        ))
+    }
+
+    /// Return the workflow's current search attributes, including any upserted by this workflow
+    pub fn search_attributes(&self) -> HashMap<String, Payload> {
+        self.shared.read().search_attributes.clone()
+    }
+
+    /// Return the workflow's current memo, including any fields upserted by this workflow
+    pub fn memo(&self) -> HashMap<String, Payload> {
+        self.shared.read().memo.clone()
+    }
+
     /// Return a stream that produces values when the named signal is sent to this workflow
     pub fn make_signal_channel(
         &self,
@@ -542,6 +695,15 @@ impl<'a> Future for LATimerBackoffFut<'a> {
                 self.next_sched_time = b.original_schedule_time.clone();
                 return Poll::Pending;
             }
+
+            if let Some(activity_resolution::Status::Promote(p)) = r.status.as_ref() {
+                let opts = self
+                    .la_opts
+                    .clone()
+                    .into_promoted(p, self.ctx.workflow_time());
+                self.current_fut = Box::pin(self.ctx.activity(opts));
+                return self.current_fut.poll_unpin(cx);
+            }
         }
         poll_res
     }
@@ -556,6 +718,59 @@ impl<'a> CancellableFuture<ActivityResolution> for LATimerBackoffFut<'a> {
     }
 }
 
+/// A series of activities pinned to the same worker, created with [WfContext::create_session]
+pub struct Session {
+    task_queue: String,
+    keepalive_id: CancellableID,
+    keepalive: Shared<BoxFuture<'static, ActivityResolution>>,
+}
+
+/// Errors returned when creating or using a [Session]
+#[derive(Debug)]
+pub enum SessionError {
+    /// No worker accepted the session. Contains the resolution of the session creation activity.
+    CreationFailed(ActivityResolution),
+    /// The worker hosting the session was lost or shut down. Contains the resolution of the
+    /// session's keepalive activity.
+    Failed(ActivityResolution),
+}
+
+impl Session {
+    /// The task queue only the session's worker polls
+    pub fn task_queue(&self) -> &str {
+        &self.task_queue
+    }
+
+    /// Run an activity on the session's worker. The activity's task queue is overridden. Resolves
+    /// with [SessionError::Failed] if the session fails first, once the activity's cancellation
+    /// has resolved.
+    pub async fn activity(
+        &self,
+        cx: &WfContext,
+        mut opts: ActivityOptions,
+    ) -> Result<ActivityResolution, SessionError> {
+        opts.task_queue = self.task_queue.clone();
+        let act = cx.activity(opts);
+        pin_mut!(act);
+        match select(act, self.keepalive.clone()).await {
+            Either::Left((res, _)) => Ok(res),
+            Either::Right((keepalive_res, act)) => {
+                act.cancel(cx);
+                // The activity must still be waited on, since its resolution is delivered to it
+                act.await;
+                Err(SessionError::Failed(keepalive_res))
+            }
+        }
+    }
+
+    /// End the session, releasing its capacity on the worker. Resolves once the session's
+    /// keepalive activity has been cancelled.
+    pub async fn complete(self, cx: &WfContext) {
+        cx.cancel(self.keepalive_id);
+        self.keepalive.await;
+    }
+}
+
 /// A stub representing an unstarted child workflow.
 #[derive(Default, Debug, Clone)]
 pub struct ChildWorkflow {
@@ -576,16 +791,157 @@ impl PendingChildWorkflow {
     /// Returns `None` if the child did not start successfully. The returned [StartedChildWorkflow]
     /// can be used to wait on, signal, or cancel the child workflow.
     pub fn into_started(self) -> Option<StartedChildWorkflow> {
+        self.try_into_started().ok()
+    }
+
+    /// Like [PendingChildWorkflow::into_started], but says why the child did not start
+    pub fn try_into_started(self) -> Result<StartedChildWorkflow, ChildWorkflowStartError> {
         match self.status {
-            ChildWorkflowStartStatus::Succeeded(s) => Some(StartedChildWorkflow {
+            ChildWorkflowStartStatus::Succeeded(s) => Ok(StartedChildWorkflow {
                 run_id: s.run_id,
                 common: self.common,
             }),
-            _ => None,
+            ChildWorkflowStartStatus::Failed(f) => Err(
+                match StartChildWorkflowExecutionFailedCause::from_i32(f.cause) {
+                    Some(StartChildWorkflowExecutionFailedCause::WorkflowAlreadyExists) => {
+                        ChildWorkflowStartError::WorkflowAlreadyStarted {
+                            workflow_id: f.workflow_id,
+                            workflow_type: f.workflow_type,
+                        }
+                    }
+                    _ => ChildWorkflowStartError::Failed {
+                        workflow_id: f.workflow_id,
+                        workflow_type: f.workflow_type,
+                        cause: f.cause,
+                    },
+                },
+            ),
+            ChildWorkflowStartStatus::Cancelled(c) => Err(ChildWorkflowStartError::Cancelled(
+                Box::new(c.failure.unwrap_or_default()),
+            )),
         }
     }
 }
 
+/// Reasons a child workflow could not be started
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChildWorkflowStartError {
+    /// A workflow with the child's id is already running, or the id reuse policy forbids reusing
+    /// it
+    WorkflowAlreadyStarted {
+        /// The child's workflow id
+        workflow_id: String,
+        /// The child's workflow type
+        workflow_type: String,
+    },
+    /// The server refused to start the child for some other reason. `cause` is a
+    /// [StartChildWorkflowExecutionFailedCause].
+    Failed {
+        /// The child's workflow id
+        workflow_id: String,
+        /// The child's workflow type
+        workflow_type: String,
+        /// Why the server refused to start the child
+        cause: i32,
+    },
+    /// The parent cancelled the child before it started
+    Cancelled(Box<Failure>),
+}
+
+impl Display for ChildWorkflowStartError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WorkflowAlreadyStarted { workflow_id, .. } => {
+                write!(f, "Child workflow {} is already started", workflow_id)
+            }
+            Self::Failed {
+                workflow_id, cause, ..
+            } => write!(
+                f,
+                "Child workflow {} failed to start with cause {}",
+                workflow_id, cause
+            ),
+            Self::Cancelled(_) => write!(f, "Child workflow was cancelled before it started"),
+        }
+    }
+}
+
+impl std::error::Error for ChildWorkflowStartError {}
+
+/// The ways a started child workflow can fail to complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildWorkflowFailureKind {
+    /// The child workflow failed
+    Failed,
+    /// The child workflow exceeded one of its timeouts
+    TimedOut,
+    /// The child workflow was terminated
+    Terminated,
+    /// The child workflow was cancelled
+    Cancelled,
+}
+
+/// Returned when a started child workflow does not complete successfully
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChildWorkflowFailure {
+    /// How the child failed
+    pub kind: ChildWorkflowFailureKind,
+    /// The failure reported for the child. Its cause chain leads to what went wrong inside the
+    /// child.
+    pub failure: Box<Failure>,
+}
+
+impl ChildWorkflowFailure {
+    /// Converts the result of a child workflow into its output if it completed successfully, or a
+    /// typed failure otherwise
+    pub fn from_result(result: ChildWorkflowResult) -> Result<Option<Payload>, Self> {
+        let (failure, cancelled) = match result.status {
+            Some(child_workflow_result::Status::Completed(s)) => return Ok(s.result),
+            Some(child_workflow_result::Status::Failed(f)) => (f.failure, false),
+            Some(child_workflow_result::Status::Cancelled(c)) => (c.failure, true),
+            None => (None, false),
+        };
+        let failure = Box::new(failure.unwrap_or_default());
+        let kind = if cancelled {
+            ChildWorkflowFailureKind::Cancelled
+        } else {
+            match failure.cause.as_ref().and_then(|c| c.failure_info.as_ref()) {
+                Some(FailureInfo::TimeoutFailureInfo(_)) => ChildWorkflowFailureKind::TimedOut,
+                Some(FailureInfo::TerminatedFailureInfo(_)) => ChildWorkflowFailureKind::Terminated,
+                Some(FailureInfo::CanceledFailureInfo(_)) => ChildWorkflowFailureKind::Cancelled,
+                _ => ChildWorkflowFailureKind::Failed,
+            }
+        };
+        Err(Self { kind, failure })
+    }
+
+    /// Iterates over the causes of this failure, from the most immediate to the root
+    pub fn causes(&self) -> impl Iterator<Item = &Failure> {
+        std::iter::successors(self.failure.cause.as_deref(), |f| f.cause.as_deref())
+    }
+
+    /// Returns the innermost cause of this failure
+    pub fn root_cause(&self) -> &Failure {
+        self.causes().last().unwrap_or(&self.failure)
+    }
+}
+
+impl Display for ChildWorkflowFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Child workflow {:?}: {}",
+            self.kind, self.failure.message
+        )?;
+        for cause in self.causes() {
+            write!(f, ": {}", cause.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ChildWorkflowFailure {}
+
 pub struct StartedChildWorkflow {
     pub run_id: String,
     common: ChildWfCommon,
@@ -642,6 +998,11 @@ impl StartedChildWorkflow {
         self.common.result_future
     }
 
+    /// Like [StartedChildWorkflow::result], but resolves to the child's output or a typed failure
+    pub async fn typed_result(self) -> Result<Option<Payload>, ChildWorkflowFailure> {
+        ChildWorkflowFailure::from_result(self.result().await)
+    }
+
     /// Cancel the child workflow
     pub fn cancel(&self, cx: &WfContext) -> impl Future<Output = CancelExternalWfResult> {
         let target = NamespacedWorkflowExecution {
@@ -1,7 +1,11 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
 use temporal_sdk_core_protos::coresdk::{
-    child_workflow::ChildWorkflowCancellationType,
-    common::{Payload, RetryPolicy},
+    activity_result::DoPromote,
+    child_workflow::{ChildWorkflowCancellationType, ParentClosePolicy},
+    common::{Payload, RetryPolicy, WorkflowIdReusePolicy},
     workflow_commands::{
         ActivityCancellationType, ScheduleActivity, ScheduleLocalActivity,
         StartChildWorkflowExecution,
@@ -57,6 +61,8 @@ pub struct ActivityOptions {
     pub heartbeat_timeout: Option<Duration>,
     /// Determines what the SDK does when the Activity is cancelled.
     pub cancellation_type: ActivityCancellationType,
+    /// How the activity is retried. If `None`, the server's default retry policy is used.
+    pub retry_policy: Option<RetryPolicy>,
 }
 
 impl IntoWorkflowCommand for ActivityOptions {
@@ -76,6 +82,7 @@ impl IntoWorkflowCommand for ActivityOptions {
             heartbeat_timeout: self.heartbeat_timeout.map(Into::into),
             cancellation_type: self.cancellation_type as i32,
             arguments: vec![self.input],
+            retry_policy: self.retry_policy,
             ..Default::default()
         }
     }
@@ -152,6 +159,55 @@ impl IntoWorkflowCommand for LocalActivityOptions {
     }
 }
 
+impl LocalActivityOptions {
+    /// Options for the normal activity which runs in place of this local activity once it has
+    /// been promoted. It is only allowed the attempts and time the local activity had left, as of
+    /// the provided workflow time.
+    pub(crate) fn into_promoted(
+        self,
+        promotion: &DoPromote,
+        now: Option<SystemTime>,
+    ) -> ActivityOptions {
+        let schedule_to_close_timeout = if self.start_to_close_timeout.is_none() {
+            // Same default as when scheduling the local activity
+            Some(
+                self.schedule_to_close_timeout
+                    .unwrap_or(Duration::from_secs(100)),
+            )
+        } else {
+            self.schedule_to_close_timeout
+        };
+        let elapsed = promotion
+            .original_schedule_time
+            .clone()
+            .and_then(|t| t.try_into().ok())
+            .zip(now)
+            .and_then(|(scheduled, now): (SystemTime, _)| now.duration_since(scheduled).ok())
+            .unwrap_or_default();
+        // A zero timeout would mean none at all
+        let schedule_to_close_timeout = schedule_to_close_timeout
+            .map(|t| t.saturating_sub(elapsed).max(Duration::from_secs(1)));
+        let mut retry_policy = self.retry_policy;
+        if retry_policy.maximum_attempts > 0 {
+            retry_policy.maximum_attempts = retry_policy
+                .maximum_attempts
+                .saturating_sub(promotion.attempt as i32)
+                .max(1);
+        }
+        ActivityOptions {
+            activity_id: self.activity_id,
+            activity_type: self.activity_type,
+            input: self.input,
+            schedule_to_start_timeout: self.schedule_to_start_timeout,
+            start_to_close_timeout: self.start_to_close_timeout,
+            schedule_to_close_timeout,
+            cancellation_type: self.cancel_type,
+            retry_policy: Some(retry_policy),
+            ..Default::default()
+        }
+    }
+}
+
 /// Options for scheduling a child workflow
 #[derive(Default, Debug, Clone)]
 pub struct ChildWorkflowOptions {
@@ -163,6 +219,29 @@ pub struct ChildWorkflowOptions {
     pub input: Vec<Payload>,
     /// Cancellation strategy for the child workflow
     pub cancel_type: ChildWorkflowCancellationType,
+    /// Task queue to run the child on. If `None`, the parent's task queue is used.
+    pub task_queue: Option<String>,
+    /// Total time the child may take, including retries and continuing as new
+    pub execution_timeout: Option<Duration>,
+    /// Time a single run of the child may take
+    pub run_timeout: Option<Duration>,
+    /// Time a single workflow task of the child may take
+    pub task_timeout: Option<Duration>,
+    /// What happens to the child if the parent closes first. If unspecified, the server
+    /// terminates it.
+    pub parent_close_policy: ParentClosePolicy,
+    /// Whether the child may reuse the id of a closed workflow
+    pub id_reuse_policy: WorkflowIdReusePolicy,
+    /// How the child is retried. If `None`, it is not retried.
+    pub retry_policy: Option<RetryPolicy>,
+    /// If set, the child is run on this cron schedule
+    pub cron_schedule: Option<String>,
+    /// Header fields to send to the child
+    pub headers: HashMap<String, Payload>,
+    /// Memo fields to start the child with
+    pub memo: HashMap<String, Payload>,
+    /// Search attributes to start the child with
+    pub search_attributes: HashMap<String, Payload>,
 }
 
 impl IntoWorkflowCommand for ChildWorkflowOptions {
@@ -172,13 +251,46 @@ impl IntoWorkflowCommand for ChildWorkflowOptions {
             seq,
             workflow_id: self.workflow_id,
             workflow_type: self.workflow_type,
+            task_queue: self.task_queue.unwrap_or_default(),
             input: self.input,
+            workflow_execution_timeout: self.execution_timeout.map(Into::into),
+            workflow_run_timeout: self.run_timeout.map(Into::into),
+            workflow_task_timeout: self.task_timeout.map(Into::into),
+            parent_close_policy: self.parent_close_policy as i32,
+            workflow_id_reuse_policy: self.id_reuse_policy as i32,
+            retry_policy: self.retry_policy,
+            cron_schedule: self.cron_schedule.unwrap_or_default(),
+            headers: self.headers,
+            memo: self.memo,
+            search_attributes: self.search_attributes,
             cancellation_type: self.cancel_type as i32,
             ..Default::default()
         }
     }
 }
 
+/// Options for creating an activity session
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    /// How long to wait for some worker to accept the session
+    pub creation_timeout: Duration,
+    /// How long the session's worker may go without heartbeating the session before the session
+    /// is considered failed
+    pub heartbeat_timeout: Duration,
+    /// The longest the session may remain open
+    pub max_duration: Duration,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            creation_timeout: Duration::from_secs(60),
+            heartbeat_timeout: Duration::from_secs(10),
+            max_duration: Duration::from_secs(60 * 60 * 24),
+        }
+    }
+}
+
 /// Options for sending a signal to an external workflow
 pub struct SignalWorkflowOptions {
     /// The workflow's id
@@ -248,6 +248,14 @@ impl Future for WorkflowFuture {
                 let mut wlock = self.ctx_shared.write();
                 wlock.is_replaying = activation.is_replaying;
                 wlock.wf_time = activation.timestamp.try_into_or_none();
+                wlock.history_length = activation.history_length;
+                wlock.history_size_bytes = activation.history_size_bytes;
+                wlock.continue_as_new_suggested = activation.continue_as_new_suggested;
+                wlock.run_deadline = activation.run_deadline.try_into_or_none();
+                if let Some(props) = activation.workflow_properties {
+                    wlock.memo = props.memo;
+                    wlock.search_attributes = props.search_attributes;
+                }
             }
 
             let mut die_of_eviction_when_done = false;
@@ -0,0 +1,429 @@
+//! Canned responses for the activities run by a time-skipping [TestWorkflowEnvironment]
+
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+};
+use temporal_sdk_core::test_env::{ActivityStubCall, TestWorkflowEnvironment};
+use temporal_sdk_core_protos::{
+    coresdk::{common::Payload, AsJsonPayloadExt, FromJsonPayloadExt},
+    temporal::api::{
+        enums::v1::TimeoutType,
+        failure::v1::{failure::FailureInfo, Failure, TimeoutFailureInfo},
+    },
+};
+
+type InputMatcher = Box<dyn Fn(&[Payload]) -> bool + Send + Sync>;
+
+/// Stubs out activities run by a [TestWorkflowEnvironment] with canned responses, and records the
+/// calls made to them so tests can assert on them.
+///
+/// Each expected activity is declared with an [ActivityExpectation], which matches on the
+/// activity's type and optionally its input, and lists the response to give on each attempt. Calls
+/// which match no expectation, or attempts beyond the end of an expectation's responses, fail the
+/// activity as non retryable and are reported by [ActivityMocker::verify].
+///
+/// ```ignore
+/// let mocker = ActivityMocker::default();
+/// mocker.expect(
+///     ActivityExpectation::new("charge_card")
+///         .with_json_input(&"order-1".to_string())
+///         .fails("card service unavailable")
+///         .returns_json(&"receipt-1".to_string()),
+/// );
+/// mocker.install(&env);
+/// // ... run the worker ...
+/// mocker.verify();
+/// ```
+#[derive(Clone, Default)]
+pub struct ActivityMocker {
+    state: Arc<Mutex<MockerState>>,
+}
+
+#[derive(Default)]
+struct MockerState {
+    expectations: Vec<ActivityExpectation>,
+    /// Which expectation each activity, keyed by run id and activity id, was matched with
+    matched: HashMap<(String, String), usize>,
+    calls: Vec<ActivityStubCall>,
+    unexpected: Vec<String>,
+}
+
+impl ActivityMocker {
+    /// Add an expected activity. Expectations are matched in the order they were added, skipping
+    /// those which have already matched as many activities as they expect.
+    pub fn expect(&self, expectation: ActivityExpectation) -> &Self {
+        self.state.lock().expectations.push(expectation);
+        self
+    }
+
+    /// Stub every activity type with an expectation so far in the provided environment, which
+    /// should be done before running its worker
+    pub fn install(&self, env: &TestWorkflowEnvironment) {
+        let types: HashSet<_> = self
+            .state
+            .lock()
+            .expectations
+            .iter()
+            .map(|e| e.activity_type.clone())
+            .collect();
+        for activity_type in types {
+            let mocker = self.clone();
+            env.register_activity_stub(activity_type, move |call| mocker.respond(call));
+        }
+    }
+
+    /// Every attempt made at running activities of the provided type, in the order they were made
+    pub fn calls(&self, activity_type: &str) -> Vec<ActivityStubCall> {
+        self.state
+            .lock()
+            .calls
+            .iter()
+            .filter(|c| c.activity_type == activity_type)
+            .cloned()
+            .collect()
+    }
+
+    /// Panics if any expectation was not met, or if any activity attempt was not expected
+    pub fn verify(&self) {
+        let state = self.state.lock();
+        let mut problems = state.unexpected.clone();
+        for (index, exp) in state.expectations.iter().enumerate() {
+            let calls = state.matched.values().filter(|&&i| i == index).count();
+            let satisfied = match exp.times {
+                Some(times) => calls == times,
+                None => calls > 0,
+            };
+            if !satisfied {
+                problems.push(format!(
+                    "Expected {} call(s) to {}, but it was called {} time(s)",
+                    exp.times
+                        .map_or_else(|| "one or more".to_string(), |t| t.to_string()),
+                    exp.description(),
+                    calls
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            panic!("Activity expectations not met:\n{}", problems.join("\n"));
+        }
+    }
+
+    fn respond(&self, call: &ActivityStubCall) -> Result<Payload, Failure> {
+        let mut state = self.state.lock();
+        state.calls.push(call.clone());
+        let key = (call.run_id.clone(), call.activity_id.clone());
+        let exp_index = match state.matched.get(&key) {
+            Some(i) => Some(*i),
+            None => {
+                let matched = &state.matched;
+                let found = state
+                    .expectations
+                    .iter()
+                    .enumerate()
+                    .position(|(index, e)| {
+                        e.matches(call)
+                            && e.times.map_or(true, |times| {
+                                matched.values().filter(|&&i| i == index).count() < times
+                            })
+                    });
+                if let Some(i) = found {
+                    state.matched.insert(key, i);
+                }
+                found
+            }
+        };
+        let response = exp_index.and_then(|i| {
+            state.expectations[i]
+                .responses
+                .get(call.attempt as usize - 1)
+                .cloned()
+        });
+        match response {
+            Some(r) => r,
+            None => {
+                let message = format!(
+                    "Unexpected call to activity {} (id {}, attempt {}) with input {:?}",
+                    call.activity_type, call.activity_id, call.attempt, call.input
+                );
+                state.unexpected.push(message.clone());
+                Err(Failure::application_failure(message, true))
+            }
+        }
+    }
+}
+
+/// An activity an [ActivityMocker] expects to be called, and how it should respond. Responses are
+/// given one per attempt, in the order they were added, so an activity which is expected to be
+/// retried should be given a failure for each attempt before the final response.
+pub struct ActivityExpectation {
+    activity_type: String,
+    matcher: Option<(String, InputMatcher)>,
+    responses: Vec<Result<Payload, Failure>>,
+    times: Option<usize>,
+}
+
+impl ActivityExpectation {
+    /// Expect an activity of the provided type, with any input
+    pub fn new(activity_type: impl Into<String>) -> Self {
+        Self {
+            activity_type: activity_type.into(),
+            matcher: None,
+            responses: vec![],
+            times: None,
+        }
+    }
+
+    /// Only match activities whose input satisfies the provided predicate
+    pub fn with_input(
+        mut self,
+        matcher: impl Fn(&[Payload]) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.matcher = Some(("a custom matcher".to_string(), Box::new(matcher)));
+        self
+    }
+
+    /// Only match activities whose single input deserializes from JSON to the provided value
+    pub fn with_json_input<T>(mut self, expected: &T) -> Self
+    where
+        T: FromJsonPayloadExt + PartialEq + Debug + Clone + Send + Sync + 'static,
+    {
+        let description = format!("{:?}", expected);
+        let expected = expected.clone();
+        self.matcher = Some((
+            description,
+            Box::new(move |input| match input {
+                [p] => T::from_json_payload(p).map_or(false, |v| v == expected),
+                _ => false,
+            }),
+        ));
+        self
+    }
+
+    /// Complete the next attempt with the provided result
+    pub fn returns(mut self, result: Payload) -> Self {
+        self.responses.push(Ok(result));
+        self
+    }
+
+    /// Complete the next attempt with the provided value serialized as JSON
+    pub fn returns_json(self, result: &impl AsJsonPayloadExt) -> Self {
+        self.returns(
+            result
+                .as_json_payload()
+                .expect("Mocked result serializes to JSON"),
+        )
+    }
+
+    /// Fail the next attempt with a retryable application failure
+    pub fn fails(mut self, message: impl Into<String>) -> Self {
+        self.responses
+            .push(Err(Failure::application_failure(message.into(), false)));
+        self
+    }
+
+    /// Fail the next attempt with a non retryable application failure
+    pub fn fails_non_retryable(mut self, message: impl Into<String>) -> Self {
+        self.responses
+            .push(Err(Failure::application_failure(message.into(), true)));
+        self
+    }
+
+    /// Time out the next attempt, as though it exceeded its start to close timeout
+    pub fn times_out(mut self) -> Self {
+        self.responses.push(Err(Failure {
+            message: "Activity timed out".to_string(),
+            failure_info: Some(FailureInfo::TimeoutFailureInfo(TimeoutFailureInfo {
+                timeout_type: TimeoutType::StartToClose as i32,
+                last_heartbeat_details: None,
+            })),
+            ..Default::default()
+        }));
+        self
+    }
+
+    /// Expect exactly this many activities to match, rather than one or more
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    fn matches(&self, call: &ActivityStubCall) -> bool {
+        call.activity_type == self.activity_type
+            && self
+                .matcher
+                .as_ref()
+                .map_or(true, |(_, matcher)| matcher(&call.input))
+    }
+
+    fn description(&self) -> String {
+        match &self.matcher {
+            Some((input, _)) => format!("{} with input {}", self.activity_type, input),
+            None => self.activity_type.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{init_time_skipping_env, TestWorker};
+    use std::{panic::AssertUnwindSafe, time::Duration};
+    use temporal_sdk::{ActivityOptions, WfContext};
+    use temporal_sdk_core::test_env::TestWorkflowResult;
+    use temporal_sdk_core_protos::{
+        coresdk::common::RetryPolicy,
+        temporal::api::{
+            enums::v1::{EventType, RetryState},
+            history::v1::history_event::Attributes,
+        },
+    };
+
+    /// Registers a workflow which runs one activity of each provided type in turn, with the input
+    /// "hi", and completes with their results, or fails with the first activity failure
+    fn register_activities_wf(worker: &mut TestWorker, retry_policy: Option<RetryPolicy>) {
+        worker.register_wf("run_acts", move |ctx: WfContext| {
+            let retry_policy = retry_policy.clone();
+            async move {
+                let types: Vec<String> = ctx
+                    .get_args()
+                    .iter()
+                    .map(|p| String::from_json_payload(p).unwrap())
+                    .collect();
+                for activity_type in types {
+                    let res = ctx
+                        .activity(ActivityOptions {
+                            activity_type,
+                            input: "hi".as_json_payload().unwrap(),
+                            start_to_close_timeout: Some(Duration::from_secs(5)),
+                            retry_policy: retry_policy.clone(),
+                            ..Default::default()
+                        })
+                        .await;
+                    if !res.completed_ok() {
+                        return Err(anyhow::anyhow!("activity failed: {:?}", res));
+                    }
+                }
+                Ok(().into())
+            }
+        });
+    }
+
+    fn activity_types(types: &[&str]) -> Vec<Payload> {
+        types.iter().map(|t| t.as_json_payload().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn mocked_activities_are_retried_on_the_virtual_clock() {
+        let (env, mut worker) = init_time_skipping_env("mocked_retries");
+        let started_at = env.now();
+        let mocker = ActivityMocker::default();
+        mocker.expect(
+            ActivityExpectation::new("flaky")
+                .with_json_input(&"hi".to_string())
+                .fails("try again")
+                .times_out()
+                .returns_json(&"done"),
+        );
+        mocker.install(&env);
+        register_activities_wf(&mut worker, None);
+        let run_id = env.start_workflow("retries", "run_acts", activity_types(&["flaky"]));
+        worker.run_until_done().await.unwrap();
+
+        mocker.verify();
+        assert!(matches!(
+            env.workflow_result(&run_id),
+            Some(TestWorkflowResult::Completed(_))
+        ));
+        let attempts: Vec<_> = mocker.calls("flaky").iter().map(|c| c.attempt).collect();
+        assert_eq!(attempts, [1, 2, 3]);
+        // Default policy backs off for one second, then two
+        assert_eq!(env.now(), started_at + Duration::from_secs(3));
+        let events = env.history(&run_id).unwrap().events;
+        assert!(matches!(
+            events
+                .iter()
+                .find(|e| e.event_type() == EventType::ActivityTaskStarted)
+                .unwrap()
+                .attributes,
+            Some(Attributes::ActivityTaskStartedEventAttributes(ref a)) if a.attempt == 3
+        ));
+    }
+
+    #[tokio::test]
+    async fn mocked_activities_stop_retrying_per_policy() {
+        let (env, mut worker) = init_time_skipping_env("mocked_retry_policy");
+        let mocker = ActivityMocker::default();
+        mocker
+            .expect(
+                ActivityExpectation::new("capped")
+                    .fails("one")
+                    .fails("two")
+                    .times(1),
+            )
+            .expect(ActivityExpectation::new("fatal").fails_non_retryable("nope"));
+        mocker.install(&env);
+        register_activities_wf(
+            &mut worker,
+            Some(RetryPolicy {
+                maximum_attempts: 2,
+                ..Default::default()
+            }),
+        );
+        let capped = env.start_workflow("capped", "run_acts", activity_types(&["capped"]));
+        let fatal = env.start_workflow("fatal", "run_acts", activity_types(&["fatal"]));
+        worker.run_until_done().await.unwrap();
+
+        mocker.verify();
+        for (run_id, retry_state) in [
+            (capped, RetryState::MaximumAttemptsReached),
+            (fatal, RetryState::NonRetryableFailure),
+        ] {
+            assert!(matches!(
+                env.workflow_result(&run_id),
+                Some(TestWorkflowResult::Failed(_))
+            ));
+            let events = env.history(&run_id).unwrap().events;
+            assert!(matches!(
+                events
+                    .iter()
+                    .find(|e| e.event_type() == EventType::ActivityTaskFailed)
+                    .unwrap()
+                    .attributes,
+                Some(Attributes::ActivityTaskFailedEventAttributes(ref a))
+                    if a.retry_state() == retry_state
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn mocker_verify_reports_unmet_and_unexpected_calls() {
+        let (env, mut worker) = init_time_skipping_env("mocker_unmet");
+        let mocker = ActivityMocker::default();
+        mocker
+            .expect(
+                ActivityExpectation::new("echo")
+                    .with_json_input(&"bye".to_string())
+                    .returns_json(&"bye"),
+            )
+            .expect(ActivityExpectation::new("never").returns_json(&"unused"));
+        mocker.install(&env);
+        register_activities_wf(&mut worker, None);
+        let run_id = env.start_workflow("unmet", "run_acts", activity_types(&["echo"]));
+        worker.run_until_done().await.unwrap();
+
+        // The input didn't match, so the activity failed without being retried
+        assert_eq!(mocker.calls("echo").len(), 1);
+        assert!(matches!(
+            env.workflow_result(&run_id),
+            Some(TestWorkflowResult::Failed(_))
+        ));
+        let err = std::panic::catch_unwind(AssertUnwindSafe(|| mocker.verify())).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("Unexpected call to activity echo"));
+        assert!(msg.contains("echo with input \"bye\""));
+        assert!(msg.contains("to never"));
+    }
+}
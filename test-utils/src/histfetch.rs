@@ -1,12 +1,13 @@
 //! Use this binary to fetch histories as proto-encoded binary. The first argument must be a
 //! workflow ID. A run id may optionally be provided as the second arg. The history is written to
-//! `{workflow_id}_history.bin`.
+//! `{workflow_id}_history.bin`. If the history can be expressed as a canned history, it is also
+//! written to `{workflow_id}_history.canned`, ready to be used in a regression test.
 //!
 //! We can use `clap` if this needs more arguments / other stuff later on.
 
 use prost::Message;
 use temporal_client::WorkflowClientTrait;
-use temporal_sdk_core_test_utils::get_integ_server_options;
+use temporal_sdk_core_test_utils::{get_integ_server_options, history_dsl::record_canned_history};
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
@@ -24,5 +25,9 @@ async fn main() -> Result<(), anyhow::Error> {
     // Serialize history to file
     let byteified = hist.encode_to_vec();
     tokio::fs::write(format!("{}_history.bin", wf_id), &byteified).await?;
+    match record_canned_history(&hist) {
+        Ok(canned) => tokio::fs::write(format!("{}_history.canned", wf_id), canned).await?,
+        Err(e) => eprintln!("History cannot be written as a canned history: {}", e),
+    }
     Ok(())
 }
@@ -0,0 +1,696 @@
+//! A compact text format for canned histories, as an alternative to writing out
+//! [TestHistoryBuilder] calls by hand, and a recorder which turns existing histories, or runs of
+//! workflows in a [TestWorkflowEnvironment], into it.
+//!
+//! Each statement adds one or more events to the history, and is ended by a newline or `;`.
+//! Arguments are separated by whitespace, and may be quoted with `"` if they contain whitespace or
+//! special characters. `#` starts a comment which runs to the end of the line. Events which refer
+//! to earlier ones, like a timer firing, name them by their timer or activity id.
+//!
+//! ```text
+//! started
+//! wft
+//! timer_started 1
+//! timer_fired 1
+//! wft
+//! activity_scheduled act1
+//! activity_started act1
+//! activity_completed act1 "some result"
+//! wft_scheduled
+//! wft_started
+//! ```
+//!
+//! The statements are:
+//!
+//! * `started`, `completed`, `failed`, `continued_as_new`, `cancel_requested`, `cancelled` -
+//!   workflow execution events
+//! * `wft` - a full workflow task, which is scheduled, started, and completed
+//! * `wft_scheduled`, `wft_started`, `wft_completed`, `wft_timed_out`, `wft_failed [message]` -
+//!   individual workflow task events
+//! * `timer_started <id>`, `timer_fired <id>`, `timer_canceled <id>`
+//! * `activity_scheduled <id>`, `activity_started <id>`, `activity_completed <id> [result]`,
+//!   `activity_failed <id> [message]`, `activity_cancel_requested <id>`, `activity_cancelled <id>`
+//! * `signal <name> [payload...]` - the workflow is signaled
+//! * `patch <id> [deprecated]` - a patch marker
+//! * `local_activity <seq> <id> [result]`, `local_activity_failed <seq> <id> [message]`,
+//!   `local_activity_cancelled <seq> <id>` - local activity markers
+//!
+//! Payloads are written as their data, which must be UTF-8. Results hold at most one payload.
+
+use anyhow::{anyhow, bail};
+use std::{collections::HashMap, fmt::Write};
+use temporal_sdk_core::{replay::TestHistoryBuilder, test_env::TestWorkflowEnvironment};
+use temporal_sdk_core_protos::{
+    constants::{LOCAL_ACTIVITY_MARKER_NAME, PATCH_MARKER_NAME},
+    coresdk::common::{
+        decode_change_marker_details, extract_local_activity_marker_data, Payload as CorePayload,
+    },
+    temporal::api::{
+        common::v1::{Payload, Payloads},
+        enums::v1::{EventType, WorkflowTaskFailedCause},
+        failure::v1::{failure::FailureInfo, Failure},
+        history::v1::{history_event::Attributes, *},
+    },
+};
+
+/// Builds a [TestHistoryBuilder] from canned history statements written inline, separated by
+/// `;`. Panics if they are not valid. Ids which are not plain identifiers or numbers must be
+/// quoted.
+///
+/// ```ignore
+/// let t = canned_history! {
+///     started; wft; timer_started 1; timer_fired 1; wft_scheduled; wft_started;
+/// };
+/// ```
+#[macro_export]
+macro_rules! canned_history {
+    ($($statements:tt)*) => {
+        $crate::history_dsl::parse_canned_history(stringify!($($statements)*))
+            .expect("Canned history is valid")
+    };
+}
+
+/// Builds a [TestHistoryBuilder] from a canned history written in the format described in the
+/// [module docs](self)
+pub fn parse_canned_history(text: &str) -> Result<TestHistoryBuilder, anyhow::Error> {
+    let mut parser = Parser::default();
+    for (line_ix, line) in text.lines().enumerate() {
+        for statement in tokenize(line).map_err(|e| anyhow!("line {}: {}", line_ix + 1, e))? {
+            parser
+                .apply(&statement)
+                .map_err(|e| anyhow!("line {}: {}", line_ix + 1, e))?;
+        }
+    }
+    Ok(parser.t)
+}
+
+/// Records the run with the provided id of a workflow in a test environment as a canned history.
+/// The environment applies every command the workflow issues as events in its history, so the
+/// recording captures the commands and how the environment responded to them.
+///
+/// Returns an error if the run does not exist, or it produced events the format does not support.
+/// See [record_canned_history] for what is dropped along the way.
+pub fn record_test_env_run(
+    env: &TestWorkflowEnvironment,
+    run_id: &str,
+) -> Result<String, anyhow::Error> {
+    let history = env
+        .history(run_id)
+        .ok_or_else(|| anyhow!("run {} is not in the test environment", run_id))?;
+    record_canned_history(&history)
+}
+
+/// Renders a history as a canned history, which [parse_canned_history] turns back into an
+/// equivalent history. Useful for turning histories produced by a test environment or fetched
+/// from a server into regression tests.
+///
+/// Only what the format can express is kept, so details like timestamps, workflow and activity
+/// types, and payload metadata are dropped. Returns an error if the history contains events the
+/// format does not support, or payloads it cannot represent.
+pub fn record_canned_history(history: &History) -> Result<String, anyhow::Error> {
+    let mut activities = HashMap::new();
+    // Hand built histories often leave the id off of timer started events, so timers are named
+    // by whichever event gives their id
+    let mut timers = HashMap::new();
+    for event in &history.events {
+        match event.attributes.as_ref() {
+            Some(Attributes::TimerStartedEventAttributes(a)) if !a.timer_id.is_empty() => {
+                timers.insert(event.event_id, a.timer_id.clone());
+            }
+            Some(Attributes::TimerFiredEventAttributes(a)) => {
+                timers.insert(a.started_event_id, a.timer_id.clone());
+            }
+            Some(Attributes::TimerCanceledEventAttributes(a)) => {
+                timers.insert(a.started_event_id, a.timer_id.clone());
+            }
+            _ => {}
+        }
+    }
+    let timer_id = |started: i64| {
+        timers
+            .get(&started)
+            .cloned()
+            .unwrap_or_else(|| started.to_string())
+    };
+    let mut statements: Vec<Vec<String>> = vec![];
+    let events = &history.events;
+    let mut ix = 0;
+    while ix < events.len() {
+        let event = &events[ix];
+        let event_type = event.event_type();
+        let is_full_wft = event_type == EventType::WorkflowTaskScheduled
+            && events.get(ix + 1).map(HistoryEvent::event_type)
+                == Some(EventType::WorkflowTaskStarted)
+            && events.get(ix + 2).map(HistoryEvent::event_type)
+                == Some(EventType::WorkflowTaskCompleted);
+        if is_full_wft {
+            statements.push(vec!["wft".to_string()]);
+            ix += 3;
+            continue;
+        }
+        ix += 1;
+
+        let statement = match (event_type, event.attributes.as_ref()) {
+            (EventType::WorkflowExecutionStarted, _) => vec!["started".to_string()],
+            (EventType::WorkflowExecutionCompleted, _) => vec!["completed".to_string()],
+            (EventType::WorkflowExecutionFailed, _) => vec!["failed".to_string()],
+            (EventType::WorkflowExecutionContinuedAsNew, _) => {
+                vec!["continued_as_new".to_string()]
+            }
+            (EventType::WorkflowExecutionCancelRequested, _) => {
+                vec!["cancel_requested".to_string()]
+            }
+            (EventType::WorkflowExecutionCanceled, _) => vec!["cancelled".to_string()],
+            (EventType::WorkflowTaskScheduled, _) => vec!["wft_scheduled".to_string()],
+            (EventType::WorkflowTaskStarted, _) => vec!["wft_started".to_string()],
+            (EventType::WorkflowTaskCompleted, _) => vec!["wft_completed".to_string()],
+            (EventType::WorkflowTaskTimedOut, _) => vec!["wft_timed_out".to_string()],
+            (
+                EventType::WorkflowTaskFailed,
+                Some(Attributes::WorkflowTaskFailedEventAttributes(a)),
+            ) => with_message("wft_failed", vec![], a.failure.as_ref()),
+            (EventType::TimerStarted, _) => {
+                vec!["timer_started".to_string(), timer_id(event.event_id)]
+            }
+            (EventType::TimerFired, Some(Attributes::TimerFiredEventAttributes(a))) => {
+                vec!["timer_fired".to_string(), timer_id(a.started_event_id)]
+            }
+            (EventType::TimerCanceled, Some(Attributes::TimerCanceledEventAttributes(a))) => {
+                vec!["timer_canceled".to_string(), timer_id(a.started_event_id)]
+            }
+            (
+                EventType::ActivityTaskScheduled,
+                Some(Attributes::ActivityTaskScheduledEventAttributes(a)),
+            ) => {
+                activities.insert(event.event_id, a.activity_id.clone());
+                vec!["activity_scheduled".to_string(), a.activity_id.clone()]
+            }
+            (
+                EventType::ActivityTaskStarted,
+                Some(Attributes::ActivityTaskStartedEventAttributes(a)),
+            ) => vec![
+                "activity_started".to_string(),
+                activity_id(&activities, a.scheduled_event_id)?,
+            ],
+            (
+                EventType::ActivityTaskCompleted,
+                Some(Attributes::ActivityTaskCompletedEventAttributes(a)),
+            ) => {
+                let mut s = vec![
+                    "activity_completed".to_string(),
+                    activity_id(&activities, a.scheduled_event_id)?,
+                ];
+                s.extend(result_text(a.result.as_ref())?);
+                s
+            }
+            (
+                EventType::ActivityTaskFailed,
+                Some(Attributes::ActivityTaskFailedEventAttributes(a)),
+            ) => with_message(
+                "activity_failed",
+                vec![activity_id(&activities, a.scheduled_event_id)?],
+                a.failure.as_ref(),
+            ),
+            (
+                EventType::ActivityTaskCancelRequested,
+                Some(Attributes::ActivityTaskCancelRequestedEventAttributes(a)),
+            ) => vec![
+                "activity_cancel_requested".to_string(),
+                activity_id(&activities, a.scheduled_event_id)?,
+            ],
+            (
+                EventType::ActivityTaskCanceled,
+                Some(Attributes::ActivityTaskCanceledEventAttributes(a)),
+            ) => vec![
+                "activity_cancelled".to_string(),
+                activity_id(&activities, a.scheduled_event_id)?,
+            ],
+            (
+                EventType::WorkflowExecutionSignaled,
+                Some(Attributes::WorkflowExecutionSignaledEventAttributes(a)),
+            ) => {
+                let mut s = vec!["signal".to_string(), a.signal_name.clone()];
+                for payload in a.input.iter().flat_map(|i| i.payloads.iter()) {
+                    s.push(payload_text(payload)?);
+                }
+                s
+            }
+            (EventType::MarkerRecorded, Some(Attributes::MarkerRecordedEventAttributes(a))) => {
+                record_marker(a)?
+            }
+            (et, _) => bail!(
+                "event {} of type {:?} is not supported by canned histories",
+                event.event_id,
+                et
+            ),
+        };
+        statements.push(statement);
+    }
+
+    let mut out = String::new();
+    for statement in statements {
+        let words: Vec<_> = statement.iter().map(|w| quote(w)).collect();
+        writeln!(out, "{}", words.join(" ")).expect("Writing to a string cannot fail");
+    }
+    Ok(out)
+}
+
+#[derive(Default)]
+struct Parser {
+    t: TestHistoryBuilder,
+    /// Started event ids of timers, by timer id
+    timers: HashMap<String, i64>,
+    /// Scheduled and (if started) started event ids of activities, by activity id
+    activities: HashMap<String, (i64, i64)>,
+}
+
+impl Parser {
+    fn apply(&mut self, statement: &[String]) -> Result<(), anyhow::Error> {
+        let (name, args) = statement.split_first().expect("Statements are never empty");
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let t = &mut self.t;
+        match (name.as_str(), args.as_slice()) {
+            ("started", []) => t.add_by_type(EventType::WorkflowExecutionStarted),
+            ("completed", []) => t.add_workflow_execution_completed(),
+            ("failed", []) => t.add_workflow_execution_failed(),
+            ("continued_as_new", []) => t.add_continued_as_new(),
+            ("cancel_requested", []) => t.add_cancel_requested(),
+            ("cancelled", []) => t.add_cancelled(),
+            ("wft", []) => t.add_full_wf_task(),
+            ("wft_scheduled", []) => t.add_workflow_task_scheduled(),
+            ("wft_started", []) => t.add_workflow_task_started(),
+            ("wft_completed", []) => t.add_workflow_task_completed(),
+            ("wft_timed_out", []) => t.add_workflow_task_timed_out(),
+            ("wft_failed", message) if message.len() <= 1 => t
+                .add_workflow_task_failed_with_failure(
+                    WorkflowTaskFailedCause::WorkflowWorkerUnhandledFailure,
+                    failure(message.first()),
+                ),
+            ("timer_started", [id]) => {
+                let started = t.add_get_event_id(
+                    EventType::TimerStarted,
+                    Some(
+                        TimerStartedEventAttributes {
+                            timer_id: id.to_string(),
+                            ..Default::default()
+                        }
+                        .into(),
+                    ),
+                );
+                self.timers.insert(id.to_string(), started);
+            }
+            ("timer_fired", [id]) => {
+                let started = Self::lookup(&self.timers, "timer", id)?;
+                t.add_timer_fired(started, id.to_string());
+            }
+            ("timer_canceled", [id]) => {
+                let started = Self::lookup(&self.timers, "timer", id)?;
+                t.add(
+                    EventType::TimerCanceled,
+                    TimerCanceledEventAttributes {
+                        started_event_id: started,
+                        timer_id: id.to_string(),
+                        ..Default::default()
+                    }
+                    .into(),
+                );
+            }
+            ("activity_scheduled", [id]) => {
+                let scheduled = t.add_activity_task_scheduled(*id);
+                self.activities.insert(id.to_string(), (scheduled, 0));
+            }
+            ("activity_started", [id]) => {
+                let (scheduled, _) = Self::lookup(&self.activities, "activity", id)?;
+                let started = t.add_activity_task_started(scheduled);
+                self.activities.insert(id.to_string(), (scheduled, started));
+            }
+            ("activity_completed", [id, result @ ..]) if result.len() <= 1 => {
+                let (scheduled, started) = Self::lookup(&self.activities, "activity", id)?;
+                t.add_activity_task_completed(
+                    scheduled,
+                    started,
+                    result.first().map(|r| core_payload(r)).unwrap_or_default(),
+                );
+            }
+            ("activity_failed", [id, message @ ..]) if message.len() <= 1 => {
+                let (scheduled, started) = Self::lookup(&self.activities, "activity", id)?;
+                t.add(
+                    EventType::ActivityTaskFailed,
+                    ActivityTaskFailedEventAttributes {
+                        scheduled_event_id: scheduled,
+                        started_event_id: started,
+                        failure: Some(failure(message.first())),
+                        ..Default::default()
+                    }
+                    .into(),
+                );
+            }
+            ("activity_cancel_requested", [id]) => {
+                let (scheduled, _) = Self::lookup(&self.activities, "activity", id)?;
+                t.add_activity_task_cancel_requested(scheduled);
+            }
+            ("activity_cancelled", [id]) => {
+                let (scheduled, started) = Self::lookup(&self.activities, "activity", id)?;
+                t.add(
+                    EventType::ActivityTaskCanceled,
+                    ActivityTaskCanceledEventAttributes {
+                        scheduled_event_id: scheduled,
+                        started_event_id: started,
+                        ..Default::default()
+                    }
+                    .into(),
+                );
+            }
+            ("signal", [name, payloads @ ..]) => {
+                t.add_we_signaled(name, payloads.iter().map(|p| api_payload(p)).collect())
+            }
+            ("patch", [id]) => t.add_has_change_marker(id, false),
+            ("patch", [id, "deprecated"]) => t.add_has_change_marker(id, true),
+            ("local_activity", [seq, id, result @ ..]) if result.len() <= 1 => t
+                .add_local_activity_result_marker(
+                    seq.parse()?,
+                    id,
+                    result.first().map(|r| core_payload(r)).unwrap_or_default(),
+                ),
+            ("local_activity_failed", [seq, id, message @ ..]) if message.len() <= 1 => {
+                t.add_local_activity_fail_marker(seq.parse()?, id, failure(message.first()))
+            }
+            ("local_activity_cancelled", [seq, id]) => {
+                t.add_local_activity_cancel_marker(seq.parse()?, id)
+            }
+            _ => bail!("invalid statement `{}`", statement.join(" ")),
+        }
+        Ok(())
+    }
+
+    fn lookup<T: Copy>(ids: &HashMap<String, T>, kind: &str, id: &str) -> Result<T, anyhow::Error> {
+        ids.get(id)
+            .copied()
+            .ok_or_else(|| anyhow!("{} `{}` was never started or scheduled", kind, id))
+    }
+}
+
+/// Splits a line into statements, each a list of its words
+fn tokenize(line: &str) -> Result<Vec<Vec<String>>, anyhow::Error> {
+    let mut statements = vec![];
+    let mut statement = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => break,
+            ';' => statements.push(std::mem::take(&mut statement)),
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => word.push(escaped),
+                            None => bail!("unterminated string"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated string"),
+                    }
+                }
+                statement.push(word);
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ';' | '#' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                statement.push(word);
+            }
+        }
+    }
+    statements.push(statement);
+    Ok(statements.into_iter().filter(|s| !s.is_empty()).collect())
+}
+
+fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, ';' | '#' | '"' | '\\'));
+    if plain {
+        word.to_string()
+    } else {
+        format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn failure(message: Option<&&str>) -> Failure {
+    Failure {
+        message: message.map(|m| m.to_string()).unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+fn core_payload(data: &str) -> CorePayload {
+    CorePayload {
+        metadata: Default::default(),
+        data: data.as_bytes().to_vec(),
+    }
+}
+
+fn api_payload(data: &str) -> Payload {
+    Payload {
+        metadata: Default::default(),
+        data: data.as_bytes().to_vec(),
+    }
+}
+
+fn payload_text(payload: &Payload) -> Result<String, anyhow::Error> {
+    String::from_utf8(payload.data.clone())
+        .map_err(|_| anyhow!("payload data is not UTF-8, which canned histories require"))
+}
+
+/// The text of a result, which canned histories hold at most one payload of. Empty results are
+/// written as no result at all.
+fn result_text(result: Option<&Payloads>) -> Result<Option<String>, anyhow::Error> {
+    match result.map(|r| r.payloads.as_slice()).unwrap_or_default() {
+        [] => Ok(None),
+        [p] if p.data.is_empty() => Ok(None),
+        [p] => payload_text(p).map(Some),
+        ps => bail!(
+            "result has {} payloads, but canned histories hold at most one",
+            ps.len()
+        ),
+    }
+}
+
+fn activity_id(activities: &HashMap<i64, String>, scheduled: i64) -> Result<String, anyhow::Error> {
+    activities.get(&scheduled).cloned().ok_or_else(|| {
+        anyhow!(
+            "activity scheduled by event {} is not in the history",
+            scheduled
+        )
+    })
+}
+
+fn with_message(name: &str, mut args: Vec<String>, failure: Option<&Failure>) -> Vec<String> {
+    let mut statement = vec![name.to_string()];
+    statement.append(&mut args);
+    statement.extend(failure.map(|f| f.message.clone()).filter(|m| !m.is_empty()));
+    statement
+}
+
+fn record_marker(attrs: &MarkerRecordedEventAttributes) -> Result<Vec<String>, anyhow::Error> {
+    if attrs.marker_name == PATCH_MARKER_NAME {
+        let (id, deprecated) = decode_change_marker_details(&attrs.details)
+            .ok_or_else(|| anyhow!("patch marker is malformed"))?;
+        let mut statement = vec!["patch".to_string(), id];
+        if deprecated {
+            statement.push("deprecated".to_string());
+        }
+        return Ok(statement);
+    }
+    if attrs.marker_name == LOCAL_ACTIVITY_MARKER_NAME {
+        let data = extract_local_activity_marker_data(&attrs.details)
+            .ok_or_else(|| anyhow!("local activity marker is malformed"))?;
+        if data.promoted {
+            bail!("promoted local activity markers are not supported by canned histories");
+        }
+        let ids = vec![data.seq.to_string(), data.activity_id];
+        return Ok(match attrs.failure.as_ref() {
+            Some(Failure {
+                failure_info: Some(FailureInfo::CanceledFailureInfo(_)),
+                ..
+            }) => {
+                let mut statement = vec!["local_activity_cancelled".to_string()];
+                statement.extend(ids);
+                statement
+            }
+            Some(f) => with_message("local_activity_failed", ids, Some(f)),
+            None => {
+                let mut statement = vec!["local_activity".to_string()];
+                statement.extend(ids);
+                statement.extend(result_text(attrs.details.get("result"))?);
+                statement
+            }
+        });
+    }
+    bail!(
+        "marker `{}` is not supported by canned histories",
+        attrs.marker_name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{canned_histories, init_time_skipping_env};
+    use std::time::Duration;
+    use temporal_sdk::{ActivityOptions, WfContext};
+    use temporal_sdk_core_protos::coresdk::AsJsonPayloadExt;
+
+    fn history(t: &TestHistoryBuilder) -> History {
+        t.get_full_history_info().unwrap().into()
+    }
+
+    /// The events of a history, without the times and run ids each builder generates anew
+    fn events(history: &History) -> Vec<HistoryEvent> {
+        let mut events = history.events.clone();
+        for event in &mut events {
+            event.event_time = None;
+            if let Some(Attributes::WorkflowExecutionStartedEventAttributes(a)) =
+                event.attributes.as_mut()
+            {
+                a.original_execution_run_id.clear();
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn canned_histories_round_trip_through_recorder() {
+        let canned = "\
+started
+wft
+timer_started 1
+timer_canceled 1
+activity_scheduled act1
+activity_started act1
+activity_failed act1 \"it \\\"broke\\\"\"
+wft_scheduled
+wft_started
+wft_failed
+signal sig one \"two words\"
+patch my-patch deprecated
+local_activity 1 la1 result
+local_activity_failed 2 la2 oops
+local_activity_cancelled 3 la3
+wft
+failed
+";
+        let original = history(&parse_canned_history(canned).unwrap());
+        let recorded = record_canned_history(&original).unwrap();
+        assert_eq!(recorded, canned);
+        let rerecorded = history(&parse_canned_history(&recorded).unwrap());
+        assert_eq!(events(&rerecorded), events(&original));
+
+        // Histories built by hand are recorded as the same events, except that timers they left
+        // unnamed are named by the events which fire or cancel them
+        for t in [
+            canned_histories::cancel_timer("wait", "cancel"),
+            canned_histories::single_activity("act1"),
+            canned_histories::two_signals("sig1", "sig2"),
+            canned_histories::timer_wf_cancel_req_cancelled("1"),
+        ] {
+            let original = history(&t);
+            let rerecorded =
+                history(&parse_canned_history(&record_canned_history(&original).unwrap()).unwrap());
+            let mut expected = events(&original);
+            for (event, rerecorded) in expected.iter_mut().zip(&rerecorded.events) {
+                if let (
+                    Some(Attributes::TimerStartedEventAttributes(a)),
+                    Some(Attributes::TimerStartedEventAttributes(named)),
+                ) = (event.attributes.as_mut(), rerecorded.attributes.as_ref())
+                {
+                    if a.timer_id.is_empty() {
+                        a.timer_id = named.timer_id.clone();
+                    }
+                }
+            }
+            assert_eq!(events(&rerecorded), expected);
+        }
+
+        assert!(parse_canned_history("started\ntimer_fired never_started").is_err());
+        assert!(parse_canned_history("not_a_statement").is_err());
+    }
+
+    #[test]
+    fn payloads_canned_histories_cannot_hold_are_errors() {
+        let mut t = parse_canned_history("started; wft; activity_scheduled act1").unwrap();
+        let started = t.add_activity_task_started(5);
+        t.add_activity_task_completed(
+            5,
+            started,
+            CorePayload {
+                metadata: Default::default(),
+                data: vec![0xff, 0xfe],
+            },
+        );
+        assert!(record_canned_history(&history(&t)).is_err());
+
+        let mut hist = history(&parse_canned_history("started; signal sig one").unwrap());
+        if let Some(Attributes::WorkflowExecutionSignaledEventAttributes(a)) =
+            hist.events[1].attributes.as_mut()
+        {
+            a.input.as_mut().unwrap().payloads[0].data = vec![0xff];
+        }
+        assert!(record_canned_history(&hist).is_err());
+
+        let mut hist =
+            history(&parse_canned_history("started; wft; local_activity 1 la1 one").unwrap());
+        if let Some(Attributes::MarkerRecordedEventAttributes(a)) =
+            hist.events[4].attributes.as_mut()
+        {
+            let result = a.details.get_mut("result").unwrap();
+            result.payloads.push(result.payloads[0].clone());
+        }
+        assert!(record_canned_history(&hist).is_err());
+    }
+
+    #[tokio::test]
+    async fn records_runs_in_test_environment() {
+        let (env, mut worker) = init_time_skipping_env("records_runs_in_test_environment");
+        env.register_activity_stub("echo", |call| Ok(call.input[0].clone()));
+        worker.register_wf("timer_then_activity", |ctx: WfContext| async move {
+            ctx.timer(Duration::from_secs(60)).await;
+            ctx.activity(ActivityOptions {
+                activity_type: "echo".to_string(),
+                activity_id: Some("act1".to_string()),
+                input: "hi".as_json_payload().unwrap(),
+                start_to_close_timeout: Some(Duration::from_secs(5)),
+                ..Default::default()
+            })
+            .await;
+            Ok(().into())
+        });
+        let run_id = env.start_workflow("recorded", "timer_then_activity", vec![]);
+        worker.run_until_done().await.unwrap();
+
+        assert_eq!(
+            record_test_env_run(&env, &run_id).unwrap(),
+            "\
+started
+wft
+timer_started 1
+timer_fired 1
+wft
+activity_scheduled act1
+activity_started act1
+activity_completed act1 \"\\\"hi\\\"\"
+wft
+completed
+"
+        );
+        assert!(record_test_env_run(&env, "not-a-run").is_err());
+    }
+}
@@ -4,7 +4,9 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod activity_mocker;
 pub mod canned_histories;
+pub mod history_dsl;
 
 use crate::stream::TryStreamExt;
 use futures::{stream, stream::FuturesUnordered, StreamExt};
@@ -29,8 +31,9 @@ use temporal_client::{
 };
 use temporal_sdk::{interceptors::WorkerInterceptor, IntoActivityFunc, Worker, WorkflowFunction};
 use temporal_sdk_core::{
-    init_replay_worker, init_worker, telemetry_init, ClientOptions, ClientOptionsBuilder,
-    TelemetryOptions, TelemetryOptionsBuilder, WorkerConfig, WorkerConfigBuilder,
+    init_replay_worker, init_worker, telemetry_init, test_env::TestWorkflowEnvironment,
+    ClientOptions, ClientOptionsBuilder, TelemetryOptions, TelemetryOptionsBuilder, WorkerConfig,
+    WorkerConfigBuilder,
 };
 use temporal_sdk_core_api::Worker as CoreWorker;
 use temporal_sdk_core_protos::{
@@ -78,6 +81,24 @@ pub fn init_core_replay_preloaded(
     (Arc::new(worker), test_name.to_string())
 }
 
+/// Create a time-skipping test environment, which runs workflows in-process without a server, and
+/// a worker driven by it. Start workflows with the environment, then run the worker until they are
+/// done.
+pub fn init_time_skipping_env(test_name: &str) -> (TestWorkflowEnvironment, TestWorker) {
+    telemetry_init(&get_integ_telem_options()).expect("Telemetry inits cleanly");
+    let worker_cfg = WorkerConfigBuilder::default()
+        .namespace(NAMESPACE)
+        .task_queue(test_name)
+        .max_cached_workflows(1000_usize)
+        .build()
+        .expect("Configuration options construct properly");
+    let env = TestWorkflowEnvironment::new(worker_cfg);
+    let mut worker = TestWorker::new(env.worker(), test_name);
+    // The environment shuts the worker down once every workflow it started is done
+    worker.auto_shutdown = false;
+    (env, worker)
+}
+
 /// Load history from a file containing the protobuf serialization of it
 pub async fn history_from_proto_binary(path_from_root: &str) -> Result<History, anyhow::Error> {
     let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -1,7 +1,11 @@
 use assert_matches::assert_matches;
+use futures::stream;
 use std::time::Duration;
 use temporal_sdk::{WfContext, Worker, WorkflowFunction};
-use temporal_sdk_core::telemetry_init;
+use temporal_sdk_core::{
+    replay::{HistoryForReplay, ReplayOutcome, Replayer, WorkflowReplayResult},
+    telemetry_init, WorkerConfigBuilder,
+};
 use temporal_sdk_core_api::errors::{PollActivityError, PollWfError};
 use temporal_sdk_core_protos::{
     coresdk::{
@@ -9,6 +13,7 @@ use temporal_sdk_core_protos::{
         workflow_commands::{ScheduleActivity, StartTimer},
         workflow_completion::WorkflowActivationCompletion,
     },
+    temporal::api::history::v1::History,
     DEFAULT_WORKFLOW_TYPE,
 };
 use temporal_sdk_core_test_utils::{
@@ -112,6 +117,58 @@ async fn replay_using_wf_function() {
     worker.run().await.unwrap();
 }
 
+#[tokio::test]
+async fn replayer_reports_each_history() {
+    telemetry_init(&get_integ_telem_options()).unwrap();
+    let timers_hist = || -> History {
+        canned_histories::long_sequential_timers(2)
+            .get_full_history_info()
+            .unwrap()
+            .into()
+    };
+    let histories = vec![
+        HistoryForReplay::new(timers_hist(), "good-1"),
+        HistoryForReplay::new(
+            canned_histories::single_activity("1")
+                .get_full_history_info()
+                .unwrap()
+                .into(),
+            "bad",
+        ),
+        HistoryForReplay::new(timers_hist(), "good-2"),
+        HistoryForReplay::new(timers_hist(), "good-3"),
+    ];
+    let worker_cfg = WorkerConfigBuilder::default()
+        .namespace("replayer")
+        .task_queue("replayer")
+        .max_cached_workflows(2_usize)
+        .build()
+        .unwrap();
+    let replayer = Replayer::new(worker_cfg, stream::iter(histories))
+        .await
+        .unwrap();
+    let mut worker = Worker::new_from_core(replayer.worker(), "replayer");
+    worker.register_wf(DEFAULT_WORKFLOW_TYPE, timers_wf(2));
+    worker.run().await.unwrap();
+
+    let report = replayer.report();
+    let wf_ids: Vec<_> = report
+        .results
+        .iter()
+        .map(|r| r.workflow_id.as_str())
+        .collect();
+    assert_eq!(wf_ids, ["good-1", "bad", "good-2", "good-3"]);
+    assert!(!report.all_succeeded());
+    assert_matches!(
+        report.failures().collect::<Vec<_>>().as_slice(),
+        [WorkflowReplayResult {
+            workflow_id,
+            outcome: ReplayOutcome::Nondeterminism { .. },
+            ..
+        }] if workflow_id == "bad"
+    );
+}
+
 fn timers_wf(num_timers: u32) -> WorkflowFunction {
     WorkflowFunction::new(move |ctx: WfContext| async move {
         for _ in 1..=num_timers {